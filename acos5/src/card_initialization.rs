@@ -0,0 +1,637 @@
+/*
+ * card_initialization.rs: Driver 'acos5' - Card initialization: Creates the file system layout of
+ *                         info/card_initialization/card_initialization.scriptor
+ *
+ * Copyright (C) 2019  Carsten Blüggel <bluecars@posteo.eu>
+ *
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; if not, write to the Free Software
+ * Foundation, 51 Franklin Street, Fifth Floor  Boston, MA 02110-1335  USA
+ */
+
+/*
+The driver-side equivalent of info/card_initialization/card_initialization.scriptor: Invoked by pkcs15-init --erase-card
+(acos5_pkcs15_erase_card), if acos5_external.profile enables it, after the card got erased by 'Zeroize Card User Data'
+(card_ctl SC_CARDCTL_ERASE_CARD, erase_card_card_ctl).
+
+Overview: This file system structure will be created
+file 0x3F00         MF
+file 0x3F000001     Global PIN file
+file 0x3F000003     Global Security Environment file
+file 0x3F002F00     PKCS#15 EF.DIR
+file 0x3F004100     PKCS#15 Application DF
+file 0x3F0041004101 Local PIN file
+file 0x3F0041004102 Local Symmetric Key file
+file 0x3F0041004103 Local Security Environment file
+file 0x3F0041004110 PKCS#15 EF.PrKDF
+file 0x3F0041004111 PKCS#15 EF.PuKDF
+file 0x3F0041004112 PKCS#15 EF.PuKDF_TRUSTED
+file 0x3F0041004113 PKCS#15 EF.SKDF
+file 0x3F0041004114 PKCS#15 EF.CDF
+file 0x3F0041004115 PKCS#15 EF.CDF_TRUSTED
+file 0x3F0041004116 PKCS#15 EF.CDF_USEFUL
+file 0x3F0041004117 PKCS#15 EF.DODF
+file 0x3F0041004118 PKCS#15 EF.AODF
+file 0x3F0041005031 PKCS#15 EF.ODF
+file 0x3F0041005032 PKCS#15 EF.TokenInfo
+file 0x3F0041004129 File to retain compatibility with ACS tool/PKCS#11 dll
+file 0x3F0041005155 File to retain compatibility with ACS tool/PKCS#11 dll
+
+Each file gets created in LCSI 'creation state' (access control isn't enforced yet), populated and activated.
+For ACOS5-EVO, the xDFs get double the size (3 times the EEPROM capacity), and EF.TokenInfo's supportedAlgorithms refer
+to the EVO algorithm references.
+*/
+
+use std::ptr::null_mut;
+use std::os::raw::c_void;
+use std::convert::TryFrom;
+
+use opensc_sys::opensc::{sc_card, sc_get_mf_path, sc_select_file, sc_transmit_apdu, sc_check_sw};
+use opensc_sys::types::{SC_APDU_CASE_1, SC_APDU_CASE_3_SHORT};
+use opensc_sys::opensc::{SC_ALGORITHM_3DES, SC_ALGORITHM_AES};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_NOT_ALLOWED, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_CARD_CMD_FAILED,
+                         SC_ERROR_SECURITY_STATUS_NOT_SATISFIED};
+
+use crate::wrappers::{wr_do_log, wr_do_log_sds, wr_do_log_t};
+use crate::constants_types::{DataPrivate, CardCtlCardInit, CardCtlEraseCard, build_apdu, FDB_MF, FDB_DF, FDB_TRANSPARENT_EF,
+                             FDB_LINEAR_FIXED_EF, FDB_LINEAR_VARIABLE_EF, FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_SE_FILE,
+                             LCSI_CREATION, LCSI_ACTIVATED, SE_RECORDS_MF, SE_RECORDS_DF, pin_record, SC_CARD_TYPE_ACOS5_64_V3,
+                             SC_CARD_TYPE_ACOS5_EVO_V4, DELETE_SELF, AC_KIND_ALWAYS, AC_KIND_CONDITIONS, ERASE_AUTH_UNKNOWN, ERASE_AUTH_FULFILLED,
+                             ERASE_AUTH_NOT_FULFILLED, ERASE_AUTH_NEVER};
+use crate::no_cdecl::{algo_ref_sym_store, enum_dir};
+use crate::cmd_card_info::{get_does_mf_exist, is_op_mode_byte_supported, set_op_mode_byte_eeprom,
+                           get_zeroize_card_disable_byte_eeprom, get_card_life_cycle_byte_eeprom,
+                           get_is_pin_authenticated, get_is_key_authenticated};
+use crate::se::{se_get_ac_report};
+use crate::crypto::{Zeroizing, DES_cblock, DES_KEY_SZ, DES_set_odd_parity};
+
+/* the PKCS#15 application's AID: ACOSPKCS-15v1.00 */
+const AID_PKCS15 : [u8; 16] = [0x41, 0x43, 0x4F, 0x53, 0x50, 0x4B, 0x43, 0x53, 0x2D, 0x31, 0x35, 0x76, 0x31, 0x2E, 0x30, 0x30];
+
+/* EF.DIR record: aid, label=eCert, path=3F004100 */
+const EF_DIR : [u8; 33] = [0x61, 0x1F, 0x4F, 0x10, 0x41, 0x43, 0x4F, 0x53, 0x50, 0x4B, 0x43, 0x53, 0x2D, 0x31, 0x35,
+    0x76, 0x31, 0x2E, 0x30, 0x30, 0x50, 0x05, 0x65, 0x43, 0x65, 0x72, 0x74, 0x51, 0x04, 0x3F, 0x00, 0x41, 0x00];
+
+/* EF.AODF: User PIN (local, reference 0x81), SO PIN (global, reference 0x01) */
+pub const EF_AODF : [u8; 105] = [0x30, 0x35, 0x30, 0x0A, 0x0C, 0x04, 0x55, 0x73, 0x65, 0x72, 0x03, 0x02, 0x06, 0xC0, 0x30,
+    0x03, 0x04, 0x01, 0x01, 0xA1, 0x22, 0x30, 0x20, 0x03, 0x03, 0x04, 0xCC, 0x00, 0x0A, 0x01, 0x01, 0x02, 0x01, 0x04, 0x02,
+    0x01, 0x08, 0x02, 0x01, 0x08, 0x80, 0x02, 0x00, 0x81, 0x04, 0x01, 0xFF, 0x30, 0x06, 0x04, 0x04, 0x3F, 0x00, 0x41, 0x00,
+    0x30, 0x30, 0x30, 0x08, 0x0C, 0x02, 0x53, 0x4F, 0x03, 0x02, 0x06, 0xC0, 0x30, 0x03, 0x04, 0x01, 0x02, 0xA1, 0x1F, 0x30,
+    0x1D, 0x03, 0x03, 0x04, 0x8F, 0x00, 0x0A, 0x01, 0x01, 0x02, 0x01, 0x04, 0x02, 0x01, 0x08, 0x02, 0x01, 0x08, 0x80, 0x01,
+    0x01, 0x04, 0x01, 0xFF, 0x30, 0x04, 0x04, 0x02, 0x3F, 0x00];
+
+/* EF.ODF: PrKDF 4110, PuKDF 4111, SKDF 4113, CDF 4114, CDF_TRUSTED 4115, CDF_USEFUL 4116, DODF 4117, AODF 4118,
+   followed by zero bytes up to the file size */
+const EF_ODF : [u8; 108] = [0xA0, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x10, 0xA1, 0x0A, 0x30,
+    0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x11, 0xA3, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41,
+    0x13, 0xA4, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x14, 0xA5, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F,
+    0x00, 0x41, 0x00, 0x41, 0x15, 0xA6, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x16, 0xA7, 0x0A, 0x30,
+    0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x17, 0xA8, 0x0A, 0x30, 0x08, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41,
+    0x18, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/* EF.TokenInfo; the last bytes of both supportedAlgorithms entries are the card's algorithm references for
+   AES-256-ECB and AES-256-CBC, replaced for EVO */
+const EF_TOKENINFO : [u8; 125] = [0x30, 0x7B, 0x02, 0x01, 0x01, 0x04, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0C,
+    0x1A, 0x41, 0x64, 0x76, 0x61, 0x6E, 0x63, 0x65, 0x64, 0x20, 0x43, 0x61, 0x72, 0x64, 0x20, 0x53, 0x79, 0x73, 0x74, 0x65,
+    0x6D, 0x73, 0x20, 0x4C, 0x74, 0x64, 0x2E, 0x80, 0x12, 0x43, 0x54, 0x4D, 0x36, 0x34, 0x5F, 0x30, 0x30, 0x30, 0x30, 0x30,
+    0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x03, 0x02, 0x04, 0x20, 0xA2, 0x3A, 0x30, 0x1B, 0x02, 0x01, 0x01, 0x02, 0x02,
+    0x10, 0x81, 0x05, 0x00, 0x03, 0x02, 0x00, 0x0C, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x29, 0x02,
+    0x01, 0x04, 0x30, 0x1B, 0x02, 0x01, 0x02, 0x02, 0x02, 0x10, 0x82, 0x05, 0x00, 0x03, 0x02, 0x00, 0x0C, 0x06, 0x09, 0x60,
+    0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2A, 0x02, 0x01, 0x06];
+const EF_TOKENINFO_POS_ALGO_REF_ECB : usize =  95;
+const EF_TOKENINFO_POS_ALGO_REF_CBC : usize = 124;
+
+/* ACS proprietary files 4129 and 5155 (records) to retain (some) compatibility with ACS tool/driver */
+const ACS_4129 : [[u8; 20]; 2] = [
+    [0, 0, 0, 0, 0x4A, 0x97, 0x4A, 0x97, 0x72, 0x49, 0x72, 0x49, 0, 0, 0, 0, 0, 0, 1, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]];
+const ACS_5155 : [[u8; 61]; 2] = [
+    [0x30, 0x3B, 0x30, 0x08, 0x0C, 0x02, 0x30, 0x31, 0x03, 0x02, 0x07, 0x80, 0x30, 0x03, 0x03, 0x01, 0x81, 0xA1, 0x2A, 0x30,
+     0x28, 0x03, 0x03, 0x07, 0xCB, 0x80, 0x0A, 0x01, 0x01, 0x02, 0x01, 0x04, 0x02, 0x01, 0x08, 0x02, 0x01, 0x08, 0xA0, 0x03,
+     0x02, 0x01, 0x08, 0x04, 0x01, 0x00, 0x18, 0x0D, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0x31, 0x32,
+     0x33],
+    [0x30, 0x3B, 0x30, 0x08, 0x0C, 0x02, 0x30, 0x32, 0x03, 0x02, 0x07, 0x80, 0x30, 0x03, 0x03, 0x01, 0x82, 0xA1, 0x2A, 0x30,
+     0x28, 0x03, 0x03, 0x07, 0xCB, 0x80, 0x0A, 0x01, 0x01, 0x02, 0x01, 0x04, 0x02, 0x01, 0x08, 0x02, 0x01, 0x08, 0xA0, 0x03,
+     0x02, 0x01, 0x08, 0x04, 0x01, 0x00, 0x18, 0x0D, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0x31, 0x32,
+     0x33]];
+
+/* The xDFs within appDF 4100 other than EF.SKDF and EF.AODF (these have content): file id, size V2/V3, size EVO */
+const XDFS_EMPTY : [(u16, u16, u16); 7] = [(0x4110, 0x0300, 0x0600), (0x4111, 0x0300, 0x0600), (0x4112, 0x0100, 0x0200),
+    (0x4114, 0x0100, 0x0200), (0x4115, 0x0100, 0x0200), (0x4116, 0x0100, 0x0200), (0x4117, 0x0100, 0x0200)];
+
+/* Tag 8C's 7 SCB bytes (following 0x7F), i.e. in the reverse order of scb8 */
+const SCB_XDF       : [u8; 7] = [0x00, 0xFF, 0x00, 0x03, 0xFF, 0x00, 0x00];
+const SCB_XDF_APPDF : [u8; 7] = [0x03, 0xFF, 0x00, 0x03, 0xFF, 0x00, 0x00]; // EF.ODF, deletable only after local PIN verification
+
+/* Create File command; the FCP tags: 83 (file id), 8A (LCSI), 82 (FDB...), others (e.g. 80, 8D, 84), 8C (SAC) */
+fn create(fid: u16, lcsi: u8, descriptor: &[u8], others: &[(u8, &[u8])], scb: &[u8; 7]) -> Vec<u8> {
+    let fid = fid.to_be_bytes();
+    let mut fcp = vec![0x83, 2, fid[0], fid[1], 0x8A, 1, lcsi, 0x82, u8::try_from(descriptor.len()).unwrap()];
+    fcp.extend_from_slice(descriptor);
+    for (tag, value) in others {
+        fcp.extend_from_slice(&[*tag, u8::try_from(value.len()).unwrap()]);
+        fcp.extend_from_slice(value);
+    }
+    fcp.extend_from_slice(&[0x8C, 8, 0x7F]);
+    fcp.extend_from_slice(scb);
+    let mut command = vec![0x00, 0xE0, 0x00, 0x00, u8::try_from(fcp.len()+2).unwrap(), 0x62, u8::try_from(fcp.len()).unwrap()];
+    command.extend_from_slice(&fcp);
+    command
+}
+
+fn select(fid: u16) -> Vec<u8> {
+    let fid = fid.to_be_bytes();
+    vec![0x00, 0xA4, 0x00, 0x00, 0x02, fid[0], fid[1]]
+}
+
+fn activate(fid: u16) -> Vec<u8> {
+    let fid = fid.to_be_bytes();
+    vec![0x00, 0x44, 0x00, 0x00, 0x02, fid[0], fid[1]]
+}
+
+fn update_binary(data: &[u8]) -> Vec<u8> {
+    let mut command = vec![0x00, 0xD6, 0x00, 0x00, u8::try_from(data.len()).unwrap()];
+    command.extend_from_slice(data);
+    command
+}
+
+fn update_record(rec_nr: u8, data: &[u8]) -> Vec<u8> {
+    let mut command = vec![0x00, 0xDC, rec_nr, 0x04, u8::try_from(data.len()).unwrap()];
+    command.extend_from_slice(data);
+    command
+}
+
+/* Sym. key record of MRL 0x26: key ID|0x80, key type (1: ext. auth. with 1 byte error counter, 2: int. auth. with 2 bytes
+   usage counter), counter unlimited, algorithm reference, key, zero padding */
+fn sym_key_record(key_id: u8, is_external: bool, algo_ref: u8, key: &[u8]) -> Vec<u8> {
+    let mut rec = if is_external { vec![0x80 | key_id, 1, 0xFF] } else { vec![0x80 | key_id, 2, 0xFF, 0xFF] };
+    rec.push(algo_ref);
+    rec.extend_from_slice(key);
+    rec.resize(0x26, 0);
+    rec
+}
+
+/* a 3DES key with the least significant bit of each byte set for odd parity, as DES requires */
+fn des_key_odd_parity(key: &[u8]) -> Zeroizing {
+    let mut key = Zeroizing(key.to_vec());
+    for block in key.chunks_exact_mut(DES_KEY_SZ) {
+        unsafe { DES_set_odd_parity(block.as_mut_ptr().cast::<DES_cblock>()) };
+    }
+    key
+}
+
+/* EF.SKDF: the 2 SM keys of file 4102, labeled SM1 and SM2 */
+fn skdf_content(is_aes: bool, key_len: u8) -> Vec<u8> {
+    let bits = (u16::from(key_len) * 8).to_be_bytes();
+    let mut content = Vec::with_capacity(0x76);
+    for key_id in 1..=2_u8 {
+        /* des2Key [3], des3Key [4] or, as PKCS#15 doesn't define an AES key type, genericSecretKey */
+        content.extend_from_slice(&[if is_aes {0x30} else if key_len == 16 {0xA3} else {0xA4}, 0x39, 0x30, 0x0C, 0x0C, 0x03, 0x53, 0x4D, 0x30 + key_id,
+            0x03, 0x02, 0x06, 0xC0, 0x04, 0x01, 0x01, 0x30, 0x0F, 0x04, 0x01, key_id, 0x03, 0x02, 0x06, 0xC0, 0x03, 0x02, 0x04,
+            0xB0, 0x02, 0x02, 0x00, 0x80 | key_id, 0xA0, 0x04, 0x02, 0x02, bits[0], bits[1], 0xA1, 0x12, 0x30, 0x10, 0x30,
+            0x0E, 0x04, 0x06, 0x3F, 0x00, 0x41, 0x00, 0x41, 0x02, 0x02, 0x01, key_id, 0x80, 0x01, 0x25]);
+    }
+    content
+}
+
+/// Returns the commands, that create the file system layout of info/card_initialization/card_initialization.scriptor on
+/// an erased card, in the order of transmission. Neither the SO PIN verification and the erasure itself, nor setting
+/// the Operation Mode Byte (that's done by `set_op_mode_byte_eeprom`) are included
+///
+/// # Errors
+/// SC_ERROR_INVALID_ARGUMENTS: An op_mode_byte or SM key type/length, that is not supported by card_type
+pub fn card_init_commands(card_type: i32, init: &CardCtlCardInit) -> Result<Vec<Vec<u8>>, i32> {
+    let is_evo = card_type == SC_CARD_TYPE_ACOS5_EVO_V4;
+    if !(init.op_mode_byte == 0xFF || is_op_mode_byte_supported(card_type, init.op_mode_byte)) || (init.sm_key_aes && !is_evo) {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    let algo_ref = algo_ref_sym_store(card_type, if init.sm_key_aes {SC_ALGORITHM_AES} else {SC_ALGORITHM_3DES},
+                                      init.sm_key_len).map_err(|_e| SC_ERROR_INVALID_ARGUMENTS)?;
+    let key_len = usize::from(init.sm_key_len);
+    let sm_key = |key: &[u8]| if init.sm_key_aes {Zeroizing(key.to_vec())} else {des_key_odd_parity(key)};
+
+    let mut commands = Vec::with_capacity(80);
+    /* MF */
+    commands.push(create(0x3F00, LCSI_CREATION, &[FDB_MF, 0], &[(0x8D, &[0x00, 0x03])],
+                         &[0x01, 0xFF, 0x01, 0x01, 0x01, 0x01, 0x01]));
+    /* Global PIN file */
+    commands.push(create(0x0001, LCSI_CREATION, &[FDB_CHV_EF, 0, 0, 0x15, 0, 0x01], &[],
+                         &[0x01, 0xFF, 0x01, 0x01, 0xFF, 0x01, 0xFF]));
+    commands.push(select(0x0001));
+    commands.push(update_record(1, &pin_record(1, 8, &init.so_pin, &init.so_puk)));
+    commands.push(activate(0x0001));
+    /* Global SE file */
+    commands.push(create(0x0003, LCSI_CREATION, &[FDB_SE_FILE, 0, 0, 0x30, 0, 0x01], &[],
+                         &[0x01, 0xFF, 0x01, 0x01, 0x00, 0x01, 0x00]));
+    commands.push(select(0x0003));
+    for (i, rec) in SE_RECORDS_MF.iter().enumerate() {
+        commands.push(update_record(u8::try_from(i+1).unwrap(), rec));
+    }
+    commands.push(activate(0x0003));
+    /* EF.DIR */
+    commands.push(create(0x2F00, LCSI_CREATION, &[FDB_TRANSPARENT_EF, 0], &[(0x80, &[0x00, 0x21])],
+                         &[0x01, 0xFF, 0x01, 0x01, 0xFF, 0x01, 0x00]));
+    commands.push(select(0x2F00));
+    commands.push(update_binary(&EF_DIR));
+    commands.push(activate(0x2F00));
+    /* PKCS#15 Application DF */
+    commands.push(create(0x4100, LCSI_CREATION, &[FDB_DF, 0], &[(0x8D, &[0x41, 0x03]), (0x84, &AID_PKCS15)],
+                         &[0x03, 0xFF, 0x03, 0x03, 0x01, 0x01, 0x01]));
+    commands.push(select(0x4100));
+    /* Local PIN file */
+    commands.push(create(0x4101, LCSI_CREATION, &[FDB_CHV_EF, 0, 0, 0x15, 0, 0x01], &[],
+                         &[0x03, 0xFF, 0x03, 0x03, 0xFF, 0x01, 0xFF]));
+    commands.push(select(0x4101));
+    commands.push(update_record(1, &pin_record(1, 8, &init.user_pin, &init.user_puk)));
+    commands.push(activate(0x4101));
+    /* Local Symmetric Key file: MRL 0x26 allows 32 byte AES keys (EVO) as well */
+    commands.push(create(0x4102, LCSI_CREATION, &[FDB_SYMMETRIC_KEY_EF, 0, 0, 0x26, 0, 0x0C], &[],
+                         &[0x03, 0xFF, 0x03, 0x03, 0x01, 0x01, 0xFF]));
+    commands.push(select(0x4102));
+    commands.push(update_record(1, &sym_key_record(1, true,  algo_ref, &sm_key(&init.sm_key_host[..key_len]))));
+    commands.push(update_record(2, &sym_key_record(2, false, algo_ref, &sm_key(&init.sm_key_card[..key_len]))));
+    commands.push(activate(0x4102));
+    /* Local SE file */
+    commands.push(create(0x4103, LCSI_CREATION, &[FDB_SE_FILE, 0, 0, 0x38, 0, 0x08], &[],
+                         &[0x03, 0xFF, 0x03, 0x03, 0x00, 0x03, 0x00]));
+    commands.push(select(0x4103));
+    for (i, rec) in SE_RECORDS_DF.iter().enumerate() {
+        commands.push(update_record(u8::try_from(i+1).unwrap(), rec));
+    }
+    commands.push(activate(0x4103));
+    /* xDFs */
+    for &(fid, size, size_evo) in &XDFS_EMPTY {
+        commands.push(create(fid, LCSI_ACTIVATED, &[FDB_TRANSPARENT_EF, 0],
+                             &[(0x80, &(if is_evo {size_evo} else {size}).to_be_bytes())], &SCB_XDF));
+        commands.push(select(fid));
+        commands.push(activate(fid));
+        if fid == 0x4112 {
+            /* EF.SKDF */
+            commands.push(create(0x4113, LCSI_CREATION, &[FDB_TRANSPARENT_EF, 0],
+                                 &[(0x80, &(if is_evo {0x0400_u16} else {0x0200}).to_be_bytes())], &SCB_XDF));
+            commands.push(select(0x4113));
+            commands.push(update_binary(&skdf_content(init.sm_key_aes, init.sm_key_len)));
+            commands.push(activate(0x4113));
+        }
+    }
+    /* EF.AODF */
+    commands.push(create(0x4118, LCSI_CREATION, &[FDB_TRANSPARENT_EF, 0],
+                         &[(0x80, &(if is_evo {0x0200_u16} else {0x0100}).to_be_bytes())], &SCB_XDF));
+    commands.push(select(0x4118));
+    commands.push(update_binary(&EF_AODF));
+    commands.push(activate(0x4118));
+    /* EF.ODF */
+    commands.push(create(0x5031, LCSI_CREATION, &[FDB_TRANSPARENT_EF, 0], &[(0x80, &[0x00, 0x6C])], &SCB_XDF_APPDF));
+    commands.push(select(0x5031));
+    commands.push(update_binary(&EF_ODF));
+    commands.push(activate(0x5031));
+    /* EF.TokenInfo */
+    let mut token_info = EF_TOKENINFO;
+    if is_evo {
+        token_info[EF_TOKENINFO_POS_ALGO_REF_ECB] = 0x14;
+        token_info[EF_TOKENINFO_POS_ALGO_REF_CBC] = 0x15;
+    }
+    commands.push(create(0x5032, LCSI_CREATION, &[FDB_TRANSPARENT_EF, 0], &[(0x80, &[0x00, 0xC0])], &SCB_XDF));
+    commands.push(select(0x5032));
+    commands.push(update_binary(&token_info));
+    commands.push(activate(0x5032));
+    /* ACS proprietary files */
+    commands.push(create(0x4129, LCSI_CREATION, &[FDB_LINEAR_FIXED_EF, 0, 0, 0x14, 0, 0x02], &[], &[0; 7]));
+    commands.push(select(0x4129));
+    for (i, rec) in ACS_4129.iter().enumerate() {
+        commands.push(update_record(u8::try_from(i+1).unwrap(), rec));
+    }
+    commands.push(activate(0x4129));
+    commands.push(create(0x5155, LCSI_CREATION, &[FDB_LINEAR_VARIABLE_EF, 0, 0, 0x82, 0, 0x02], &[],
+                         &[0x01, 0xFF, 0x00, 0x01, 0x01, 0x01, 0x00]));
+    commands.push(select(0x5155));
+    for (i, rec) in ACS_5155.iter().enumerate() {
+        commands.push(update_record(u8::try_from(i+1).unwrap(), rec));
+    }
+    commands.push(activate(0x5155));
+    /* activate the DFs last */
+    for &fid in &[0x4100_u16, 0x3F00] {
+        commands.push(select(fid));
+        commands.push(activate(fid));
+    }
+    Ok(commands)
+}
+
+/// Sets the Operation Mode Byte (unless 0xFF), creates the file system layout of
+/// info/card_initialization/card_initialization.scriptor on an erased card (no MF), then enumerates the new file system
+/// as acos5_init does
+/// @return  SC_SUCCESS, SC_ERROR_INVALID_ARGUMENTS (parameters not supported by the card type), SC_ERROR_NOT_ALLOWED
+///          (MF exists, i.e. the card isn't erased), or the error of the first command that failed
+pub fn card_init_card_ctl(card: &mut sc_card, init: &CardCtlCardInit) -> i32 {
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"card_init_card_ctl\0");
+    log3ifc!(ctx,f,line!());
+    /* the commands contain the PINs and keys */
+    let commands : Vec<Zeroizing> = match card_init_commands(card.type_, init) {
+        Ok(commands) => commands.into_iter().map(Zeroizing).collect(),
+        Err(e) => {
+            log3ifr!(ctx,f,line!(), cstru!(b"Parameters not supported by the card type\0"), e);
+            return e;
+        },
+    };
+    if init.validate_only {
+        return SC_SUCCESS;
+    }
+
+    /* the card must be virgin: MF doesn't exist */
+    let mut rv = match get_does_mf_exist(card) {
+        Ok(false) => SC_SUCCESS,
+        Ok(true)  => SC_ERROR_NOT_ALLOWED,
+        Err(e)    => e,
+    };
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"MF exists: The card must be erased first\0"), rv);
+        return rv;
+    }
+    if init.op_mode_byte != 0xFF {
+        if let Err(e) = set_op_mode_byte_eeprom(card, init.op_mode_byte) {
+            return e;
+        }
+    }
+
+    for command in &commands {
+        let mut apdu = build_apdu(ctx, command, SC_APDU_CASE_3_SHORT, &mut[]);
+        rv = unsafe { sc_transmit_apdu(card, &mut apdu) };
+        if rv == SC_SUCCESS {
+            rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+        }
+        if rv != SC_SUCCESS {
+            log3ifr!(ctx,f,line!(), cstru!(b"Card initialization command failed\0"), rv);
+            return rv;
+        }
+    }
+
+    files_reset_to_mf(card, true);
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    dp.is_running_init = true;
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+
+    let path_mf = unsafe { *sc_get_mf_path() };
+    rv = enum_dir(card, &path_mf, true);
+
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    dp.is_running_init = false;
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+    if rv == SC_SUCCESS {
+        unsafe { sc_select_file(card, &path_mf, null_mut()) };
+    }
+    rv
+}
+
+/* The driver's knowledge about files is from the erased file system: Start over with MF only */
+fn files_reset_to_mf(card: &mut sc_card, does_mf_exist: bool) {
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    dp.files.retain(|&file_id, _| file_id == 0x3F00);
+    if let Some(mf) = dp.files.get_mut(&0x3F00) {
+        mf.1 = [FDB_MF, 0xFF, 0x3F, 0x00, 0x00, 0x00, 0xFF, 0xFF];
+        mf.2 = None;
+        mf.3 = None;
+        mf.4 = None;
+    }
+    dp.does_mf_exist = does_mf_exist;
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+    card.cache.current_path = unsafe { *sc_get_mf_path() };
+}
+
+/* Whether the condition of 'Zeroize Card User Data', the MF's DELETE_SELF SCB, is fulfilled: ERASE_AUTH_* */
+fn zeroize_auth_state(card: &mut sc_card) -> Result<u8, i32> {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let does_mf_exist = dp.does_mf_exist;
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    if !does_mf_exist {
+        return Ok(ERASE_AUTH_FULFILLED);
+    }
+    let op = se_get_ac_report(card, 0x3F00)?.ops[DELETE_SELF];
+    if op.kind == AC_KIND_ALWAYS {
+        return Ok(ERASE_AUTH_FULFILLED);
+    }
+    /* the driver doesn't send Zeroize Card User Data in SM mode */
+    if op.kind != AC_KIND_CONDITIONS || op.sm_mac {
+        return Ok(ERASE_AUTH_NEVER);
+    }
+    if card.type_ != SC_CARD_TYPE_ACOS5_64_V3 {
+        return Ok(ERASE_AUTH_UNKNOWN);
+    }
+    let mut vec_is_fulfilled = Vec::with_capacity(usize::from(op.conditions_len));
+    for condition in &op.conditions[..usize::from(op.conditions_len)] {
+        vec_is_fulfilled.push((condition.pin == 0 || get_is_pin_authenticated(card, condition.pin)?) &&
+                              (condition.key == 0 || get_is_key_authenticated(card, condition.key)?));
+    }
+    let is_fulfilled = if op.is_all { vec_is_fulfilled.iter().all(|&b| b) } else { vec_is_fulfilled.iter().any(|&b| b) };
+    Ok(if is_fulfilled {ERASE_AUTH_FULFILLED} else {ERASE_AUTH_NOT_FULFILLED})
+}
+
+/// Implements SC_CARDCTL_ERASE_CARD: 'Zeroize Card User Data' with pre-flight checks: The Zeroize Card Disable Byte (if
+/// readable) and the SO authentication (only V3.00 can report that; for other card types, the card decides).
+/// With `erase.dry_run`, only the pre-flight checks get done. Afterwards, it's verified, that the card is virgin,
+/// i.e. there is no MF and EEPROM is readable
+pub fn erase_card_card_ctl(card: &mut sc_card, erase: &mut CardCtlEraseCard) -> i32 {
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"erase_card_card_ctl\0");
+    log3ifc!(ctx,f,line!());
+
+    /* pre-flight checks */
+    erase.zeroize_card_disable_byte = get_zeroize_card_disable_byte_eeprom(card).unwrap_or(0xFF);
+    if ![0, 0xFF].contains(&erase.zeroize_card_disable_byte) {
+        log3if!(ctx,f,line!(), cstru!(
+            b"### WARNING: Zeroize Card User Data is disabled (0x%02X): Once initialized, the card can't be re-initialized ever ###\0"),
+            erase.zeroize_card_disable_byte);
+    }
+    erase.so_auth_state = match zeroize_auth_state(card) {
+        Ok(val) => val,
+        Err(e) => return e,
+    };
+    if erase.so_auth_state == ERASE_AUTH_NEVER {
+        log3if!(ctx,f,line!(), cstru!(
+            b"### Zeroize Card User Data is impossible: The MF's DELETE_SELF access condition is NEVER, invalid or requires SM ###\0"));
+    }
+    if erase.dry_run {
+        return SC_SUCCESS;
+    }
+    if [ERASE_AUTH_NEVER, ERASE_AUTH_NOT_FULFILLED].contains(&erase.so_auth_state) {
+        log3ifr!(ctx,f,line!(), cstru!(b"SO authentication is missing, nothing erased\0"), SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+        return SC_ERROR_SECURITY_STATUS_NOT_SATISFIED;
+    }
+
+    /* as card_initialization.scriptor does, the command is repeated; the last one's SW decides */
+    let mut apdu = build_apdu(ctx, &[0x80, 0x30, 0, 0], SC_APDU_CASE_1, &mut[]);
+    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
+    rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: ### Impossible to Zeroize Card User Data ###\0"), rv);
+        return rv;
+    }
+    files_reset_to_mf(card, false);
+
+    /* post-erase verification */
+    erase.is_virgin = get_does_mf_exist(card) == Ok(false) && get_card_life_cycle_byte_eeprom(card).is_ok();
+    erase.zeroize_card_disable_byte = get_zeroize_card_disable_byte_eeprom(card).unwrap_or(0xFF);
+    if !erase.is_virgin {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: ### The card isn't virgin after Zeroize Card User Data ###\0"), SC_ERROR_CARD_CMD_FAILED);
+        return SC_ERROR_CARD_CMD_FAILED;
+    }
+    SC_SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Acos5Simulator, ApduProcessor, SimulatedCard};
+    use crate::constants_types::{SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3};
+
+    /* the simulator, recording the CLA INS of each command */
+    struct Recorder {
+        sim : Acos5Simulator,
+        ins : Vec<[u8; 2]>,
+    }
+
+    impl ApduProcessor for Recorder {
+        fn card_type(&self) -> i32 { self.sim.card_type }
+        fn process(&mut self, cmd: &[u8]) -> Vec<u8> {
+            self.ins.push([cmd[0], cmd[1]]);
+            self.sim.process(cmd)
+        }
+    }
+
+    fn erase_card(sim: Acos5Simulator, so_pin_verified: bool, dry_run: bool) -> (i32, CardCtlEraseCard, Recorder) {
+        let mut sim_card = SimulatedCard::connect(Recorder { sim, ins: Vec::new() }).unwrap();
+        if so_pin_verified {
+            let resp = sim_card.simulator().sim.process(&[0, 0x20, 0, 1, 8, 0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31]);
+            assert_eq!(resp, [0x90, 0]);
+        }
+        sim_card.simulator().ins.clear();
+        let mut erase = CardCtlEraseCard { dry_run, ..CardCtlEraseCard::default() };
+        let rv = erase_card_card_ctl(unsafe { &mut *sim_card.card }, &mut erase);
+        let recorder = Recorder { sim: sim_card.simulator().sim.clone(), ins: sim_card.simulator().ins.clone() };
+        (rv, erase, recorder)
+    }
+
+    /* the parameters of info/card_initialization/card_initialization.scriptor */
+    fn init_scriptor() -> CardCtlCardInit {
+        let mut init = CardCtlCardInit { op_mode_byte: 0xFF, so_pin: *b"87654321", so_puk: *b"87654321",
+            user_pin: *b"12345678", user_puk: *b"12345678", sm_key_len: 24, ..CardCtlCardInit::default() };
+        init.sm_key_host[..24].copy_from_slice(&[0xF1, 0xE0, 0xD0, 0xC1, 0xB0, 0xA1, 0x89, 0x08, 0x07, 0x16, 0x45, 0x04,
+            0x13, 0x02, 0x01, 0xF1, 0x89, 0xFE, 0xB3, 0xC8, 0x37, 0x45, 0x16, 0x94]);
+        init.sm_key_card[..24].copy_from_slice(&[0xF1, 0x01, 0x02, 0x13, 0x04, 0x85, 0x16, 0x07, 0x08, 0x49, 0xA1, 0xB0,
+            0xC1, 0xD0, 0xE0, 0xF1, 0x45, 0x89, 0xB3, 0x16, 0xFE, 0x94, 0x37, 0xC8]);
+        init
+    }
+
+    #[test]
+    fn test_card_init_commands() {
+        /* identical to the script's file creation commands */
+        let mut sim = Acos5Simulator::new(SC_CARD_TYPE_ACOS5_64_V3);
+        let script : Vec<Vec<u8>> = sim.run_script(include_str!("../../info/card_initialization/card_initialization.scriptor"))
+            .into_iter().map(|(cmd, _resp)| cmd).filter(|cmd| ![0x20, 0x30, 0x14].contains(&cmd[1])).collect();
+        let commands = card_init_commands(SC_CARD_TYPE_ACOS5_64_V3, &init_scriptor()).unwrap();
+        assert_eq!(commands, script);
+
+        /* EVO: AES SM keys and bigger xDFs, accepted by a virgin card */
+        let mut init = init_scriptor();
+        assert_eq!(card_init_commands(SC_CARD_TYPE_ACOS5_64_V3, &CardCtlCardInit { sm_key_aes: true, ..init }),
+                   Err(SC_ERROR_INVALID_ARGUMENTS));
+        assert_eq!(card_init_commands(SC_CARD_TYPE_ACOS5_64_V2, &CardCtlCardInit { op_mode_byte: 2, ..init }),
+                   Err(SC_ERROR_INVALID_ARGUMENTS));
+        init.op_mode_byte = 1;
+        init.sm_key_aes = true;
+        init.sm_key_len = 32;
+        let mut sim = Acos5Simulator::new(SC_CARD_TYPE_ACOS5_EVO_V4);
+        for command in card_init_commands(SC_CARD_TYPE_ACOS5_EVO_V4, &init).unwrap() {
+            let resp = sim.process(&command);
+            assert_eq!(resp[resp.len()-2..], [0x90, 0], "cmd: {:X?}", command);
+        }
+        let prkdf = sim.file_by_path(&[0x3F, 0, 0x41, 0, 0x41, 0x10]).unwrap();
+        assert_eq!((prkdf.size, prkdf.lcsi), (0x0600, LCSI_ACTIVATED));
+        let token_info = sim.file_by_path(&[0x3F, 0, 0x41, 0, 0x50, 0x32]).unwrap();
+        assert_eq!(token_info.data[EF_TOKENINFO_POS_ALGO_REF_CBC], 0x15);
+    }
+
+    #[test]
+    fn test_card_init_commands_sm_key_parity() {
+        /* 3DES keys get odd parity, AES keys remain unchanged; the key is at offset 4 (host) resp. 5 (card) of the record */
+        let mut init = init_scriptor();
+        init.sm_key_host = [0; 32];
+        init.sm_key_card = [0x03; 32];
+        let commands = card_init_commands(SC_CARD_TYPE_ACOS5_64_V3, &init).unwrap();
+        let pos = commands.iter().position(|cmd| *cmd == select(0x4102)).unwrap();
+        assert_eq!(commands[pos+1][5+4..5+4+24], [0x01; 24]);
+        assert_eq!(commands[pos+2][5+5..5+5+24], [0x02; 24]);
+
+        init.sm_key_aes = true;
+        let commands = card_init_commands(SC_CARD_TYPE_ACOS5_EVO_V4, &init).unwrap();
+        let pos = commands.iter().position(|cmd| *cmd == select(0x4102)).unwrap();
+        assert_eq!(commands[pos+1][5+4..5+4+24], [0; 24]);
+        assert_eq!(commands[pos+2][5+5..5+5+24], [0x03; 24]);
+    }
+
+    /* the pre-flight checks of SC_CARDCTL_ERASE_CARD on the simulator; Zeroize Card User Data is 80 30 */
+    #[test]
+    fn test_erase_card_simulated() {
+        let zeroize_sent = |recorder: &Recorder| recorder.ins.contains(&[0x80, 0x30]);
+
+        /* dry run: reports, but doesn't erase, even if it could */
+        let (rv, erase, recorder) = erase_card(Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3), true, true);
+        assert_eq!((rv, erase.so_auth_state, erase.zeroize_card_disable_byte), (SC_SUCCESS, ERASE_AUTH_FULFILLED, 0xFF));
+        assert!(!zeroize_sent(&recorder) && recorder.sim.does_mf_exist() && !erase.is_virgin);
+
+        /* refused without SO authentication: the MF's DELETE_SELF requires SE record 1, i.e. global PIN 1 */
+        let (rv, erase, recorder) = erase_card(Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3), false, false);
+        assert_eq!((rv, erase.so_auth_state), (SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, ERASE_AUTH_NOT_FULFILLED));
+        assert!(!zeroize_sent(&recorder) && recorder.sim.does_mf_exist());
+
+        /* refused, if the MF's DELETE_SELF is NEVER, even with SO authentication */
+        let sim = Acos5Simulator::new_initialized_modified(SC_CARD_TYPE_ACOS5_64_V3,
+            &[("8D 02 00 03 8C 08 7F 01 FF 01 01 01 01 01", "8D 02 00 03 8C 08 7F FF FF 01 01 01 01 01")]);
+        assert!(sim.does_mf_exist());
+        let (rv, erase, recorder) = erase_card(sim, true, false);
+        assert_eq!((rv, erase.so_auth_state), (SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, ERASE_AUTH_NEVER));
+        assert!(!zeroize_sent(&recorder) && recorder.sim.does_mf_exist());
+
+        /* erased and verified virgin */
+        let (rv, erase, recorder) = erase_card(Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3), true, false);
+        assert_eq!((rv, erase.so_auth_state, erase.zeroize_card_disable_byte), (SC_SUCCESS, ERASE_AUTH_FULFILLED, 0));
+        assert!(zeroize_sent(&recorder) && !recorder.sim.does_mf_exist() && erase.is_virgin);
+
+        /* Zeroize Card Disable Byte set: the card refuses, nothing is verified virgin */
+        let mut sim = Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3);
+        sim.zeroize_disable = 1;
+        let (rv, erase, recorder) = erase_card(sim, true, false);
+        assert_ne!(rv, SC_SUCCESS);
+        assert!(recorder.sim.does_mf_exist() && !erase.is_virgin);
+    }
+
+    #[test]
+    fn test_skdf_content() {
+        assert_eq!(skdf_content(false, 24)[0], 0xA4);
+        assert_eq!(skdf_content(false, 16)[0], 0xA3);
+        assert_eq!(skdf_content(true,  16)[0], 0x30);
+        assert_eq!(skdf_content(false, 16)[0x3B..0x3B+3], [0xA3, 0x39, 0x30]);
+    }
+
+    #[test]
+    fn test_pin_record() {
+        assert_eq!(pin_record(1, 8, b"12345678", b"87654321"), [0xC1, 0x88, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
+            0x38, 0x88, 8, 0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31]);
+        /* a local PIN reference 0x82 without PUK and 3 tries */
+        assert_eq!(pin_record(2, 3, b"1234", &[]), [0x82, 0x33, 4, 0x31, 0x32, 0x33, 0x34]);
+    }
+}
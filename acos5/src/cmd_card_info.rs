@@ -21,13 +21,17 @@
 /* functions, (most of) callable via sc_card_ctl(acos5_card_ctl), mostly used by acos5_gui */
 
 use std::convert::TryFrom;
+use std::os::raw::c_void;
 
 use opensc_sys::opensc::{sc_card, sc_transmit_apdu, sc_check_sw, SC_PROTO_T1};
-use opensc_sys::types::{sc_serial_number, SC_MAX_SERIALNR, SC_APDU_CASE_1, SC_APDU_CASE_2_SHORT, SC_APDU_CASE_2_EXT};
-use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_CARD_CMD_FAILED, SC_ERROR_INVALID_ARGUMENTS};
+use opensc_sys::types::{sc_serial_number, SC_MAX_SERIALNR, SC_APDU_CASE_1, SC_APDU_CASE_2_SHORT, SC_APDU_CASE_2_EXT,
+                        SC_APDU_CASE_3_SHORT};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_CARD_CMD_FAILED, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_NOT_ALLOWED};
 
-use crate::constants_types::{build_apdu, SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3};
+use crate::constants_types::{build_apdu, SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3, SC_CARD_TYPE_ACOS5_EVO_V4,
+                             DataPrivate, CardCtlOpModeByte};
 use crate::wrappers::{wr_do_log};
+use crate::no_cdecl::{rsa_algorithms_register};
 
 //QS
 /// Get card's (hardware identifying) serial number. Copies result to card.serialnr
@@ -303,6 +307,16 @@ pub fn get_rom_sha1(card: &mut sc_card) -> Result<[u8; 20], i32>
 /// # Errors
 #[allow(clippy::missing_errors_doc)]
 pub fn get_op_mode_byte(card: &mut sc_card) -> Result<u8, i32>
+{
+    let card_type = card.type_;
+    get_op_mode_byte_of_type(card, card_type)
+}
+
+/// Like `get_op_mode_byte`, but for card_type instead of card.type_, which isn't set yet within acos5_match_card
+///
+/// # Errors
+#[allow(clippy::missing_errors_doc)]
+pub fn get_op_mode_byte_of_type(card: &mut sc_card, card_type: i32) -> Result<u8, i32>
 {
     assert!(!card.ctx.is_null());
     let ctx = unsafe { &mut *card.ctx };
@@ -315,8 +329,8 @@ pub fn get_op_mode_byte(card: &mut sc_card) -> Result<u8, i32>
     /* the reference manuals says: the response status word is 0x95NN, but actually for V2.00 and V3.00 it's 0x90NN */
     /* the reference manuals says: the response status word is 0x95NN, and it actually is for V4 */
     if rv == SC_SUCCESS && (
-        (card.type_ == SC_CARD_TYPE_ACOS5_64_V3 && [0,1, 2,16].contains(&apdu.sw2)) ||
-        (card.type_ != SC_CARD_TYPE_ACOS5_64_V3 && [0,1].contains(&apdu.sw2)) )  {
+        (card_type == SC_CARD_TYPE_ACOS5_64_V3 && [0,1, 2,16].contains(&apdu.sw2)) ||
+        (card_type != SC_CARD_TYPE_ACOS5_64_V3 && [0,1].contains(&apdu.sw2)) )  {
         /*
             for SC_CARD_TYPE_ACOS5_EVO_V4: apdu.sw2:
              0: 64K Mode (Non-FIPS)                (factory default) RECOMMENDED FOR THIS DRIVER !!!
@@ -485,3 +499,125 @@ pub fn get_card_life_cycle_byte_eeprom(card: &mut sc_card) -> Result<u8, i32>
     }
     Ok(rbuf[0])
 }
+
+/// Whether a card is virgin, i.e. there is no MF. Selects the MF by a plain APDU, independent from what the driver
+/// knows about the file system (which may be outdated, e.g. after erasing the card)
+///
+/// # Errors
+/// Will return `Result::Err` if sc_transmit_apdu fails
+pub fn get_does_mf_exist(card: &mut sc_card) -> Result<bool, i32>
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"get_does_mf_exist\0");
+    log3ifc!(ctx,f,line!());
+
+    let mut apdu = build_apdu(ctx, &[0, 0xA4, 0, 0, 2, 0x3F, 0], SC_APDU_CASE_3_SHORT, &mut[]);
+    let rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return Err(rv); }
+    Ok(unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) } == SC_SUCCESS)
+}
+
+/// Whether the driver supports an Operation Mode Byte (EVO: Configuration Mode Byte) value for a card type:
+/// V2.00: 0 (64K); V3.00: 0 (FIPS 140-2 Level 3-Compliant) or 2 (64K); EVO: 0 (FIPS) or 1 (non-FIPS).
+/// The Emulated 32K Mode and NSH-1 Mode are not supported
+#[must_use]
+pub fn is_op_mode_byte_supported(card_type: i32, op_mode_byte: u8) -> bool
+{
+    match card_type {
+        SC_CARD_TYPE_ACOS5_64_V2  => op_mode_byte == 0,
+        SC_CARD_TYPE_ACOS5_64_V3  => [0, 2].contains(&op_mode_byte),
+        SC_CARD_TYPE_ACOS5_EVO_V4 => [0, 1].contains(&op_mode_byte),
+        _                         => false,
+    }
+}
+
+/// Whether an Operation Mode Byte value means FIPS 140-2 Level 3-Compliant Mode for a card type
+#[must_use]
+pub fn is_fips_mode_byte(card_type: i32, op_mode_byte: u8) -> bool
+{
+    card_type != SC_CARD_TYPE_ACOS5_64_V2 && op_mode_byte == 0
+}
+
+/* This is NOT a card command, but writing to EEPROM; allowed only in stage manufacturer */
+/// Sets the Operation Mode Byte of a virgin card, verified by reading it back.
+/// The driver's FIPS mode and registered RSA key sizes get adapted accordingly
+///
+/// # Errors
+/// SC_ERROR_INVALID_ARGUMENTS if the value isn't supported for the card type (see `is_op_mode_byte_supported`),
+/// SC_ERROR_NOT_ALLOWED if there is a MF, SC_ERROR_CARD_CMD_FAILED if writing failed
+pub fn set_op_mode_byte_eeprom(card: &mut sc_card, op_mode_byte: u8) -> Result<(), i32>
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"set_op_mode_byte_eeprom\0");
+    log3ifc!(ctx,f,line!());
+
+    if !is_op_mode_byte_supported(card.type_, op_mode_byte) {
+        log3if!(ctx,f,line!(), cstru!(b"Error: Operation Mode Byte value not supported for this card type\0"));
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    if get_does_mf_exist(card)? {
+        log3if!(ctx,f,line!(), cstru!(b"Error: The Operation Mode Byte can be set on a virgin card (no MF) only\0"));
+        return Err(SC_ERROR_NOT_ALLOWED);
+    }
+    let mut apdu = build_apdu(ctx, &[0, 0xD6, 0xC1, 0x91, 1, op_mode_byte], SC_APDU_CASE_3_SHORT, &mut[]);
+    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return Err(rv); }
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    if rv != SC_SUCCESS || get_op_mode_byte_eeprom(card)? != op_mode_byte {
+        log3if!(ctx,f,line!(), cstru!(b"Error: ACOS5 'Set Operation Mode Byte' failed\0"));
+        return Err(SC_ERROR_CARD_CMD_FAILED);
+    }
+
+    let is_fips_mode = is_fips_mode_byte(card.type_, op_mode_byte);
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    dp.is_fips_mode = is_fips_mode;
+    let rsa_algo_flags = dp.rsa_caps;
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+    match rsa_algorithms_register(card, rsa_algo_flags, is_fips_mode) {
+        SC_SUCCESS => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Gets and optionally sets (virgin card only) the Operation Mode Byte for card_ctl SC_CARDCTL_ACOS5_OP_MODE_BYTE.
+/// With a MF, the value in effect is reported by 'Get Card Info', otherwise the EEPROM is read
+/// @return  SC_SUCCESS or the error of `set_op_mode_byte_eeprom`/reading the value
+pub fn op_mode_byte_card_ctl(card: &mut sc_card, data: &mut CardCtlOpModeByte) -> i32
+{
+    if data.set {
+        if let Err(e) = set_op_mode_byte_eeprom(card, data.value) {
+            return e;
+        }
+    }
+    let value = match get_does_mf_exist(card) {
+        Ok(true)  => if card.type_ == SC_CARD_TYPE_ACOS5_64_V2 { Ok(0) } else { get_op_mode_byte(card) },
+        Ok(false) => get_op_mode_byte_eeprom(card),
+        Err(e) => Err(e),
+    };
+    match value {
+        Ok(value) => {
+            data.value = value;
+            data.is_fips_mode = is_fips_mode_byte(card.type_, value);
+            SC_SUCCESS
+        },
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_op_mode_byte_supported() {
+        assert!( is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_64_V2, 0));
+        assert!(!is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_64_V2, 1));
+        assert!( is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_64_V3, 2));
+        assert!(!is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_64_V3, 16));
+        assert!( is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_EVO_V4, 1));
+        assert!(!is_op_mode_byte_supported(SC_CARD_TYPE_ACOS5_EVO_V4, 2));
+        assert!(!is_fips_mode_byte(SC_CARD_TYPE_ACOS5_64_V2, 0));
+        assert!( is_fips_mode_byte(SC_CARD_TYPE_ACOS5_64_V3, 0));
+        assert!(!is_fips_mode_byte(SC_CARD_TYPE_ACOS5_EVO_V4, 1));
+    }
+}
@@ -32,7 +32,8 @@ use opensc_sys::types::{sc_file, sc_apdu, sc_crt, sc_object_id, SC_MAX_CRTS_IN_S
 use opensc_sys::pkcs15::{SC_PKCS15_PRKDF, SC_PKCS15_PUKDF, SC_PKCS15_PUKDF_TRUSTED,
                          SC_PKCS15_SKDF, SC_PKCS15_CDF, SC_PKCS15_CDF_TRUSTED, SC_PKCS15_CDF_USEFUL,
                          SC_PKCS15_DODF, SC_PKCS15_AODF, sc_pkcs15_id};
-use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_INTERNAL};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_INTERNAL, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_INVALID_PIN_REFERENCE,
+                         SC_ERROR_TOO_MANY_OBJECTS};
 use opensc_sys::iso7816::{/*ISO7816_TAG_FCI, ISO7816_TAG_FCP,*/ ISO7816_TAG_FCP_SIZE, ISO7816_TAG_FCP_TYPE,
                           ISO7816_TAG_FCP_FID, ISO7816_TAG_FCP_DF_NAME, ISO7816_TAG_FCP_LCS};
 
@@ -142,6 +143,40 @@ pub const FDB_ECC_KEY_EF         : u8 = 0x19; // Elliptic Curve Cryptography Key
 /* Proprietary internal EF */
 pub const FDB_SE_FILE            : u8 = 0x1C; // Security Environment File, exactly 1 file only in each DF; DF's header/FCI points to this
 
+/* Life Cycle Status Integer (LCSI), last byte of File Info */
+pub const LCSI_CREATION         : u8 = 0x01; // Creation state
+pub const LCSI_DEACTIVATED      : u8 = 0x04; // Operational state (deactivated): access control is not enforced
+pub const LCSI_ACTIVATED        : u8 = 0x05; // Operational state (activated)
+pub const LCSI_TERMINATED       : u8 = 0x0C; // Termination state
+
+/* PIN references: bit 7 set denotes a local PIN (stored in a DF's PIN file), else global (stored in MF's PIN file); the
+   PIN ID, bits 0-4, is also the record number within the PIN file */
+pub const PIN_ID_MAX           : u8 = 31;
+pub const PIN_ID_MAX_EVO       : u8 = 30;
+pub const PIN_LEN_MAX          : usize = 8; // also for PUK
+pub const PIN_LEN_MAX_EVO      : usize = 20;
+
+/* The Security Environment records written into a new SE file by card initialization (card_initialization.rs) and by
+   pkcs15-init (acos5_pkcs15_init_card, acos5_pkcs15_create_dir): The MF's global SE file and an application DF's local one */
+pub const SE_RECORDS_MF : [&[u8]; 1] = [
+    /* 1: verify global PIN 1 */
+    &[0x80, 0x01, 0x01, 0xA4, 0x06, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08]];
+pub const SE_RECORDS_DF : [&[u8]; 6] = [
+    /* 1: verify local PIN 1 */
+    &[0x80, 0x01, 0x01, 0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x08],
+    /* 2: originating from ACS client kit */
+    &[0x80, 0x01, 0x02, 0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x08, 0xB4, 0x09, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08,
+      0x80, 0x01, 0x02, 0xB8, 0x09, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08, 0x80, 0x01, 0x02],
+    /* 3: verify global PIN 1 */
+    &[0x80, 0x01, 0x03, 0xA4, 0x06, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08],
+    /* 4: verify local PIN 1 and global PIN 1 */
+    &[0x80, 0x01, 0x04, 0xA4, 0x09, 0x83, 0x01, 0x81, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08],
+    /* 5, 6: SM with session key, local key 1 authenticated by ext. auth. */
+    &[0x80, 0x01, 0x05, 0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x80, 0xB4, 0x08, 0x84, 0x00, 0x95, 0x01, 0x30, 0x80,
+      0x01, 0x02],
+    &[0x80, 0x01, 0x06, 0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x80, 0xB4, 0x08, 0x84, 0x00, 0x95, 0x01, 0x30, 0x80,
+      0x01, 0x02, 0xB8, 0x08, 0x84, 0x00, 0x95, 0x01, 0x30, 0x80, 0x01, 0x02]];
+
 /* the Control Reference Template (CRT) Tags understood by acos
 ATTENTION with CRT_TAG_CT Confidentiality Template: In reality acos makes no difference for asym/sym, there is 0xB8 only
 The distinction is artificial and for some reason, corrected later
@@ -246,7 +281,7 @@ pub const SC_SEC_OPERATION_ENCIPHER_ECCPUBLIC  : i32 = 0x000F; // to be substitu
  * Generic card_ctl calls, see opensc-sys: cardctl.rs
  */
 pub const SC_CARDCTL_GENERIC_BASE            : c_ulong =  0x0000_0000;
-pub const SC_CARDCTL_ERASE_CARD              : c_ulong =  0x0000_0001;
+pub const SC_CARDCTL_ERASE_CARD              : c_ulong =  0x0000_0001; // data: *mut CardCtlEraseCard or null,  erase_card
 pub const SC_CARDCTL_GET_DEFAULT_KEY         : c_ulong =  0x0000_0002;
 pub const SC_CARDCTL_LIFECYCLE_GET           : c_ulong =  0x0000_0003;
 pub const SC_CARDCTL_LIFECYCLE_SET           : c_ulong =  0x0000_0004;
@@ -261,7 +296,7 @@ pub const SC_CARDCTL_PKCS11_INIT_PIN         : c_ulong =  0x0000_0009;
  *
  * for an internal driver these will move to cardctl.h
 */
-pub const SC_CARDCTL_ACOS5_SANITY_CHECK            : c_ulong =  0x0000_0010; // data: null
+pub const SC_CARDCTL_ACOS5_SANITY_CHECK            : c_ulong =  0x0000_0010; // data: null or *mut CardCtlSanityCheck,  sanity_check
 
 pub const SC_CARDCTL_ACOS5_GET_COUNT_FILES_CURR_DF : c_ulong =  0x0000_0011; // data: *mut u16,  get_count_files_curr_df
 pub const SC_CARDCTL_ACOS5_GET_FILE_INFO           : c_ulong =  0x0000_0012; // data: *mut CardCtlArray8,  get_file_info
@@ -297,6 +332,48 @@ pub const SC_CARDCTL_ACOS5_ENCRYPT_ASYM            : c_ulong =  0x0000_0028; //
 pub const SC_CARDCTL_ACOS5_DECRYPT_SYM             : c_ulong =  0x0000_0029; // data: *mut CardCtl_crypt_sym,  do_decrypt_sym
 ////pub const SC_CARDCTL_ACOS5_DECRYPT_ASYM        : c_ulong =  0x0000_002A; // data: *mut CardCtl_crypt_asym, do_decrypt_asym; is available via decipher
 
+pub const SC_CARDCTL_ACOS5_GET_SM_COUNTERS         : c_ulong =  0x0000_002B; // data: *mut CardCtlSmCounters
+pub const SC_CARDCTL_ACOS5_GET_AC_REPORT           : c_ulong =  0x0000_002C; // data: *mut CardCtlAcReport,  se_get_ac_report
+
+pub const SC_CARDCTL_ACOS5_SE_RECORD_GET           : c_ulong =  0x0000_002D; // data: *mut CardCtlSeRecord,  se_record_get
+pub const SC_CARDCTL_ACOS5_SE_RECORD_ADD           : c_ulong =  0x0000_002E; // data: *mut CardCtlSeRecord,  se_record_put
+pub const SC_CARDCTL_ACOS5_SE_RECORD_REPLACE       : c_ulong =  0x0000_002F; // data: *mut CardCtlSeRecord,  se_record_put
+pub const SC_CARDCTL_ACOS5_SE_RECORD_REMOVE        : c_ulong =  0x0000_0030; // data: *mut CardCtlSeRecord,  se_record_remove
+pub const SC_CARDCTL_ACOS5_SANITY_REPAIR           : c_ulong =  0x0000_0031; // data: *mut CardCtlSanityRepair,  sanity_repair
+pub const SC_CARDCTL_ACOS5_CARD_INIT               : c_ulong =  0x0000_0032; // data: *mut CardCtlCardInit,  card_init
+pub const SC_CARDCTL_ACOS5_OP_MODE_BYTE            : c_ulong =  0x0000_0033; // data: *mut CardCtlOpModeByte,  op_mode_byte
+pub const SC_CARDCTL_ACOS5_LIFECYCLE_REPORT        : c_ulong =  0x0000_0034; // data: *mut CardCtlLifecycle,  lifecycle_get
+
+// CardCtlEraseCard so_auth_state: Whether the condition of 'Zeroize Card User Data' (the MF's DELETE_SELF SCB) is fulfilled
+pub const ERASE_AUTH_UNKNOWN       : u8 = 0; // the card type can't report authentication states (only V3.00 can)
+pub const ERASE_AUTH_FULFILLED     : u8 = 1;
+pub const ERASE_AUTH_NOT_FULFILLED : u8 = 2;
+pub const ERASE_AUTH_NEVER         : u8 = 3; // SCB NEVER, invalid or SM required: Zeroize Card User Data is impossible
+
+// card_ctl SC_CARDCTL_ACOS5_SANITY_CHECK: data may be null (findings get printed only) or *mut CardCtlSanityCheck
+pub const SANITY_SEVERITY_INFO    : u8 = 0;
+pub const SANITY_SEVERITY_WARNING : u8 = 1;
+pub const SANITY_SEVERITY_ERROR   : u8 = 2;
+
+// check ids of sanity findings
+pub const SANITY_CHECK_MF              : u8 =  1; // MF exists (card is initialized)
+pub const SANITY_CHECK_SE_FILE         : u8 =  2; // DF/MF declares an existing, accessible SE file
+pub const SANITY_CHECK_SE_REFERENCES   : u8 =  3; // references to SE file records are satisfied
+pub const SANITY_CHECK_PIN_FILE        : u8 =  4; // PIN file count per DF/MF
+pub const SANITY_CHECK_KEY_FILE        : u8 =  5; // sym. key file count per DF/MF, and required for SM
+pub const SANITY_CHECK_FILE_PROTECTION : u8 =  6; // PIN/sym. key files never readable and activated
+pub const SANITY_CHECK_SE_RECORD_USE   : u8 =  7; // unused SE file records, records for SM
+pub const SANITY_CHECK_SE_RECORD_ID    : u8 =  8; // SE file record no. == internal id
+pub const SANITY_CHECK_SE_HOLE         : u8 =  9; // no empty record within SE file
+pub const SANITY_CHECK_SAE             : u8 = 10; // SAE instructions meaningful for the hardware
+pub const SANITY_CHECK_LCSI            : u8 = 11; // files activated
+pub const SANITY_CHECK_PKCS15_DIR       : u8 = 12; // EF.DIR records, appDF paths and AIDs
+pub const SANITY_CHECK_PKCS15_ODF       : u8 = 13; // EF.ODF entries point to existing xDFs
+pub const SANITY_CHECK_PKCS15_TOKENINFO : u8 = 14; // EF.TokenInfo decodes as PKCS15.TokenInfo
+pub const SANITY_CHECK_PKCS15_OBJECTS   : u8 = 15; // PrKDF/PuKDF/SKDF entries point to existing files/records of the right FDB
+
+pub const SANITY_MAX_TEXT : usize = 192; // incl. terminating NUL
+
 // array indices of some file related commands in scb8:
 pub const READ         : usize =  0;
 pub const DELETE_CHILD : usize =  0;
@@ -304,6 +381,9 @@ pub const UPDATE       : usize =  1;
 pub const CREATE_EF    : usize =  1;
 pub const CRYPTO       : usize =  2;
 pub const CREATE_DF    : usize =  2;
+pub const DEACTIVATE   : usize =  3;
+pub const ACTIVATE     : usize =  4;
+pub const TERMINATE    : usize =  5;
 pub const DELETE_SELF  : usize =  6;
 
 
@@ -582,6 +662,202 @@ pub struct CardCtlAuthState {
     pub value      : bool, // OUT
 }
 
+// struct for SC_CARDCTL_ACOS5_GET_SM_COUNTERS
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct CardCtlSmCounters {
+    pub sessions_opened       : u32, // OUT  count of mutual authentications
+    pub commands              : u32, // OUT  count of SM commands in total
+    pub commands_curr_session : u32, // OUT  count of SM commands within the current session
+    pub commands_max_session  : u32, // OUT  max. count of SM commands within one session
+}
+
+/* kinds of access control requirement, see AcOpReport.kind */
+pub const AC_KIND_ALWAYS     : u8 = 0; // SCB 0x00
+pub const AC_KIND_NEVER      : u8 = 1; // SCB 0xFF
+pub const AC_KIND_CONDITIONS : u8 = 2; // conditions and/or SM apply
+pub const AC_KIND_INVALID    : u8 = 3; // invalid SCB encoding, or the SE record referred to doesn't exist or is unsuitable for SM
+pub const AC_KIND_UNUSED     : u8 = 4; // the SCB has no meaning for this file type
+
+pub const AC_REPORT_MAX_CONDITIONS : usize = 8;
+pub const AC_REPORT_MAX_SAE        : usize = 8;
+
+// one condition of an AcOpReport; pin and key both non-zero: both must be fulfilled (AT usage 0x88)
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct AcCondition {
+    pub pin : u8, // pin reference to be verified,      id | 0x80 for local; 0: none
+    pub key : u8, // key reference to be authenticated, id | 0x80 for local; 0: none
+}
+
+// the access control requirement for 1 operation (SCB)
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct AcOpReport {
+    pub scb            : u8,
+    pub kind           : u8,   // AC_KIND_*
+    pub is_all         : bool, // true: all conditions must be fulfilled (AND), false: any one of them (OR)
+    pub sm_mac         : bool, // SM mode Authenticity (CCT) is required
+    pub sm_enc         : bool, // SM mode Confidentiality (CT) is required in addition
+    pub conditions_len : u8,
+    pub conditions     : [AcCondition; AC_REPORT_MAX_CONDITIONS],
+}
+
+// an SAE entry of the directory, overriding access control on the instruction level
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct AcSaeReport {
+    pub tag_amdo      : u8,      // tells, which of cla, p1, p2 are relevant (ins always is)
+    pub cla_ins_p1_p2 : [u8; 4],
+    pub tag_scdo      : u8,      // 0xA0: any of scbs, 0xAF: all of scbs
+    pub scbs_len      : u8,
+    pub scbs          : [AcOpReport; SAE_MAX_SCDO],
+}
+
+// struct for SC_CARDCTL_ACOS5_GET_AC_REPORT
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct CardCtlAcReport {
+    pub file_id : u16,                  // IN
+    pub fdb     : u8,                   // OUT
+    pub scb8    : [u8; 8],              // OUT
+    pub ops     : [AcOpReport; 7],      // OUT  for scb8[0..7], the meaning depends on fdb, see se_ac_op_names
+    pub sae_len : u8,                   // OUT
+    pub sae     : [AcSaeReport; AC_REPORT_MAX_SAE], // OUT  SAE of the file's directory (of itself for DF/MF)
+}
+
+// struct for SC_CARDCTL_ACOS5_SE_RECORD_GET, SC_CARDCTL_ACOS5_SE_RECORD_ADD, SC_CARDCTL_ACOS5_SE_RECORD_REPLACE and SC_CARDCTL_ACOS5_SE_RECORD_REMOVE
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone,  PartialEq)]
+pub struct CardCtlSeRecord {
+    pub file_id_dir : u16,     // IN     the DF/MF, whose SE file is concerned
+    pub sac_info    : SACinfo, // INOUT  reference is the record id == record no.: IN for GET, REPLACE, REMOVE; OUT for ADD;
+                               //        the CRTs: OUT for GET; IN for ADD, REPLACE
+}
+
+// element of the findings buffer of CardCtlSanityCheck
+#[repr(C)]
+#[derive(Debug, Copy, Clone,  PartialEq, Eq)]
+pub struct SanityFinding {
+    pub severity      : u8,  // SANITY_SEVERITY_*
+    pub check_id      : u8,  // SANITY_CHECK_*
+    pub file_id       : u16, // the file/directory concerned, 0 if none
+    pub message       : [u8; SANITY_MAX_TEXT], // NUL-terminated, possibly truncated
+    pub suggested_fix : [u8; SANITY_MAX_TEXT], // NUL-terminated, possibly truncated, may be empty
+}
+
+impl Default for SanityFinding {
+    fn default() -> Self {
+        Self { severity: 0, check_id: 0, file_id: 0, message: [0; SANITY_MAX_TEXT], suggested_fix: [0; SANITY_MAX_TEXT] }
+    }
+}
+
+// struct for SC_CARDCTL_ACOS5_SANITY_CHECK
+// If a buffer is too small, the ..._len members tell the required size and SC_ERROR_BUFFER_TOO_SMALL gets returned
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CardCtlSanityCheck {
+    pub findings       : *mut SanityFinding, // IN     caller-provided buffer, may be null
+    pub findings_cap   : usize,              // IN     capacity of findings (no. of elements)
+    pub findings_len   : usize,              // OUT    no. of findings
+    pub count_errors   : usize,              // OUT    no. of findings with SANITY_SEVERITY_ERROR
+    pub count_warnings : usize,              // OUT    no. of findings with SANITY_SEVERITY_WARNING
+    pub json           : *mut c_char,        // IN     caller-provided buffer for the findings as JSON array, NUL-terminated; may be null
+    pub json_cap       : usize,              // IN     capacity of json (bytes)
+    pub json_len       : usize,              // OUT    length of JSON text, excl. terminating NUL
+}
+
+impl Default for CardCtlSanityCheck {
+    fn default() -> Self {
+        Self { findings: std::ptr::null_mut(), findings_cap: 0, findings_len: 0, count_errors: 0, count_warnings: 0,
+               json: std::ptr::null_mut(), json_cap: 0, json_len: 0 }
+    }
+}
+
+// struct for SC_CARDCTL_ACOS5_SANITY_REPAIR
+// Applying requires is_confirmed (the user consented interactively to the plan shown, within this run), and the plan text (IN) must equal
+// the one the planning call returned, i.e. exactly what the user confirmed will be applied
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CardCtlSanityRepair {
+    pub apply         : bool,        // IN     false: plan only; true: apply the plan
+    pub is_confirmed  : bool,        // IN     applying: the user confirmed the plan interactively, within this run
+    pub plan          : *mut c_char, // INOUT  caller-provided buffer for the plan, one action per line, NUL-terminated: OUT for planning, IN for applying
+    pub plan_cap      : usize,       // IN     capacity of plan (bytes)
+    pub plan_len      : usize,       // OUT    length of plan text, excl. terminating NUL
+    pub count_actions : usize,       // OUT    no. of repair actions planned
+    pub count_applied : usize,       // OUT    no. of repair actions applied successfully
+}
+
+impl Default for CardCtlSanityRepair {
+    fn default() -> Self {
+        Self { apply: false, is_confirmed: false, plan: std::ptr::null_mut(), plan_cap: 0, plan_len: 0, count_actions: 0, count_applied: 0 }
+    }
+}
+
+// struct for SC_CARDCTL_ACOS5_CARD_INIT
+// Creates the file system layout of info/card_initialization/card_initialization.scriptor on an erased (virgin) card.
+// PINs/PUKs are 8 bytes each (the profile's min-/max-pin-length), SM keys are 3DES (or AES for EVO only) of sm_key_len bytes
+// It holds secrets: Not Copy, Clone or Debug, and the PINs/PUKs and keys get overwritten with zeros when dropped
+#[repr(C)]
+#[derive(Default)]
+pub struct CardCtlCardInit {
+    pub validate_only : bool,      // IN  true: check the parameters against the card type only, don't touch the card
+    pub op_mode_byte  : u8,        // IN  Operation Mode Byte (EVO: Configuration Mode Byte) to be written; 0xFF: leave unchanged
+    pub so_pin        : [u8; 8],   // IN  global PIN 1
+    pub so_puk        : [u8; 8],   // IN
+    pub user_pin      : [u8; 8],   // IN  local PIN 1 (within appDF 4100)
+    pub user_puk      : [u8; 8],   // IN
+    pub sm_key_aes    : bool,      // IN  false: 3DES; true: AES (EVO only)
+    pub sm_key_len    : u8,        // IN  3DES: 16 or 24; AES: 16, 24 or 32
+    pub sm_key_host   : [u8; 32],  // IN  local sym. key 1: host/terminal key for external authentication
+    pub sm_key_card   : [u8; 32],  // IN  local sym. key 2: card key for internal authentication
+}
+
+impl Drop for CardCtlCardInit {
+    fn drop(&mut self) {
+        for byte in self.so_pin.iter_mut().chain(self.so_puk.iter_mut()).chain(self.user_pin.iter_mut())
+            .chain(self.user_puk.iter_mut()).chain(self.sm_key_host.iter_mut()).chain(self.sm_key_card.iter_mut()) {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+// struct for SC_CARDCTL_ACOS5_OP_MODE_BYTE
+// The Operation Mode Byte (EVO: Configuration Mode Byte) can be set only if there is no MF (virgin card, e.g. after
+// pkcs15-init --erase-card). Values supported: V2.00: 0 (64K); V3.00: 0 (FIPS) or 2 (64K); EVO: 0 (FIPS) or 1 (non-FIPS)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CardCtlOpModeByte {
+    pub set          : bool, // IN     false: get only; true: write value
+    pub value        : u8,   // INOUT  IN: the value to be written if set==true; OUT: the current value
+    pub is_fips_mode : bool, // OUT    whether value means FIPS 140-2 Level 3-Compliant Mode
+}
+
+// struct for SC_CARDCTL_ERASE_CARD
+// 'Zeroize Card User Data' after pre-flight checks, verifying afterwards that the card is virgin (no MF, EEPROM readable)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CardCtlEraseCard {
+    pub dry_run                   : bool, // IN   true: pre-flight checks only, nothing gets erased
+    pub zeroize_card_disable_byte : u8,   // OUT  0xFF if not readable (there is an MF), else the EEPROM value: Non-zero disables
+                                          //      Zeroize Card User Data, i.e. once initialized, the card can't be re-initialized
+    pub so_auth_state             : u8,   // OUT  ERASE_AUTH_*, before erasing
+    pub is_virgin                 : bool, // OUT  post-erase verification result
+}
+
+// struct for SC_CARDCTL_ACOS5_LIFECYCLE_REPORT (SC_CARDCTL_LIFECYCLE_GET/SET take a *mut i32, see lifecycle.rs)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CardCtlLifecycle {
+    pub card_life_cycle_byte : u8,  // OUT  the EEPROM's Card Life Cycle Byte; readable only as long as there is no MF, otherwise 0xFF
+    pub file_id              : u16, // OUT  the currently selected file
+    pub lcsi                 : u8,  // OUT  it's LCSI (File Info byte 7), one of LCSI_*
+    pub lifecycle            : i32, // OUT  SC_CARDCTRL_LIFECYCLE_USER if lcsi is LCSI_ACTIVATED, SC_CARDCTRL_LIFECYCLE_OTHER if
+                                    //      LCSI_TERMINATED, otherwise (access control isn't enforced) SC_CARDCTRL_LIFECYCLE_ADMIN
+}
+
 // struct for SC_CARDCTL_ACOS5_GENERATE_KEY_FILES_EXIST and SC_CARDCTL_ACOS5_GENERATE_KEY_FILES_CREATE, SC_CARDCTL_ACOS5_ENCRYPT_ASYM// data: *mut CardCtl_generate_crypt_asym, do_generate_asym, do_crypt_asym
 // not all data are require for do_crypt_asym (exponent, exponent_std, key_len_code, key_priv_type_code)
 #[repr(C)]
@@ -753,8 +1029,13 @@ pub struct SCDO { // for SCDO_TAGs Always_Deny ..AuthT every scdo content is in
 }
 */
 
+/* max. number of SCDO conditions within an SCDO_TAG.Any (0xA0) or SCDO_TAG.All (0xAF) group, that SAEinfo can hold */
+pub const SAE_MAX_SCDO : usize = 3;
+
 /* Stores SAE information for an instruction from <AMDO><SCDO> simple-TLV, intended to be placed in a Vec, stored with the DF
-   TODO SCDO Tags 0xA0 and 0xAF are not yet covered */
+   Every SCDO condition gets mapped to an scb: 0x90 -> 0, 0x97 -> 0xFF, 0x9E -> it's value, 0xA4 -> id of a virtual SE record.
+   For SCDO Tags 0xA0 (Any: OR) and 0xAF (All: AND), scbs holds the scb of each condition of the group,
+   otherwise scbs[0] == scb and scbs_len == 1 */
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_snake_case)]
 #[repr(C)]
@@ -767,9 +1048,9 @@ pub struct SAEinfo {
     pub p2       : u8,
 
     pub tag_SCDO : u8,    // SCDO TAG: 0x90 <= tag_SCDO <= 0xAF  ; the 'leading' tag of a <AMDO><SCDO_oneAtLeast><SCDO_opt><SCDO_opt> group, that of <SCDO_oneAtLeast>
-    pub scb      : u8,
-//    pub scdo : [SCDO; 3], // CONVENTION : max 3 conditions for SCDO_TAG.Any or SCDO_TAG.All
-//    pub scdo_len : i32,
+    pub scb      : u8,    // for 0xA0 and 0xAF: the scb of the first condition of the group
+    pub scbs     : [u8; SAE_MAX_SCDO], // CONVENTION : max 3 conditions for SCDO_TAG.Any or SCDO_TAG.All
+    pub scbs_len : u8,
 }
 
 pub type KeyTypeFiles   = u16;
@@ -780,6 +1061,37 @@ pub type ValueTypeFiles = ([u8; SC_MAX_PATH_SIZE], [u8; 8], Option<[u8; 8]>, Opt
 //                              ^ path len actually used
 //                                                     ^            ^ misc., e.g. SE-file id for MF/DF
 //                                                                               ^ PKCS#15 file type or 0xFF, see PKCS15_FILE_TYPE_*
+/* When to open a new SM session, see sm_manage_initialize; set from opensc.conf: sm_session_policy */
+pub const SM_POLICY_TIMED      : u8 = 0; // default: reuse only for repetitive read/update within a timeout
+pub const SM_POLICY_ALWAYS_NEW : u8 = 1; // a new session for each SM command
+pub const SM_POLICY_REUSE      : u8 = 2; // reuse across commands until max_commands, the SSC would wrap or the DF changes
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SmPolicy {
+    pub mode                : u8,
+    pub timeout_read_update : u32, // ms, SM_POLICY_TIMED only
+    pub timeout_pin         : u32, // ms, SM_POLICY_TIMED only
+    pub timeout_other       : u32, // ms, SM_POLICY_TIMED only
+    pub max_commands        : u32, // SM_POLICY_REUSE only
+}
+
+impl Default for SmPolicy {
+    fn default() -> Self {
+        Self { mode: SM_POLICY_TIMED, timeout_read_update: 300, timeout_pin: 200, timeout_other: 150, max_commands: 1000 }
+    }
+}
+
+/* The PinAttributes of an AODF entry, as far as relevant for the PIN policy */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinAttributes {
+    pub encoding      : u32,   // SC_PIN_ENCODING_*, from pinType
+    pub min_length    : usize,
+    pub stored_length : usize,
+    pub max_length    : usize, // 0, if absent
+    pub pad_char      : Option<u8>,
+}
+
 #[repr(C)]
 #[derive(/*Debug, Copy,*/ Clone)]
 pub struct DataPrivate { // see settings in acos5_init
@@ -792,6 +1104,8 @@ pub struct DataPrivate { // see settings in acos5_init
 //  pub sec_env_algo_flags : u32, // remember the padding scheme etc. selected for RSA; required in acos5_64_set_security_env
     pub time_stamp : std::time::Instant,
     pub sm_cmd : u32,
+    pub sm_policy : SmPolicy,
+    pub sm_counters : CardCtlSmCounters,
     pub rsa_caps : u32, // remember how the rsa_algo_flags where set for _sc_card_add_rsa_alg
     pub sec_env_mod_len : u16, //u32,
     pub rfu_align_pad1  : u16,
@@ -812,6 +1126,9 @@ pub struct DataPrivate { // see settings in acos5_init
     pub sym_key_rec_idx : u8,
     pub sym_key_rec_cnt : u8,
     pub last_keygen_priv_id: sc_pkcs15_id,
+    pub files_created : Vec<u16>, // file ids created by acos5_create_file in this session; see lifecycle.rs files_activate
+    pub pin_attributes : Vec<(u16, u8, PinAttributes)>, // (AODF file id, pinReference, ..) of the AODFs' PIN objects, cached
+                                                        // by analyze_PKCS15_PKCS15Objects_5031; see pin_set_policy
     #[cfg(iup_user_consent)]
     pub ui_ctx : ui_context,
 }
@@ -894,6 +1211,123 @@ pub fn file_id(file_info_bytes: [u8; 8]) ->u16 {
     u16::from_be_bytes([file_info_bytes[2], file_info_bytes[3]])
 }
 
+/* PIN file record: PIN ID|0x80 (|0x40, if there is a PUK), PIN tries (max<<4|left), PIN length, PIN and optionally
+   PUK tries, PUK length, PUK. The same tries (1..=15) apply to PIN and PUK */
+#[allow(clippy::missing_panics_doc)]
+#[must_use]
+pub fn pin_record(pin_id: u8, tries: u8, pin: &[u8], puk: &[u8]) -> Vec<u8> {
+    assert!(pin_id > 0 && pin_id <= PIN_ID_MAX && tries > 0 && tries <= 15);
+    assert!(pin.len() <= PIN_LEN_MAX_EVO && puk.len() <= PIN_LEN_MAX_EVO);
+    let tries = tries << 4 | tries;
+    let mut rec = vec![0x80 | if puk.is_empty() {0} else {0x40} | pin_id, tries, u8::try_from(pin.len()).unwrap()];
+    rec.extend_from_slice(pin);
+    if !puk.is_empty() {
+        rec.extend_from_slice(&[tries, u8::try_from(puk.len()).unwrap()]);
+        rec.extend_from_slice(puk);
+    }
+    rec
+}
+
+/* Whether a PIN reference is in use, from the SW of 'Verify' without PIN data: 63 Cx for an existing PIN, 69 83 for an
+   existing, but blocked one, 90 00 for a verified one and 6A 88, if there is no such PIN. None for any other SW */
+#[must_use]
+pub fn is_pin_reference_in_use_sw(sw1: u8, sw2: u8) -> Option<bool> {
+    match (sw1, sw2) {
+        (0x63, sw2) if sw2 & 0xF0 == 0xC0 => Some(true),
+        (0x69, 0x83) | (0x90, 0) => Some(true),
+        (0x6A, 0x88) => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the PIN ID for a new PIN: For pin_id 0 the first one of 1..=pin_id_max, that isn't in use, otherwise pin_id,
+/// if that is within range and not in use
+///
+/// # Errors
+/// `SC_ERROR_INVALID_PIN_REFERENCE` for a pin_id out of range or in use, `SC_ERROR_TOO_MANY_OBJECTS` if all are in use,
+/// or the error of is_in_use
+pub fn pin_id_select<F: FnMut(u8) -> Result<bool, i32>>(pin_id: u8, pin_id_max: u8, mut is_in_use: F) -> Result<u8, i32> {
+    if pin_id > 0 {
+        return if pin_id > pin_id_max || is_in_use(pin_id)? {Err(SC_ERROR_INVALID_PIN_REFERENCE)} else {Ok(pin_id)};
+    }
+    for pin_id in 1..=pin_id_max {
+        if !is_in_use(pin_id)? {
+            return Ok(pin_id);
+        }
+    }
+    Err(SC_ERROR_TOO_MANY_OBJECTS)
+}
+
+/* The standard RSA public exponent 0x010001 == 65537 in the 16 byte form of cos5 'Generate RSA Key Pair' */
+pub const RSA_PUB_EXPONENT_STANDARD : [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1];
+
+/// Converts a big-endian RSA public exponent to the right-aligned 16 byte form of cos5 'Generate RSA Key Pair'
+///
+/// # Errors
+/// `SC_ERROR_INVALID_ARGUMENTS`, if the card won't accept the exponent: It must be odd, > 1 and of max. 16 bytes
+/// (leading zero bytes disregarded)
+pub fn rsa_pub_exponent_16(exponent: &[u8]) -> Result<[u8; 16], i32> {
+    let pos = exponent.iter().position(|&b| b != 0).unwrap_or(exponent.len());
+    let exponent = &exponent[pos..];
+    if exponent.is_empty() || exponent.len() > 16 || exponent[exponent.len()-1] & 1 == 0 || exponent == [1] {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    let mut result = [0; 16];
+    result[16-exponent.len()..].copy_from_slice(exponent);
+    Ok(result)
+}
+
+/* ECC key files (ACOS5-EVO only), FDB_ECC_KEY_EF: Assumed to be laid out like the RSA key files (see read_public_key):
+   a 5 byte header, of which byte 1 is the curve code; then follows the private value d (private key file), or the
+   coordinates X||Y of the public point, each of the field's byte length (public key file).
+   This layout (and the curve codes of ecc_curve_code) is inferred, neither confirmed by the reference manual nor by the
+   dump of a real card's file, thus ecc_pub_key_file_point insists on the exact file size and a point on the curve, to
+   fail rather than deliver a wrong key */
+pub const ECC_KEY_FILE_HEADER_LEN : usize = 5;
+
+/// The cos5 curve code of 'Generate ECC Key Pair' for a field length in bits, `None` for unsupported curves
+#[must_use]
+pub fn ecc_curve_code(field_length: usize) -> Option<u8> {
+    match field_length {
+        224 => Some(1),
+        256 => Some(2),
+        384 => Some(3),
+        521 => Some(4),
+        _   => None,
+    }
+}
+
+/// The field length in bits of a cos5 curve code, the inverse of `ecc_curve_code`
+#[must_use]
+pub fn ecc_field_length(curve_code: u8) -> Option<usize> {
+    match curve_code {
+        1 => Some(224),
+        2 => Some(256),
+        3 => Some(384),
+        4 => Some(521),
+        _ => None,
+    }
+}
+
+/// The byte length of d, X and Y for a field length in bits, `None` for unsupported curves
+#[must_use]
+pub fn ecc_coordinate_len(field_length: usize) -> Option<usize> {
+    match field_length {
+        224 => Some(28),
+        256 => Some(32),
+        384 => Some(48),
+        521 => Some(66),
+        _   => None,
+    }
+}
+
+/// The file sizes (private, public) required for an ECC key pair of `field_length` bits
+#[must_use]
+pub fn ecc_key_file_sizes(field_length: usize) -> Option<(usize, usize)> {
+    let len = ecc_coordinate_len(field_length)?;
+    Some((ECC_KEY_FILE_HEADER_LEN + len, ECC_KEY_FILE_HEADER_LEN + 2*len))
+}
+
 /*
  a 2-byte slot [4..6] gets used only by some file types:
  for DF/MF its the id of an SE file
@@ -904,6 +1338,12 @@ pub fn file_id_se(file_info_bytes: [u8; 8]) ->u16 {
     u16::from_be_bytes([file_info_bytes[4], file_info_bytes[5]])
 }
 
+/* the file size of a non-record based file, i.e. the same slot [4..6] as file_id_se */
+#[must_use]
+pub fn file_size(file_info_bytes: [u8; 8]) ->u16 {
+    u16::from_be_bytes([file_info_bytes[4], file_info_bytes[5]])
+}
+
 /* SCB: Security Condition Byte
  * convert_bytes_tag_fcp_sac_to_scb_array expands the (possibly) "compressed" tag_fcp_sac (0x8C) bytes from card file/director's
  * header to a 'standard' 8 byte SCB array, interpreting the AM byte (AMB);
@@ -4,15 +4,16 @@
 
 use num_integer::Integer;
 
-use std::os::raw::{/*c_char, c_ulong,*/ c_long, c_int};
+use std::os::raw::{/*c_char, c_ulong,*/ c_long, c_int, c_void};
 use std::convert::{TryFrom/*, TryInto*/};
+use std::ops::{Deref, DerefMut};
+use std::ptr::null_mut;
+
+use opensc_sys::errors::{SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_INTERNAL};
 
 //from openssl  des.h and rand.h
-#[allow(non_upper_case_globals)]
-pub const DES_KEY_SZ_u8 : u8    = 8; // sizeof(DES_cblock)
 pub const DES_KEY_SZ    : usize = 8; // sizeof(DES_cblock)
-#[allow(dead_code)]
-const AES_BLOCK_SIZE: usize = 16;
+pub const AES_BLOCK_SIZE: usize = 16;
 
 pub const Encrypt: i32 = 1;
 pub const Decrypt: i32 = 0;
@@ -34,6 +35,21 @@ struct DES_key_schedule {
     ks: [DES_cblock; 16],
 }
 
+#[repr(C)]
+pub struct EVP_MD {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct EC_GROUP {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct EC_POINT {
+    _private: [u8; 0],
+}
+
 #[repr(C)]
 struct AES_KEY {
     rd_key: [u32; 60],
@@ -81,6 +97,54 @@ extern "C" {
     fn AES_ecb_encrypt(in_: *const u8, out: *mut u8, key: *const AES_KEY, enc: i32);
     #[allow(dead_code)]
     fn AES_cbc_encrypt(in_: *const u8, out: *mut u8, length: usize, key: *const AES_KEY, ivec: *mut u8, enc: i32);
+
+    // returns 1 on success or 0 on error
+    fn PKCS5_PBKDF2_HMAC(pass: *const u8, passlen: c_int, salt: *const u8, saltlen: c_int, iter: c_int,
+                         digest: *const EVP_MD, keylen: c_int, out: *mut u8) -> c_int;
+    fn EVP_sha256() -> *const EVP_MD;
+    fn OPENSSL_cleanse(ptr: *mut c_void, len: usize);
+
+    fn EC_GROUP_new_by_curve_name(nid: c_int) -> *mut EC_GROUP;
+    fn EC_GROUP_free(group: *mut EC_GROUP);
+    fn EC_POINT_new(group: *const EC_GROUP) -> *mut EC_POINT;
+    fn EC_POINT_free(point: *mut EC_POINT);
+    // returns 1 on success or 0 on error; the BN_CTX may be NULL
+    fn EC_POINT_oct2point(group: *const EC_GROUP, p: *mut EC_POINT, buf: *const u8, len: usize, ctx: *mut c_void) -> c_int;
+    // returns 1 if the point is on the curve, 0 if not, -1 on error
+    fn EC_POINT_is_on_curve(group: *const EC_GROUP, point: *const EC_POINT, ctx: *mut c_void) -> c_int;
+}
+
+/* Overwrites secret data with zeros in a way, that the compiler doesn't optimize away */
+pub fn cleanse(buf: &mut [u8]) {
+    unsafe { OPENSSL_cleanse(buf.as_mut_ptr().cast::<c_void>(), buf.len()) };
+}
+
+/* A buffer holding secret data (keys, passphrases, decrypted key material), that gets cleansed when dropped.
+   No PartialEq (not constant time) and Debug prints the length only */
+#[derive(Default)]
+pub struct Zeroizing(pub Vec<u8>);
+
+impl std::fmt::Debug for Zeroizing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Zeroizing(<{} bytes>)", self.0.len())
+    }
+}
+
+impl Deref for Zeroizing {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Vec<u8> { &self.0 }
+}
+
+impl DerefMut for Zeroizing {
+    fn deref_mut(&mut self) -> &mut Vec<u8> { &mut self.0 }
+}
+
+impl Drop for Zeroizing {
+    fn drop(&mut self) {
+        let len = self.0.capacity();
+        self.0.resize(len, 0);
+        cleanse(&mut self.0);
+    }
 }
 
 /*
@@ -263,8 +327,7 @@ pi is relevant here only for Decrypt:
 if pi==01, then it's known, that  a 0x80 byte was added (padding was applied and must be stripped in mode == Decrypt)
 if pi==00, then it's known, that no 0x80 byte was added
 */
-#[allow(dead_code)]
-fn aes_cbc_pad_80(data: &[u8], key: &[u8], ivec: &mut [u8; AES_BLOCK_SIZE], mode: i32, pi: u8) -> Vec<u8> {
+pub fn aes_cbc_pad_80(data: &[u8], key: &[u8], ivec: &mut [u8; AES_BLOCK_SIZE], mode: i32, pi: u8) -> Vec<u8> {
     assert!(mode==Encrypt || data.len().is_multiple_of(&AES_BLOCK_SIZE));
     assert!([16, 24, 32].contains(&key.len()));
 
@@ -300,12 +363,53 @@ fn aes_cbc_pad_80(data: &[u8], key: &[u8], ivec: &mut [u8; AES_BLOCK_SIZE], mode
     output
 }
 
+/* PBKDF2 with HMAC-SHA256, as used by `openssl enc -pbkdf2` to derive key and iv from a passphrase.
+   SC_ERROR_INVALID_ARGUMENTS for iter < 1 or lengths exceeding c_int, SC_ERROR_INTERNAL if OpenSSL fails */
+pub fn pbkdf2_hmac_sha256(passphrase: &[u8], salt: &[u8], iter: i32, len: usize) -> Result<Zeroizing, i32> {
+    let (passlen, saltlen, keylen) = match (c_int::try_from(passphrase.len()), c_int::try_from(salt.len()), c_int::try_from(len)) {
+        (Ok(passlen), Ok(saltlen), Ok(keylen)) if iter >= 1 => (passlen, saltlen, keylen),
+        _ => return Err(SC_ERROR_INVALID_ARGUMENTS),
+    };
+    let mut output = Zeroizing(vec![0_u8; len]);
+    let rv = unsafe { PKCS5_PBKDF2_HMAC(passphrase.as_ptr(), passlen, salt.as_ptr(), saltlen, iter, EVP_sha256(), keylen,
+                                        output.as_mut_ptr()) };
+    if rv == 1 { Ok(output) } else { Err(SC_ERROR_INTERNAL) }
+}
+
+/* Whether point (uncompressed form 04||X||Y) is a point on the NIST curve P-224/P-256/P-384/P-521 of field_length bits */
+#[must_use]
+pub fn ec_point_is_on_curve(field_length: usize, point: &[u8]) -> bool {
+    /* NID_secp224r1, NID_X9_62_prime256v1, NID_secp384r1, NID_secp521r1 from openssl/obj_mac.h */
+    let nid = match field_length {
+        224 => 713,
+        256 => 415,
+        384 => 715,
+        521 => 716,
+        _   => return false,
+    };
+    unsafe {
+        let group = EC_GROUP_new_by_curve_name(nid);
+        if group.is_null() {
+            return false;
+        }
+        let ec_point = EC_POINT_new(group);
+        let result = !ec_point.is_null() &&
+            EC_POINT_oct2point(group, ec_point, point.as_ptr(), point.len(), null_mut()) == 1 &&
+            EC_POINT_is_on_curve(group, ec_point, null_mut()) == 1;
+        EC_POINT_free(ec_point);
+        EC_GROUP_free(group);
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use num_integer::Integer;
     use super::{Encrypt, Decrypt, DES_KEY_SZ, DES_cblock, des_ecb3_unpadded_8, des_ede3_cbc_pad_80,
                 des_ede3_cbc_pad_80_mac /*, des_ecb3_pad_pkcs5*/,
-                AES_BLOCK_SIZE, aes_ecb_unpadded_16, aes_cbc_pad_80, DES_set_odd_parity, };
+                AES_BLOCK_SIZE, aes_ecb_unpadded_16, aes_cbc_pad_80, DES_set_odd_parity,
+                pbkdf2_hmac_sha256, ec_point_is_on_curve};
+    use opensc_sys::errors::SC_ERROR_INVALID_ARGUMENTS;
 /*
     #[test]
     fn test_des_ecb3_pad_pkcs5() {
@@ -425,4 +529,27 @@ mod tests {
 */
 //println!("{:X?}", e);
     }
+
+    #[test]
+    fn test_pbkdf2_hmac_sha256() { // RFC 6070 inputs, with SHA-256
+        assert_eq!(Ok(vec![0xAE, 0x4D, 0x0C, 0x95, 0xAF, 0x6B, 0x46, 0xD3, 0x2D, 0x0A, 0xDF, 0xF9, 0x28, 0xF0, 0x6D, 0xD0]),
+                   pbkdf2_hmac_sha256(b"password", b"salt", 2, 16).map(|key| key.to_vec()));
+        assert_eq!(Err(SC_ERROR_INVALID_ARGUMENTS), pbkdf2_hmac_sha256(b"password", b"salt", 0, 16).map(|key| key.to_vec()));
+        /* the key material doesn't show up in Debug output */
+        assert_eq!("Ok(Zeroizing(<16 bytes>))", format!("{:?}", pbkdf2_hmac_sha256(b"password", b"salt", 2, 16)));
+    }
+
+    #[test]
+    fn test_ec_point_is_on_curve() { // the base point G of P-256 (FIPS 186-4, D.1.2.3)
+        let mut g = vec![0x04_u8,
+            0x6B, 0x17, 0xD1, 0xF2, 0xE1, 0x2C, 0x42, 0x47, 0xF8, 0xBC, 0xE6, 0xE5, 0x63, 0xA4, 0x40, 0xF2,
+            0x77, 0x03, 0x7D, 0x81, 0x2D, 0xEB, 0x33, 0xA0, 0xF4, 0xA1, 0x39, 0x45, 0xD8, 0x98, 0xC2, 0x96,
+            0x4F, 0xE3, 0x42, 0xE2, 0xFE, 0x1A, 0x7F, 0x9B, 0x8E, 0xE7, 0xEB, 0x4A, 0x7C, 0x0F, 0x9E, 0x16,
+            0x2B, 0xCE, 0x33, 0x57, 0x6B, 0x31, 0x5E, 0xCE, 0xCB, 0xB6, 0x40, 0x68, 0x37, 0xBF, 0x51, 0xF5];
+        assert!(ec_point_is_on_curve(256, &g));
+        assert!(!ec_point_is_on_curve(384, &g));
+        assert!(!ec_point_is_on_curve(192, &g));
+        g[64] ^= 1;
+        assert!(!ec_point_is_on_curve(256, &g));
+    }
 }
@@ -76,7 +76,7 @@ use std::convert::TryInto;
 // use ::function_name::named;
 
 use opensc_sys::opensc::{sc_card, sc_card_driver, sc_card_operations, sc_security_env,
-    sc_pin_cmd_data,
+    sc_pin_cmd_data, sc_ec_parameters,
     sc_get_iso7816_driver, sc_get_mf_path, sc_file_set_prop_attr, sc_select_file, sc_read_binary,
     sc_transmit_apdu, sc_check_sw, sc_get_version,
     SC_ALGORITHM_RSA_HASH_NONE, SC_ALGORITHM_ECDSA_RAW, SC_CARD_CAP_RNG, SC_CARD_CAP_USE_FCI_AC,
@@ -90,8 +90,8 @@ use opensc_sys::opensc::{sc_card, sc_card_driver, sc_card_operations, sc_securit
     SC_CARD_CAP_ISO7816_PIN_INFO, SC_ALGORITHM_AES,
     SC_ALGORITHM_EXT_EC_NAMEDCURVE, SC_CARD_CAP_APDU_EXT,
     SC_SEC_OPERATION_ENCRYPT_SYM, SC_SEC_OPERATION_DECRYPT_SYM, SC_ALGORITHM_EC,
-    SC_PROTO_T1
-//  SC_ALGORITHM_ECDH_CDH_RAW, SC_ALGORITHM_ECDSA_HASH_NONE, SC_ALGORITHM_ECDSA_HASH_SHA1,
+    SC_PROTO_T1, SC_ALGORITHM_ECDSA_HASH_NONE,
+//  SC_ALGORITHM_ECDH_CDH_RAW,
 //  SC_ALGORITHM_EXT_EC_UNCOMPRESES,
 //  ,sc_pin_cmd_pin, sc_pin_cmd//, sc_update_binary, sc_path_set, sc_verify
 };
@@ -106,10 +106,11 @@ use opensc_sys::opensc::{sc_update_record, SC_SEC_ENV_PARAM_IV, SC_SEC_ENV_PARAM
 //                         , SC_SEC_OPERATION_WRAP
 };
 
-use opensc_sys::types::{SC_AC_CHV, sc_aid, sc_path, sc_file, sc_serial_number, SC_MAX_PATH_SIZE,
+use opensc_sys::types::{SC_AC_CHV, sc_aid, sc_path, sc_object_id, sc_lv_data, sc_file, sc_serial_number, SC_MAX_PATH_SIZE, SC_MAX_APDU_BUFFER_SIZE,
                         SC_PATH_TYPE_FILE_ID, SC_PATH_TYPE_DF_NAME, SC_PATH_TYPE_PATH,
 //                        SC_PATH_TYPE_PATH_PROT, SC_PATH_TYPE_FROM_CURRENT, SC_PATH_TYPE_PARENT,
-                        SC_FILE_TYPE_DF, SC_FILE_TYPE_INTERNAL_EF, SC_FILE_EF_TRANSPARENT,/* SC_AC_NONE,
+                        SC_FILE_TYPE_DF, SC_FILE_TYPE_INTERNAL_EF, SC_FILE_EF_TRANSPARENT, SC_FILE_STATUS_ACTIVATED,
+                        SC_FILE_STATUS_INVALIDATED, SC_FILE_STATUS_CREATION, SC_FILE_STATUS_TERMINATION,/* SC_AC_NONE,
                         SC_AC_KEY_REF_NONE, SC_AC_OP_LIST_FILES, SC_AC_OP_SELECT, SC_AC_OP_DELETE, SC_AC_OP_CREATE_EF,
                         SC_AC_OP_CREATE_DF, SC_AC_OP_INVALIDATE, SC_AC_OP_REHABILITATE, SC_AC_OP_LOCK, SC_AC_OP_READ,
                         SC_AC_OP_UPDATE, SC_AC_OP_CRYPTO, SC_AC_OP_DELETE_SELF, SC_AC_OP_CREATE, SC_AC_OP_WRITE,
@@ -126,31 +127,32 @@ use opensc_sys::errors::{SC_SUCCESS/*, SC_ERROR_INTERNAL*/, SC_ERROR_INVALID_ARG
 
 // #[cfg(sanity)]
 use opensc_sys::errors::{SC_ERROR_INVALID_CARD};
-use opensc_sys::internal::{_sc_card_add_rsa_alg, _sc_card_add_ec_alg, sc_pkcs1_encode, _sc_match_atr};
+use opensc_sys::internal::{_sc_card_add_ec_alg, sc_pkcs1_encode, _sc_match_atr};
 use opensc_sys::log::{sc_dump_hex};
-use opensc_sys::cardctl::{SC_CARDCTL_GET_SERIALNR, SC_CARDCTL_LIFECYCLE_SET};
+use opensc_sys::cardctl::{SC_CARDCTL_ERASE_CARD, SC_CARDCTL_GET_SERIALNR, SC_CARDCTL_LIFECYCLE_GET, SC_CARDCTL_LIFECYCLE_SET};
 use opensc_sys::asn1::{sc_asn1_put_tag/*, sc_asn1_skip_tag, sc_asn1_read_tag, sc_asn1_print_tags, sc_asn1_find_tag*/};
 use opensc_sys::iso7816::{ISO7816_TAG_FCP_TYPE, ISO7816_TAG_FCP_LCS,  ISO7816_TAG_FCP, ISO7816_TAG_FCP_SIZE,
                           ISO7816_TAG_FCP_FID, ISO7816_TAG_FCP_DF_NAME};
 use opensc_sys::pkcs15::{sc_pkcs15_pubkey_rsa, sc_pkcs15_bignum, sc_pkcs15_encode_pubkey_rsa, sc_pkcs15_bind,
+                         sc_pkcs15_pubkey_ec, sc_pkcs15_u8, sc_pkcs15_encode_pubkey_ec,
                          sc_pkcs15_unbind, sc_pkcs15_auth_info, sc_pkcs15_get_objects, SC_PKCS15_TYPE_AUTH_PIN,
                          sc_pkcs15_id
                          /*,sc_pkcs15_object, sc_pkcs15_card*/}; // , SC_PKCS15_AODF
 use opensc_sys::sm::{SM_TYPE_CWA14890, SM_CMD_PIN, SM_CMD_PIN_VERIFY, SM_CMD_PIN_SET_PIN, SM_CMD_PIN_RESET,
-                     SM_CMD_FILE_UPDATE, SM_CMD_FILE_DELETE};
+                     SM_CMD_FILE_UPDATE, SM_CMD_FILE_DELETE, SM_CMD_FILE_CREATE};
 
 
 #[macro_use]
 mod macros;
 
-#[cfg(card_initialization)]
 mod card_initialization;
-// use card_initialization::{};
+use card_initialization::{card_init_card_ctl, erase_card_card_ctl};
 
 mod cmd_card_info;
 use cmd_card_info::{get_cos_version, get_count_files_curr_df, get_file_info, get_free_space, get_is_fips_compliant,
                     get_is_ident_self_okay, get_is_key_authenticated, get_is_pin_authenticated, get_manufacture_date,
-                    get_op_mode_byte, get_rom_sha1, get_serialnr};
+                    get_op_mode_byte, get_op_mode_byte_of_type, get_rom_sha1, get_serialnr, is_op_mode_byte_supported,
+                    op_mode_byte_card_ctl};
 
 mod constants_types;
 use constants_types::{BLOCKCIPHER_PAD_TYPE_ANSIX9_23, BLOCKCIPHER_PAD_TYPE_ONEANDZEROES,
@@ -161,7 +163,8 @@ use constants_types::{BLOCKCIPHER_PAD_TYPE_ANSIX9_23, BLOCKCIPHER_PAD_TYPE_ONEAN
                       FDB_CHV_EF, FDB_CYCLIC_EF, FDB_DF, FDB_ECC_KEY_EF, FDB_LINEAR_FIXED_EF,
                       FDB_LINEAR_VARIABLE_EF, FDB_MF, FDB_PURSE_EF, FDB_RSA_KEY_EF, FDB_SE_FILE,
                       FDB_SYMMETRIC_KEY_EF, FDB_TRANSPARENT_EF, ISO7816_RFU_TAG_FCP_SAC,
-                      ISO7816_RFU_TAG_FCP_SEID, KeyTypeFiles, PKCS15_FILE_TYPE_NONE, PKCS15_FILE_TYPE_PIN,
+                      ISO7816_RFU_TAG_FCP_SEID, KeyTypeFiles, LCSI_CREATION, LCSI_DEACTIVATED, LCSI_TERMINATED, PKCS15_FILE_TYPE_NONE,
+                      PKCS15_FILE_TYPE_PIN,
                       /*PKCS15_FILE_TYPE_RSAPRIVATEKEY, PKCS15_FILE_TYPE_RSAPUBLICKEY,*/ PKCS15_FILE_TYPE_SECRETKEY,
                       RSAPUB_MAX_LEN, SC_CARDCTL_ACOS5_DECRYPT_SYM, SC_CARDCTL_ACOS5_ENCRYPT_ASYM,
                       SC_CARDCTL_ACOS5_ENCRYPT_SYM, SC_CARDCTL_ACOS5_GET_COS_VERSION,
@@ -182,7 +185,13 @@ use constants_types::{BLOCKCIPHER_PAD_TYPE_ANSIX9_23, BLOCKCIPHER_PAD_TYPE_ONEAN
                       file_id_from_path_value, file_id, file_id_se, Fci,
                       SC_CARDCTL_ACOS5_ALGO_REF_SYM_STORE, CardCtlAlgoRefSymStore, CRT_TAG_DST, CRT_TAG_CT,
                       SC_SEC_OPERATION_GENERATE_ECCPRIVATE, SC_SEC_OPERATION_GENERATE_ECCPUBLIC,
-                      SC_SEC_OPERATION_ENCIPHER_ECCPUBLIC
+                      SC_SEC_OPERATION_ENCIPHER_ECCPUBLIC, CREATE_EF, CREATE_DF, SC_CARDCTL_ACOS5_GET_SM_COUNTERS,
+                      CardCtlSmCounters, SM_POLICY_REUSE, SC_CARDCTL_ACOS5_GET_AC_REPORT, CardCtlAcReport,
+                      SC_CARDCTL_ACOS5_SE_RECORD_GET, SC_CARDCTL_ACOS5_SE_RECORD_ADD, SC_CARDCTL_ACOS5_SE_RECORD_REPLACE,
+                      SC_CARDCTL_ACOS5_SE_RECORD_REMOVE, CardCtlSeRecord, CardCtlSanityCheck,
+                      SC_CARDCTL_ACOS5_SANITY_REPAIR, CardCtlSanityRepair, SC_CARDCTL_ACOS5_CARD_INIT, CardCtlCardInit,
+                      SC_CARDCTL_ACOS5_OP_MODE_BYTE, CardCtlOpModeByte, CardCtlLifecycle, CardCtlEraseCard,
+                      SC_CARDCTL_ACOS5_LIFECYCLE_REPORT, PIN_LEN_MAX, PIN_LEN_MAX_EVO
                       /*,PKCS15_FILE_TYPE_ECCPRIVATEKEY, PKCS15_FILE_TYPE_ECCPUBLICKEY, READ*/};
 
 #[cfg(iup_user_consent)]
@@ -190,12 +199,15 @@ use constants_types::{ui_context, set_ui_ctx, get_ui_ctx, acos5_ask_user_consent
 
 mod crypto;
 
+mod lifecycle;
+use lifecycle::{lifecycle_get, lifecycle_set};
+
 mod missing_exports;
 use missing_exports::{me_card_add_symmetric_alg, me_card_find_alg, me_get_max_recv_size,
                              me_pkcs1_strip_01_padding, me_pkcs1_strip_02_padding};//, me_get_encoding_flags
 
 mod no_cdecl;
-use no_cdecl::{select_file_by_path, enum_dir,
+use no_cdecl::{select_file_by_path, enum_dir, rsa_algorithms_register,
     pin_get_policy, tracking_select_file, acos5_supported_atrs,
                       /*encrypt_public_rsa,*/ get_sec_env, set_sec_env,// get_rsa_caps,
     get_is_running_cmd_long_response, set_is_running_cmd_long_response, is_any_known_digestAlgorithm,
@@ -204,7 +216,7 @@ use no_cdecl::{select_file_by_path, enum_dir,
     ACL_CATEGORY_DF_MF, ACL_CATEGORY_EF_CHV, ACL_CATEGORY_KEY, ACL_CATEGORY_SE,
     get_is_running_compute_signature, set_is_running_compute_signature, algo_ref_sym_store,
     common_read, common_update, acos5_supported_ec_curves, logout_pin, sym_en_decrypt,
-    algo_ref_mse_sedo
+    algo_ref_mse_sedo, ecc_pub_key_file_point, sign_ecdsa
 };
 
 mod path;
@@ -213,13 +225,14 @@ use path::{file_id_from_cache_current_path, current_path_df};
 // #[cfg(sanity)]
 mod sanity;
 // #[cfg(sanity)]
-use sanity::{sanity_check};
+use sanity::{sanity_check, sanity_check_card_ctl, sanity_repair_card_ctl};
 
 mod se;
-use se::{map_scb8_to_acl, se_get_is_scb_suitable_for_sm_has_ct, se_parse_sae, se_get_sae_scb};
+use se::{map_scb8_to_acl, se_get_is_scb_suitable_for_sm_has_ct, se_parse_sae, se_get_sae_scb, se_get_ac_report,
+         se_record_get, se_record_put, se_record_remove};
 
 mod sm;
-use sm::{sm_erase_binary, sm_delete_file, sm_pin_cmd, sm_pin_cmd_get_policy};
+use sm::{sm_erase_binary, sm_delete_file, sm_create_file, sm_pin_cmd, sm_pin_cmd_get_policy, sm_policy_from_conf};
 
 cfg_if::cfg_if! {
     if #[cfg(not(target_os = "windows"))] {
@@ -227,7 +240,7 @@ cfg_if::cfg_if! {
         use tasn1_pkcs15_array::tasn1_pkcs15_definitions;
 
         mod tasn1_pkcs15_util;
-        use tasn1_pkcs15_util::{analyze_PKCS15_DIRRecord_2F00, analyze_PKCS15_PKCS15Objects_5031 /* , analyze_PKCS15_TokenInfo_5032*/};
+        use tasn1_pkcs15_util::{analyze_PKCS15_DIRRecord_2F00, analyze_PKCS15_PKCS15Objects_5031};
 
         mod tasn1_sys;
         use tasn1_sys::{ASN1_SUCCESS, asn1_node, asn1_array2tree, asn1_delete_structure};
@@ -235,9 +248,15 @@ cfg_if::cfg_if! {
     }
 }
 
+mod transcript;
+use transcript::{op_scope, transcript_start_from_conf, transcript_stop};
+
 mod wrappers;
 use wrappers::{wr_do_log, wr_do_log_rv, wr_do_log_sds, wr_do_log_t, wr_do_log_tt, wr_do_log_tu, wr_do_log_tuv};
 
+#[cfg(test)]
+mod simulator;
+
 /*
 #[cfg(test)]
 #[cfg(test_v2_v3_token)]
@@ -476,6 +495,7 @@ TODO how to set opensc.conf, such that a minimum of trials to match atr is done
 //#[named]
 extern "C" fn acos5_match_card(card_ptr: *mut sc_card) -> i32
 {
+    let _op = op_scope("acos5_match_card");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || unsafe { (*card_ptr).reader.is_null() } {
         return 0;
     }
@@ -546,48 +566,19 @@ extern "C" fn acos5_match_card(card_ptr: *mut sc_card) -> i32
         SC_CARD_TYPE_ACOS5_64_V3  =>  { if rbuf_card_os_version[6] != 1  { return 0; } },
         _                         =>  { return 0; },
     }
+    / **/
 
-        /* excludes any mode except 64K (no FIPS, no 32K, no NSH-1 (ICP Brasil)) */
-        if type_out == SC_CARD_TYPE_ACOS5_64_V3 {
-
-            /* check 'Operation Mode Byte Setting', must be set to  */
-            let op_mode_byte = match get_op_mode_byte(card) {
-                Ok(op_mb) => op_mb,
-                Err(_err) =>  0x7FFF_FFFFu32,
-            };
-
-            if op_mode_byte != 2 {
-                let fmt = cstru!(b"ACOS5-64 v3.00 'Operation mode==Non-FIPS (64K)'-check failed. Trying to change the mode of operation to Non-FIPS/64K mode (no other mode is supported currently)....\0");
-                log3if!(ctx,f,line!(), fmt);
-                // FIXME try to change the operation mode byte if there is no MF
-                let mf_path_ref: &sc_path = unsafe { & *sc_get_mf_path() };
-                let mut file = null_mut();
-                let guard_file = GuardFile::new(&mut file);
-                let mut rv = unsafe { sc_select_file(card, mf_path_ref, *guard_file) };
-                println!("rv from sc_select_file: {}, file: {:?}", rv, file); // rv from sc_select_file: -1200, file: 0x0
-                let fmt = cstru!(b"Card doesn't match: sc_transmit_apdu or 'change to operation mode 64K' failed ! Have a look into docs how to change the mode of operation to Non-FIPS/64K mode. No other mode is supported currently\0");
-                if rv == SC_SUCCESS {
-                    log3if!(ctx,f,line!(), fmt);
-                    return 0;
-                }
-                // if sc_select_file failed, try to write value 2 to address 0xC191
-                let command = [0, 0xD6, 0xC1, 0x91, 0x01, 0x02];
-                let mut apdu = sc_apdu::default();
-                rv = sc_bytes2apdu_wrapper(ctx, &command, &mut apdu);
-                assert_eq!(rv, SC_SUCCESS);
-                assert_eq!(apdu.cse, SC_APDU_CASE_3_SHORT);
-                rv = unsafe { sc_transmit_apdu(card, &mut apdu) };
-                if rv != SC_SUCCESS || apdu.sw1 != 0x90 || apdu.sw2 != 0x00 {
-                    log3if!(ctx,f,line!(), fmt);
-                    return 0;
-                }
-                else {
-                    let fmt = cstru!(b"Card was set to Operation Mode 64K (SUCCESS) !\0");
-                    log3if!(ctx,f,line!(), fmt);
-                }
-            }
+    /* check the 'Operation Mode Byte Setting': Every mode, that card_ctl SC_CARDCTL_ACOS5_OP_MODE_BYTE may set, is
+       supported (acos5_init registers the RSA key sizes accordingly), others are not. V2.00 has no such command */
+    if type_out != SC_CARD_TYPE_ACOS5_64_V2 {
+        match get_op_mode_byte_of_type(card, type_out) {
+            Ok(val) => if !is_op_mode_byte_supported(type_out, val) {
+                log3if!(ctx,f,line!(), cstru!(b"Card doesn't match: The 'Operation Mode Byte Setting' %u isn't supported\0"), u32::from(val));
+                return 0;
+            },
+            Err(_e) => { log3if!(ctx,f,line!(), cstru!(b"Card doesn't match: get_op_mode_byte failed!\0")); return 0 },
         }
-    / **/
+    }
 
     // Only now, on success, set card.type
     card.type_ = type_out;
@@ -615,6 +606,7 @@ what can we rely on, when this get's called:
 #[allow(clippy::too_many_lines)]
 extern "C" fn acos5_init(card_ptr: *mut sc_card) -> i32
 {
+    let _op = op_scope("acos5_init");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -623,6 +615,7 @@ extern "C" fn acos5_init(card_ptr: *mut sc_card) -> i32
     let f = cstru!(b"acos5_init\0");
     log3if!(ctx,f,line!(), cstru!(b"called with card.type: %d, card.atr.value: %s\0"), card.type_,
         unsafe {sc_dump_hex(card.atr.value.as_ptr(), card.atr.len) });
+    transcript_start_from_conf(card);
     let mut app_name = f;
     if !ctx.app_name.is_null() {
         app_name = unsafe { CStr::from_ptr(ctx.app_name) }; // app_name: e.g. "pkcs15-init"
@@ -679,23 +672,16 @@ extern "C" fn acos5_init(card_ptr: *mut sc_card) -> i32
         SC_CARD_TYPE_ACOS5_EVO_V4 => get_op_mode_byte(card).unwrap()==0,
         _ => false,
     };
-    let mut rv;
-    let     rsa_key_len_from : u32 = if is_fips_mode { 2048 } else {  512 };
-    let     rsa_key_len_step : u32 = if is_fips_mode { 1024 } else {  256 };
-    let     rsa_key_len_to   : u32 = if is_fips_mode && card.type_==SC_CARD_TYPE_ACOS5_64_V3 { 3072 } else { 4096 };
-    let mut rsa_key_len = rsa_key_len_from;
-    /* TODO currently there is no support for public exponents differing from 0x10001 */
-    while   rsa_key_len <= rsa_key_len_to {
-        rv = unsafe { _sc_card_add_rsa_alg(card, rsa_key_len, c_ulong::from(rsa_algo_flags), 0/*0x10001*/) };
-        if rv != SC_SUCCESS {
-            return rv;
-        }
-        rsa_key_len += rsa_key_len_step;
+    /* card_ctl SC_CARDCTL_ACOS5_OP_MODE_BYTE calls this again, if the Operation Mode Byte got changed */
+    let mut rv = rsa_algorithms_register(card, rsa_algo_flags, is_fips_mode);
+    if rv != SC_SUCCESS {
+        return rv;
     }
 // ECC
     if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {
-        let flags = SC_ALGORITHM_ONBOARD_KEY_GEN | SC_ALGORITHM_ECDSA_RAW; /*| SC_ALGORITHM_ECDH_CDH_RAW |
-                           SC_ALGORITHM_ECDSA_HASH_NONE | SC_ALGORITHM_ECDSA_HASH_SHA1*/
+        /* no SC_ALGORITHM_ECDSA_HASH_SHA1/SHA256: OpenSC hashes in software and acos5_compute_signature gets the hash,
+           as the card's hashing isn't verified for EVO, see sign_ecdsa */
+        let flags = SC_ALGORITHM_ONBOARD_KEY_GEN | SC_ALGORITHM_ECDSA_RAW | SC_ALGORITHM_ECDSA_HASH_NONE; /*| SC_ALGORITHM_ECDH_CDH_RAW*/
         let ext_flags = SC_ALGORITHM_EXT_EC_NAMEDCURVE; /*| SC_ALGORITHM_EXT_EC_UNCOMPRESES*/
         for elem in &mut acos5_supported_ec_curves() {
             unsafe { _sc_card_add_ec_alg(card, elem.size, c_ulong::from(flags), c_ulong::from(ext_flags), &mut elem.curve_oid) };
@@ -755,6 +741,8 @@ cfg_if::cfg_if! {
         agi: CardCtl_generate_inject_asym::default(),
         time_stamp: std::time::Instant::now(),
         sm_cmd: 0,
+        sm_policy: sm_policy_from_conf(ctx),
+        sm_counters: CardCtlSmCounters::default(),
         rsa_caps: rsa_algo_flags,
         sec_env_mod_len: 0,
         rfu_align_pad1: 0,
@@ -770,6 +758,8 @@ cfg_if::cfg_if! {
         sym_key_rec_idx: 0,
         sym_key_rec_cnt: 0,
         last_keygen_priv_id: sc_pkcs15_id::default(),
+        files_created: Vec::new(),
+        pin_attributes: Vec::new(),
         #[cfg(iup_user_consent)]
         ui_ctx: ui_context::default(),
     } );
@@ -867,7 +857,7 @@ DataPrivate:                                                size_of: 1792, align
         Box::leak(dp);
         // card.drv_data = Box::into_raw(dp) as p_void;
         if !does_mf_exist {
-            let _rv = sanity_check(card, app_name);
+            let _rv = sanity_check(card, app_name, false);
             return SC_ERROR_INVALID_CARD;
         }
     }
@@ -933,6 +923,7 @@ cfg_if::cfg_if! {
  */
 extern "C" fn acos5_finish(card_ptr: *mut sc_card) -> i32
 {
+    let _op = op_scope("acos5_finish");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1108,6 +1099,7 @@ println!("sc_update_binary: rv: {}", rv);
 
     drop(dp);
     card.drv_data = null_mut();
+    transcript_stop(card);
     SC_SUCCESS
 }
 
@@ -1128,6 +1120,7 @@ called only from sc_erase_binary, but that is used nowhere in OpenSC, except in
 */
 extern "C" fn acos5_erase_binary(card_ptr: *mut sc_card, idx: u32, count: usize, flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_erase_binary");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1216,6 +1209,7 @@ extern "C" fn acos5_erase_binary(card_ptr: *mut sc_card, idx: u32, count: usize,
 #[allow(clippy::too_many_lines)]
 extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr: p_void) -> i32
 {
+    let _op = op_scope("acos5_card_ctl");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1223,20 +1217,42 @@ extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr:
     let ctx = unsafe { &mut *card.ctx };
     log3ifc!(ctx, cstru!(b"acos5_card_ctl\0"), line!());
 
-    if data_ptr.is_null() && ![SC_CARDCTL_LIFECYCLE_SET, SC_CARDCTL_ACOS5_HASHMAP_SET_FILE_INFO, SC_CARDCTL_ACOS5_SANITY_CHECK].contains(&command)
+    if data_ptr.is_null() && ![SC_CARDCTL_ERASE_CARD, SC_CARDCTL_ACOS5_HASHMAP_SET_FILE_INFO, SC_CARDCTL_ACOS5_SANITY_CHECK].contains(&command)
     { return SC_ERROR_INVALID_ARGUMENTS; }
 
     match command {
 //        SC_CARDCTL_GENERIC_BASE |
-//        SC_CARDCTL_ERASE_CARD |
 //        SC_CARDCTL_GET_DEFAULT_KEY |
-//        SC_CARDCTL_LIFECYCLE_GET |
-        SC_CARDCTL_LIFECYCLE_SET =>
 //        SC_CARDCTL_GET_SE_INFO |
 //        SC_CARDCTL_GET_CHV_REFERENCE_IN_SE |
 //        SC_CARDCTL_PKCS11_INIT_TOKEN |
 //        SC_CARDCTL_PKCS11_INIT_PIN |
-            SC_ERROR_NOT_SUPPORTED, // see sc_pkcs15init_bind
+        SC_CARDCTL_ERASE_CARD =>
+            {
+                let mut erase = CardCtlEraseCard::default();
+                let rm_erase = if data_ptr.is_null() { &mut erase } else { unsafe { &mut *data_ptr.cast::<CardCtlEraseCard>() } };
+                erase_card_card_ctl(card, rm_erase)
+            },
+        SC_CARDCTL_LIFECYCLE_GET =>
+            {
+                let rm_lifecycle = unsafe { &mut *data_ptr.cast::<i32>() };
+                *rm_lifecycle = match lifecycle_get(card) {
+                    Ok(val) => val.lifecycle,
+                    Err(e) => return e,
+                };
+                SC_SUCCESS
+            },
+        SC_CARDCTL_ACOS5_LIFECYCLE_REPORT =>
+            {
+                let rm_lifecycle = unsafe { &mut *data_ptr.cast::<CardCtlLifecycle>() };
+                *rm_lifecycle = match lifecycle_get(card) {
+                    Ok(val) => val,
+                    Err(e) => return e,
+                };
+                SC_SUCCESS
+            },
+        SC_CARDCTL_LIFECYCLE_SET => // see sc_pkcs15init_bind
+            lifecycle_set(card, unsafe { *data_ptr.cast::<i32>() }),
         SC_CARDCTL_GET_SERIALNR =>
             {
                 let rm_serialnr = unsafe { &mut *data_ptr.cast::<sc_serial_number>() };
@@ -1362,6 +1378,50 @@ extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr:
                 };
                 SC_SUCCESS
             },
+        SC_CARDCTL_ACOS5_GET_SM_COUNTERS =>
+            {
+                let rm_sm_counters = unsafe { &mut *data_ptr.cast::<CardCtlSmCounters>() };
+                let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+                *rm_sm_counters = dp.sm_counters;
+                Box::leak(dp);
+                SC_SUCCESS
+            },
+        SC_CARDCTL_ACOS5_GET_AC_REPORT =>
+            {
+                let rm_ac_report = unsafe { &mut *data_ptr.cast::<CardCtlAcReport>() };
+                *rm_ac_report = match se_get_ac_report(card, rm_ac_report.file_id) {
+                    Ok(val) => val,
+                    Err(e) => return e,
+                };
+                SC_SUCCESS
+            },
+        SC_CARDCTL_ACOS5_SE_RECORD_GET =>
+            {
+                let rm_se_record = unsafe { &mut *data_ptr.cast::<CardCtlSeRecord>() };
+                rm_se_record.sac_info = match se_record_get(card, rm_se_record.file_id_dir, rm_se_record.sac_info.reference) {
+                    Ok(val) => val,
+                    Err(e) => return e,
+                };
+                SC_SUCCESS
+            },
+        SC_CARDCTL_ACOS5_SE_RECORD_ADD |
+        SC_CARDCTL_ACOS5_SE_RECORD_REPLACE =>
+            {
+                let rm_se_record = unsafe { &mut *data_ptr.cast::<CardCtlSeRecord>() };
+                match se_record_put(card, rm_se_record.file_id_dir, &mut rm_se_record.sac_info,
+                                    command == SC_CARDCTL_ACOS5_SE_RECORD_REPLACE) {
+                    Ok(()) => SC_SUCCESS,
+                    Err(e) => e,
+                }
+            },
+        SC_CARDCTL_ACOS5_SE_RECORD_REMOVE =>
+            {
+                let rm_se_record = unsafe { &mut *data_ptr.cast::<CardCtlSeRecord>() };
+                match se_record_remove(card, rm_se_record.file_id_dir, rm_se_record.sac_info.reference) {
+                    Ok(()) => SC_SUCCESS,
+                    Err(e) => e,
+                }
+            },
         SC_CARDCTL_ACOS5_HASHMAP_GET_FILE_INFO =>
             {
                 let rm_files_hashmap_info = unsafe { &mut *data_ptr.cast::<CardCtlArray32>() };
@@ -1386,8 +1446,7 @@ extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr:
                     analyze_PKCS15_DIRRecord_2F00(card, &mut aid);
                     //println!("AID: {:X?}", &aid.value[..aid.len]);
                     analyze_PKCS15_PKCS15Objects_5031(card);
-                    // the only missing check is that EF.Tokeninfo is PKCS#15 compliant
-                    // analyze_PKCS15_TokenInfo_5032(card);
+                    // EF.TokenInfo isn't marked here; it gets validated by sanity_check, see validate_PKCS15
                 }
                 /* */
                 SC_SUCCESS
@@ -1433,13 +1492,21 @@ extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr:
         // #[cfg(sanity)]
         SC_CARDCTL_ACOS5_SANITY_CHECK =>
             {
+                /* with data, the caller takes the findings: don't print them */
                 match sanity_check(card, unsafe {
                     if !ctx.app_name.is_null() { CStr::from_ptr(ctx.app_name) }
-                    else { CStr::from_bytes_with_nul_unchecked(b"\0") } } )  {
-                    Ok(()) => SC_SUCCESS,
+                    else { CStr::from_bytes_with_nul_unchecked(b"\0") } }, !data_ptr.is_null())  {
+                    Ok(findings) => if data_ptr.is_null() { SC_SUCCESS }
+                                    else { sanity_check_card_ctl(&findings, unsafe { &mut *data_ptr.cast::<CardCtlSanityCheck>() }) },
                     Err(e) => e,
                 }
             },
+        SC_CARDCTL_ACOS5_SANITY_REPAIR =>
+            sanity_repair_card_ctl(card, unsafe { &mut *data_ptr.cast::<CardCtlSanityRepair>() }),
+        SC_CARDCTL_ACOS5_CARD_INIT =>
+            card_init_card_ctl(card, unsafe { & *data_ptr.cast::<CardCtlCardInit>() }),
+        SC_CARDCTL_ACOS5_OP_MODE_BYTE =>
+            op_mode_byte_card_ctl(card, unsafe { &mut *data_ptr.cast::<CardCtlOpModeByte>() }),
         _   => SC_ERROR_NO_CARD_SUPPORT
     } // match command
 } // acos5_card_ctl
@@ -1452,6 +1519,7 @@ extern "C" fn acos5_card_ctl(card_ptr: *mut sc_card, command: c_ulong, data_ptr:
  */
 extern "C" fn acos5_select_file(card_ptr: *mut sc_card, path_ptr: *const sc_path, file_out_ptr: *mut *mut sc_file) -> i32
 {
+    let _op = op_scope("acos5_select_file");
     if card_ptr.is_null() || path_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1465,7 +1533,10 @@ extern "C" fn acos5_select_file(card_ptr: *mut sc_card, path_ptr: *const sc_path
     let target_file_id = file_id_from_path_value(&path_ref.value[..path_ref.len]); // wrong result for SC_PATH_TYPE_DF_NAME, but doesn't matter
 
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
-    dp.sm_cmd = 0;
+    let is_sm_reuse = dp.sm_policy.mode == SM_POLICY_REUSE;
+    if !is_sm_reuse {
+        dp.sm_cmd = 0;
+    }
     let does_mf_exist = dp.does_mf_exist;
     let does_file_exist = dp.files.contains_key(&target_file_id);
     let force_process_fci = !dp.is_running_init && file_out_ptr.is_null() &&
@@ -1474,14 +1545,22 @@ extern "C" fn acos5_select_file(card_ptr: *mut sc_card, path_ptr: *const sc_path
     if !does_mf_exist { return SC_ERROR_NOT_ALLOWED; }
     // if !does_file_exist { return SC_ERROR_FILE_NOT_FOUND; }
     let file_opt = unsafe { file_out_ptr.as_mut() };
+    let df_before = if is_sm_reuse { current_path_df(card).to_vec() } else { Vec::new() };
 
-    match path_ref.type_ {
+    let rv = match path_ref.type_ {
         SC_PATH_TYPE_PATH     => select_file_by_path(card, path_ref, file_opt, force_process_fci),
         SC_PATH_TYPE_DF_NAME |
         SC_PATH_TYPE_FILE_ID  => tracking_select_file(card, path_ref, file_opt, force_process_fci),
         /*SC_PATH_TYPE_PATH_PROT | SC_PATH_TYPE_FROM_CURRENT | SC_PATH_TYPE_PARENT  => SC_ERROR_NO_CARD_SUPPORT,*/
         _  => SC_ERROR_NO_CARD_SUPPORT,
+    };
+    /* leaving a DF resets the card's security state of that DF, thus the SM session can't be reused any more */
+    if is_sm_reuse && current_path_df(card) != df_before.as_slice() {
+        let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+        dp.sm_cmd = 0;
+        card.drv_data = Box::into_raw(dp).cast::<c_void>();
     }
+    rv
 }
 
 /*
@@ -1507,6 +1586,7 @@ SW1 SW2   Definition
 #[allow(clippy::suspicious_else_formatting)]
 extern "C" fn acos5_get_response(card_ptr: *mut sc_card, count_ptr: *mut usize, buf_ptr: *mut u8) -> i32
 {
+    let _op = op_scope("acos5_get_response");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || count_ptr.is_null() || buf_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1595,6 +1675,7 @@ println!("### acos5_get_response returned apdu.sw1: {:X}, apdu.sw2: {:X}   Unkno
  */
 extern "C" fn acos5_get_challenge(card_ptr: *mut sc_card, buf_ptr: *mut u8, count: usize) -> i32
 {
+    let _op = op_scope("acos5_get_challenge");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || buf_ptr.is_null() || count > 1024 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1632,6 +1713,7 @@ extern "C" fn acos5_get_challenge(card_ptr: *mut sc_card, buf_ptr: *mut u8, coun
 /* currently refers to pins only, but what about authenticated keys */
 extern "C" fn acos5_logout(card_ptr: *mut sc_card) -> i32
 {
+    let _op = op_scope("acos5_logout");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1670,10 +1752,17 @@ extern "C" fn acos5_logout(card_ptr: *mut sc_card) -> i32
 }
 
 
-/* TODO this isn't yet completed: 1. the hashmap-entry/path+fileinfo must be set and 2. there is more to do for MF/DF */
-/* expects some entries in file, see acos5_construct_fci */
+/* The SE file id that acos5_construct_fci declares for a new MF/DF: The MF's global SE file is 0003,
+   a DF's local one has the DF's file id + 3 (e.g. 4103 for 4100) */
+fn se_file_id_of_new_df(file: &sc_file) -> u16 {
+    if file.id == 0x3F00 {3} else {u16::try_from(file.id+3).unwrap()}
+}
+
+/* expects some entries in file, see acos5_construct_fci. The MF can be created only on a virgin card, i.e. after
+   pkcs15-init --erase-card within the same session (acos5_init rejects a card without MF) */
 extern "C" fn acos5_create_file(card_ptr: *mut sc_card, file_ptr: *mut sc_file) -> i32
 {
+    let _op = op_scope("acos5_create_file");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || file_ptr.is_null() || unsafe {(*file_ptr).id==0} {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1685,7 +1774,9 @@ extern "C" fn acos5_create_file(card_ptr: *mut sc_card, file_ptr: *mut sc_file)
     log3ifc!(ctx,f,line!());
 
     let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
-    if dp.files.contains_key(&(u16::try_from(file.id).unwrap())) {
+    /* on a virgin card (erased within this session), the MF's entry is a placeholder */
+    let is_mf_creation = file.id == 0x3F00 && !dp.does_mf_exist;
+    if dp.files.contains_key(&(u16::try_from(file.id).unwrap())) && !is_mf_creation {
         Box::leak(dp);
         // card.drv_data = Box::into_raw(dp) as p_void;
         rv = SC_ERROR_NOT_ALLOWED;
@@ -1704,9 +1795,41 @@ extern "C" fn acos5_create_file(card_ptr: *mut sc_card, file_ptr: *mut sc_file)
         file.path = path;
     }
 
-    /* iso7816_create_file calls acos5_construct_fci */
-    let func_ptr = unsafe { (*(*sc_get_iso7816_driver()).ops).create_file.unwrap() };
-    rv = unsafe { func_ptr(card, file_ptr) };
+    /* the SCB for CREATE_EF/CREATE_DF of the DF, where the file will be created: If it requires SM, the FCP can't
+       be sent by iso7816_create_file */
+    let df_id = file_id_from_path_value(current_path_df(card));
+    /* file.type_ is OpenSC's SC_FILE_TYPE_DF, or the FDB as the acos5 profile declares it (see acos5_construct_fci) */
+    let is_df = file.type_ == SC_FILE_TYPE_DF || is_DFMF(u8::try_from(file.type_).unwrap_or(0));
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let scb_create = match dp.files.get(&df_id) {
+        Some(x) if x.2.is_some() => x.2.unwrap()[if is_df {CREATE_DF} else {CREATE_EF}],
+        _ => 0,
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+
+    if (scb_create & 0x40) == 0x40 && scb_create != 0xFF {
+        let res_se_sm = se_get_is_scb_suitable_for_sm_has_ct(card, df_id, scb_create & 0x0F);
+        if !res_se_sm.0 {
+            rv = SC_ERROR_SECURITY_STATUS_NOT_SATISFIED;
+            log3ifr!(ctx,f,line!(),cstru!(b"No create_file will be done: The DF has acl SM-protected CREATE\0"), rv);
+            return rv;
+        }
+        let mut fcp = [0_u8; SC_MAX_APDU_BUFFER_SIZE];
+        let mut fcp_len = fcp.len();
+        let rv_fci = acos5_construct_fci(card, file, fcp.as_mut_ptr(), &mut fcp_len);
+        if rv_fci != SC_SUCCESS {
+            log3ifr!(ctx,f,line!(), cstru!(b"Error: acos5_construct_fci failed with\0"), rv_fci);
+            return rv_fci;
+        }
+        card.sm_ctx.info.cmd = SM_CMD_FILE_CREATE;
+        rv = sm_create_file(card, &fcp[..fcp_len], res_se_sm.1);
+    }
+    else {
+        /* iso7816_create_file calls acos5_construct_fci */
+        let func_ptr = unsafe { (*(*sc_get_iso7816_driver()).ops).create_file.unwrap() };
+        rv = unsafe { func_ptr(card, file_ptr) };
+    }
 
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), cstru!(b"Error: failed with\0"), rv);
@@ -1730,9 +1853,15 @@ extern "C" fn acos5_create_file(card_ptr: *mut sc_card, file_ptr: *mut sc_file)
             x.1[4..6].copy_from_slice(&u16::try_from(file_ref.size).unwrap().to_be_bytes());
         }
         else { // MF/DF
-            x.1[4..6].copy_from_slice(&u16::try_from(file_ref.id+3).unwrap().to_be_bytes());
+            x.1[4..6].copy_from_slice(&se_file_id_of_new_df(file_ref).to_be_bytes());
         }
+        if is_mf_creation {
+            dp.does_mf_exist = true;
+        }
+        dp.files_created.push(u16::try_from(file_ref.id).unwrap());
         card.drv_data = Box::into_raw(dp).cast::<c_void>();
+        /* the new file becomes the current one */
+        card.cache.current_path = file_ref.path;
 
         log3if!(ctx,f,line!(), cstru!(b"file_id %04X added to hashmap\0"), file_ref.id);
     }
@@ -1746,6 +1875,7 @@ iso7816_delete_file: condition: (path->type == SC_PATH_TYPE_FILE_ID && (path->le
 /* even with a given path with len==2, acos expects a select_file ! */
 extern "C" fn acos5_delete_file(card_ptr: *mut sc_card, path_ref_ptr: *const sc_path) -> i32
 {
+    let _op = op_scope("acos5_delete_file");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || path_ref_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1802,7 +1932,7 @@ println!("file_id: {:X} is not a key of hashmap dp.files", file_id);
         }
         else {
             card.sm_ctx.info.cmd = SM_CMD_FILE_DELETE;
-            rv = sm_delete_file(card);
+            rv = sm_delete_file(card, res_se_sm.1);
         }
     }
     else {
@@ -1850,6 +1980,7 @@ x files will not be listed by this function: TODO add a warning message
  */
 extern "C" fn acos5_list_files(card_ptr: *mut sc_card, buf_ptr: *mut u8, buflen: usize) -> i32
 {
+    let _op = op_scope("acos5_list_files");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || buf_ptr.is_null() || buflen<2 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -1951,8 +2082,15 @@ extern "C" fn acos5_process_fci(card_ptr: *mut sc_card, file_ptr: *mut sc_file,
         file.size          = fci.size.into();
     }
 
-    /* Map from scb8 to file.acl array */
+    /* Map from scb8 to file.acl array. In creation state, the card doesn't enforce access conditions: file.status tells
+       about that, such that pkcs15-init may skip authentication (see acos5_pkcs15's authenticate) */
     map_scb8_to_acl(card, file, fci.scb8, fci.fdb);
+    file.status = match fci.lcsi {
+        LCSI_CREATION => SC_FILE_STATUS_CREATION,
+        LCSI_DEACTIVATED | 0x06 => SC_FILE_STATUS_INVALIDATED,
+        LCSI_TERMINATED..=0x0F => SC_FILE_STATUS_TERMINATION,
+        _ => SC_FILE_STATUS_ACTIVATED,
+    };
 
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
     assert!(dp.files.contains_key(&fci.fid));
@@ -2155,7 +2293,7 @@ println!("Failure: Non-match in let acl_category. file_ref.type_: {}", file_ref.
     }
 
     /* 3 bytes will be written for tag ISO7816_TAG_FCP_LCS (0x8A) */
-    buf2[0] = 1; // skip cos5 command "Activate File" and create as activated
+    buf2[0] = 1; // creation state; activated by SC_CARDCTL_LIFECYCLE_SET (pkcs15-init finalize_card), see lifecycle.rs
     unsafe { sc_asn1_put_tag(u32::from(ISO7816_TAG_FCP_LCS), buf2.as_ptr(), 1, p, *outlen-ptr_diff_sum, &mut p) };
     ptr_diff_sum += 3;
 
@@ -2174,7 +2312,7 @@ println!("Failure: Non-match in let acl_category. file_ref.type_: {}", file_ref.
 
     if is_DFMF(fdb) {
         /* 4 bytes will be written for tag ISO7816_RFU_TAG_FCP_SEID (0x8D) */
-        buf2.copy_from_slice(&u16::try_from(file_ref.id+3).unwrap().to_be_bytes());
+        buf2.copy_from_slice(&se_file_id_of_new_df(file_ref).to_be_bytes());
         unsafe { sc_asn1_put_tag(u32::from(ISO7816_RFU_TAG_FCP_SEID), buf2.as_ptr(), 2, p, *outlen-ptr_diff_sum, &mut p) };
         ptr_diff_sum += 4;
 
@@ -2202,6 +2340,7 @@ println!("Failure: Non-match in let acl_category. file_ref.type_: {}", file_ref.
 #[allow(clippy::too_many_lines)]
 extern "C" fn acos5_pin_cmd(card_ptr: *mut sc_card, data_ptr: *mut sc_pin_cmd_data, tries_left_ptr: *mut i32) -> i32
 {
+    let _op = op_scope("acos5_pin_cmd");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || data_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -2390,7 +2529,8 @@ println!();
         if pin_cmd_data.pin1.len <= 0 || pin_cmd_data.pin1.data.is_null() ||
            pin_cmd_data.pin2.len <= 0 || pin_cmd_data.pin2.data.is_null() ||
            pin_cmd_data.pin1.len != pin_cmd_data.pin2.len ||
-           pin_cmd_data.pin1.len  > 8 {
+           usize::try_from(pin_cmd_data.pin1.len).unwrap() >
+               if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_LEN_MAX_EVO} else {PIN_LEN_MAX} {
             return SC_ERROR_INVALID_ARGUMENTS;
         }
 
@@ -2432,7 +2572,8 @@ println!();
         if pin_cmd_data.pin1.len <= 0 || pin_cmd_data.pin1.data.is_null() ||
             pin_cmd_data.pin2.len <= 0 || pin_cmd_data.pin2.data.is_null() ||
             pin_cmd_data.pin1.len != pin_cmd_data.pin2.len ||
-            pin_cmd_data.pin1.len  > 8 {
+            usize::try_from(pin_cmd_data.pin1.len).unwrap() >
+                if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_LEN_MAX_EVO} else {PIN_LEN_MAX} {
             return SC_ERROR_INVALID_ARGUMENTS;
         }
 
@@ -2442,11 +2583,11 @@ println!();
         else {
             let file_id = file_id_from_cache_current_path(card);
 //println!("file_id: {:X}", file_id);
-            let scb_unblock_pin = se_get_sae_scb(card, [0_u8,0x24,0,u8::try_from(pin_cmd_data.pin_reference).unwrap()]);
+            let scb_unblock_pin = se_get_sae_scb(card, [0_u8,0x2C,0,u8::try_from(pin_cmd_data.pin_reference).unwrap()]);
 //println!("scb_unblock_pin: {:X}", scb_unblock_pin);
 
             if scb_unblock_pin == 0xFF {
-                log3if!(ctx,f,line!(), cstru!(b"SC_PIN_CMD_CHANGE won't be done: It's not allowed by SAE\0"));
+                log3if!(ctx,f,line!(), cstru!(b"SC_PIN_CMD_UNBLOCK won't be done: It's not allowed by SAE\0"));
                 SC_ERROR_SECURITY_STATUS_NOT_SATISFIED
             }
             else if (scb_unblock_pin & 0x40) == 0x40  &&  SC_AC_CHV == pin_cmd_data.pin_type {
@@ -2454,7 +2595,7 @@ println!();
 //println!("res_se_sm: {:?}", res_se_sm);
                 // TODO think about whether SM mode Confidentiality should be enforced
                 if !res_se_sm.0 {
-                    log3if!(ctx,f,line!(), cstru!(b"SC_PIN_CMD_CHANGE won't be done: It's SM protected, but the CRT \
+                    log3if!(ctx,f,line!(), cstru!(b"SC_PIN_CMD_UNBLOCK won't be done: It's SM protected, but the CRT \
                         template(s) don't accomplish requirements\0"));
                     SC_ERROR_SECURITY_STATUS_NOT_SATISFIED
                 }
@@ -2497,10 +2638,11 @@ extern "C" fn acos5_read_public_key(card_ptr: *mut sc_card,
                                     algorithm: u32,
                                     key_path_ptr: *mut sc_path,
                                     key_reference: u32, /* unused */
-                                    modulus_length: u32, /* bits, max. 4096 */
+                                    modulus_length: u32, /* bits, max. 4096; for EC the field length or 0 */
                                     out: *mut *mut u8,
                                     out_len: *mut usize) -> i32
-{  // TODO also for EVO
+{
+    let _op = op_scope("acos5_read_public_key");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || key_path_ptr.is_null() || out.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS; // TODO possibly check for out_len to small
     }
@@ -2509,6 +2651,9 @@ extern "C" fn acos5_read_public_key(card_ptr: *mut sc_card,
     let f = cstru!(b"acos5_read_public_key\0");
     log3ifc!(ctx,f,line!());
 
+    if algorithm == SC_ALGORITHM_EC && card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {
+        return read_public_key_ec(card, key_path_ptr, modulus_length, out, out_len);
+    }
     if algorithm != SC_ALGORITHM_RSA {
         let rv = SC_ERROR_NO_CARD_SUPPORT;
         log3ifr!(ctx,f,line!(), rv);
@@ -2567,10 +2712,57 @@ extern "C" fn acos5_read_public_key(card_ptr: *mut sc_card,
     SC_SUCCESS
 }
 
+/* the public key file of an ECC key pair holds the point's coordinates, see ECC_KEY_FILE_HEADER_LEN; the curve is
+   known from the header, thus field_length (if not 0) serves as a cross-check only */
+fn read_public_key_ec(card: &mut sc_card, key_path_ptr: *mut sc_path, field_length: u32, out: *mut *mut u8,
+                      out_len: *mut usize) -> i32
+{
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"read_public_key_ec\0");
+    let mut file = null_mut();
+    let guard_file = GuardFile::new(&mut file);
+    let mut rv = unsafe { sc_select_file(card, key_path_ptr, *guard_file) };
+    if rv != SC_SUCCESS {
+        log3if!(ctx,f,line!(), cstru!(b"failed to select public key file\0"));
+        return rv;
+    }
+    let mut rbuf = vec![0; unsafe { (*file).size }];
+    rv = unsafe { sc_read_binary(card, 0, rbuf.as_mut_ptr(), rbuf.len(), 0) };
+    if rv < 0 {
+        log3if!(ctx,f,line!(), cstru!(b"get key failed\0"));
+        return rv;
+    }
+    rbuf.truncate(usize::try_from(rv).unwrap());
+    let (field_length_file, mut point) = match ecc_pub_key_file_point(&rbuf) {
+        Ok(val) => val,
+        Err(e) => {
+            log3ifr!(ctx,f,line!(), cstru!(b"### failed: the raw content of ECC pub file doesn't hold a valid public key ###\0"), e);
+            return e;
+        },
+    };
+    if field_length != 0 && usize::try_from(field_length).unwrap() != field_length_file {
+        log3if!(ctx,f,line!(), cstru!(b"### failed: the ECC pub file holds a key of field length %zu ###\0"), field_length_file);
+        return SC_ERROR_INCOMPATIBLE_KEY;
+    }
+    let mut ec_key = sc_pkcs15_pubkey_ec {
+        params: sc_ec_parameters { named_curve: null_mut(), id: sc_object_id { value: [-1; 16] },
+            der: sc_lv_data { value: null_mut(), len: 0 }, type_: 1, field_length: field_length_file },
+        ecpointQ: sc_pkcs15_u8 { value: point.as_mut_ptr(), len: point.len() }
+    };
+    /* transform the raw content to der-encoded (OCTET STRING ecpointQ) */
+    rv = unsafe { sc_pkcs15_encode_pubkey_ec(ctx, &mut ec_key, out, out_len) };
+    if rv < 0 {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: sc_pkcs15_encode_pubkey_ec failed: returning with\0"), rv);
+        return rv;
+    }
+    SC_SUCCESS
+}
+
 
 #[allow(clippy::too_many_lines)]
 extern "C" fn acos5_set_security_env(card_ptr: *mut sc_card, env_ref_ptr: *const sc_security_env, _se_num: i32) -> i32
 {
+    let _op = op_scope("acos5_set_security_env");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || env_ref_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -2648,7 +2840,11 @@ Tokenunfo
             log3ifr!(ctx,f,line!(), rv);
             return rv;
         }
-        /* sign may need decrypt (for non-SHA1/SHA256 hashes), thus prepare for a CT as well */
+        /* sign may need decrypt (for non-SHA1/SHA256 hashes), thus prepare for a CT as well; not so for ECDSA */
+        if env_ref.algorithm == SC_ALGORITHM_EC {
+            log3ifr!(ctx,f,line!(), rv);
+            return rv;
+        }
         algo = algo_ref_mse_sedo(card.type_, 0, CRT_TAG_CT,
             env_ref.algorithm, 0, false, false).unwrap();
         command = [0x00, 0x22, 0x01, CRT_TAG_CT, 0x0A, 0x80, 0x01, algo, 0x81, 0x02,
@@ -2911,6 +3107,7 @@ pub const SC_SEC_ENV_PARAM_DES_CBC           : u32 = 4;
 extern "C" fn acos5_decipher(card_ptr: *mut sc_card, crgram_ref_ptr: *const u8, crgram_len: usize,
                                                        out_ptr:        *mut u8,     outlen: usize) -> i32
 {
+    let _op = op_scope("acos5_decipher");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || crgram_ref_ptr.is_null() || out_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3033,6 +3230,8 @@ extern "C" fn acos5_decipher(card_ptr: *mut sc_card, crgram_ref_ptr: *const u8,
  It's not safe to use outlen as indicator for  keylen_bytes, e.g.: pkcs15-crypt --sign --key=5 --input=test_in_sha1.hex --output=test_out_sig_pkcs1.hex --sha-1 --pkcs1 --pin=12345678
  uses outlen==1024
 
+ ECDSA (ACOS5-EVO only) is delegated to sign_ecdsa, if the security environment was set for SC_ALGORITHM_EC
+
  * @apiNote
  * @param
  * @return  error code (neg. value) or number of bytes written into out
@@ -3042,26 +3241,43 @@ extern "C" fn acos5_decipher(card_ptr: *mut sc_card, crgram_ref_ptr: *const u8,
 extern "C" fn acos5_compute_signature(card_ptr: *mut sc_card, data_ref_ptr: *const u8, data_len: usize,
                                                                    out_ptr:   *mut u8,   outlen: usize) -> i32
 {
+    let _op = op_scope("acos5_compute_signature");
     if data_len == 0 || outlen == 0 {
         return 0;
     }
-    if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || data_ref_ptr.is_null() || out_ptr.is_null() ||
-        outlen < 64 { // cos5 supports RSA beginning from moduli 512 bits = 64 bytes
+    if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || data_ref_ptr.is_null() || out_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
-    assert!(data_len <= outlen);
-    assert!(data_len <= 512); // cos5 supports max RSA 4096 bit keys
-//println!("acos5_compute_signature called with: in_len: {}, out_len: {}", data_len, outlen);
     let card       = unsafe { &mut *card_ptr };
     let ctx = unsafe { &mut *card.ctx };
     let f = cstru!(b"acos5_compute_signature\0");
     log3ift!(ctx,f,line!(), cstru!(b"called with: in_len: %zu, out_len: %zu\0"), data_len, outlen);
     set_is_running_compute_signature(card, false); // thus is an info valuable only when delegating to acos5_decipher
 
-//    #[cfg(    any(v0_17_0, v0_18_0))]
-//    let mut rv = SC_SUCCESS;
-//    #[cfg(not(any(v0_17_0, v0_18_0)))]
     let mut rv; // = SC_SUCCESS;
+    if get_sec_env(card).algorithm == SC_ALGORITHM_EC {
+        #[cfg(iup_user_consent)]
+        {
+            if get_ui_ctx(card).user_consent_enabled == 1 {
+                /* (Requested by DGP): on signature operation, ask user consent */
+                rv = acos5_ask_user_consent();
+                if rv < 0 {
+                    log3ifr!(ctx,f,line!(), cstru!(b"returning with: User consent denied\0"), rv);
+                    return rv;
+                }
+            }
+        }
+        rv = sign_ecdsa(card, unsafe { from_raw_parts(data_ref_ptr, data_len) }, unsafe { from_raw_parts_mut(out_ptr, outlen) });
+        log3ifr!(ctx,f,line!(), rv);
+        return rv;
+    }
+    if outlen < 64 { // cos5 supports RSA beginning from moduli 512 bits = 64 bytes
+        return SC_ERROR_INVALID_ARGUMENTS;
+    }
+    assert!(data_len <= outlen);
+    assert!(data_len <= 512); // cos5 supports max RSA 4096 bit keys
+//println!("acos5_compute_signature called with: in_len: {}, out_len: {}", data_len, outlen);
+
     //   sha1     sha256  +md2/5 +sha1  +sha224  +sha256  +sha384  +sha512
     if ![20_usize, 32,     34,    35,    47,      51,      67,      83, get_sec_env_mod_len(card)].contains(&data_len) {
         rv = SC_ERROR_NOT_SUPPORTED;
@@ -3290,6 +3506,7 @@ when pkcs1_strip_PSS_padding works
 #[cfg(not(any(v0_17_0, v0_18_0, v0_19_0)))]
 extern "C" fn acos5_unwrap(card_ptr: *mut sc_card, crgram: *const u8, crgram_len: usize) -> i32
 {
+    let _op = op_scope("acos5_unwrap");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3342,6 +3559,7 @@ extern "C" fn acos5_unwrap(card_ptr: *mut sc_card, crgram: *const u8, crgram_len
  */
 extern "C" fn acos5_delete_record(card_ptr: *mut sc_card, rec_nr: u32) -> i32
 {
+    let _op = op_scope("acos5_delete_record");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || rec_nr==0 || rec_nr>0xFF {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3357,6 +3575,7 @@ extern "C" fn acos5_delete_record(card_ptr: *mut sc_card, rec_nr: u32) -> i32
 
 extern "C" fn acos5_append_record(card_ptr: *mut sc_card, buf_ptr: *const u8, count: usize, _flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_append_record");
     if card_ptr.is_null() || buf_ptr.is_null() || count==0 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3375,6 +3594,7 @@ extern "C" fn acos5_append_record(card_ptr: *mut sc_card, buf_ptr: *const u8, co
 extern "C" fn acos5_read_binary(card_ptr: *mut sc_card, idx: u32,
                                 buf_ptr: *mut u8, count: usize, flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_read_binary");
     if card_ptr.is_null() || buf_ptr.is_null() || count==0 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3388,6 +3608,7 @@ extern "C" fn acos5_read_binary(card_ptr: *mut sc_card, idx: u32,
 extern "C" fn acos5_read_record(card_ptr: *mut sc_card, rec_nr: u32,
                                 buf_ptr: *mut u8, count: usize, _flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_read_record");
     if card_ptr.is_null() || buf_ptr.is_null() || count==0 /* || count>255*/ {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3402,6 +3623,7 @@ extern "C" fn acos5_read_record(card_ptr: *mut sc_card, rec_nr: u32,
 extern "C" fn acos5_update_binary(card_ptr: *mut sc_card, idx: u32,
                                   buf_ptr: *const u8, count: usize, flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_update_binary");
     if card_ptr.is_null() || buf_ptr.is_null() || count==0 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3415,6 +3637,7 @@ extern "C" fn acos5_update_binary(card_ptr: *mut sc_card, idx: u32,
 extern "C" fn acos5_update_record(card_ptr: *mut sc_card, rec_nr: u32,
                                   buf_ptr: *const u8, count: usize, _flags: c_ulong) -> i32
 {
+    let _op = op_scope("acos5_update_record");
     if card_ptr.is_null() || buf_ptr.is_null() || count==0 {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -3432,6 +3655,7 @@ extern "C" fn acos5_update_record(card_ptr: *mut sc_card, rec_nr: u32,
 extern "C" fn acos5_encrypt_sym(card_ptr: *mut sc_card, plaintext: *const u8, plaintext_len: usize,
     out: *mut u8, outlen: usize, algorithm: u32, algorithm_flags: u32, _key_ref: *const [u8; 8]) -> i32
 {
+    let _op = op_scope("acos5_encrypt_sym");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || algorithm != SC_ALGORITHM_AES ||
         ![SC_ALGORITHM_AES_CBC_PAD, SC_ALGORITHM_AES_CBC, SC_ALGORITHM_AES_ECB].contains(&algorithm_flags) {
         return SC_ERROR_INVALID_ARGUMENTS;
@@ -3463,6 +3687,7 @@ extern "C" fn acos5_encrypt_sym(card_ptr: *mut sc_card, plaintext: *const u8, pl
 extern "C" fn acos5_decrypt_sym(card_ptr: *mut sc_card, crgram: *const u8, crgram_len: usize,
     out: *mut u8, outlen: usize, algorithm: u32, algorithm_flags: u32, key_ref: *const [u8; 8]) -> i32
 {
+    let _op = op_scope("acos5_decrypt_sym");
     if card_ptr.is_null() || unsafe { (*card_ptr).ctx.is_null() } || algorithm != SC_ALGORITHM_AES ||
         ![SC_ALGORITHM_AES_CBC_PAD, SC_ALGORITHM_AES_CBC, SC_ALGORITHM_AES_ECB].contains(&algorithm_flags) {
         return SC_ERROR_INVALID_ARGUMENTS;
@@ -0,0 +1,268 @@
+/*
+ * lifecycle.rs: Driver 'acos5' - Life cycle states of the card and of files: SC_CARDCTL_LIFECYCLE_GET/SET,
+ *               SC_CARDCTL_ACOS5_LIFECYCLE_REPORT
+ *
+ * Copyright (C) 2019  Carsten Blüggel <bluecars@posteo.eu>
+ *
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; if not, write to the Free Software
+ * Foundation, 51 Franklin Street, Fifth Floor  Boston, MA 02110-1335  USA
+ */
+
+/*
+The card's life cycle is stored in EEPROM (Card Life Cycle Byte), readable only as long as there is no MF.
+Each file has it's own Life Cycle Status Integer (LCSI), File Info byte 7 in dp.files:
+LCSI_CREATION    (0x01) access control isn't enforced; acos5_construct_fci creates all files in this state
+LCSI_ACTIVATED   (0x05) access control is enforced
+LCSI_DEACTIVATED (0x04) access control isn't enforced
+LCSI_TERMINATED  (0x0C) the file is unusable; irreversible
+
+SC_CARDCTL_LIFECYCLE_GET takes a *mut i32 as well and gets one of SC_CARDCTRL_LIFECYCLE_* for the currently selected
+file; SC_CARDCTL_ACOS5_LIFECYCLE_REPORT gets the details (CardCtlLifecycle).
+SC_CARDCTL_LIFECYCLE_SET takes a *mut i32, as sc_pkcs15init_bind/sc_pkcs15init_set_lifecycle pass it:
+SC_CARDCTRL_LIFECYCLE_ADMIN  nothing to do: New files get created in creation state anyway, and existing files must not
+                             lose their protection just because pkcs15-init binds to the card
+SC_CARDCTRL_LIFECYCLE_USER   activates the files created in this session (dp.files_created), that are still in creation
+                             state; a DF after it's children. Files in creation state, that were created otherwise (e.g.
+                             by a foreign application or left over deliberately) remain untouched
+SC_CARDCTRL_LIFECYCLE_OTHER  not supported
+The values LCSI_ACTIVATED, LCSI_DEACTIVATED and LCSI_TERMINATED (disjoint from SC_CARDCTRL_LIFECYCLE_*) transition the
+currently selected file by commands 'Activate File' (00 44), 'Deactivate File' (00 04), 'Terminate DF' (00 E6) or
+'Terminate EF' (00 E8), if it's SCB allows that.
+*/
+
+use std::ptr::null_mut;
+use std::os::raw::c_void;
+use std::convert::TryFrom;
+use std::collections::HashMap;
+
+use opensc_sys::opensc::{sc_card, sc_select_file, sc_transmit_apdu, sc_check_sw};
+use opensc_sys::types::{sc_path, SC_MAX_PATH_SIZE, SC_PATH_TYPE_PATH, SC_APDU_CASE_1};
+use opensc_sys::cardctl::{SC_CARDCTRL_LIFECYCLE_ADMIN, SC_CARDCTRL_LIFECYCLE_USER, SC_CARDCTRL_LIFECYCLE_OTHER};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_NOT_ALLOWED, SC_ERROR_NOT_SUPPORTED, SC_ERROR_INVALID_ARGUMENTS,
+                         SC_ERROR_FILE_NOT_FOUND, SC_ERROR_SECURITY_STATUS_NOT_SATISFIED};
+
+use crate::wrappers::{wr_do_log_t, wr_do_log_tu, wr_do_log_rv};
+use crate::constants_types::{DataPrivate, CardCtlLifecycle, KeyTypeFiles, ValueTypeFiles, build_apdu, is_DFMF, ACTIVATE, DEACTIVATE, TERMINATE,
+                             LCSI_CREATION, LCSI_DEACTIVATED, LCSI_ACTIVATED, LCSI_TERMINATED};
+use crate::cmd_card_info::{get_card_life_cycle_byte_eeprom};
+use crate::path::{file_id_from_cache_current_path};
+
+/// The command (CLA INS P1 P2) that transitions a file with `fdb` from `lcsi` to `lcsi_target`, and the index of the
+/// scb8 byte that controls it. `Ok(None)`, if the file is in state `lcsi_target` already
+///
+/// # Errors
+/// `SC_ERROR_INVALID_ARGUMENTS` for a `lcsi_target` other than `LCSI_ACTIVATED`, `LCSI_DEACTIVATED`, `LCSI_TERMINATED`;
+/// `SC_ERROR_NOT_ALLOWED` for transitions the card doesn't support, e.g. any from termination state
+pub fn lifecycle_transition(fdb: u8, lcsi: u8, lcsi_target: u8) -> Result<Option<([u8; 4], usize)>, i32>
+{
+    if ![LCSI_ACTIVATED, LCSI_DEACTIVATED, LCSI_TERMINATED].contains(&lcsi_target) {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    if lcsi == lcsi_target {
+        return Ok(None);
+    }
+    match (lcsi, lcsi_target) {
+        (LCSI_TERMINATED, _)       => Err(SC_ERROR_NOT_ALLOWED),
+        (_, LCSI_ACTIVATED)        => Ok(Some(([0, 0x44, 0, 0], ACTIVATE))),
+        (LCSI_ACTIVATED, LCSI_DEACTIVATED) => Ok(Some(([0, 0x04, 0, 0], DEACTIVATE))),
+        (_, LCSI_DEACTIVATED)      => Err(SC_ERROR_NOT_ALLOWED),
+        _                          => Ok(Some(([0, if is_DFMF(fdb) {0xE6} else {0xE8}, 0, 0], TERMINATE))),
+    }
+}
+
+/* Whether the SCB for op (ACTIVATE/DEACTIVATE/TERMINATE) lets a non-SM command pass; access control is enforced only in
+   LCSI_ACTIVATED, and if the driver doesn't know the SCB yet, the card will decide */
+fn is_lifecycle_cmd_allowed(lcsi: u8, scb8: Option<[u8; 8]>, op: usize) -> bool {
+    if lcsi != LCSI_ACTIVATED {
+        return true;
+    }
+    match scb8 {
+        Some(scb8) => scb8[op] != 0xFF && (scb8[op] & 0x40) == 0,
+        None => true,
+    }
+}
+
+/* Issues the command for transition of the currently selected file file_id to lcsi_target, updates dp.files */
+fn file_lifecycle_set(card: &mut sc_card, file_id: u16, lcsi_target: u8) -> Result<(), i32> {
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"file_lifecycle_set\0");
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let val = dp.files.get(&file_id).map(|val| (val.1[0], val.1[7], val.2));
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    let (fdb, lcsi, scb8) = match val {
+        Some(val) => val,
+        None => return Err(SC_ERROR_FILE_NOT_FOUND),
+    };
+
+    let (command, op) = match lifecycle_transition(fdb, lcsi, lcsi_target)? {
+        Some(val) => val,
+        None => return Ok(()),
+    };
+    if !is_lifecycle_cmd_allowed(lcsi, scb8, op) {
+        log3if!(ctx,f,line!(), cstru!(
+            b"No life cycle transition of file %04X will be done: It's acl is NEVER or SM-protected (LCSI 0x%02X)\0"),
+            file_id, lcsi);
+        return Err(SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+    }
+
+    let mut apdu = build_apdu(ctx, &command, SC_APDU_CASE_1, &mut[]);
+    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };
+    if rv == SC_SUCCESS {
+        rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    }
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), rv);
+        return Err(rv);
+    }
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    if let Some(val) = dp.files.get_mut(&file_id) { val.1[7] = lcsi_target; }
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+    Ok(())
+}
+
+/* Selects from files those in creation state, that got created in this session (created), deepest path first, i.e.
+   children before their DF: (path len, path, file id) */
+fn files_to_activate(files: &HashMap<KeyTypeFiles, ValueTypeFiles>, created: &[u16])
+    -> Vec<(usize, [u8; SC_MAX_PATH_SIZE], u16)>
+{
+    let mut vec_path_file_id : Vec<(usize, [u8; SC_MAX_PATH_SIZE], u16)> = files.iter()
+        .filter(|(key, val)| val.1[7] == LCSI_CREATION && created.contains(key))
+        .map(|(&key, val)| (usize::from(val.1[1]), val.0, key)).collect();
+    vec_path_file_id.sort_unstable_by(|a, b| b.cmp(a));
+    vec_path_file_id
+}
+
+/* Activates the files created in this session, that are in creation state, children before their DF.
+   Returns the count of files activated */
+fn files_activate(card: &mut sc_card) -> Result<usize, i32> {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let vec_path_file_id = files_to_activate(&dp.files, &dp.files_created);
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+
+    for (len, value, file_id) in &vec_path_file_id {
+        let mut path = sc_path { type_: SC_PATH_TYPE_PATH, len: *len, ..sc_path::default() };
+        path.value[..*len].copy_from_slice(&value[..*len]);
+        let rv = unsafe { sc_select_file(card, &path, null_mut()) };
+        if rv != SC_SUCCESS {
+            return Err(rv);
+        }
+        file_lifecycle_set(card, *file_id, LCSI_ACTIVATED)?;
+    }
+    Ok(vec_path_file_id.len())
+}
+
+/// The card's life cycle and the one of the currently selected file
+///
+/// # Errors
+/// `SC_ERROR_FILE_NOT_FOUND`, if the currently selected file isn't known to the driver
+pub fn lifecycle_get(card: &mut sc_card) -> Result<CardCtlLifecycle, i32>
+{
+    let file_id = file_id_from_cache_current_path(card);
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let lcsi = dp.files.get(&file_id).map(|val| val.1[7]);
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    let lcsi = lcsi.ok_or(SC_ERROR_FILE_NOT_FOUND)?;
+
+    let lifecycle = match lcsi {
+        LCSI_ACTIVATED  => SC_CARDCTRL_LIFECYCLE_USER,
+        LCSI_TERMINATED => SC_CARDCTRL_LIFECYCLE_OTHER,
+        _               => SC_CARDCTRL_LIFECYCLE_ADMIN,
+    };
+    Ok(CardCtlLifecycle {
+        card_life_cycle_byte: get_card_life_cycle_byte_eeprom(card).unwrap_or(0xFF),
+        file_id,
+        lcsi,
+        lifecycle: i32::try_from(lifecycle).unwrap(),
+    })
+}
+
+/// Implements SC_CARDCTL_LIFECYCLE_SET: `lifecycle` is one of SC_CARDCTRL_LIFECYCLE_* or LCSI_ACTIVATED,
+/// LCSI_DEACTIVATED, LCSI_TERMINATED; see the explanation at the top of this file
+pub fn lifecycle_set(card: &mut sc_card, lifecycle: i32) -> i32
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"lifecycle_set\0");
+    log3if!(ctx,f,line!(), cstru!(b"called for lifecycle: %d\0"), lifecycle);
+
+    let result =
+        if      lifecycle == i32::try_from(SC_CARDCTRL_LIFECYCLE_ADMIN).unwrap() { Ok(()) }
+        else if lifecycle == i32::try_from(SC_CARDCTRL_LIFECYCLE_USER).unwrap()  { files_activate(card).map(|_| ()) }
+        else if lifecycle == i32::try_from(SC_CARDCTRL_LIFECYCLE_OTHER).unwrap() { Err(SC_ERROR_NOT_SUPPORTED) }
+        else {
+            match u8::try_from(lifecycle) {
+                Ok(lcsi_target) if [LCSI_ACTIVATED, LCSI_DEACTIVATED, LCSI_TERMINATED].contains(&lcsi_target) =>
+                    file_lifecycle_set(card, file_id_from_cache_current_path(card), lcsi_target),
+                _ => Err(SC_ERROR_INVALID_ARGUMENTS),
+            }
+        };
+    let rv = match result { Ok(()) => SC_SUCCESS, Err(e) => e };
+    log3ifr!(ctx,f,line!(), rv);
+    rv
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use super::{lifecycle_transition, is_lifecycle_cmd_allowed, files_to_activate};
+    use crate::constants_types::{KeyTypeFiles, ValueTypeFiles, FDB_DF, FDB_TRANSPARENT_EF, ACTIVATE, DEACTIVATE, TERMINATE,
+                                 LCSI_CREATION, LCSI_DEACTIVATED, LCSI_ACTIVATED, LCSI_TERMINATED};
+    use opensc_sys::errors::{SC_ERROR_NOT_ALLOWED, SC_ERROR_INVALID_ARGUMENTS};
+
+    #[test]
+    fn test_lifecycle_transition() {
+        assert_eq!(lifecycle_transition(FDB_TRANSPARENT_EF, LCSI_CREATION, LCSI_ACTIVATED),    Ok(Some(([0, 0x44, 0, 0], ACTIVATE))));
+        assert_eq!(lifecycle_transition(FDB_TRANSPARENT_EF, LCSI_DEACTIVATED, LCSI_ACTIVATED), Ok(Some(([0, 0x44, 0, 0], ACTIVATE))));
+        assert_eq!(lifecycle_transition(FDB_TRANSPARENT_EF, LCSI_ACTIVATED, LCSI_DEACTIVATED), Ok(Some(([0, 0x04, 0, 0], DEACTIVATE))));
+        assert_eq!(lifecycle_transition(FDB_TRANSPARENT_EF, LCSI_ACTIVATED, LCSI_TERMINATED),  Ok(Some(([0, 0xE8, 0, 0], TERMINATE))));
+        assert_eq!(lifecycle_transition(FDB_DF,             LCSI_CREATION,  LCSI_TERMINATED),  Ok(Some(([0, 0xE6, 0, 0], TERMINATE))));
+        assert_eq!(lifecycle_transition(FDB_DF,             LCSI_ACTIVATED, LCSI_ACTIVATED),   Ok(None));
+        assert_eq!(lifecycle_transition(FDB_DF,             LCSI_CREATION,  LCSI_DEACTIVATED), Err(SC_ERROR_NOT_ALLOWED));
+        assert_eq!(lifecycle_transition(FDB_DF,             LCSI_TERMINATED, LCSI_ACTIVATED),  Err(SC_ERROR_NOT_ALLOWED));
+        assert_eq!(lifecycle_transition(FDB_DF,             LCSI_ACTIVATED, LCSI_CREATION),    Err(SC_ERROR_INVALID_ARGUMENTS));
+
+        let scb8 = Some([0x00, 0x01, 0x00, 0xFF, 0x41, 0x01, 0x00, 0xFF]);
+        assert!( is_lifecycle_cmd_allowed(LCSI_ACTIVATED,   scb8, TERMINATE));
+        assert!(!is_lifecycle_cmd_allowed(LCSI_ACTIVATED,   scb8, DEACTIVATE));
+        assert!(!is_lifecycle_cmd_allowed(LCSI_ACTIVATED,   scb8, ACTIVATE));
+        assert!( is_lifecycle_cmd_allowed(LCSI_DEACTIVATED, scb8, ACTIVATE));
+        assert!( is_lifecycle_cmd_allowed(LCSI_ACTIVATED,   None, DEACTIVATE));
+    }
+
+    #[test]
+    fn test_files_to_activate() {
+        let mut files : HashMap<KeyTypeFiles, ValueTypeFiles> = HashMap::new();
+        let mut insert = |file_id: u16, path: &[u8], fdb: u8, lcsi: u8| {
+            let mut value = [0_u8; 16];
+            value[..path.len()].copy_from_slice(path);
+            files.insert(file_id, (value, [fdb, u8::try_from(path.len()).unwrap(), path[path.len()-2], path[path.len()-1],
+                0, 0, 0xFF, lcsi], None, None, None));
+        };
+        insert(0x4100, &[0x3F, 0, 0x41, 0],             FDB_DF,             LCSI_CREATION);
+        insert(0x4101, &[0x3F, 0, 0x41, 0, 0x41, 1],    FDB_TRANSPARENT_EF, LCSI_CREATION);
+        insert(0x4102, &[0x3F, 0, 0x41, 0, 0x41, 2],    FDB_TRANSPARENT_EF, LCSI_ACTIVATED);
+        /* a foreign application's DF and EF in creation state, not created in this session */
+        insert(0x4200, &[0x3F, 0, 0x42, 0],             FDB_DF,             LCSI_CREATION);
+        insert(0x4201, &[0x3F, 0, 0x42, 0, 0x42, 1],    FDB_TRANSPARENT_EF, LCSI_CREATION);
+
+        let file_ids : Vec<u16> = files_to_activate(&files, &[0x4100, 0x4101, 0x4102]).iter().map(|x| x.2).collect();
+        assert_eq!(file_ids, vec![0x4101, 0x4100]);
+        assert!(files_to_activate(&files, &[]).is_empty());
+    }
+}
@@ -27,13 +27,14 @@ use std::ffi::{/*CString,*/ CStr};
 use std::fs;//::{read/*, write*/};
 use std::ptr::{null_mut};
 use std::convert::{From, TryFrom, TryInto};
-use std::slice::from_raw_parts;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
 
 use num_integer::Integer;
 
-use opensc_sys::opensc::{sc_card, sc_pin_cmd_data, sc_security_env, sc_transmit_apdu,
+use opensc_sys::opensc::{sc_card, sc_algorithm_info, sc_pin_cmd_data, sc_security_env, sc_transmit_apdu,
                          sc_read_record, sc_format_path, sc_select_file, sc_check_sw, //SC_ALGORITHM_RSA_PAD_PKCS1,
                          SC_RECORD_BY_REC_NR, SC_PIN_ENCODING_ASCII, SC_READER_SHORT_APDU_MAX_RECV_SIZE,
+                         SC_PIN_ENCODING_BCD, SC_PIN_ENCODING_GLP,
                          SC_SEC_ENV_ALG_PRESENT, SC_SEC_ENV_FILE_REF_PRESENT, SC_ALGORITHM_RSA, SC_SEC_ENV_KEY_REF_PRESENT,
                          SC_ALGORITHM_3DES, SC_ALGORITHM_DES, sc_get_iso7816_driver, SC_SEC_ENV_ALG_REF_PRESENT,
                          sc_format_apdu, sc_file_new, sc_file_get_acl_entry, sc_check_apdu, sc_list_files,
@@ -71,10 +72,11 @@ use opensc_sys::errors::{/*SC_ERROR_NO_READERS_FOUND, SC_ERROR_UNKNOWN, SC_ERROR
                          SC_SUCCESS, SC_ERROR_INVALID_ARGUMENTS, //SC_ERROR_KEYPAD_TIMEOUT,
                          SC_ERROR_KEYPAD_MSG_TOO_LONG,/*, SC_ERROR_WRONG_PADDING, SC_ERROR_INTERNAL*/
 SC_ERROR_WRONG_LENGTH, SC_ERROR_NOT_ALLOWED, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_INCORRECT_PARAMETERS, SC_ERROR_CARD_CMD_FAILED,
-SC_ERROR_OUT_OF_MEMORY, SC_ERROR_UNKNOWN_DATA_RECEIVED, SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, SC_ERROR_NO_CARD_SUPPORT,
+SC_ERROR_OUT_OF_MEMORY, SC_ERROR_UNKNOWN_DATA_RECEIVED, SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, SC_ERROR_NO_CARD_SUPPORT, SC_ERROR_INCOMPATIBLE_KEY,
+SC_ERROR_NOT_SUPPORTED, SC_ERROR_BUFFER_TOO_SMALL,
 SC_ERROR_SM_RAND_FAILED, SC_ERROR_KEYPAD_TIMEOUT
 };
-use opensc_sys::internal::{sc_atr_table};
+use opensc_sys::internal::{sc_atr_table, _sc_card_add_rsa_alg};
 use opensc_sys::asn1::{sc_asn1_read_tag};
 use opensc_sys::iso7816::{ISO7816_TAG_FCI, ISO7816_TAG_FCP};
 use opensc_sys::sm::{SM_SMALL_CHALLENGE_LEN, SM_CMD_FILE_READ, SM_CMD_FILE_UPDATE};
@@ -94,7 +96,9 @@ use crate::constants_types::{ATR_MASK, ATR_V2, ATR_V3, BLOCKCIPHER_PAD_TYPE_ANSI
                              Acos5EcCurve, build_apdu, is_DFMF, ATR_MASK_TCK, // p_void,
                              // ISO7816_RFU_TAG_FCP_SFI, ISO7816_RFU_TAG_FCP_SAC, ISO7816_RFU_TAG_FCP_SEID, ISO7816_RFU_TAG_FCP_SAE,
                              GuardFile, SC_CARD_TYPE_ACOS5_EVO_V4, NAME_V4, ATR_V4_1, ATR_V4_2, ATR_V4_3, //, ATR_V4
-                             file_id_from_path_value, file_id_se,
+                             file_id_from_path_value, file_id_se, file_size, FDB_CHV_EF, PinAttributes, PIN_LEN_MAX,
+                             PIN_LEN_MAX_EVO, READ, rsa_pub_exponent_16, ECC_KEY_FILE_HEADER_LEN, ecc_field_length,
+                             ecc_coordinate_len, FDB_ECC_KEY_EF,
                              CRT_TAG_HT, CRT_TAG_CCT, CRT_TAG_DST, CRT_TAG_CT,
                              SC_SEC_OPERATION_GENERATE_ECCPRIVATE, SC_SEC_OPERATION_GENERATE_ECCPUBLIC //, APDUShortExtendedSwitcher
 };
@@ -103,7 +107,7 @@ use crate::path::{cut_path, file_id_from_cache_current_path, current_path_df, is
 use crate::missing_exports::me_get_max_recv_size;
 use crate::cmd_card_info::{get_is_pin_authenticated};
 use crate::sm::{SM_SMALL_CHALLENGE_LEN_u8, sm_common_read, sm_common_update};
-use crate::crypto::{RAND_bytes, des_ecb3_unpadded_8, Encrypt};
+use crate::crypto::{RAND_bytes, des_ecb3_unpadded_8, ec_point_is_on_curve, Encrypt};
 
 use super::{acos5_process_fci/*, acos5_list_files, acos5_select_file, acos5_set_security_env*/};
 
@@ -947,16 +951,6 @@ pub fn convert_acl_array_to_bytes_tag_fcp_sac(/*card: &mut sc_card,*/ acl: &[*mu
         SC_AC_NONE, SC_AC_CHV, SC_AC_TERM, SC_AC_PRO, SC_AC_AUT, SC_AC_SYMBOLIC, SC_AC_SEN, SC_AC_SCB, SC_AC_IDA, SC_AC_SESSION/*, SC_AC_CONTEXT_SPECIFIC*/, SC_AC_UNKNOWN, SC_AC_NEVER];
     let mut result = [0x7F_u8,0,0,0,0,0,0,0];
     match acl_category {
-        ACL_CATEGORY_SE => {
-            let p = acl[usize::try_from(SC_AC_OP_READ).unwrap()];
-            if p.is_null() {                      result[7] = 0; }
-            else if p==(1 as *mut sc_acl_entry) { result[7] = 0xFF; }
-            else if p==(2 as *mut sc_acl_entry) { result[7] = 0; }
-            else if p==(3 as *mut sc_acl_entry) { result[7] = 0xFF; }
-            else {
-
-            }
-        },
         ACL_CATEGORY_DF_MF => {
             let mut p = acl[usize::try_from(SC_AC_OP_DELETE).unwrap()];
             if p.is_null() {                      result[7] = 0; }
@@ -1033,7 +1027,7 @@ pub fn convert_acl_array_to_bytes_tag_fcp_sac(/*card: &mut sc_card,*/ acl: &[*mu
                 result[5] = u8::try_from(p_ref.key_ref).unwrap();
             }
         }
-        ACL_CATEGORY_EF_CHV => {
+        ACL_CATEGORY_EF_CHV | ACL_CATEGORY_SE => {
             let mut p = acl[usize::try_from(SC_AC_OP_READ).unwrap()];
             if p.is_null() {                      result[7] = 0; }
             else if p==(1 as *mut sc_acl_entry) { result[7] = 0xFF; }
@@ -1117,25 +1111,107 @@ pub fn convert_acl_array_to_bytes_tag_fcp_sac(/*card: &mut sc_card,*/ acl: &[*mu
 }
 
 
-/*
- * What it does
- * @apiNote
- * @param
- * @return
- */
-pub fn pin_get_policy(card: &mut sc_card, data: &mut sc_pin_cmd_data, tries_left: &mut i32) -> i32
+/* DER TLV splitting of a constructed value (single byte tags, length of max. 3 bytes), tolerant to trailing zero bytes
+   (unused part of a file) and truncation */
+fn der_tlv_list(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut result = Vec::new();
+    while data.len() >= 2 && data[0] != 0 {
+        let (len, pos) = match data[1] {
+            len if len < 0x80 => (usize::from(len), 2),
+            0x81 if data.len() >= 3 => (usize::from(data[2]), 3),
+            0x82 if data.len() >= 4 => (usize::from(u16::from_be_bytes([data[2], data[3]])), 4),
+            _ => break,
+        };
+        if data.len() < pos+len {
+            break;
+        }
+        result.push((data[0], &data[pos..pos+len]));
+        data = &data[pos+len..];
+    }
+    result
+}
+
+/// Returns the pinReference and PinAttributes of all PIN objects of EF.AODF content.
+/// The pinReference is taken from the last byte, as it's often encoded as negative INTEGER, e.g. 0x81
+#[must_use]
+pub fn aodf_pin_attributes(aodf: &[u8]) -> Vec<(u8, PinAttributes)> {
+    let mut result = Vec::new();
+    for (_, pin_object) in der_tlv_list(aodf).into_iter().filter(|&(tag, _)| tag == 0x30) {
+        let pin_attributes = match der_tlv_list(pin_object).into_iter().find(|&(tag, _)| tag == 0xA1)
+            .and_then(|(_, val)| der_tlv_list(val).into_iter().find(|&(tag, _)| tag == 0x30)) {
+            Some((_, val)) => val,
+            None => continue,
+        };
+        let mut attrs = PinAttributes { encoding: SC_PIN_ENCODING_ASCII, min_length: 0, stored_length: 0, max_length: 0,
+            pad_char: None };
+        let mut pin_reference = 0; // DEFAULT 0
+        let mut integers = [0_usize; 3];
+        let mut integers_cnt = 0;
+        for (tag, val) in der_tlv_list(pin_attributes) {
+            match tag {
+                /* pinType: bcd(0), ascii-numeric(1), utf8(2), half-nibble-bcd(3), iso9564-1(4) */
+                0x0A => attrs.encoding = match val {
+                    [0] | [3] => SC_PIN_ENCODING_BCD,
+                    [4] => SC_PIN_ENCODING_GLP,
+                    _ => SC_PIN_ENCODING_ASCII,
+                },
+                /* minLength, storedLength, maxLength OPTIONAL */
+                0x02 if integers_cnt < 3 && val.len() <= 2 => {
+                    integers[integers_cnt] = val.iter().fold(0, |acc, &b| acc << 8 | usize::from(b));
+                    integers_cnt += 1;
+                },
+                0x80 => pin_reference = val.last().copied().unwrap_or(0),
+                0x04 if val.len() == 1 => attrs.pad_char = Some(val[0]),
+                _ => (),
+            }
+        }
+        if integers_cnt >= 2 {
+            attrs.min_length = integers[0];
+            attrs.stored_length = integers[1];
+            attrs.max_length = integers[2];
+            result.push((pin_reference, attrs));
+        }
+    }
+    result
+}
+
+/* Reads the records of a record based file (PIN file) known to dp.files */
+fn read_records_known(card: &mut sc_card, path_value: [u8; 16], file_info: [u8; 8]) -> Option<Vec<Vec<u8>>> {
+    let path = sc_path { type_: SC_PATH_TYPE_PATH, value: path_value, len: usize::from(file_info[1]), ..sc_path::default() };
+    if unsafe { sc_select_file(card, &path, null_mut()) } != SC_SUCCESS {
+        return None;
+    }
+    let (mrl, nor) = (usize::from(file_info[4]), file_info[5]);
+    let mut records = Vec::with_capacity(usize::from(nor));
+    for rec_nr in 1..=nor {
+        let mut buf = vec![0; mrl];
+        let rv = unsafe { sc_read_record(card, u32::from(rec_nr), buf.as_mut_ptr(), mrl, SC_RECORD_BY_REC_NR) };
+        if rv < 0 {
+            return None;
+        }
+        records.push(buf);
+    }
+    Some(records)
+}
+
+/// Sets the PIN policy of `data.pin1`: Card type dependent defaults, overridden by the AODF entry of the PIN reference
+/// (as cached in dp.pin_attributes during enumeration; an AODF within the current DF is preferred).
+/// The max. tries get read from the PIN record, if the PIN file is readable
+/// within the current session, otherwise they are reported as 0 (unknown).
+/// The card's current file gets restored afterwards
+pub fn pin_set_policy(card: &mut sc_card, data: &mut sc_pin_cmd_data)
 {
-/* when is AODF read for the pin details info info ? */
     assert!(!card.ctx.is_null());
     let ctx = unsafe { &mut *card.ctx };
-    let f = cstru!(b"pin_get_policy\0");
+    let f = cstru!(b"pin_set_policy\0");
     log3ifc!(ctx,f,line!());
 
+    let reference = u8::try_from(data.pin_reference).unwrap_or(0);
     data.pin1.min_length = 4; /* min length of PIN */
-    data.pin1.max_length = 8; /* max length of PIN */
+    data.pin1.max_length = if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_LEN_MAX_EVO} else {PIN_LEN_MAX}; /* max length of PIN */
     #[cfg(any(v0_17_0, v0_18_0, v0_19_0, v0_20_0))]
     {
-        data.pin1.stored_length = 8; /* stored length of PIN */
+        data.pin1.stored_length = data.pin1.max_length; /* stored length of PIN */
     }
     data.pin1.encoding = SC_PIN_ENCODING_ASCII; /* ASCII-numeric, BCD, etc */
 //  data.pin1.pad_length    = 0; /* filled in by the card driver */
@@ -1143,12 +1219,76 @@ pub fn pin_get_policy(card: &mut sc_card, data: &mut sc_pin_cmd_data, tries_left
     data.pin1.offset = 5; /* PIN offset in the APDU */
     #[cfg(any(v0_17_0, v0_18_0, v0_19_0, v0_20_0))]
     {
-//      data.pin1.length_offset = 5;
         data.pin1.length_offset = 0; /* Effective PIN length offset in the APDU */
     }
+    data.pin1.max_tries = 0; /* Used for signaling back from SC_PIN_CMD_GET_INFO */
+
+    let saved_path = card.cache.current_path;
+    /* the AODFs' entries for reference: (path value of the AODF, length of it's DF path, attributes) */
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let mut vec_attrs : Vec<([u8; 16], usize, PinAttributes)> = dp.pin_attributes.iter()
+        .filter(|(_, pin_reference, _)| *pin_reference == reference)
+        .filter_map(|(file_id, _, attrs)| dp.files.get(file_id).map(|val| (val.0, usize::from(val.1[1]).saturating_sub(2), *attrs)))
+        .collect();
+    Box::leak(dp);
+    vec_attrs.sort_by_key(|(path_value, df_len, _)|
+        !(saved_path.len >= *df_len && path_value[..*df_len] == saved_path.value[..*df_len]));
+
+    /* the DF of the AODF, that has the PIN object, is also the DF of a local PIN */
+    let mut df_path_value = None;
+    if let Some(&(path_value, df_len, attrs)) = vec_attrs.first() {
+        data.pin1.min_length = attrs.min_length;
+        if attrs.max_length > 0 {
+            data.pin1.max_length = attrs.max_length.min(data.pin1.max_length);
+        }
+        #[cfg(any(v0_17_0, v0_18_0, v0_19_0, v0_20_0))]
+        {
+            data.pin1.stored_length = attrs.stored_length;
+        }
+        data.pin1.encoding = attrs.encoding;
+        if let Some(pad_char) = attrs.pad_char {
+            data.pin1.pad_char = pad_char;
+        }
+        df_path_value = Some((path_value, df_len));
+        log3if!(ctx,f,line!(), cstru!(b"PIN policy from AODF: min_length %zu, max_length %zu\0"),
+            data.pin1.min_length, data.pin1.max_length);
+    }
+
+    /* the PIN file: of MF for a global PIN */
+    let (dir_value, dir_len) = if reference & 0x80 == 0 { ([0x3F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 2) }
+                               else { df_path_value.unwrap_or((saved_path.value, saved_path.len)) };
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let pin_file = dp.files.values().find(|val| val.1[0] == FDB_CHV_EF && usize::from(val.1[1]) == dir_len+2 &&
+        val.0[..dir_len] == dir_value[..dir_len] && val.2.map_or(0xFF, |scb8| scb8[READ]) != 0xFF).map(|val| (val.0, val.1));
+    Box::leak(dp);
+    if let Some((path_value, file_info)) = pin_file {
+        if let Some(records) = read_records_known(card, path_value, file_info) {
+            if let Some(rec) = records.iter().find(|rec| rec.len() >= 2 && rec[0] & 0x80 != 0 && rec[0] & 0x1F == reference & 0x1F) {
+                data.pin1.max_tries = i32::from(rec[1] >> 4);
+            }
+        }
+    }
 
-    data.pin1.max_tries = 8;//pin_tries_max; /* Used for signaling back from SC_PIN_CMD_GET_INFO */ /* assume: 8 as factory setting; max allowed number of retries is unretrievable with proper file access condition NEVER read */
+    if saved_path.len > 0 && (card.cache.current_path.len != saved_path.len ||
+        card.cache.current_path.value[..saved_path.len] != saved_path.value[..saved_path.len]) {
+        unsafe { sc_select_file(card, &saved_path, null_mut()) };
+    }
+}
 
+/*
+ * What it does
+ * @apiNote
+ * @param
+ * @return
+ */
+pub fn pin_get_policy(card: &mut sc_card, data: &mut sc_pin_cmd_data, tries_left: &mut i32) -> i32
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"pin_get_policy\0");
+    log3ifc!(ctx,f,line!());
+
+    pin_set_policy(card, data);
     let mut apdu = build_apdu(ctx, &[0x00_u8, 0x20, 0x00, u8::try_from(data.pin_reference).unwrap()], SC_APDU_CASE_1, &mut[]);
     let rv = unsafe { sc_transmit_apdu(card, &mut apdu) };
     if rv != SC_SUCCESS || apdu.sw1 != 0x63 || (apdu.sw2 & 0xC0) != 0xC0 {
@@ -1309,6 +1449,33 @@ fn get_rsa_caps(card: &mut sc_card) -> u32
     result
 }
 
+/// Registers the RSA key sizes the card supports in its Operation Mode: FIPS: 2048 and 3072 (EVO: and 4096) bits,
+/// otherwise 512 - 4096 bits in steps of 256. RSA algorithms registered before get removed, such that this may be
+/// called again after the Operation Mode Byte got changed
+/// @return  SC_SUCCESS or the error of _sc_card_add_rsa_alg
+pub fn rsa_algorithms_register(card: &mut sc_card, rsa_algo_flags: u32, is_fips_mode: bool) -> i32
+{
+    if card.algorithm_count > 0 {
+        let algorithms = unsafe { from_raw_parts_mut(card.algorithms, usize::try_from(card.algorithm_count).unwrap()) };
+        let kept : Vec<sc_algorithm_info> = algorithms.iter().filter(|info| info.algorithm != SC_ALGORITHM_RSA).copied().collect();
+        algorithms[..kept.len()].copy_from_slice(&kept);
+        card.algorithm_count = i32::try_from(kept.len()).unwrap();
+    }
+    let     rsa_key_len_from : u32 = if is_fips_mode { 2048 } else {  512 };
+    let     rsa_key_len_step : u32 = if is_fips_mode { 1024 } else {  256 };
+    let     rsa_key_len_to   : u32 = if is_fips_mode && card.type_==SC_CARD_TYPE_ACOS5_64_V3 { 3072 } else { 4096 };
+    let mut rsa_key_len = rsa_key_len_from;
+    /* exponent 0: Any public exponent is supported, i.e. odd, > 1 and of max. 16 bytes, see generate_asym */
+    while   rsa_key_len <= rsa_key_len_to {
+        let rv = unsafe { _sc_card_add_rsa_alg(card, rsa_key_len, c_ulong::from(rsa_algo_flags), 0/*0x10001*/) };
+        if rv != SC_SUCCESS {
+            return rv;
+        }
+        rsa_key_len += rsa_key_len_step;
+    }
+    SC_SUCCESS
+}
+
 pub fn set_sec_env(card: &mut sc_card, value: &sc_security_env)
 {
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
@@ -1523,6 +1690,12 @@ pub fn generate_asym(card: &mut sc_card, data: &mut CardCtl_generate_crypt_asym)
     let f = cstru!(b"generate_asym\0");
     log3ifc!(ctx,f,line!());
 
+    if data.key_curve_code==0 && !data.do_generate_with_standard_rsa_pub_exponent {
+        if let Err(e) = rsa_pub_exponent_16(&data.rsa_pub_exponent) {
+            log3ifr!(ctx,f,line!(), cstru!(b"Error: The RSA public exponent must be odd, > 1 and of max. 16 bytes\0"), e);
+            return e;
+        }
+    }
     let mut rv;
 
     if data.perform_mse {
@@ -1574,6 +1747,126 @@ pub fn generate_asym(card: &mut sc_card, data: &mut CardCtl_generate_crypt_asym)
     rv
 }
 
+/// Extracts the public point from the raw content of an ECC public key file, see `ECC_KEY_FILE_HEADER_LEN`
+///
+/// Returns the field length in bits and the point in uncompressed form 04||X||Y (not DER, as `sc_pkcs15_pubkey_ec.ecpointQ`)
+///
+/// The layout and the curve codes are inferred only (see `ECC_KEY_FILE_HEADER_LEN`), thus the point gets accepted only,
+/// if it's on the curve, that the header's curve code maps to: That checks both against what the card actually wrote.
+/// # Errors
+/// `SC_ERROR_INCOMPATIBLE_KEY`, if the header's curve code is unknown, the content's length doesn't match the curve
+/// exactly or the point isn't on the curve
+pub fn ecc_pub_key_file_point(raw: &[u8]) -> Result<(usize, Vec<u8>), i32>
+{
+    if raw.len() <= ECC_KEY_FILE_HEADER_LEN {
+        return Err(SC_ERROR_INCOMPATIBLE_KEY);
+    }
+    let field_length = ecc_field_length(raw[1]).ok_or(SC_ERROR_INCOMPATIBLE_KEY)?;
+    let coord_len = ecc_coordinate_len(field_length).unwrap();
+    if raw.len() != ECC_KEY_FILE_HEADER_LEN + 2*coord_len {
+        return Err(SC_ERROR_INCOMPATIBLE_KEY);
+    }
+    let mut point = Vec::with_capacity(1 + 2*coord_len);
+    point.push(4);
+    point.extend_from_slice(&raw[ECC_KEY_FILE_HEADER_LEN..ECC_KEY_FILE_HEADER_LEN + 2*coord_len]);
+    if !ec_point_is_on_curve(field_length, &point) {
+        return Err(SC_ERROR_INCOMPATIBLE_KEY);
+    }
+    Ok((field_length, point))
+}
+
+/// Converts an ECDSA signature as returned by the card to the form OpenSC expects: r||s, each of `coord_len` bytes.
+/// Accepted is the DER encoded Ecdsa-Sig-Value SEQUENCE { r INTEGER, s INTEGER } as well as r||s already.
+/// # Errors
+/// `SC_ERROR_UNKNOWN_DATA_RECEIVED`, if `sig` is neither
+pub fn ecdsa_signature_to_rs(sig: &[u8], coord_len: usize) -> Result<Vec<u8>, i32>
+{
+    let sequence = der_tlv_list(sig);
+    if sequence.len() == 1 && sequence[0].0 == 0x30 && sig.ends_with(sequence[0].1) {
+        let integers = der_tlv_list(sequence[0].1);
+        if integers.len() == 2 && integers.iter().all(|&(tag, _)| tag == 2) &&
+            integers[0].1.len() + integers[1].1.len() + 4 == sequence[0].1.len() {
+            let mut result = vec![0_u8; 2*coord_len];
+            for (i, &(_, integer)) in integers.iter().enumerate() {
+                let pos = integer.iter().position(|&b| b != 0).unwrap_or(integer.len());
+                let value = &integer[pos..];
+                if value.len() > coord_len {
+                    return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED);
+                }
+                result[(i+1)*coord_len - value.len()..(i+1)*coord_len].copy_from_slice(value);
+            }
+            return Ok(result);
+        }
+    }
+    if sig.len() == 2*coord_len {
+        return Ok(sig.to_vec());
+    }
+    Err(SC_ERROR_UNKNOWN_DATA_RECEIVED)
+}
+
+/// ECDSA signature, ACOS5-EVO only; the MSE for DST was done by acos5_set_security_env already.
+///
+/// `data` is the hash: only SC_ALGORITHM_ECDSA_RAW is announced (see acos5_init), thus OpenSC hashes in software.
+/// Hashing on card (PSO Hash) isn't used: It's command sequence for EVO isn't backed by the reference manual or by a
+/// transcript of a real card, and a wrong one would fail or sign something else than intended. Hashing on the host
+/// doesn't expose the private key.
+/// Returns the length of the signature r||s written to `out` or an error code
+#[allow(clippy::missing_panics_doc)]
+pub fn sign_ecdsa(card: &mut sc_card, data: &[u8], out: &mut [u8]) -> i32
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"sign_ecdsa\0");
+    let sec_env = get_sec_env(card);
+    if card.type_ != SC_CARD_TYPE_ACOS5_EVO_V4 || sec_env.file_ref.len < 2 || data.is_empty() {
+        return SC_ERROR_NOT_SUPPORTED;
+    }
+    /* the coordinate length is known from the private key file's size, see ECC_KEY_FILE_HEADER_LEN */
+    let file_id = file_id_from_path_value(&sec_env.file_ref.value[..sec_env.file_ref.len]);
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let coord_len = dp.files.get(&file_id).filter(|val| val.1[0] == FDB_ECC_KEY_EF)
+        .and_then(|val| usize::from(file_size(val.1)).checked_sub(ECC_KEY_FILE_HEADER_LEN))
+        .filter(|&len| [224, 256, 384, 521].iter().any(|&field_length| ecc_coordinate_len(field_length) == Some(len)));
+    Box::leak(dp);
+    let coord_len = match coord_len {
+        Some(len) => len,
+        None => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: The private key file %04X isn't a known ECC key file\0"), u32::from(file_id));
+            return SC_ERROR_INCOMPATIBLE_KEY;
+        },
+    };
+
+    let mut rv;
+    /* a hash longer than the order gets truncated to it's leftmost bits; for P-521 no hash is that long */
+    let data = &data[..std::cmp::min(data.len(), coord_len)];
+
+    /* PSO Compute Digital Signature; the last 4 bytes are placeholders only for sc_bytes2apdu */
+    let mut rbuf = [0_u8; 256];
+    let mut apdu = build_apdu(ctx, &[0, 0x2A, 0x9E, 0x9A, 0x02, 0xFF, 0xFF, 0x00], SC_APDU_CASE_4_SHORT, &mut rbuf);
+    apdu.data    = data.as_ptr();
+    apdu.datalen = data.len();
+    apdu.lc      = data.len();
+    set_is_running_cmd_long_response(card, true); // switch to false is done by acos5_get_response
+    rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: 'Compute Digital Signature' failed\0"), rv);
+        return rv;
+    }
+    let signature = match ecdsa_signature_to_rs(&rbuf[..apdu.resplen], coord_len) {
+        Ok(val) => val,
+        Err(e) => {
+            log3ifr!(ctx,f,line!(), cstru!(b"Error: Unknown ECDSA signature format\0"), e);
+            return e;
+        },
+    };
+    if out.len() < signature.len() {
+        return SC_ERROR_BUFFER_TOO_SMALL;
+    }
+    out[..signature.len()].copy_from_slice(&signature);
+    i32::try_from(signature.len()).unwrap()
+}
+
 
 /*
   The EMSA-PKCS1-v1_5 DigestInfo digestAlgorithm (all content excluding the trailing hash) is known, same the length of hash
@@ -2481,7 +2774,8 @@ pub fn common_update(card: &mut sc_card,
 mod tests {
     use super::{convert_amdo_to_cla_ins_p1_p2_array, algo_ref_mse_sedo, SC_SEC_OPERATION_SIGN,
                 trailing_blockcipher_padding_calculate, trailing_blockcipher_padding_get_length,
-                SC_ALGORITHM_RSA};
+                SC_ALGORITHM_RSA, ecc_pub_key_file_point, SC_ERROR_INCOMPATIBLE_KEY, ecdsa_signature_to_rs,
+                SC_ERROR_UNKNOWN_DATA_RECEIVED, aodf_pin_attributes};
     use crate::constants_types::*;
     // use num_integer::Integer;
 
@@ -2660,4 +2954,78 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_rsa_pub_exponent_16() {
+        assert_eq!(rsa_pub_exponent_16(&[1, 0, 1]), Ok(RSA_PUB_EXPONENT_STANDARD));
+        assert_eq!(rsa_pub_exponent_16(&[0, 0, 3]), Ok([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]));
+        assert_eq!(rsa_pub_exponent_16(&[0xFF; 16]), Ok([0xFF; 16]));
+        assert!(rsa_pub_exponent_16(&[0xFF; 17]).is_err()); // too long
+        assert!(rsa_pub_exponent_16(&[1, 0, 0]).is_err());  // even
+        assert!(rsa_pub_exponent_16(&[0, 1]).is_err());
+        assert!(rsa_pub_exponent_16(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_ecc_pub_key_file_point() {
+        assert_eq!(ecc_key_file_sizes(256), Some((37, 69)));
+        assert_eq!(ecc_key_file_sizes(521), Some((71, 137)));
+        assert_eq!(ecc_key_file_sizes(192), None);
+        for &field_length in &[224_usize, 256, 384, 521] {
+            assert_eq!(ecc_field_length(ecc_curve_code(field_length).unwrap()), Some(field_length));
+        }
+        /* the inferred layout holding the P-256 base point G, not the dump of a real card's file */
+        let g = [0x6B_u8, 0x17, 0xD1, 0xF2, 0xE1, 0x2C, 0x42, 0x47, 0xF8, 0xBC, 0xE6, 0xE5, 0x63, 0xA4, 0x40, 0xF2,
+                 0x77, 0x03, 0x7D, 0x81, 0x2D, 0xEB, 0x33, 0xA0, 0xF4, 0xA1, 0x39, 0x45, 0xD8, 0x98, 0xC2, 0x96,
+                 0x4F, 0xE3, 0x42, 0xE2, 0xFE, 0x1A, 0x7F, 0x9B, 0x8E, 0xE7, 0xEB, 0x4A, 0x7C, 0x0F, 0x9E, 0x16,
+                 0x2B, 0xCE, 0x33, 0x57, 0x6B, 0x31, 0x5E, 0xCE, 0xCB, 0xB6, 0x40, 0x68, 0x37, 0xBF, 0x51, 0xF5];
+        let mut raw = vec![0_u8, 2, 0, 0, 0];
+        raw.extend_from_slice(&g);
+        assert_eq!(raw.len(), ecc_key_file_sizes(256).unwrap().1);
+        let (field_length, point) = ecc_pub_key_file_point(&raw).unwrap();
+        assert_eq!(field_length, 256);
+        assert_eq!(point[0], 4);
+        assert_eq!(point[1..], g);
+        assert_eq!(ecc_pub_key_file_point(&raw[..68]), Err(SC_ERROR_INCOMPATIBLE_KEY)); // too short
+        raw.push(0);
+        assert_eq!(ecc_pub_key_file_point(&raw), Err(SC_ERROR_INCOMPATIBLE_KEY)); // too long
+        raw.pop();
+        raw[1] = 5;
+        assert_eq!(ecc_pub_key_file_point(&raw), Err(SC_ERROR_INCOMPATIBLE_KEY)); // unknown curve code
+        raw[1] = 3;
+        raw.extend_from_slice(&[0x11; 32]);
+        assert_eq!(ecc_pub_key_file_point(&raw), Err(SC_ERROR_INCOMPATIBLE_KEY)); // length fits P-384, G isn't on it
+        raw.truncate(69);
+        raw[1] = 2;
+        raw[68] ^= 1;
+        assert_eq!(ecc_pub_key_file_point(&raw), Err(SC_ERROR_INCOMPATIBLE_KEY)); // not on the curve
+        let mut raw = vec![0_u8; 69];
+        raw[1] = 2;
+        assert_eq!(ecc_pub_key_file_point(&raw), Err(SC_ERROR_INCOMPATIBLE_KEY)); // nothing generated
+    }
+
+    #[test]
+    fn test_ecdsa_signature_to_rs() {
+        /* DER: r with a leading zero byte (high bit set), s shorter than coord_len */
+        let der = [0x30, 0x0A, 0x02, 0x05, 0x00, 0x81, 0x02, 0x03, 0x04, 0x02, 0x01, 0x05];
+        assert_eq!(ecdsa_signature_to_rs(&der, 4), Ok(vec![0x81, 2, 3, 4, 0, 0, 0, 5]));
+        assert_eq!(ecdsa_signature_to_rs(&der, 3), Err(SC_ERROR_UNKNOWN_DATA_RECEIVED)); // r too long
+        /* r||s passes unchanged */
+        let rs = [0x30_u8, 1, 2, 3, 4, 5, 6, 7];
+        assert_eq!(ecdsa_signature_to_rs(&rs, 4), Ok(rs.to_vec()));
+        assert_eq!(ecdsa_signature_to_rs(&rs[..7], 4), Err(SC_ERROR_UNKNOWN_DATA_RECEIVED));
+    }
+
+    #[test]
+    fn test_aodf_pin_attributes() {
+        use crate::card_initialization::EF_AODF;
+        use opensc_sys::opensc::SC_PIN_ENCODING_ASCII;
+        let user_pin = PinAttributes { encoding: SC_PIN_ENCODING_ASCII, min_length: 4, stored_length: 8, max_length: 8,
+            pad_char: Some(0xFF) };
+        assert_eq!(aodf_pin_attributes(&EF_AODF), [(0x81, user_pin), (0x01, user_pin)]);
+        /* zero bytes following the entries (unused part of the file) */
+        let mut aodf = EF_AODF.to_vec();
+        aodf.resize(256, 0);
+        assert_eq!(aodf_pin_attributes(&aodf), [(0x81, user_pin), (0x01, user_pin)]);
+        assert_eq!(aodf_pin_attributes(&[0; 16]), []);
+    }
 }
@@ -1,23 +1,42 @@
 use std::ptr::{/*null,*/ null_mut};
 use std::ffi::CStr;
+use std::os::raw::{c_void, c_ulong};
+use std::ops::Range;
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
-use opensc_sys::opensc::{sc_card, sc_get_mf_path/*, sc_format_path*/, sc_select_file};
-use opensc_sys::types::{sc_crt};
+use opensc_sys::opensc::{sc_card, sc_get_mf_path/*, sc_format_path*/, sc_select_file, sc_read_record, SC_RECORD_BY_REC_NR,
+                         sc_update_record, sc_delete_file, sc_create_file, sc_file_new, sc_file_free, sc_file_add_acl_entry};
+use opensc_sys::types::{sc_file, sc_crt, sc_path, SC_MAX_PATH_SIZE, SC_PATH_TYPE_PATH, SC_AC_NONE, SC_AC_NEVER, SC_AC_SCB,
+                        SC_AC_KEY_REF_NONE, SC_AC_OP_READ, SC_AC_OP_UPDATE, SC_AC_OP_CRYPTO, SC_AC_OP_INVALIDATE,
+                        SC_AC_OP_REHABILITATE, SC_AC_OP_LOCK, SC_AC_OP_DELETE_SELF};
+use opensc_sys::scconf::{scconf_get_bool};
 //#[cfg(not(target_os = "windows"))]
 //use opensc_sys::types::{sc_aid};
-use opensc_sys::errors::{/*SC_SUCCESS,*/ SC_ERROR_NOT_ALLOWED, SC_ERROR_FILE_NOT_FOUND};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_NOT_ALLOWED, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_BUFFER_TOO_SMALL, SC_ERROR_INVALID_ARGUMENTS,
+                         SC_ERROR_OUT_OF_MEMORY};
 // /*, SC_ERROR_INTERNAL*/, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_KEYPAD_MSG_TOO_LONG,
 //                          SC_ERROR_NO_CARD_SUPPORT, SC_ERROR_INCOMPATIBLE_KEY, SC_ERROR_WRONG_CARD, SC_ERROR_WRONG_PADDING,
 //                          SC_ERROR_INCORRECT_PARAMETERS, SC_ERROR_NOT_SUPPORTED, SC_ERROR_BUFFER_TOO_SMALL, SC_ERROR_NOT_ALLOWED,
 //                          SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, SC_ERROR_CARD_CMD_FAILED
 
 
-use crate::wrappers::{wr_do_log, wr_do_log_ttt};
+use crate::wrappers::{wr_do_log, wr_do_log_sds, wr_do_log_ttt};
 use crate::cmd_card_info::{get_card_life_cycle_byte_eeprom, get_op_mode_byte_eeprom, get_zeroize_card_disable_byte_eeprom};
 use crate::no_cdecl::{update_hashmap};
-use crate::constants_types::{DataPrivate, is_DFMF, FDB_SE_FILE, READ, file_id_se, is_child_of /*file_id, p_void*/};
-use crate::se::se_get_references;
+use crate::constants_types::{DataPrivate, is_DFMF, FDB_SE_FILE, READ, file_id_se, is_child_of, ValueTypeFiles, SACinfo,
+                             FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, LCSI_ACTIVATED, SC_CARD_TYPE_ACOS5_64_V2,
+                             SanityFinding, CardCtlSanityCheck, SANITY_MAX_TEXT, SANITY_SEVERITY_INFO, SANITY_SEVERITY_WARNING,
+                             SANITY_SEVERITY_ERROR, SANITY_CHECK_MF, SANITY_CHECK_SE_FILE, SANITY_CHECK_SE_REFERENCES,
+                             SANITY_CHECK_PIN_FILE, SANITY_CHECK_KEY_FILE, SANITY_CHECK_FILE_PROTECTION, SANITY_CHECK_SE_RECORD_USE,
+                             SANITY_CHECK_SE_RECORD_ID, SANITY_CHECK_SE_HOLE, SANITY_CHECK_SAE, SANITY_CHECK_LCSI,
+                             SANITY_CHECK_PKCS15_DIR, SANITY_CHECK_PKCS15_ODF, SANITY_CHECK_PKCS15_TOKENINFO,
+                             SANITY_CHECK_PKCS15_OBJECTS, CardCtlSanityRepair, CREATE_EF /*file_id, p_void*/};
+use crate::se::{se_get_references, se_get_ac_report, se_render_ac_report, se_file_of_dir, se_encode_sac, se_read_records};
+use crate::lifecycle::{lifecycle_set};
+use crate::sm::{sm_conf_block};
+#[cfg(not(target_os = "windows"))]
+use crate::tasn1_pkcs15_util::{validate_PKCS15, dangling_xdf_entries, remove_xdf_entries};
 /* * /
 cfg_if::cfg_if! {
     if #[cfg(not(target_os = "windows"))] {
@@ -35,14 +54,143 @@ fn select_mf(card: &mut sc_card) -> Result<i32, i32> {
     }
 }
 
+/// One result of `sanity_check`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity      : u8,  // SANITY_SEVERITY_*
+    pub check_id      : u8,  // SANITY_CHECK_*
+    pub file_id       : u16, // 0 if no file is concerned
+    pub message       : String,
+    pub suggested_fix : String,
+}
+
+/* Collects the findings of all checks; prints them as well, if printable */
+struct Findings {
+    printable : bool,
+    vec       : Vec<Finding>,
+}
+
+impl Findings {
+    fn add(&mut self, severity: u8, check_id: u8, file_id: u16, message: String, suggested_fix: &str) {
+        self.push(Finding { severity, check_id, file_id, message, suggested_fix: suggested_fix.to_string() });
+    }
+
+    fn push(&mut self, finding: Finding) {
+        if self.printable {
+            match finding.severity {
+                SANITY_SEVERITY_ERROR   => println!("[X] ERROR: {}", finding.message),
+                SANITY_SEVERITY_WARNING => println!("[X] WARNING: {}", finding.message),
+                _                       => println!("[X] {}", finding.message),
+            }
+        }
+        self.vec.push(finding);
+    }
+}
+
+fn severity_name(severity: u8) -> &'static str {
+    match severity {
+        SANITY_SEVERITY_ERROR   => "error",
+        SANITY_SEVERITY_WARNING => "warning",
+        _                       => "info",
+    }
+}
+
+fn check_name(check_id: u8) -> &'static str {
+    match check_id {
+        SANITY_CHECK_MF              => "mf",
+        SANITY_CHECK_SE_FILE         => "se_file",
+        SANITY_CHECK_SE_REFERENCES   => "se_references",
+        SANITY_CHECK_PIN_FILE        => "pin_file",
+        SANITY_CHECK_KEY_FILE        => "key_file",
+        SANITY_CHECK_FILE_PROTECTION => "file_protection",
+        SANITY_CHECK_SE_RECORD_USE   => "se_record_use",
+        SANITY_CHECK_SE_RECORD_ID    => "se_record_id",
+        SANITY_CHECK_SE_HOLE         => "se_hole",
+        SANITY_CHECK_SAE             => "sae",
+        SANITY_CHECK_LCSI            => "lcsi",
+        SANITY_CHECK_PKCS15_DIR      => "pkcs15_dir",
+        SANITY_CHECK_PKCS15_ODF      => "pkcs15_odf",
+        SANITY_CHECK_PKCS15_TOKENINFO => "pkcs15_tokeninfo",
+        SANITY_CHECK_PKCS15_OBJECTS  => "pkcs15_objects",
+        _                            => "unknown",
+    }
+}
+
+fn json_escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len()+8);
+    for c in text.chars() {
+        match c {
+            '"'  => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Serializes findings as JSON array of objects with members
+/// severity, check_id, check, file_id (4 hex digits), message, suggested_fix
+#[must_use]
+pub fn findings_to_json(findings: &[Finding]) -> String {
+    let vec : Vec<String> = findings.iter().map(|finding| format!(
+        "{{\"severity\":\"{}\",\"check_id\":{},\"check\":\"{}\",\"file_id\":\"{:04X}\",\"message\":\"{}\",\"suggested_fix\":\"{}\"}}",
+        severity_name(finding.severity), finding.check_id, check_name(finding.check_id), finding.file_id,
+        json_escape(&finding.message), json_escape(&finding.suggested_fix))).collect();
+    format!("[{}]", vec.join(","))
+}
+
+/* copy text NUL-terminated into dst, truncated if required */
+fn copy_text(dst: &mut [u8; SANITY_MAX_TEXT], text: &str) {
+    let len = std::cmp::min(text.len(), SANITY_MAX_TEXT-1);
+    dst[..len].copy_from_slice(&text.as_bytes()[..len]);
+    dst[len..].iter_mut().for_each(|b| *b = 0);
+}
+
+/// Hands out findings via the caller-provided buffers of `CardCtlSanityCheck`
+/// @return  SC_SUCCESS or SC_ERROR_BUFFER_TOO_SMALL, if a buffer doesn't take all; the ..._len members are set anyway
+pub fn sanity_check_card_ctl(findings: &[Finding], data: &mut CardCtlSanityCheck) -> i32 {
+    let mut rv = SC_SUCCESS;
+    data.findings_len = findings.len();
+    data.count_errors = findings.iter().filter(|finding| finding.severity == SANITY_SEVERITY_ERROR).count();
+    data.count_warnings = findings.iter().filter(|finding| finding.severity == SANITY_SEVERITY_WARNING).count();
+    if !data.findings.is_null() {
+        if data.findings_cap < findings.len() { rv = SC_ERROR_BUFFER_TOO_SMALL; }
+        let buf = unsafe { std::slice::from_raw_parts_mut(data.findings, std::cmp::min(data.findings_cap, findings.len())) };
+        for (dst, finding) in buf.iter_mut().zip(findings) {
+            *dst = SanityFinding { severity: finding.severity, check_id: finding.check_id, file_id: finding.file_id, ..SanityFinding::default() };
+            copy_text(&mut dst.message, &finding.message);
+            copy_text(&mut dst.suggested_fix, &finding.suggested_fix);
+        }
+    }
+    let json = findings_to_json(findings);
+    data.json_len = json.len();
+    if !data.json.is_null() {
+        if data.json_cap > json.len() {
+            unsafe {
+                std::ptr::copy_nonoverlapping(json.as_ptr(), data.json.cast::<u8>(), json.len());
+                *data.json.add(json.len()) = 0;
+            }
+        }
+        else { rv = SC_ERROR_BUFFER_TOO_SMALL; }
+    }
+    rv
+}
+
+/// Checks the card's file system and access control for consistency and possible pitfalls
+/// @param   quiet  if false, the findings get printed as well (except for opensc-pkcs11)
+/// @return  Result::Ok(findings), or an OpenSC error, if the checks couldn't be done
 #[cold]
 #[allow(clippy::too_many_lines)]
-pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
+pub fn sanity_check(card: &mut sc_card, app_name: &CStr, quiet: bool) -> Result<Vec<Finding>, i32> {
     assert!(!card.ctx.is_null());
     let ctx = unsafe { &mut *card.ctx };
     let f = cstru!(b"sanity_check\0");
     log3ifc!(ctx,f,line!());
-    let printable = app_name != unsafe { CStr::from_bytes_with_nul_unchecked(b"opensc-pkcs11\0") };
+    let printable = !quiet && app_name != unsafe { CStr::from_bytes_with_nul_unchecked(b"opensc-pkcs11\0") };
+    let mut findings = Findings { printable, vec: Vec::with_capacity(16) };
     if printable {
         println!();
         println!("The following sanity checks are implemented [X] or planned []. Some or all may depend on prior check(s)");
@@ -54,22 +202,22 @@ pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
         println!("File system, Security Access Conditions (SAC) and Security Attributes Expanded (SAE) etc.");
         println!("[X] Does each DF/MF specify the mandatory security environment (SE) file and it does exist and is accessible?");
         println!("[X] Check whether all references to SE file are satisfied; list unused SE file record(s)");
-        println!("[] PIN files are required for at least MF and each appDF, with max. 1 such file existing there. Is that okay?");
-        println!("[] Sym. key file(s) are required only if Secure Messaging (SM) is involved in a DF/MF, with max. 1 such file existing there. Is that okay?");
-        println!("[] Are the PIN files and Sym. key file(s) constrained to: Never readable, and files activated such that the constraint will be upheld by the cos (card operating system?");
-        println!("[] List all SE file record entries, that are unused (and might be deleted), and list all that refer to SM");
+        println!("[X] PIN files are required for at least MF and each appDF, with max. 1 such file existing there. Is that okay?");
+        println!("[X] Sym. key file(s) are required only if Secure Messaging (SM) is involved in a DF/MF, with max. 1 such file existing there. Is that okay?");
+        println!("[X] Are the PIN files and Sym. key file(s) constrained to: Never readable, and files activated such that the constraint will be upheld by the cos (card operating system?");
+        println!("[X] List all SE file record entries, that are unused (and might be deleted), and list all that refer to SM");
         println!("[] Are the SE file record entries (if used) meaningful, i.e. don't refer to something that doesn't exist?");
-        println!("[] Are there 'holes' in SE file? Reading records stops at first zero record");
-        println!("[] Is SE file record index == internal id of record? 00 B2 06 04 38:  80 01 06 ...");
+        println!("[X] Are there 'holes' in SE file? Reading records stops at first zero record");
+        println!("[X] Is SE file record index == internal id of record? 00 B2 06 04 38:  80 01 06 ...");
         println!("[] TODO check other recommended access rights, e.g. for RSA key pair files");
-        println!("[] List all DF/MF that specify SAE and try a plain explanation of constraints");
-        println!("  [] SAE: Check whether the instruction (cla,p1,p2) referred to are allowed for current hardware and meaningful (e.g. disallowing ins A4 would be nonsense: nothing would work in that DF ");
+        println!("[X] List the access control requirements of all files, incl. a plain explanation of SAE constraints of their DF/MF");
+        println!("  [X] SAE: Check whether the instruction (cla,p1,p2) referred to are allowed for current hardware and meaningful (e.g. disallowing ins A4 would be nonsense: nothing would work in that DF ");
         /*
         AB 0B
         84 01 2C 97 00         INS 2C (Unblock Pin)  Never allow
         84 01 24 9E 01 42      INS 24 (Change Code)  SAC SC byte 42  This is wrong for ACOS5 2.00 which doesn't support SM for INS 24
         */
-        println!("[] Warn about non-security-activated files");
+        println!("[X] Warn about non-security-activated files");
         println!("TODO more to check here");
         println!();
         println!("Issues, that can't be checked, i.e. just be careful or ?");
@@ -80,10 +228,12 @@ pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
         println!("It's inadvisable to block Your emergency exit door: Make sure - by all means -, that Access Control always allows to issue command 'Zeroize Card' (part of re-initialization)");
         println!();
         println!("PKCS#15 related checks");
-        println!("[] Does EF.DIR 3F002F00 exist, is accessible and has appropriate content, specifying at least 1 appDF path and it's aid?");
-        println!("   [] List for all appDF(s) the information encoded in EF.DIR");
-        println!("   [] Do appDF's path exist in file system and are accessible?");
-        println!("      [] Does EF.ODF exist in each appDF and is accessible?");
+        println!("[X] Does EF.DIR 3F002F00 exist, is accessible and has appropriate content, specifying at least 1 appDF path and it's aid?");
+        println!("   [X] List for all appDF(s) the information encoded in EF.DIR");
+        println!("   [X] Do appDF's path exist in file system and are accessible?");
+        println!("      [X] Does EF.ODF exist in each appDF and is accessible? Do it's entries point to existing xDFs?");
+        println!("      [X] Does EF.TokenInfo exist in each appDF and decode as PKCS15.TokenInfo?");
+        println!("      [X] Do all PrKDF/PuKDF/SKDF entries point to existing key files (and records) of the right FDB?");
         println!("[TODO more to check here]");
         println!("-------------------------------------------------------------------------------------------------------");
         println!();
@@ -107,10 +257,13 @@ pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
         };
 
         if printable { println!("### There is no MF: The card is uninitialized/virgin/in factory state ### (Card Life Cycle Byte is 0x{:X}, Operation Mode Byte is 0x{:X}, Zeroize Card Disable Byte is 0x{:X})", card_life_cycle_byte, operation_mode_byte, zeroize_card_disable_byte) }
+        findings.printable = false;
+        findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_MF, 0x3F00, "There is no MF: The card is uninitialized/virgin/in factory state".to_string(),
+            "Initialize the card, e.g. with info/card_initialization/card_initialization.scriptor");
         log3ift!(ctx,f,line!(), cstru!(
                 b"### There is no MF: The card is uninitialized/virgin/in factory state ### (Card Life Cycle Byte is 0x%02X, Operation Mode Byte is 0x%02X, Zeroize Card Disable Byte is 0x%02X)\0"),
                 card_life_cycle_byte, operation_mode_byte, zeroize_card_disable_byte);
-        return Ok(());
+        return Ok(findings.vec);
     }
     if printable { println!("[X] Does MF exist?  Yes") }
     update_hashmap(card);
@@ -130,21 +283,26 @@ pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
     }
     / * */
     let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
-    for (&key_dfmf, val) in &dp.files {
-        if is_DFMF(val.1[0]) {
+    for key_dfmf in dirs_sorted(&dp) {
+        let val = &dp.files[&key_dfmf];
+        {
             let child_id = file_id_se(val.1);
             assert!(dp.files.contains_key(&child_id)); // or it doesn't exist
             let dpfv_child = &dp.files[&child_id];
             if  dpfv_child.1[0] != FDB_SE_FILE || !is_child_of(dpfv_child, val) {
-                println!("DF {:04X} does declare SE file id {:04X}, but either this is no SE-file or is not a child", key_dfmf, child_id);
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_FILE, key_dfmf,
+                    format!("DF {:04X} does declare SE file id {:04X}, but either this is no SE-file or is not a child", key_dfmf, child_id),
+                    "Create the SE file (FDB 0x1C) within the DF with the file id declared in the DF's header");
             }
             else if dpfv_child.2.is_none() || dpfv_child.2.unwrap()[READ] != 0 {
-                println!("WARNING: Security Access Condition of SE file id {:04X} is different from 'ALWAYS READABLE'. \
-                Hence, OpenSC and this driver won't know any file related Security Access Constraint in directory \
-                {:04X} and You may run into all sorts of errors related to Access Control", child_id, key_dfmf);
+                findings.add(SANITY_SEVERITY_WARNING, SANITY_CHECK_SE_FILE, child_id,
+                    format!("Security Access Condition of SE file id {:04X} is different from 'ALWAYS READABLE'. \
+                    Hence, OpenSC and this driver won't know any file related Security Access Constraint in directory \
+                    {:04X} and You may run into all sorts of errors related to Access Control", child_id, key_dfmf),
+                    "Make the SE file always readable (SCB 0x00 for READ)");
             }
             else {
-                println!("\n[X] DF/MF {:04X} mandatory security environment (SE) file {:04X} seems to be okay (content checked next).", key_dfmf, child_id);
+                if printable { println!("\n[X] DF/MF {:04X} mandatory security environment (SE) file {:04X} seems to be okay (content checked next).", key_dfmf, child_id); }
                 let mut index_used : HashSet<u8> = HashSet::with_capacity(14);
                 for &b in &val.2.unwrap() {
                     if ![0, 255].contains(&b) {
@@ -163,25 +321,27 @@ pub fn sanity_check(card: &mut sc_card, app_name: &CStr) -> Result<(), i32> {
                         }
                     }
                 }
-println!("[X] DF/MF {:04X} references found: {:X?} ", key_dfmf, index_used);
+if printable { println!("[X] DF/MF {:04X} references found: {:X?} ", key_dfmf, index_used); }
                 let mut vec_add_to_set = Vec::with_capacity(8);
                 for &index in &index_used {
                     if (index & 0x30) > 0 {
-println!("[X] DF/MF {:04X} reference {:X} has bit(s) set that are unused", key_dfmf, index);
+                        findings.add(SANITY_SEVERITY_WARNING, SANITY_CHECK_SE_REFERENCES, key_dfmf,
+                            format!("DF/MF {:04X} reference {:X} has bit(s) set that are unused", key_dfmf, index),
+                            "Clear bits 0x30 of the SCB");
                         vec_add_to_set.push(index & 0x0F);
                     }
                     if (index & 0x40) > 0 {
-println!("[X] DF/MF {:04X} reference {:X} has bit 'Secure Messaging' set", key_dfmf, index);
+if printable { println!("[X] DF/MF {:04X} reference {:X} has bit 'Secure Messaging' set", key_dfmf, index); }
                         vec_add_to_set.push(index & 0x0F);
                     }
                     if (index & 0x80) > 0 {
-println!("[X] DF/MF {:04X} reference {:X} has MSB bit set: TODO check whether OpenSC/driver supports logical conjunction of constraints", key_dfmf, index);
+if printable { println!("[X] DF/MF {:04X} reference {:X} has MSB bit set: TODO check whether OpenSC/driver supports logical conjunction of constraints", key_dfmf, index); }
                         vec_add_to_set.push(index & 0x0F);
                     }
                 }
                 index_used.retain(|&k| (k & 0xB0) == 0);
                 index_used.extend(vec_add_to_set);
-println!("[X] DF/MF {:04X} references (reduced set) found: {:X?} ", key_dfmf, index_used);
+if printable { println!("[X] DF/MF {:04X} references (reduced set) found: {:X?} ", key_dfmf, index_used); }
                 let mut once = false;
                 for &index in &index_used {
                     if index & 0x40 > 0 &&
@@ -189,14 +349,18 @@ println!("[X] DF/MF {:04X} references (reduced set) found: {:X?} ", key_dfmf, in
                         se_get_references(card, key_dfmf, index & 0x0F, &sc_crt::new_CCT(0x70), false).is_empty()
                     {
                         once = true;
-                        println!("[X] ERROR: The record #{} in SE-file {} shall be used for SM, but it has no suitable CCT template", index & 0x0F, child_id);
+                        findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_REFERENCES, child_id,
+                            format!("The record #{} in SE-file {:04X} shall be used for SM, but it has no suitable CCT template", index & 0x0F, child_id),
+                            "Add a CCT (tag B4) to the record, or clear the SM bit 0x40 of the referring SCB");
                     }
                     if se_get_references(card, key_dfmf, index & 0x0F, &sc_crt::new_AT(0x88), true).is_empty() {
                         once = true;
-                        println!("[X] ERROR: There is no record #{} in SE-file {}, but it gets referenced for Access Control", index & 0x0F, child_id);
+                        findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_REFERENCES, child_id,
+                            format!("There is no record #{} in SE-file {:04X}, but it gets referenced for Access Control", index & 0x0F, child_id),
+                            "Add the record (card_ctl SC_CARDCTL_ACOS5_SE_RECORD_ADD), or change the referring SCB");
                     }
                 }
-                if !once {
+                if !once && printable {
                     println!("[X] DF/MF {:04X} mandatory security environment (SE) file {:04X} content satisfies all references).", key_dfmf, child_id);
                 }
 
@@ -220,11 +384,610 @@ println!("[X] DF/MF {:04X} references (reduced set) found: {:X?} ", key_dfmf, in
     //         println!("val is_SE  : {:X?}", *val);
     //     }
     // }
+    let mut vec_path_file_id : Vec<([u8; SC_MAX_PATH_SIZE], u16)> = dp.files.iter().map(|(&key, val)| (val.0, key)).collect();
+    vec_path_file_id.sort_unstable();
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+
+    if printable { println!(); }
+    check_pin_and_key_files(card, &mut findings);
+    check_se_records(card, &mut findings);
+    check_sae(card, &mut findings);
+    check_lcsi(card, &mut findings);
+    #[cfg(not(target_os = "windows"))]
+    {
+        if printable { println!("\nPKCS#15 related checks"); }
+        for finding in validate_PKCS15(card) {
+            findings.push(finding);
+        }
+    }
+
+    if printable {
+        println!("\n[X] Access control requirements per file:");
+        for (_path, file_id) in vec_path_file_id {
+            match se_get_ac_report(card, file_id) {
+                Ok(report) => print!("{}", se_render_ac_report(&report)),
+                Err(e) => println!("File {:04X}: access control report not available (error {})", file_id, e),
+            }
+        }
+    }
+    Ok(findings.vec)
+}
+
+/* INS of commands, that ACOS5 knows (CLA 0x00 or 0x80) */
+const KNOWN_INS : [u8; 30] = [0x14, 0x30, 0x2E, 0x8A, 0xA4, 0xC0, 0xB0, 0xD6, 0x0E, 0xB2, 0xDC, 0xE2, 0x20, 0x24, 0x2C,
+    0x84, 0x82, 0x88, 0x22, 0x2A, 0x46, 0xE0, 0xE4, 0x44, 0x04, 0xE6, 0xE8, 0xCA, 0xDA, 0x1E];
+
+/* DF/MF file ids, sorted by path */
+fn dirs_sorted(dp: &DataPrivate) -> Vec<u16> {
+    let mut vec_path_file_id : Vec<([u8; SC_MAX_PATH_SIZE], u16)> = dp.files.iter().filter(|(_, val)| is_DFMF(val.1[0]))
+        .map(|(&key, val)| (val.0, key)).collect();
+    vec_path_file_id.sort_unstable();
+    vec_path_file_id.into_iter().map(|(_, key)| key).collect()
+}
+
+/* The EFs directly contained in DF/MF file_id_dir */
+fn children_of(dp: &DataPrivate, file_id_dir: u16) -> Vec<(u16, &ValueTypeFiles)> {
+    let val_dir = &dp.files[&file_id_dir];
+    dp.files.iter().filter(|(_, val)| !is_DFMF(val.1[0]) && is_child_of(val, val_dir)).map(|(&key, val)| (key, val)).collect()
+}
+
+/* All SCBs of DF/MF file_id_dir and it's EFs, that refer to the DF's SE file, and the SAE ones (those refer to virtual records from 16 on) */
+fn scbs_of(dp: &DataPrivate, file_id_dir: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut scbs = Vec::with_capacity(64);
+    if let Some(scb8) = dp.files[&file_id_dir].2 { scbs.extend_from_slice(&scb8); }
+    for (_, val) in children_of(dp, file_id_dir) {
+        if let Some(scb8) = val.2 { scbs.extend_from_slice(&scb8); }
+    }
+    scbs.retain(|&scb| scb != 0 && scb != 0xFF);
+    let mut scbs_sae = Vec::with_capacity(8);
+    if let Some(vec_sae_info) = &dp.files[&file_id_dir].4 {
+        for sae_info in vec_sae_info {
+            scbs_sae.extend(sae_info.scbs[..usize::from(sae_info.scbs_len)].iter().filter(|&&scb| scb != 0 && scb != 0xFF));
+        }
+    }
+    (scbs, scbs_sae)
+}
+
+/* PIN file and sym. key file: max. 1 per DF/MF, a PIN file is required for MF and each appDF (has EF.ODF 5031),
+   a sym. key file is required, if SM is used. Both must be never readable and activated */
+fn check_pin_and_key_files(card: &mut sc_card, findings: &mut Findings) {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let mut once = false;
+    let vec_dir = dirs_sorted(&dp);
+    let mf_has_key_file = dp.files.contains_key(&0x3F00) && children_of(&dp, 0x3F00).iter().any(|(_, val)| val.1[0] == FDB_SYMMETRIC_KEY_EF);
+    for &file_id_dir in &vec_dir {
+        let children = children_of(&dp, file_id_dir);
+        let pin_files : Vec<&(u16, &ValueTypeFiles)> = children.iter().filter(|(_, val)| val.1[0] == FDB_CHV_EF).collect();
+        let key_files : Vec<&(u16, &ValueTypeFiles)> = children.iter().filter(|(_, val)| val.1[0] == FDB_SYMMETRIC_KEY_EF).collect();
+        let is_app_df = children.iter().any(|&(key, _)| key == 0x5031);
+        if pin_files.len() > 1 {
+            once = true;
+            findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_PIN_FILE, file_id_dir,
+                format!("DF/MF {:04X} has {} PIN files, max. 1 is allowed", file_id_dir, pin_files.len()),
+                "Delete all but 1 PIN file of the DF/MF");
+        }
+        else if pin_files.is_empty() && (file_id_dir == 0x3F00 || is_app_df) {
+            once = true;
+            findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_PIN_FILE, file_id_dir,
+                format!("{} {:04X} has no PIN file", if file_id_dir == 0x3F00 {"MF"} else {"appDF"}, file_id_dir),
+                "Create a PIN file (FDB 0x0A) within the DF/MF");
+        }
+        if key_files.len() > 1 {
+            once = true;
+            findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_KEY_FILE, file_id_dir,
+                format!("DF/MF {:04X} has {} sym. key files, max. 1 is allowed", file_id_dir, key_files.len()),
+                "Delete all but 1 sym. key file of the DF/MF");
+        }
+        let (scbs, scbs_sae) = scbs_of(&dp, file_id_dir);
+        let is_sm_used = scbs.iter().chain(scbs_sae.iter()).any(|&scb| (scb & 0x40) != 0);
+        if is_sm_used && key_files.is_empty() && !mf_has_key_file {
+            once = true;
+            findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_KEY_FILE, file_id_dir,
+                format!("DF/MF {:04X} uses SM, but there is no sym. key file, neither in the DF nor in MF", file_id_dir),
+                "Create a sym. key file (FDB 0x0C) with the SM keys, or don't require SM");
+        }
+        else if !is_sm_used && !key_files.is_empty() {
+            findings.add(SANITY_SEVERITY_INFO, SANITY_CHECK_KEY_FILE, key_files[0].0,
+                format!("DF/MF {:04X} has a sym. key file {:04X}, but doesn't use SM (the keys may be used for authentication only)",
+                file_id_dir, key_files[0].0), "");
+        }
+        for &&(file_id, val) in pin_files.iter().chain(key_files.iter()) {
+            let what = if val.1[0] == FDB_CHV_EF {"PIN"} else {"sym. key"};
+            if val.2.map(|scb8| scb8[READ]) != Some(0xFF) {
+                once = true;
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_FILE_PROTECTION, file_id,
+                    format!("The {} file {:04X} is not constrained to: Never readable", what, file_id),
+                    "Set the file's SCB for READ to 0xFF (never)");
+            }
+            if val.1[7] != LCSI_ACTIVATED {
+                once = true;
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_FILE_PROTECTION, file_id,
+                    format!("The {} file {:04X} is not activated (LCSI 0x{:02X}): The cos won't enforce it's access control", what, file_id, val.1[7]),
+                    "Activate the file (command 00 44)");
+            }
+        }
+    }
+    if !once && findings.printable {
+        println!("[X] PIN files and sym. key files are okay");
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+}
+
+/* SE file records: index == internal id, no holes, list unused ones and those for SM */
+fn check_se_records(card: &mut sc_card, findings: &mut Findings) {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let mut vec_dir_count = Vec::with_capacity(8);
+    for file_id_dir in dirs_sorted(&dp) {
+        let vec_sac_info : Vec<&SACinfo> = match &dp.files[&file_id_dir].3 {
+            Some(vec_sac_info) => vec_sac_info.iter().filter(|sac_info| sac_info.reference <= 15).collect(),
+            None => continue,
+        };
+        for (idx, sac_info) in vec_sac_info.iter().enumerate() {
+            if sac_info.reference != u32::try_from(idx+1).unwrap() {
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_RECORD_ID, file_id_dir,
+                    format!("DF/MF {:04X}: SE file record no. {} has the internal id {}", file_id_dir, idx+1, sac_info.reference),
+                    "Rewrite the record such that it starts with 80 01 <record no.>");
+            }
+        }
+        let (scbs, _scbs_sae) = scbs_of(&dp, file_id_dir);
+        let mut vec_sae_ref = Vec::with_capacity(8);
+        if let Some(vec_sae_info) = &dp.files[&file_id_dir].4 {
+            for sae_info in vec_sae_info {
+                vec_sae_ref.extend(sae_info.scbs[..usize::from(sae_info.scbs_len)].iter().filter(|&&scb| (scb & 0xF0) != 0x10 && scb != 0xFF));
+            }
+        }
+        for sac_info in &vec_sac_info {
+            let reference = u8::try_from(sac_info.reference).unwrap();
+            if !scbs.iter().chain(vec_sae_ref.iter()).any(|&scb| (scb & 0x0F) == reference) {
+                findings.add(SANITY_SEVERITY_INFO, SANITY_CHECK_SE_RECORD_USE, file_id_dir,
+                    format!("DF/MF {:04X}: SE file record #{} is unused (and might be deleted)", file_id_dir, reference),
+                    "Remove the record (card_ctl SC_CARDCTL_ACOS5_SE_RECORD_REMOVE), if it's the last one");
+            }
+            if sac_info.crts[..sac_info.crts_len].iter().any(|crt| [0xB4, 0xB8].contains(&crt.tag)) {
+                findings.add(SANITY_SEVERITY_INFO, SANITY_CHECK_SE_RECORD_USE, file_id_dir,
+                    format!("DF/MF {:04X}: SE file record #{} refers to SM", file_id_dir, reference), "");
+            }
+        }
+        vec_dir_count.push((file_id_dir, vec_sac_info.len()));
+    }
     Box::leak(dp);
     // card.drv_data = Box::into_raw(dp) as p_void;
+
+    /* holes: reading records stops at first empty record, thus the records behind are invisible to the driver */
+    for (file_id_dir, count) in vec_dir_count {
+        let (path, mrl, nor) = match se_file_of_dir(card, file_id_dir) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+        if u32::try_from(count+1).unwrap() >= nor || unsafe { sc_select_file(card, &path, null_mut()) } != SC_SUCCESS {
+            continue;
+        }
+        for rec_nr in u32::try_from(count+2).unwrap()..=nor {
+            let mut buf = [0_u8; 255];
+            let rv = unsafe { sc_read_record(card, rec_nr, buf.as_mut_ptr(), std::cmp::min(mrl, buf.len()), SC_RECORD_BY_REC_NR) };
+            if rv >= 1 && buf[0] != 0 {
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_HOLE, file_id_dir,
+                    format!("DF/MF {:04X}: SE file has a hole: record no. {} is empty, but record no. {} is not. The driver doesn't know the records behind the hole",
+                    file_id_dir, count+1, rec_nr), "Move the records behind the hole such that record numbers are consecutive, and adapt the ids/references");
+                break;
+            }
+        }
+    }
+}
+
+/* SAE: the instructions referred to must be known and the constraints meaningful for the hardware */
+fn check_sae(card: &mut sc_card, findings: &mut Findings) {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    for file_id_dir in dirs_sorted(&dp) {
+        let vec_sae_info = match &dp.files[&file_id_dir].4 {
+            Some(vec_sae_info) => vec_sae_info,
+            None => continue,
+        };
+        for sae_info in vec_sae_info {
+            let scbs = &sae_info.scbs[..usize::from(sae_info.scbs_len)];
+            let is_never = if sae_info.tag_SCDO == 0xA0 { scbs.iter().all(|&scb| scb == 0xFF) } else { scbs.contains(&0xFF) };
+            if !KNOWN_INS.contains(&sae_info.ins) {
+                findings.add(SANITY_SEVERITY_WARNING, SANITY_CHECK_SAE, file_id_dir,
+                    format!("DF/MF {:04X}: SAE refers to INS {:02X}, which is no ACOS5 instruction", file_id_dir, sae_info.ins),
+                    "Remove this SAE entry from the DF's header");
+            }
+            if is_never && [0xA4, 0xC0].contains(&sae_info.ins) {
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SAE, file_id_dir,
+                    format!("DF/MF {:04X}: SAE disallows INS {:02X}: Nothing would work in that DF", file_id_dir, sae_info.ins),
+                    "Remove this SAE entry from the DF's header");
+            }
+            if card.type_ == SC_CARD_TYPE_ACOS5_64_V2 && sae_info.ins == 0x24 && scbs.iter().any(|&scb| scb != 0xFF && (scb & 0x40) != 0) {
+                findings.add(SANITY_SEVERITY_ERROR, SANITY_CHECK_SAE, file_id_dir,
+                    format!("DF/MF {:04X}: SAE requires SM for INS 24 (Change Code), which ACOS5 V2.00 doesn't support", file_id_dir),
+                    "Clear the SM bit 0x40 of the SAE's SCB for INS 24");
+            }
+        }
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+}
+
+/* Files not in LCSI 'Operational state (activated)' don't get their access control enforced */
+fn check_lcsi(card: &mut sc_card, findings: &mut Findings) {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let mut vec_path_file_id : Vec<([u8; SC_MAX_PATH_SIZE], u16, u8)> = dp.files.iter()
+        .filter(|(_, val)| val.1[7] != LCSI_ACTIVATED).map(|(&key, val)| (val.0, key, val.1[7])).collect();
+    vec_path_file_id.sort_unstable();
+    for (_, file_id, lcsi) in vec_path_file_id {
+        findings.add(SANITY_SEVERITY_WARNING, SANITY_CHECK_LCSI, file_id,
+            format!("File {:04X} is not activated (LCSI 0x{:02X}): It's access control is not enforced", file_id, lcsi),
+            "Activate the file (command 00 44)");
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+}
+
+/* A mechanical fix of a sanity finding, that sanity_repair can apply, or a finding listed in the plan as not repaired */
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Repair {
+    /* Recreate the SE file with READ always allowed. The records get preserved, if they are readable (after SO
+       authentication); otherwise (READ never allowed) the SE file gets the initial content as for SeFileCreate, which
+       gets planned only with opensc.conf: sanity_repair_drop_se_records = yes; */
+    SeFileReadable { file_id_dir: u16, file_id_se: u16, is_preserving: bool, pin_ref: u8 },
+    /* Create the SE file declared by the DF/MF, with 1 record: verify PIN pin_ref. If is_replacing, the (non-SE) file
+       with that id gets deleted before */
+    SeFileCreate { file_id_dir: u16, file_id_se: u16, is_replacing: bool, pin_ref: u8 },
+    /* Remove the xDF entries, that refer to non-existing key files */
+    XdfEntriesRemove { file_id_xdf: u16, ranges: Vec<Range<usize>> },
+    /* No action: SE records from..=to are referenced but missing; which access condition they shall express is up to
+       the user (SC_CARDCTL_ACOS5_SE_RECORD_ADD) */
+    SeRecordsMissing { file_id_dir: u16, from: u8, to: u8 },
+    /* No action: The SE file isn't always readable, but recreating it isn't possible or would drop it's records */
+    SeFileNotReadable { file_id_dir: u16, file_id_se: u16, is_create_ef_allowed: bool },
+}
+
+impl Repair {
+    /* false for the findings, that are listed in the plan without an action */
+    fn is_action(&self) -> bool {
+        !matches!(self, Repair::SeRecordsMissing { .. } | Repair::SeFileNotReadable { .. })
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Repair::SeFileReadable { file_id_dir, file_id_se, is_preserving: true, .. } =>
+                format!("DF/MF {:04X}: Recreate SE file {:04X} with READ always allowed, preserving it's records (the SE file gets deleted and created again)",
+                    file_id_dir, file_id_se),
+            Repair::SeFileReadable { file_id_dir, file_id_se, is_preserving: false, pin_ref } =>
+                format!("DF/MF {:04X}: Recreate SE file {:04X} with READ always allowed. It's records are never readable, thus they get LOST and replaced by 1 record: verify PIN 0x{:02X}",
+                    file_id_dir, file_id_se, pin_ref),
+            Repair::SeFileCreate { file_id_dir, file_id_se, is_replacing, pin_ref } =>
+                format!("DF/MF {:04X}: {}Create SE file {:04X} with 1 record: verify PIN 0x{:02X} (run the sanity check again for records referenced additionally)",
+                    file_id_dir, if *is_replacing { format!("DELETE the non-SE file {:04X} and it's content. ", file_id_se) } else { String::new() },
+                    file_id_se, pin_ref),
+            Repair::XdfEntriesRemove { file_id_xdf, ranges } =>
+                format!("xDF {:04X}: Remove {} entr{} referring to a non-existing key file", file_id_xdf, ranges.len(),
+                    if ranges.len() == 1 {"y"} else {"ies"}),
+            Repair::SeRecordsMissing { file_id_dir, from, to } =>
+                format!("DF/MF {:04X}: NOT repaired: SE file record(s) #{}-#{} are referenced but missing; add them according to Your access conditions",
+                    file_id_dir, from, to),
+            Repair::SeFileNotReadable { file_id_dir, file_id_se, is_create_ef_allowed: false } =>
+                format!("DF/MF {:04X}: NOT repaired: SE file {:04X} isn't always readable, and the DF's CREATE_EF isn't always allowed, thus it can't be recreated",
+                    file_id_dir, file_id_se),
+            Repair::SeFileNotReadable { file_id_dir, file_id_se, is_create_ef_allowed: true } =>
+                format!("DF/MF {:04X}: NOT repaired: SE file {:04X} is never readable; recreating it would drop it's records (opensc.conf: sanity_repair_drop_se_records = yes;)",
+                    file_id_dir, file_id_se),
+        }
+    }
+}
+
+/* The plan as presented to the user: one action (or finding not repaired) per line */
+fn repair_plan_text(repairs: &[Repair]) -> String {
+    repairs.iter().enumerate().map(|(i, repair)| format!("{}. {}\n", i+1, repair.describe())).collect()
+}
+
+/* The value of tag 0x8C (SAC) for scb8: The inverse of convert_bytes_tag_fcp_sac_to_scb_array for AM byte 0x7F */
+#[cfg(test)]
+fn bytes_tag_fcp_sac_of(scb8: [u8; 8]) -> [u8; 8] {
+    [0x7F, scb8[6], scb8[5], scb8[4], scb8[3], scb8[2], scb8[1], scb8[0]]
+}
+
+/* The ops of an EF/SE file's acl, that convert_acl_array_to_bytes_tag_fcp_sac maps to scb8[0..7] */
+const SCB8_EF_OPS : [u32; 7] = [SC_AC_OP_READ, SC_AC_OP_UPDATE, SC_AC_OP_CRYPTO, SC_AC_OP_INVALIDATE,
+                                SC_AC_OP_REHABILITATE, SC_AC_OP_LOCK, SC_AC_OP_DELETE_SELF];
+
+/* The acl entries of file for scb8, such that acos5_construct_fci encodes tag 0x8C as bytes_tag_fcp_sac_of(scb8) does */
+fn file_add_acl_scb8(file: &mut sc_file, scb8: [u8; 8]) -> Result<(), i32> {
+    for (&op, &scb) in SCB8_EF_OPS.iter().zip(scb8.iter()) {
+        let (method, key_ref) = match scb {
+            0    => (SC_AC_NONE,  SC_AC_KEY_REF_NONE),
+            0xFF => (SC_AC_NEVER, SC_AC_KEY_REF_NONE),
+            _    => (SC_AC_SCB,   c_ulong::from(scb)),
+        };
+        let rv = unsafe { sc_file_add_acl_entry(file, op, method, key_ref) };
+        if rv != SC_SUCCESS {
+            return Err(rv);
+        }
+    }
     Ok(())
 }
 
+/* SCBs of a created SE file, as in info/card_initialization/card_initialization.scriptor: READ always, LOCK never,
+   everything else requires record #1 */
+const SCB8_SE_FILE_CREATE : [u8; 8] = [0, 1, 0, 1, 1, 0xFF, 1, 0xFF];
+
+/* The PIN reference for SE records of DF/MF file_id_dir: the local PIN #1, if the DF has a PIN file, else the global one */
+fn pin_ref_of(dp: &DataPrivate, file_id_dir: u16) -> Option<u8> {
+    let has_pin_file = |file_id_dir: u16| dp.files.contains_key(&file_id_dir) &&
+        children_of(dp, file_id_dir).iter().any(|(_, val)| val.1[0] == FDB_CHV_EF && val.1[5] > 0);
+    if file_id_dir != 0x3F00 && has_pin_file(file_id_dir) { Some(0x81) }
+    else if has_pin_file(0x3F00)                           { Some(0x01) }
+    else                                                    { None }
+}
+
+/* An SE record verifying PIN pin_ref: 80 01 <reference> A4 06 83 01 <pin_ref> 95 01 08, padded to mrl */
+fn se_record_pin(reference: u8, pin_ref: u8, mrl: usize) -> Vec<u8> {
+    let mut sac_info = SACinfo { reference: u32::from(reference), crts_len: 1, ..SACinfo::default() };
+    sac_info.crts[0] = sc_crt::new_AT(0x08);
+    sac_info.crts[0].refs[0] = u32::from(pin_ref);
+    let mut record = vec![0x80, 1, reference];
+    record.extend_from_slice(&se_encode_sac(&sac_info));
+    record.resize(std::cmp::max(mrl, record.len()), 0);
+    record
+}
+
+/* The repairs, that are possible mechanically; anything else needs manual intervention. Recreating an SE file requires
+   CREATE_EF of the DF/MF always allowed; dropping SE records requires is_drop_enabled */
+fn repair_plan(card: &mut sc_card, is_drop_enabled: bool) -> Vec<Repair> {
+    let mut result = Vec::with_capacity(8);
+    if select_mf(card).is_err() {
+        return result;
+    }
+    update_hashmap(card);
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    for file_id_dir in dirs_sorted(&dp) {
+        let val_dir = &dp.files[&file_id_dir];
+        let file_id_se = file_id_se(val_dir.1);
+        let pin_ref = match pin_ref_of(&dp, file_id_dir) {
+            Some(pin_ref) => pin_ref,
+            None => continue, // SE records can't refer to any PIN
+        };
+        match dp.files.get(&file_id_se) {
+            None => {
+                result.push(Repair::SeFileCreate { file_id_dir, file_id_se, is_replacing: false, pin_ref });
+                continue;
+            },
+            Some(val) if val.1[0] != FDB_SE_FILE => {
+                /* a DF or a file elsewhere with that id can't be replaced mechanically */
+                if !is_DFMF(val.1[0]) && is_child_of(val, val_dir) {
+                    result.push(Repair::SeFileCreate { file_id_dir, file_id_se, is_replacing: true, pin_ref });
+                }
+                continue;
+            },
+            Some(val) if !is_child_of(val, val_dir) => continue,
+            Some(val) if val.2.map(|scb8| scb8[READ]) != Some(0) => {
+                let is_preserving = val.2.map(|scb8| scb8[READ]) != Some(0xFF);
+                let is_create_ef_allowed = val_dir.2.map(|scb8| scb8[CREATE_EF]) == Some(0);
+                result.push(if is_create_ef_allowed && (is_preserving || is_drop_enabled) {
+                    Repair::SeFileReadable { file_id_dir, file_id_se, is_preserving, pin_ref } }
+                    else { Repair::SeFileNotReadable { file_id_dir, file_id_se, is_create_ef_allowed } });
+                continue;
+            },
+            Some(_) => (),
+        }
+
+        /* missing records: only those behind the last one, and only if the record ids are okay */
+        let vec_sac_info : Vec<&SACinfo> = match &val_dir.3 {
+            Some(vec_sac_info) => vec_sac_info.iter().filter(|sac_info| sac_info.reference <= 15).collect(),
+            None => Vec::new(),
+        };
+        if vec_sac_info.iter().enumerate().any(|(idx, sac_info)| sac_info.reference != u32::try_from(idx+1).unwrap()) {
+            continue;
+        }
+        let count = u8::try_from(vec_sac_info.len()).unwrap();
+        let (mut scbs, _scbs_sae) = scbs_of(&dp, file_id_dir);
+        if let Some(vec_sae_info) = &val_dir.4 {
+            for sae_info in vec_sae_info {
+                scbs.extend(sae_info.scbs[..usize::from(sae_info.scbs_len)].iter().filter(|&&scb| (scb & 0xF0) != 0x10 && scb != 0xFF));
+            }
+        }
+        let max_missing = scbs.iter().map(|&scb| scb & 0x0F).filter(|&reference| reference != 15).max().unwrap_or(0);
+        if max_missing > count && max_missing <= dp.files[&file_id_se].1[5] {
+            result.push(Repair::SeRecordsMissing { file_id_dir, from: count+1, to: max_missing });
+        }
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut vec_dangling = dangling_xdf_entries(card);
+        vec_dangling.sort_by_key(|(file_id_xdf, range)| (*file_id_xdf, range.start));
+        for (file_id_xdf, range) in vec_dangling {
+            match result.last_mut() {
+                Some(Repair::XdfEntriesRemove { file_id_xdf: file_id, ranges }) if *file_id == file_id_xdf => ranges.push(range),
+                _ => result.push(Repair::XdfEntriesRemove { file_id_xdf, ranges: vec![range] }),
+            }
+        }
+    }
+    result
+}
+
+/* The path of a file known to the hashmap */
+fn path_of(card: &mut sc_card, file_id: u16) -> sc_path {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let val = &dp.files[&file_id];
+    let len = usize::from(val.1[1]);
+    let mut path = sc_path { type_: SC_PATH_TYPE_PATH, len, ..sc_path::default() };
+    path.value[..len].copy_from_slice(&val.0[..len]);
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    path
+}
+
+/* Creates SE file file_id_se within DF/MF file_id_dir by sc_create_file in LCSI 'creation state' (access control isn't
+   enforced yet), writes the records and activates it, as done in info/card_initialization/card_initialization.scriptor */
+fn se_file_create(card: &mut sc_card, file_id_dir: u16, file_id_se: u16, mrl: u8, nor: u8, scb8: [u8; 8], records: &[Vec<u8>]) -> Result<(), i32> {
+    let mut path = path_of(card, file_id_dir);
+    let rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    path.value[path.len..path.len+2].copy_from_slice(&file_id_se.to_be_bytes());
+    path.len += 2;
+
+    let file_ptr = unsafe { sc_file_new() };
+    if file_ptr.is_null() {
+        return Err(SC_ERROR_OUT_OF_MEMORY);
+    }
+    let file = unsafe { &mut *file_ptr };
+    /* acos5_construct_fci expects the FDB in type_ */
+    file.type_ = u32::from(FDB_SE_FILE);
+    file.id = i32::from(file_id_se);
+    file.path = path;
+    file.record_length = usize::from(mrl);
+    file.record_count = usize::from(nor);
+    let mut rv = match file_add_acl_scb8(file, scb8) {
+        Ok(()) => unsafe { sc_create_file(card, file) },
+        Err(e) => e,
+    };
+    unsafe { sc_file_free(file) };
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    /* acos5_create_file made the file known to the driver, except it's SCBs */
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    if let Some(val) = dp.files.get_mut(&file_id_se) { val.2 = Some(scb8); }
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+
+    rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    for (idx, record) in records.iter().enumerate() {
+        if rv < SC_SUCCESS { break; }
+        rv = unsafe { sc_update_record(card, u32::try_from(idx+1).unwrap(), record.as_ptr(), record.len(), SC_RECORD_BY_REC_NR) };
+    }
+    if rv < SC_SUCCESS {
+        return Err(rv);
+    }
+    rv = lifecycle_set(card, i32::from(LCSI_ACTIVATED));
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    se_read_records(card, file_id_dir).map(|_| ())
+}
+
+/* Reads all records of the SE file (empty ones as well), trailing empty records excluded */
+fn se_file_read_raw(card: &mut sc_card, file_id_dir: u16) -> Result<Vec<Vec<u8>>, i32> {
+    let (path, mrl, nor) = se_file_of_dir(card, file_id_dir)?;
+    let rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    let mut result = Vec::with_capacity(usize::try_from(nor).unwrap());
+    for rec_nr in 1..=nor {
+        let mut buf = vec![0_u8; mrl];
+        let rv = unsafe { sc_read_record(card, rec_nr, buf.as_mut_ptr(), buf.len(), SC_RECORD_BY_REC_NR) };
+        if rv < 0 {
+            return Err(rv);
+        }
+        result.push(buf);
+    }
+    while result.last().map(|record| record.iter().all(|&b| b == 0)) == Some(true) {
+        result.pop();
+    }
+    Ok(result)
+}
+
+fn repair_apply(card: &mut sc_card, repair: &Repair) -> Result<(), i32> {
+    match repair {
+        Repair::SeFileReadable { file_id_dir, file_id_se, is_preserving, pin_ref } => {
+            let (path, mrl, nor) = se_file_of_dir(card, *file_id_dir)?;
+            let (scb8, records) = if *is_preserving {
+                /* read before anything gets deleted */
+                let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+                let mut scb8 = dp.files[file_id_se].2.unwrap_or(SCB8_SE_FILE_CREATE);
+                Box::leak(dp);
+                // card.drv_data = Box::into_raw(dp) as p_void;
+                scb8[READ] = 0;
+                (scb8, se_file_read_raw(card, *file_id_dir)?)
+            }
+            else { (SCB8_SE_FILE_CREATE, vec![se_record_pin(1, *pin_ref, mrl)]) };
+            let rv = unsafe { sc_delete_file(card, &path) };
+            if rv != SC_SUCCESS {
+                return Err(rv);
+            }
+            se_file_create(card, *file_id_dir, *file_id_se, u8::try_from(mrl).unwrap(), u8::try_from(nor).unwrap(), scb8, &records)
+        },
+        Repair::SeFileCreate { file_id_dir, file_id_se, is_replacing, pin_ref } => {
+            if *is_replacing {
+                let path = path_of(card, *file_id_se);
+                let rv = unsafe { sc_delete_file(card, &path) };
+                if rv != SC_SUCCESS {
+                    return Err(rv);
+                }
+            }
+            /* MRL and NOR as for the local SE file in info/card_initialization/card_initialization.scriptor */
+            se_file_create(card, *file_id_dir, *file_id_se, 0x38, 8, SCB8_SE_FILE_CREATE, &[se_record_pin(1, *pin_ref, 0x38)])
+        },
+        Repair::SeRecordsMissing { .. } | Repair::SeFileNotReadable { .. } => Ok(()),
+        #[cfg(not(target_os = "windows"))]
+        Repair::XdfEntriesRemove { file_id_xdf, ranges } => remove_xdf_entries(card, *file_id_xdf, ranges),
+        #[cfg(target_os = "windows")]
+        Repair::XdfEntriesRemove { .. } => Err(opensc_sys::errors::SC_ERROR_NOT_SUPPORTED),
+    }
+}
+
+/* opensc.conf: card_driver acos5_external { sanity_repair_drop_se_records = yes; } */
+fn is_conf_enabled(card: &mut sc_card, name: &CStr) -> bool {
+    let ctx = unsafe { &mut *card.ctx };
+    let blk = sm_conf_block(ctx);
+    !blk.is_null() && unsafe { scconf_get_bool(blk, name.as_ptr(), 0) } != 0
+}
+
+/// Plans or applies the repair of sanity findings, that have a mechanical fix: SE file not always readable (if the DF's
+/// CREATE_EF is always allowed; dropping unreadable records requires `sanity_repair_drop_se_records = yes;`), SE file
+/// missing or declared by a DF but being no SE file, and xDF entries referring to non-existing key files. SE records
+/// referenced but missing get listed in the plan without an action. Applying requires the planned text passed back with
+/// is_confirmed set: The caller must have shown the plan to the user and got their explicit consent within the same run
+/// (an opensc.conf setting doesn't qualify). It should be done after SO authentication, such that all files required
+/// may be deleted/created/updated
+/// @return  SC_SUCCESS, SC_ERROR_BUFFER_TOO_SMALL (planning: plan_len is set anyway), SC_ERROR_NOT_ALLOWED (applying is
+///          not confirmed), SC_ERROR_INVALID_ARGUMENTS (the plan passed differs from the current one), or the error of
+///          the first repair that failed (the following ones don't get applied)
+pub fn sanity_repair_card_ctl(card: &mut sc_card, data: &mut CardCtlSanityRepair) -> i32 {
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"sanity_repair_card_ctl\0");
+    log3ifc!(ctx,f,line!());
+    data.count_applied = 0;
+    let is_drop_enabled = is_conf_enabled(card, cstru!(b"sanity_repair_drop_se_records\0"));
+    let repairs = repair_plan(card, is_drop_enabled);
+    let plan = repair_plan_text(&repairs);
+    data.count_actions = repairs.iter().filter(|repair| repair.is_action()).count();
+    data.plan_len = plan.len();
+    if !data.apply {
+        if data.plan.is_null() { return SC_SUCCESS; }
+        if data.plan_cap <= plan.len() { return SC_ERROR_BUFFER_TOO_SMALL; }
+        unsafe {
+            std::ptr::copy_nonoverlapping(plan.as_ptr(), data.plan.cast::<u8>(), plan.len());
+            *data.plan.add(plan.len()) = 0;
+        }
+        return SC_SUCCESS;
+    }
+
+    if !data.is_confirmed {
+        log3if!(ctx,f,line!(), cstru!(b"Error: applying the repair plan requires the user's confirmation\0"));
+        return SC_ERROR_NOT_ALLOWED;
+    }
+    if data.plan.is_null() || unsafe { CStr::from_ptr(data.plan) }.to_bytes() != plan.as_bytes() {
+        log3if!(ctx,f,line!(), cstru!(b"Error: the plan confirmed differs from the current one\0"));
+        return SC_ERROR_INVALID_ARGUMENTS;
+    }
+    for repair in repairs.iter().filter(|repair| repair.is_action()) {
+        if let Err(e) = repair_apply(card, repair) {
+            log3ifr!(ctx,f,line!(), cstru!(b"Error: a repair failed with\0"), e);
+            return e;
+        }
+        data.count_applied += 1;
+    }
+    SC_SUCCESS
+}
+
 #[allow(dead_code)]
 #[cold]
 fn explain_the_driver() {
@@ -288,3 +1051,149 @@ fn explain_the_driver() {
     println!("acos5_get_response: Short APDUS allow a 1-byte le only");
     println!("acos5_compute_signature");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Finding, Findings, findings_to_json, sanity_check_card_ctl, Repair, repair_plan_text, bytes_tag_fcp_sac_of,
+                se_record_pin, SCB8_SE_FILE_CREATE, update_hashmap, check_pin_and_key_files, check_se_records, check_sae,
+                check_lcsi};
+    use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_BUFFER_TOO_SMALL};
+    use crate::constants_types::{SanityFinding, CardCtlSanityCheck, SANITY_SEVERITY_ERROR, SANITY_SEVERITY_WARNING,
+                                 SANITY_SEVERITY_INFO, SANITY_CHECK_PIN_FILE, SANITY_CHECK_KEY_FILE, SANITY_CHECK_FILE_PROTECTION,
+                                 SANITY_CHECK_SE_RECORD_USE, SANITY_CHECK_SE_RECORD_ID, SANITY_CHECK_SE_HOLE, SANITY_CHECK_SAE,
+                                 SANITY_CHECK_LCSI, SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3,
+                                 convert_bytes_tag_fcp_sac_to_scb_array};
+    use crate::simulator::{Acos5Simulator, SimulatedCard};
+
+    /* DF 4100 as created by info/card_initialization/card_initialization.scriptor; with SAE it's length changes */
+    const DF_4100 : &str = "00 E0 00 00 2D 62 2B 83 02 41 00 8A 01 01 82 02 38 00 8D 02 41 03 84 10 41 43 4F 53 50 4B 43 53 2D 31 35 76 31 2E 30 30 8C 08 7F 03 FF 03 03 01 01 01";
+    /* EF.DODF: the SCB for TERMINATE is FF */
+    const EF_DODF : &str = "83 02 41 17 8A 01 05 82 02 01 00 80 02 01 00 8C 08 7F 00 FF 00 03 FF 00 00";
+
+    fn df_4100_with_sae(sae: &str) -> String {
+        let len = (sae.len()+1)/3;
+        format!("00 E0 00 00 {:02X} 62 {:02X}{} {}", 0x2D+len, 0x2B+len, &DF_4100[20..], sae)
+    }
+
+    fn ef_dodf_terminate(scb: &str) -> String {
+        EF_DODF.replace("7F 00 FF", &format!("7F 00 {}", scb))
+    }
+
+    /* The findings of those checks, that work on the card's file system and access control, for a modified initialization */
+    fn findings_of(card_type: i32, replacements: &[(&str, &str)]) -> Vec<Finding> {
+        let sim_card = SimulatedCard::connect(Acos5Simulator::new_initialized_modified(card_type, replacements)).unwrap();
+        let card = unsafe { &mut *sim_card.card };
+        update_hashmap(card);
+        let mut findings = Findings { printable: false, vec: Vec::new() };
+        check_pin_and_key_files(card, &mut findings);
+        check_se_records(card, &mut findings);
+        check_sae(card, &mut findings);
+        check_lcsi(card, &mut findings);
+        findings.vec
+    }
+
+    fn has(findings: &[Finding], severity: u8, check_id: u8, file_id: u16) -> bool {
+        findings.iter().any(|finding| (finding.severity, finding.check_id, finding.file_id) == (severity, check_id, file_id))
+    }
+
+    #[test]
+    fn test_sanity_findings_hand_out() {
+        let findings = vec![
+            Finding { severity: SANITY_SEVERITY_ERROR, check_id: SANITY_CHECK_PIN_FILE, file_id: 0x3F00,
+                      message: "MF 3F00 has no PIN file".to_string(), suggested_fix: "Create a \"PIN\" file".to_string() },
+            Finding { severity: SANITY_SEVERITY_INFO, check_id: SANITY_CHECK_SE_RECORD_USE, file_id: 0x4100,
+                      message: "unused".to_string(), suggested_fix: String::new() } ];
+        assert_eq!(findings_to_json(&findings),
+            "[{\"severity\":\"error\",\"check_id\":4,\"check\":\"pin_file\",\"file_id\":\"3F00\",\"message\":\"MF 3F00 has no PIN file\",\
+            \"suggested_fix\":\"Create a \\\"PIN\\\" file\"},\
+            {\"severity\":\"info\",\"check_id\":7,\"check\":\"se_record_use\",\"file_id\":\"4100\",\"message\":\"unused\",\"suggested_fix\":\"\"}]");
+        assert_eq!(findings_to_json(&[]), "[]");
+
+        let mut buf = [SanityFinding::default(); 1];
+        let mut json = [0_i8; 16];
+        let mut data = CardCtlSanityCheck { findings: buf.as_mut_ptr(), findings_cap: buf.len(),
+            json: json.as_mut_ptr().cast(), json_cap: json.len(), ..CardCtlSanityCheck::default() };
+        assert_eq!(sanity_check_card_ctl(&findings, &mut data), SC_ERROR_BUFFER_TOO_SMALL);
+        assert_eq!((data.findings_len, data.count_errors, data.count_warnings), (2, 1, 0));
+        assert_eq!(data.json_len, findings_to_json(&findings).len());
+        assert_eq!((buf[0].severity, buf[0].check_id, buf[0].file_id), (SANITY_SEVERITY_ERROR, SANITY_CHECK_PIN_FILE, 0x3F00));
+        assert_eq!(&buf[0].message[..24], b"MF 3F00 has no PIN file\0");
+        assert_eq!(json[0], 0);
+
+        let mut buf = [SanityFinding::default(); 2];
+        let mut json = vec![0_u8; data.json_len+1];
+        let mut data = CardCtlSanityCheck { findings: buf.as_mut_ptr(), findings_cap: buf.len(),
+            json: json.as_mut_ptr().cast(), json_cap: json.len(), ..CardCtlSanityCheck::default() };
+        assert_eq!(sanity_check_card_ctl(&findings, &mut data), SC_SUCCESS);
+        assert_eq!(&json[..data.json_len], findings_to_json(&findings).as_bytes());
+        assert_eq!(json[data.json_len], 0);
+        assert_eq!(buf[1].file_id, 0x4100);
+    }
+
+    #[test]
+    fn test_sanity_checks_simulated() {
+        /* the initialized card: infos only, e.g. SE record #2 is unused and the sym. key file isn't used for SM */
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[]);
+        assert!(findings.iter().all(|finding| finding.severity == SANITY_SEVERITY_INFO), "{:?}", findings);
+        assert!(findings.iter().any(|finding| finding.message.contains("record #2 is unused")));
+        assert!(has(&findings, SANITY_SEVERITY_INFO, SANITY_CHECK_KEY_FILE, 0x4102));
+
+        /* check_pin_and_key_files */
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[("00 E0 00 00 1B 62 19 83 02 41 01", "#00 E0 00 00 1B 62 19 83 02 41 01")]);
+        assert!(has(&findings, SANITY_SEVERITY_ERROR, SANITY_CHECK_PIN_FILE, 0x4100));
+        let terminate_sm = ef_dodf_terminate("45");
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[("00 E0 00 00 1B 62 19 83 02 41 02", "#00 E0 00 00 1B 62 19 83 02 41 02"),
+                                                               (EF_DODF, &terminate_sm)]);
+        assert!(has(&findings, SANITY_SEVERITY_ERROR, SANITY_CHECK_KEY_FILE, 0x4100));
+        assert!(!has(&findings, SANITY_SEVERITY_INFO, SANITY_CHECK_KEY_FILE, 0x4102));
+        /* check_pin_and_key_files and check_lcsi: the PIN file is readable and not activated */
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[
+            ("83 02 41 01 8A 01 01 82 06 0A 00 00 15 00 01 8C 08 7F 03 FF 03 03 FF 01 FF",
+             "83 02 41 01 8A 01 01 82 06 0A 00 00 15 00 01 8C 08 7F 03 FF 03 03 FF 01 00"),
+            ("00 44 00 00 02 41 01", "#00 44 00 00 02 41 01")]);
+        assert_eq!(findings.iter().filter(|finding| finding.check_id == SANITY_CHECK_FILE_PROTECTION && finding.file_id == 0x4101).count(), 2);
+        assert!(has(&findings, SANITY_SEVERITY_WARNING, SANITY_CHECK_LCSI, 0x4101));
+
+        /* check_se_records */
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[("00 DC 03 04 0B 80 01 03", "00 DC 03 04 0B 80 01 07")]);
+        assert!(has(&findings, SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_RECORD_ID, 0x4100));
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[("00 DC 05 04 15 80 01 05", "#00 DC 05 04 15 80 01 05")]);
+        assert!(has(&findings, SANITY_SEVERITY_ERROR, SANITY_CHECK_SE_HOLE, 0x4100));
+        let terminate_2 = ef_dodf_terminate("02");
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[(EF_DODF, &terminate_2)]);
+        assert!(!findings.iter().any(|finding| finding.check_id == SANITY_CHECK_SE_RECORD_USE && finding.message.contains("record #2 is unused")));
+
+        /* check_sae */
+        for &(card_type, sae, severity) in &[(SC_CARD_TYPE_ACOS5_64_V3, "AB 05 84 01 99 97 00", SANITY_SEVERITY_WARNING), // unknown INS
+                                           (SC_CARD_TYPE_ACOS5_64_V3, "AB 05 84 01 A4 97 00", SANITY_SEVERITY_ERROR),   // never select
+                                           (SC_CARD_TYPE_ACOS5_64_V2, "AB 06 84 01 24 9E 01 41", SANITY_SEVERITY_ERROR)] { // SM for 24
+            let df = df_4100_with_sae(sae);
+            let findings = findings_of(card_type, &[(DF_4100, &df)]);
+            assert!(has(&findings, severity, SANITY_CHECK_SAE, 0x4100), "SAE {}", sae);
+        }
+        let df = df_4100_with_sae("AB 06 84 01 24 9E 01 41");
+        let findings = findings_of(SC_CARD_TYPE_ACOS5_64_V3, &[(DF_4100, &df)]);
+        assert!(!findings.iter().any(|finding| finding.check_id == SANITY_CHECK_SAE));
+    }
+
+    #[test]
+    fn test_sanity_repair_plan() -> Result<(), i32> {
+        /* as in info/card_initialization/card_initialization.scriptor for the global SE file: 8C 08 7F 01 FF 01 01 00 01 00 */
+        assert_eq!(bytes_tag_fcp_sac_of(SCB8_SE_FILE_CREATE), [0x7F, 1, 0xFF, 1, 1, 0, 1, 0]);
+        let scb8 = [0x45, 0x01, 0x00, 0x03, 0x00, 0x05, 0x00, 0xFF];
+        assert_eq!(convert_bytes_tag_fcp_sac_to_scb_array(&bytes_tag_fcp_sac_of(scb8))?, scb8);
+        /* 80 01 01 A4 06 83 01 81 95 01 08 */
+        assert_eq!(se_record_pin(1, 0x81, 0), vec![0x80, 1, 1, 0xA4, 6, 0x83, 1, 0x81, 0x95, 1, 8]);
+        assert_eq!(se_record_pin(3, 0x01, 0x38).len(), 0x38);
+
+        let repairs = vec![Repair::SeRecordsMissing { file_id_dir: 0x4100, from: 7, to: 7 },
+                           Repair::SeFileNotReadable { file_id_dir: 0x4100, file_id_se: 0x4103, is_create_ef_allowed: false },
+                           Repair::XdfEntriesRemove { file_id_xdf: 0x4110, ranges: vec![0..50, 100..150] }];
+        assert_eq!(repair_plan_text(&repairs),
+            "1. DF/MF 4100: NOT repaired: SE file record(s) #7-#7 are referenced but missing; add them according to Your access conditions\n\
+             2. DF/MF 4100: NOT repaired: SE file 4103 isn't always readable, and the DF's CREATE_EF isn't always allowed, thus it can't be recreated\n\
+             3. xDF 4110: Remove 2 entries referring to a non-existing key file\n");
+        assert_eq!(repairs.iter().filter(|repair| repair.is_action()).count(), 1);
+        Ok(())
+    }
+}
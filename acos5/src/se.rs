@@ -71,32 +71,46 @@ It's clear how to encode this in SACinfo, but how to communicate this to opensc
 what to be shown in opensc-tool -f
 
 
-For SAE (Security Attributes Expanded), TODO
+For SAE (Security Attributes Expanded), the FCP's tag 0xAB content gets parsed by se_parse_sae into Vec<SAEinfo>,
+stored with the DF: an AMDO (the command header) followed by one SCDO condition, which may also be a group of
+conditions: SCDO tag 0xA0 (Any: OR) or 0xAF (All: AND). 0xA4 SCDOs get stored as virtual SE records (ids from 16 on).
+se_get_sae_scb evaluates that for a command and decides about the one scb that controls how to send the command.
 */
 
 use std::convert::TryFrom;
 
-use opensc_sys::opensc::{sc_card, sc_file_add_acl_entry};
+use std::ffi::c_void;
+use std::ptr::null_mut;
+
+use opensc_sys::opensc::{sc_card, sc_file_add_acl_entry, sc_select_file, sc_read_record, sc_update_record,
+                         SC_RECORD_BY_REC_NR};
 use opensc_sys::types::{sc_file, sc_crt, SC_AC_NONE, SC_AC_NEVER, SC_AC_UNKNOWN, SC_AC_KEY_REF_NONE,
 SC_AC_CHV, SC_AC_AUT, SC_AC_PRO,
 SC_AC_OP_LIST_FILES, SC_AC_OP_SELECT, SC_AC_OP_DELETE, SC_AC_OP_CREATE_EF,
 SC_AC_OP_CREATE_DF, SC_AC_OP_INVALIDATE, SC_AC_OP_REHABILITATE, SC_AC_OP_LOCK, SC_AC_OP_READ,
 SC_AC_OP_UPDATE, SC_AC_OP_CRYPTO, SC_AC_OP_DELETE_SELF, SC_AC_OP_CREATE, SC_AC_OP_WRITE,
-SC_AC_OP_GENERATE, SC_MAX_CRTS_IN_SE};
+SC_AC_OP_GENERATE, SC_MAX_CRTS_IN_SE, sc_path, SC_PATH_TYPE_PATH};
 
-use opensc_sys::errors::{SC_SUCCESS};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_INVALID_DATA, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_INTERNAL, SC_ERROR_INVALID_ARGUMENTS,
+                         SC_ERROR_RECORD_NOT_FOUND, SC_ERROR_NOT_ENOUGH_MEMORY, SC_ERROR_NOT_ALLOWED};
 use opensc_sys::asn1::{sc_asn1_read_tag, SC_ASN1_TAG_EOC};
 
 use crate::constants_types::{DataPrivate, FDB_RSA_KEY_EF, FDB_SE_FILE, FDB_SYMMETRIC_KEY_EF, SACinfo, SAEinfo, Tlv,
-                             is_DFMF, FDB_ECC_KEY_EF, UPDATE, CRYPTO, DELETE_SELF, CREATE_EF, CREATE_DF,
+                             SAE_MAX_SCDO, SC_CARD_TYPE_ACOS5_64_V3, CardCtlAcReport, AcOpReport, AcCondition,
+                             AC_KIND_ALWAYS, AC_KIND_NEVER, AC_KIND_CONDITIONS, AC_KIND_INVALID, AC_KIND_UNUSED,
+                             AC_REPORT_MAX_CONDITIONS, FDB_CHV_EF, is_child_of, file_id_se, is_DFMF, FDB_ECC_KEY_EF, UPDATE, CRYPTO, DELETE_SELF, CREATE_EF, CREATE_DF,
                              file_id_from_path_value /*, p_void*/};
 use crate::path::{current_path_df};
+use crate::cmd_card_info::{get_is_pin_authenticated, get_is_key_authenticated};
+use crate::no_cdecl::{select_file_by_path};
+use crate::wrappers::{wr_do_log, wr_do_log_t};
 
 /*
 It's not possible to map file access conditions from ACOS5 (scb8) to OpenSC exactly:
 1. ACOS5 (but not OpenSC) allows to OR-combine access conditions, saying somethings like: "At least one of the following conditions must be fulfilled".
    OpenSC only has the AND operator, saying: "All of the following conditions must be fulfilled".
-   Therefore se_file_add_acl_entry will pass only the first of several OR-combined alternatives to OpenSC
+   Therefore se_file_add_acl_entry will pass all conditions for AND-combined ones, but only one of several
+   OR-combined alternatives to OpenSC: An alternative, that is fulfilled already (V3.00 only can tell), otherwise the first
 2. As far as I understand the OpenSC code, it doesn't allow authentication of a key as file access condition, the way ACOS5
    does that via external authentication procedure, i.e. it's not about comparing secrets directly as with pins, but indirectly:
    whether both parties encrypt a challenge to the same result value, which implies the keys used must be the same.
@@ -152,19 +166,34 @@ pub fn map_scb8_to_acl(card: &mut sc_card, file: &mut sc_file, scb8: [u8; 8], fd
 
 /**
  * Controls 'decoding' of SCB meaning and finally calls sc_file_add_acl_entry
- * @apiNote TODO add SM and logical AND/OR of access control conditions (if OpenSc can handle that)
+ * @apiNote Each AT CRT (tag 0xA4) of the referenced SE record contributes it's references as alternatives, each of which
+ *          requires: usage 0x08: the pin, usage 0x80: the key, usage 0x88: both the pin and the key (same id).
+ *          SCB 1-14 (OR): only one alternative get's passed, see se_choose_alternative;
+ *          SCB 129-142 (AND): all alternatives get passed;
+ *          SCB 65-78 (SM): key 0x81 by CONVENTION is the one that get's authenticated in SM mode, thus it's considered
+ *          fulfilled (an alternative that requires key 0x81 only, fulfills the OR already); SC_AC_PRO is added as marker
  * @param  card  INOUT
  * @param  file  INOUT it's field acl (Access Control List) will get an sc_acl_entry added
  * @param  scb   IN    the Security Condition Byte (SCB) for @param op, as retrieved from FCI; it's pointing to an SE id
  *                     in associated Sec. Env. file, or it's an encoding of either SC_AC_NONE or SC_AC_NEVER
  * @param  op    IN    the operation that @param scb refers to, e.g. SC_AC_OP_READ
  */
-#[allow(clippy::needless_return)]
+/*
+SCB: 01; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => CHV129 (or CHV1, if only that is verified)
+SCB: 41; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => invalid, missing info for SM
+SCB: 41; [80 01 01  A4 09 83 01 01 83 01 81 95 01 08  B4 08 84 00 95 01 30 80 01 02] => CHV1   PROT
+SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => CHV129 CHV1 (AND: both conditions must be fulfilled)
+
+SCB: 41; [80 01 01  A4 09 83 01 01 83 01 81 95 01 80  B4 08 84 00 95 01 30 80 01 02] => PROT
+SCB: 41; [80 01 01  A4 09 83 01 01 83 01 82 95 01 80  B4 08 84 00 95 01 30 80 01 02] => AUT1   PROT
+SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 80]                                => AUT129 AUT1
+
+SCB: 01; [80 01 01  A4 09 83 01 81 83 01 01 95 01 88]                                => CHV129 AUT129
+SCB: 41; [80 01 01  A4 06          83 01 81 95 01 88  B4 08 84 00 95 01 30 80 01 02] => CHV129 PROT
+SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 88]                                => CHV129 AUT129 CHV1 AUT1
+*/
 fn se_file_add_acl_entry(card: &mut sc_card, file: &mut sc_file, scb: u8, op: u32)
 {
-//    assert!(!card.ctx.is_null());
-//    let ctx = unsafe { &mut *card.ctx };
-//    let f = cstru!(b"se_file_add_acl_entry\0");
     let mut rv;
     if op == 0xFF {} // it's used to denote, that there is no operation that this scb can refer to; e.g. for  EF/CHV, the byte at 3. position has no meaning
     else if  scb == 0
@@ -184,133 +213,103 @@ fn se_file_add_acl_entry(card: &mut sc_card, file: &mut sc_file, scb: u8, op: u3
     }
     else {
         let file_id = u16::try_from(file.id).unwrap();
-        let res_se_sm = if (scb & 0x40) == 0 {(false,false)}
-                                    else {se_get_is_scb_suitable_for_sm_has_ct(card, file_id, scb & 0x0F)};
-        let pin_ref = se_get_references(card, file_id, scb & 0x0F, &sc_crt::new_AT(0x08), false);
-        if !pin_ref.is_empty() {
-/*
-SCB: 01; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => CHV129 (CHV1 as OR alternative get's dropped)
-SCB: 01; [80 01 01  A4 09 83 01 01 83 01 81 95 01 08]                                => CHV1   (CHV129 as OR alternative get's dropped)
-
-SCB: 41; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => invalid, missing info for SM
-SCB: 41; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08  B4 08 84 00 95 01 30 80 01 02] => CHV129 PROT (CHV1 as OR alternative get's dropped)
-SCB: 41; [80 01 01  A4 09 83 01 01 83 01 81 95 01 08  B4 08 84 00 95 01 30 80 01 02] => CHV1   PROT (CHV129 as OR alternative get's dropped)
-
-SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 08]                                => CHV129 CHV1 (AND: both conditions must be fulfilled)
-*/
-            if (scb & 0x40) != 0 && !res_se_sm.0 { // invalid, missing info for SM
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_UNKNOWN, SC_AC_KEY_REF_NONE) };
-                assert_eq!(SC_SUCCESS, rv);
-                return;
-            }
-            /* SM implicitly has the (unsupported by OpenSC) OR operator for access conditions, thus drop any references except the first */
-            let loop_cnt = if (scb & 0x40) != 0 || (scb & 0x80) == 0 { 1_usize }
-                                  else { pin_ref.len() };
-            for &elem in pin_ref.iter().take(loop_cnt) {
-                rv =unsafe { sc_file_add_acl_entry(file, op, SC_AC_CHV, elem.into()) };
-                assert_eq!(SC_SUCCESS, rv);
-            }
-            if (scb & 0x40) != 0 && res_se_sm.0 { // SM processing is requested and possible: add SC_AC_PRO as a marker
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_PRO, SC_AC_KEY_REF_NONE) };
-                assert_eq!(SC_SUCCESS, rv);
-                return;
-            }
-
+        let is_sm = (scb & 0x40) != 0;
+        if is_sm && !se_get_is_scb_suitable_for_sm_has_ct(card, file_id, scb & 0x0F).0 { // invalid, missing info for SM
+            rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_UNKNOWN, SC_AC_KEY_REF_NONE) };
+            assert_eq!(SC_SUCCESS, rv);
             return;
         }
-/* */
-        let mut contains_sm_key : bool = false;
-        let key_ref = se_get_references(card, file_id, scb & 0x0F, &sc_crt::new_AT(0x80), false);
-        if !key_ref.is_empty() {
-/*
-key 0x81 by CONVENTION is the one that get's authenticated in SM mode, thus itself and alternatives get stripped
-SCB: 01; [80 01 01  A4 09 83 01 81 83 01 01 95 01 80]                                => AUT129 (AUT1 as OR alternative get's dropped)
-SCB: 01; [80 01 01  A4 09 83 01 01 83 01 81 95 01 80]                                => AUT1   (AUT129 as OR alternative get's dropped)
+        let alternatives = se_get_ac_alternatives(card, file_id, scb & 0x0F, is_sm);
+        let conditions = se_combine_alternatives(alternatives, (scb & 0x80) != 0,
+                                                 |alternatives| se_choose_alternative(card, alternatives));
 
-SCB: 41; [80 01 01  A4 09 83 01 81 83 01 01 95 01 80]                                => invalid, missing info for SM
-SCB: 41; [80 01 01  A4 06          83 01 81 95 01 80  B4 08 84 00 95 01 30 80 01 02] => PROT
-SCB: 41; [80 01 01  A4 09 83 01 01 83 01 81 95 01 80  B4 08 84 00 95 01 30 80 01 02] => PROT
-SCB: 41; [80 01 01  A4 09 83 01 01 83 01 82 95 01 80  B4 08 84 00 95 01 30 80 01 02] => AUT1   PROT (AUT130 as OR alternative get's dropped)
-
-SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 80]                                => AUT129 AUT1 (AND: both conditions must be fulfilled)
-SCB: 81; [80 01 01  A4 09 83 01 01 83 01 81 95 01 80  B4 08 84 00 95 01 30 80 01 02] => AUT1 AUT129 (AND: both conditions must be fulfilled)
-*/
-            if (scb & 0x40) != 0 {
-                if !res_se_sm.0 {
-                    rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_UNKNOWN, SC_AC_KEY_REF_NONE) };
-                    assert_eq!(SC_SUCCESS, rv);
-                    return;
-                }
-                contains_sm_key = key_ref.iter().any(|&x| x == 0x81);
-            }
-
-            /* SM implicitly has the (unsupported by OpenSC) OR operator for access conditions, thus drop any references except the first */
-            let loop_cnt = if contains_sm_key { 0_usize }
-                                  else if (scb & 0x40) != 0 || (scb & 0x80) == 0 { 1_usize }
-                                  else { key_ref.len() };
-            for &elem in key_ref.iter().take(loop_cnt) {
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_AUT, elem.into()) };
-                assert_eq!(SC_SUCCESS, rv);
-            }
-            if (scb & 0x40) != 0 && res_se_sm.0 {
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_PRO, SC_AC_KEY_REF_NONE) };
-                assert_eq!(SC_SUCCESS, rv);
-                return;
-            }
+        for (method, key_ref) in conditions {
+            rv = unsafe { sc_file_add_acl_entry(file, op, method, key_ref.into()) };
+            assert_eq!(SC_SUCCESS, rv);
+        }
+        if is_sm { // SM processing is requested and possible: add SC_AC_PRO as a marker
+            rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_PRO, SC_AC_KEY_REF_NONE) };
+            assert_eq!(SC_SUCCESS, rv);
+        }
+    }
+}
 
-            return;
+/* One alternative of access conditions, all of which must be fulfilled: (SC_AC_CHV or SC_AC_AUT, pin/key reference) */
+type AcAlternative = Vec<(u32, u32)>;
 
+/**
+ * Combines the access condition alternatives according to the SCB form
+ * @param   alternatives  IN    as returned from se_get_ac_alternatives
+ * @param   is_and        IN    SCB 129-142 (AND): all alternatives, deduplicated; otherwise (OR) only one of them
+ * @param   choose        IN    picks the one alternative for OR, see se_choose_alternative; not called, if an
+ *                              alternative is fulfilled by SM already
+ * @return  the conditions that shall be passed to OpenSC
+ */
+fn se_combine_alternatives<F>(alternatives: Vec<AcAlternative>, is_and: bool, choose: F) -> AcAlternative
+    where F: FnOnce(Vec<AcAlternative>) -> AcAlternative
+{
+    if is_and {
+        let mut all : AcAlternative = Vec::with_capacity(8);
+        for &condition in alternatives.iter().flatten() {
+            if !all.contains(&condition) { all.push(condition); }
         }
-/* */
-        let pin_key_ref = se_get_references(card, file_id, scb & 0x0F, &sc_crt::new_AT(0x88), false);
-        if !pin_key_ref.is_empty() {
-/*
-key 0x81 by CONVENTION is the one that get's authenticated in SM mode, thus itself and alternatives get stripped
-SCB: 01; [80 01 01  A4 09 83 01 81 83 01 01 95 01 88]                                => CHV129 AUT129 (CHV1   AUT1   as OR alternative get's dropped)
-SCB: 01; [80 01 01  A4 09 83 01 01 83 01 81 95 01 88]                                => CHV1   AUT1   (CHV129 AUT129 as OR alternative get's dropped)
-
-SCB: 41; [80 01 01  A4 09 83 01 81 83 01 01 95 01 88]                                => invalid, missing info for SM
-SCB: 41; [80 01 01  A4 06          83 01 81 95 01 88  B4 08 84 00 95 01 30 80 01 02] => CHV129 PROT
-SCB: 41; [80 01 01  A4 09 83 01 01 83 01 81 95 01 88  B4 08 84 00 95 01 30 80 01 02] => CHV1   AUT1 PROT
-SCB: 41; [80 01 01  A4 09 83 01 01 83 01 82 95 01 88  B4 08 84 00 95 01 30 80 01 02] => CHV1   AUT1 PROT (CHV130 AUT130 as OR alternative get's dropped)
+        all
+    }
+    else if alternatives.iter().any(Vec::is_empty) { Vec::new() } // OR, fulfilled by SM already
+    else { choose(alternatives) }
+}
 
-SCB: 81; [80 01 01  A4 09 83 01 81 83 01 01 95 01 88]                                => CHV129 AUT129 CHV1 AUT1 (AND: both conditions must be fulfilled)
-*/
-            if (scb & 0x40) != 0 {
-                if !res_se_sm.0 {
-                    rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_UNKNOWN, SC_AC_KEY_REF_NONE) };
-                    assert_eq!(SC_SUCCESS, rv);
-                    return;
-                }
-                contains_sm_key = pin_key_ref.iter().any(|&x| x == 0x81);
-            }
+/**
+ * Collects the access condition alternatives from all AT CRTs of an SE record
+ * @param   card             INOUT
+ * @param   file_id          IN    the file_id, for which info is requested; relevant is the SE file info of file_id's directory
+ * @param   se_reference     IN    the SE file record's id (3.byte) matching SCB & 0x0F
+ * @param   is_sm            IN    whether SM will be applied, i.e. key 0x81 get's authenticated by SM
+ * @return  for each reference of each AT CRT: the conditions required (may be empty, if SM fulfills them)
+ */
+fn se_get_ac_alternatives(card: &mut sc_card, file_id: u16, se_reference: u8, is_sm: bool) -> Vec<AcAlternative>
+{
+    let mut result = Vec::new();
+    for crt in se_get_crts(card, file_id, se_reference).iter().filter(|crt| crt.tag == 0xA4) {
+        for &elem in crt.refs.iter().take_while(|&&elem| elem != 0) {
+            let mut alternative = Vec::with_capacity(2);
+            if (crt.usage & 0x08) != 0 { alternative.push((SC_AC_CHV, elem)); }
+            if (crt.usage & 0x80) != 0 && !(is_sm && elem == 0x81) { alternative.push((SC_AC_AUT, elem)); }
+            result.push(alternative);
+        }
+    }
+    result
+}
 
-            /* SM implicitly has the (unsupported by OpenSC) OR operator for access conditions, thus drop any references except the first */
-            let loop_cnt = //if contains_sm_key { 0_usize }
-                if (scb & 0x40) != 0 || (scb & 0x80) == 0 { 1_usize }
-                else { pin_key_ref.len() };
-            for &elem in pin_key_ref.iter().take(loop_cnt) {
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_CHV, elem.into()) };
-                assert_eq!(SC_SUCCESS, rv);
-                if contains_sm_key {}
-                else {
-                    rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_AUT, elem.into()) };
-                    assert_eq!(SC_SUCCESS, rv);
-                }
-            }
-            if (scb & 0x40) != 0 && res_se_sm.0 {
-                rv = unsafe { sc_file_add_acl_entry(file, op, SC_AC_PRO, SC_AC_KEY_REF_NONE) };
-                assert_eq!(SC_SUCCESS, rv);
-                return;
+/**
+ * Chooses one of OR-combined alternatives, that shall be passed to OpenSC
+ * @apiNote Preferred is an alternative that is fulfilled already: This can be asked for from V3.00 only
+ *          (Get Card Info, Pin/Key Authentication State). Otherwise (and as fallback) it's the first alternative
+ * @param   card          INOUT
+ * @param   alternatives  IN
+ * @return  the conditions of the alternative chosen
+ */
+fn se_choose_alternative(card: &mut sc_card, mut alternatives: Vec<AcAlternative>) -> AcAlternative
+{
+    if alternatives.is_empty() {
+        return Vec::new();
+    }
+    if alternatives.len() > 1 && card.type_ == SC_CARD_TYPE_ACOS5_64_V3 {
+        for (idx, alternative) in alternatives.iter().enumerate() {
+            let is_fulfilled = alternative.iter().all(|&(method, key_ref)| {
+                let reference = u8::try_from(key_ref).unwrap();
+                let res = if method == SC_AC_CHV { get_is_pin_authenticated(card, reference) }
+                          else                   { get_is_key_authenticated(card, reference) };
+                res == Ok(true)
+            });
+            if is_fulfilled {
+                return alternatives.swap_remove(idx);
             }
-
-            return;
         }
-/* */
     }
+    alternatives.swap_remove(0)
 }
 
-
 /**
  * Performs look-up of SCB meaning in the database - HashMap dp.files - based on a search_template
  * @apiNote
@@ -378,6 +377,38 @@ pub fn se_get_references(card: &mut sc_card, file_id: u16, se_reference: u8, sea
     result
 }
 
+/**
+ * Performs look-up of SCB meaning in the database - HashMap dp.files
+ * @param   card             INOUT
+ * @param   file_id          IN    the file_id, for which info is requested; relevant is the SE file info of file_id's directory
+ * @param   se_reference     IN    the SE file record's id (3.byte) matching SCB & 0x0F, though 0x0F is RFU for cos5 !
+ * @return  all CRTs of the SE record, empty if there is no such record
+ */
+pub fn se_get_crts(card: &mut sc_card, file_id: u16, se_reference: u8) -> Vec<sc_crt>
+{
+    let mut result = Vec::new();
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    if dp.files.contains_key(&file_id) {
+        let dp_files_value = &dp.files[&file_id];
+        let fdb        = dp_files_value.1[0];
+        let file_id_dir =
+            if is_DFMF(fdb) { file_id }
+            else {
+                let path_len = usize::from(dp_files_value.1[1]);
+                assert!(path_len>=4);
+                u16::from_be_bytes([dp_files_value.0[path_len-4], dp_files_value.0[path_len-3]])
+            };
+        if let Some(vec_sac_info) = &dp.files[&file_id_dir].3 {
+            if let Some(sac_info) = vec_sac_info.iter().find(|sac_info| sac_info.reference == u32::from(se_reference)) {
+                result.extend_from_slice(&sac_info.crts[..sac_info.crts_len]);
+            }
+        }
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    result
+}
+
 /**
  * Performs look-up of SCB meaning in the database - HashMap dp.files - (internal search_templates)
  * @apiNote
@@ -458,9 +489,48 @@ pub fn se_get_is_scb_suitable_for_sm_has_ct(card: &mut sc_card, file_id: u16, se
     result
 }
 
+/**
+ * Collapses the SCDO condition(s) of an SAE entry to the one scb, that controls how the command must be sent
+ * @apiNote  SCDO_TAG.Any (0xA0, OR): An always-allowed alternative wins; impossible alternatives (0xFF) are dropped.
+ *           Of the remaining ones, an SM protected alternative is preferred if SM is usable for it, because the SM
+ *           session itself establishes the key authentication, while a pin condition depends on a previous verification.
+ *           SCDO_TAG.All (AND): Any impossible condition makes the command impossible. If any condition requires SM,
+ *           the command must be sent in SM mode, thus that scb is chosen; other conditions must be fulfilled in advance.
+ * @param   sae_info       IN
+ * @param   is_sm_usable   IN  tells, whether the CRT templates for an scb with SM bit 0x40 accomplish the requirements for SM
+ * @return  scb; 0: no constraint, 0xFF: never allowed
+ */
+fn sae_select_scb<F: FnMut(u8) -> bool>(sae_info: &SAEinfo, mut is_sm_usable: F) -> u8
+{
+    let scbs = &sae_info.scbs[..usize::from(sae_info.scbs_len)];
+    match sae_info.tag_SCDO {
+        0xA0 => {
+            if scbs.is_empty() || scbs.contains(&0) { return 0; }
+            let candidates : Vec<u8> = scbs.iter().copied().filter(|&scb| scb != 0xFF).collect();
+            if candidates.is_empty() { return 0xFF; }
+            if let Some(&scb) = candidates.iter().find(|&&scb| (scb & 0x40) != 0 && is_sm_usable(scb)) { scb }
+            else if let Some(&scb) = candidates.iter().find(|&&scb| (scb & 0x40) == 0) { scb }
+            else { candidates[0] }
+        },
+        0xAF => {
+            if scbs.contains(&0xFF) { return 0xFF; }
+            if let Some(&scb) = scbs.iter().find(|&&scb| (scb & 0x40) != 0) { scb }
+            else if let Some(&scb) = scbs.iter().find(|&&scb| scb != 0) { scb }
+            else { 0 }
+        },
+        _ => sae_info.scb,
+    }
+}
+
+/**
+ * Looks up the SAE of current DF for a command and evaluates it's condition(s)
+ * @param   card           INOUT
+ * @param   cla_ins_p1_p2  IN     the command header
+ * @return  scb; 0 if there is no SAE entry for the command, see also sae_select_scb
+ */
 pub fn se_get_sae_scb(card: &mut sc_card, cla_ins_p1_p2: [u8; 4]) -> u8
 {
-    let mut scb = 0;
+    let mut sae_info_found = None;
     let file_id_dir = file_id_from_path_value(current_path_df(card));
 
     let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
@@ -472,21 +542,237 @@ pub fn se_get_sae_scb(card: &mut sc_card, cla_ins_p1_p2: [u8; 4]) -> u8
 //              if (sae_info.tag_AMDO&4)>0 && sae_info.ins != cla_ins_p1_p2[1] { continue; }
                 if (sae_info.tag_AMDO&2)>0 && sae_info.p1  != cla_ins_p1_p2[2] { continue; }
                 if (sae_info.tag_AMDO&1)>0 && sae_info.p2  != cla_ins_p1_p2[3] { continue; }
-
-                match sae_info.tag_SCDO {
-                    0x90 => { scb = 0;            break; }, // always allowed, thus no constraint
-                    0x97 => { scb = 0xFF;         break; }, // always disallowed
-                    0x9E |
-                    0xA4 => { scb = sae_info.scb; break; }, // depends on scb
-                    0xA0 | 0xAF => { /*panic!();*/ }, // not implemented
-                    _    => { unreachable!("Encountered unexpected byte"); },
-                }
+                sae_info_found = Some(*sae_info);
+                break;
             }
         }
     }
     Box::leak(dp);
     // card.drv_data = Box::into_raw(dp) as p_void;
-    scb
+    match sae_info_found {
+        Some(sae_info) => sae_select_scb(&sae_info, |scb| se_get_is_scb_suitable_for_sm_has_ct(card, file_id_dir, scb & 0x1F).0),
+        None => 0,
+    }
+}
+
+/**
+ * Names of the operations, that the 7 used bytes of scb8 refer to, depending on file type
+ * @param   fdb  IN  File Descriptor Byte
+ * @return  names; "" for a byte without meaning for this file type
+ */
+#[must_use]
+pub fn se_ac_op_names(fdb: u8) -> [&'static str; 7]
+{
+    if is_DFMF(fdb) {
+        ["DELETE_CHILD", "CREATE_EF", "CREATE_DF", "DEACTIVATE", "ACTIVATE", "TERMINATE", "DELETE_SELF"]
+    }
+    else if [FDB_SE_FILE, FDB_RSA_KEY_EF, FDB_ECC_KEY_EF, FDB_SYMMETRIC_KEY_EF].contains(&fdb) {
+        ["READ",         "UPDATE",    "CRYPTO",    "DEACTIVATE", "ACTIVATE", "TERMINATE", "DELETE_SELF"]
+    }
+    else {
+        ["READ",         "UPDATE",    "",          "DEACTIVATE", "ACTIVATE", "TERMINATE", "DELETE_SELF"]
+    }
+}
+
+/**
+ * Explains an SCB: Decodes it and looks up the SE record referred to
+ * @param   card     INOUT
+ * @param   file_id  IN    the file_id, for which info is requested; relevant is the SE file info of file_id's directory
+ * @param   scb      IN    from scb8 or from SAE
+ * @param   is_sae   IN    whether scb stems from SAE, i.e. may refer to a virtual SE record (ids 16-31)
+ * @return  the requirement
+ */
+fn se_explain_scb(card: &mut sc_card, file_id: u16, scb: u8, is_sae: bool) -> AcOpReport
+{
+    let mut report = AcOpReport { scb, ..AcOpReport::default() };
+    let is_virtual = is_sae && (scb & 0xF0) == 0x10;
+    report.kind =
+        if      scb == 0    { AC_KIND_ALWAYS }
+        else if scb == 0xFF { AC_KIND_NEVER }
+        else if !is_virtual && ([0, 15].contains(&(scb & 0x0F)) || (scb & 0x30) != 0 || (scb & 0xC0) == 0xC0) { AC_KIND_INVALID }
+        else                { AC_KIND_CONDITIONS };
+    if report.kind != AC_KIND_CONDITIONS {
+        return report;
+    }
+
+    let se_reference = if is_virtual { scb } else { scb & 0x0F };
+    let crts = se_get_crts(card, file_id, se_reference);
+    if crts.is_empty() { // there is no such SE record
+        report.kind = AC_KIND_INVALID;
+        return report;
+    }
+    report.is_all = (scb & 0x80) != 0;
+    if (scb & 0x40) != 0 {
+        let res_se_sm = se_get_is_scb_suitable_for_sm_has_ct(card, file_id, se_reference);
+        report.sm_mac = res_se_sm.0;
+        report.sm_enc = res_se_sm.0 && res_se_sm.1;
+        if !res_se_sm.0 {
+            report.kind = AC_KIND_INVALID;
+        }
+    }
+    for crt in crts.iter().filter(|crt| crt.tag == 0xA4) {
+        for &elem in crt.refs.iter().take_while(|&&elem| elem != 0) {
+            if usize::from(report.conditions_len) == AC_REPORT_MAX_CONDITIONS { break; }
+            let reference = u8::try_from(elem).unwrap();
+            report.conditions[usize::from(report.conditions_len)] = AcCondition {
+                pin: if (crt.usage & 0x08) != 0 { reference } else { 0 },
+                key: if (crt.usage & 0x80) != 0 { reference } else { 0 },
+            };
+            report.conditions_len += 1;
+        }
+    }
+    report
+}
+
+/**
+ * Returns the scb8 of a file from DataPrivate.files
+ * @apiNote If it isn't known yet, the file will be selected
+ * @param   card     INOUT
+ * @param   file_id  IN    a key of DataPrivate.files
+ * @return  scb8 or an error, e.g. SC_ERROR_FILE_NOT_FOUND if file_id is unknown
+ */
+fn se_get_scb8(card: &mut sc_card, file_id: u16) -> Result<[u8; 8], i32>
+{
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let (path_value, path_len, scb8_opt) = match dp.files.get(&file_id) {
+        Some(val) => (val.0, usize::from(val.1[1]), val.2),
+        None => { Box::leak(dp); return Err(SC_ERROR_FILE_NOT_FOUND); },
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    if let Some(scb8) = scb8_opt {
+        return Ok(scb8);
+    }
+    let mut path = sc_path { type_: SC_PATH_TYPE_PATH, len: path_len, ..sc_path::default() };
+    path.value[..path_len].copy_from_slice(&path_value[..path_len]);
+    let rv = select_file_by_path(card, &path, None, true);
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let scb8_opt = dp.files[&file_id].2;
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    scb8_opt.ok_or(SC_ERROR_INTERNAL)
+}
+
+/**
+ * Collects the access control requirements of a file for each operation, and the SAE of it's directory
+ * @apiNote If the file's scb8 isn't known yet, the file will be selected
+ * @param   card     INOUT
+ * @param   file_id  IN    a key of DataPrivate.files
+ * @return  the report or an error, e.g. SC_ERROR_FILE_NOT_FOUND if file_id is unknown
+ */
+#[allow(clippy::missing_errors_doc)]
+pub fn se_get_ac_report(card: &mut sc_card, file_id: u16) -> Result<CardCtlAcReport, i32>
+{
+    let mut report = CardCtlAcReport { file_id, ..CardCtlAcReport::default() };
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let (path_value, file_info) = match dp.files.get(&file_id) {
+        Some(val) => (val.0, val.1),
+        None => { Box::leak(dp); return Err(SC_ERROR_FILE_NOT_FOUND); },
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    let path_len = usize::from(file_info[1]);
+    report.fdb  = file_info[0];
+    report.scb8 = se_get_scb8(card, file_id)?;
+
+    let names = se_ac_op_names(report.fdb);
+    for (idx, op_report) in report.ops.iter_mut().enumerate() {
+        *op_report = if names[idx].is_empty() { AcOpReport { scb: report.scb8[idx], kind: AC_KIND_UNUSED, ..AcOpReport::default() } }
+                     else { se_explain_scb(card, file_id, report.scb8[idx], false) };
+    }
+
+    let file_id_dir = if is_DFMF(report.fdb) { file_id }
+                      else {
+                          assert!(path_len>=4);
+                          u16::from_be_bytes([path_value[path_len-4], path_value[path_len-3]])
+                      };
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let vec_sae_info = match dp.files.get(&file_id_dir) {
+        Some(val) => val.4.clone().unwrap_or_default(),
+        None => Vec::new(),
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    for (sae_report, sae_info) in report.sae.iter_mut().zip(vec_sae_info.iter()) {
+        sae_report.tag_amdo      = sae_info.tag_AMDO;
+        sae_report.cla_ins_p1_p2 = [sae_info.cla, sae_info.ins, sae_info.p1, sae_info.p2];
+        sae_report.tag_scdo      = sae_info.tag_SCDO;
+        sae_report.scbs_len      = sae_info.scbs_len;
+        for (scb_report, &scb) in sae_report.scbs.iter_mut().zip(sae_info.scbs[..usize::from(sae_info.scbs_len)].iter()) {
+            *scb_report = se_explain_scb(card, file_id_dir, scb, true);
+        }
+        report.sae_len += 1;
+    }
+    Ok(report)
+}
+
+fn se_render_ac_op(op: &AcOpReport) -> String
+{
+    match op.kind {
+        AC_KIND_ALWAYS  => String::from("always"),
+        AC_KIND_NEVER   => String::from("never"),
+        AC_KIND_UNUSED  => String::from("-"),
+        AC_KIND_INVALID => format!("SCB 0x{:02X}: INVALID (bad encoding, or SE record missing or unsuitable for SM)", op.scb),
+        _ => {
+            let name = |reference: u8, what: &str| format!("{} {} {}",
+                if (reference & 0x80) != 0 {"local"} else {"global"}, what, reference & 0x7F);
+            let conditions : Vec<String> = op.conditions[..usize::from(op.conditions_len)].iter().map(|cond| {
+                match (cond.pin, cond.key) {
+                    (0, 0)   => String::from("?"),
+                    (pin, 0) => name(pin, "PIN"),
+                    (0, key) => name(key, "key"),
+                    (pin, key) => format!("({} and {})", name(pin, "PIN"), name(key, "key")),
+                }
+            }).collect();
+            let mut parts = Vec::with_capacity(2);
+            if !conditions.is_empty() {
+                parts.push(if conditions.len() == 1 { conditions[0].clone() }
+                           else if op.is_all { format!("all of: {}", conditions.join(" AND ")) }
+                           else              { format!("any of: {}", conditions.join(" OR ")) });
+            }
+            if op.sm_mac {
+                parts.push(String::from(if op.sm_enc {"SM (MAC and encryption)"} else {"SM (MAC)"}));
+            }
+            format!("SCB 0x{:02X}: {}", op.scb, parts.join(", "))
+        },
+    }
+}
+
+/**
+ * Renders a report from se_get_ac_report as text, 1 line per operation and per SAE entry
+ * @param   report  IN
+ * @return  multi-line text
+ */
+#[must_use]
+pub fn se_render_ac_report(report: &CardCtlAcReport) -> String
+{
+    let mut result = format!("File {:04X} (FDB 0x{:02X}), scb8 {:02X?}\n", report.file_id, report.fdb, report.scb8);
+    for (name, op) in se_ac_op_names(report.fdb).iter().zip(report.ops.iter()) {
+        if op.kind == AC_KIND_UNUSED { continue; }
+        result.push_str(&format!("  {:<12}: {}\n", name, se_render_ac_op(op)));
+    }
+    if report.sae_len > 0 {
+        result.push_str("  SAE of directory (instruction level, takes precedence):\n");
+    }
+    for sae in &report.sae[..usize::from(report.sae_len)] {
+        let mut header = String::new();
+        for (idx, label) in ["CLA", "INS", "P1", "P2"].iter().enumerate() {
+            if (sae.tag_amdo & (8 >> idx)) != 0 {
+                header.push_str(&format!("{} {:02X} ", label, sae.cla_ins_p1_p2[idx]));
+            }
+        }
+        let scbs : Vec<String> = sae.scbs[..usize::from(sae.scbs_len)].iter().map(se_render_ac_op).collect();
+        let requirement = match sae.tag_scdo {
+            0xA0 => format!("any of: [{}]", scbs.join("] OR [")),
+            0xAF => format!("all of: [{}]", scbs.join("] AND [")),
+            _    => scbs.join(""),
+        };
+        result.push_str(&format!("    {:<22}: {}\n", header.trim_end(), requirement));
+    }
+    result
 }
 
 /*
@@ -583,12 +869,313 @@ pub fn se_parse_sac(/*card: &mut sc_card,*/ reference: u32, data: &[u8], se_info
 }
 
 
+/*
+ * Maps a single SCDO (not 0xA0 or 0xAF) to an scb; an 0xA4 SCDO get's stored as virtual SE-file record (SAC)
+ * @param   vec_sac_info_opt  INOUT  the directory's SACinfo, receiving virtual records
+ * @param   idx_virtual       INOUT  the id of the last virtual record added
+ * @param   tag               IN     the SCDO tag
+ * @param   value             IN     the SCDO value
+ * @return                    the scb or an error, if the SCDO is invalid (includes nesting of 0xA0/0xAF)
+ */
+fn se_parse_scdo(vec_sac_info_opt: &mut Option<Vec<SACinfo>>, idx_virtual: &mut u8, tag: u8, value: &[u8]) -> Result<u8, i32>
+{
+    use num_integer::Integer;
+
+    match tag {
+        0x90 => Ok(0),
+        0x97 => Ok(0xFF),
+        0x9E => {
+            if value.len() != 1 { return Err(SC_ERROR_INVALID_DATA); }
+            Ok(value[0])
+        },
+        0xA4 => {
+            if value.len() < 6 || !value.len().is_multiple_of(&3) { return Err(SC_ERROR_INVALID_DATA); }
+            let mut sac_info = SACinfo::default();
+            *idx_virtual += 1;
+            sac_info.reference = (*idx_virtual).into();
+            sac_info.crts_len  = 1;
+            sac_info.crts[0].tag = tag.into();
+            let mut idx_ref = 0;
+            for chunk in value.chunks(3) {
+                if chunk[1] != 1 { return Err(SC_ERROR_INVALID_DATA); }
+                match chunk[0] {
+                    0x95 => { sac_info.crts[0].usage         = chunk[2].into(); },
+                    0x83 => { sac_info.crts[0].refs[idx_ref] = chunk[2].into(); idx_ref += 1; },
+                    0x81 => { /*if card.type_== SC_CARD_TYPE_ACOS5_EVO_V4 {TODO EVO also has tag 0x81} else {panic!()}*/ }
+                    _    => return Err(SC_ERROR_INVALID_DATA),
+                }
+            }
+            vec_sac_info_opt.get_or_insert(Vec::new()).push(sac_info);
+            Ok(*idx_virtual)
+        },
+        _ => Err(SC_ERROR_INVALID_DATA),
+    }
+}
+
+/*
+ * Encodes the CRTs of an SE record: The inverse of se_parse_sac (tag 0x81, that se_parse_sac skips, can't be restored)
+ * A reference 0x84 is encoded as 83 01 84 within an AT (local key 4), otherwise as 84 00
+ * @param   se_info_node  IN
+ * @return  what se_parse_sac expects as data, i.e. the record content from it's 4. byte, e.g. [A4 06 83 01 81 95 01 08]
+ */
+#[must_use]
+pub fn se_encode_sac(se_info_node: &SACinfo) -> Vec<u8>
+{
+    let mut result = Vec::with_capacity(64);
+    for crt in &se_info_node.crts[..se_info_node.crts_len] {
+        let mut value = Vec::with_capacity(24);
+        for &elem in crt.refs.iter().take_while(|&&elem| elem != 0) {
+            if elem == 0x84 && crt.tag != 0xA4 { value.extend_from_slice(&[0x84, 0]); }
+            else                               { value.extend_from_slice(&[0x83, 1, u8::try_from(elem).unwrap()]); }
+        }
+        if crt.usage != 0 { value.extend_from_slice(&[0x95, 1, u8::try_from(crt.usage).unwrap()]); }
+        if crt.algo  != 0 { value.extend_from_slice(&[0x80, 1, u8::try_from(crt.algo).unwrap()]); }
+        result.push(u8::try_from(crt.tag).unwrap());
+        result.push(u8::try_from(value.len()).unwrap());
+        result.extend_from_slice(&value);
+    }
+    result
+}
+
+/* The SE file of a DF/MF: It's path, MRL and NOR */
+pub fn se_file_of_dir(card: &mut sc_card, file_id_dir: u16) -> Result<(sc_path, usize, u32), i32>
+{
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let result = match dp.files.get(&file_id_dir) {
+        Some(val_dir) if is_DFMF(val_dir.1[0]) => {
+            match dp.files.get(&file_id_se(val_dir.1)) {
+                Some(val) if val.1[0] == FDB_SE_FILE && is_child_of(val, val_dir) => {
+                    let len = usize::from(val.1[1]);
+                    let mut path = sc_path { type_: SC_PATH_TYPE_PATH, len, ..sc_path::default() };
+                    path.value[..len].copy_from_slice(&val.0[..len]);
+                    Ok((path, usize::from(val.1[4]), u32::from(val.1[5])))
+                },
+                _ => Err(SC_ERROR_FILE_NOT_FOUND),
+            }
+        },
+        _ => Err(SC_ERROR_INVALID_ARGUMENTS),
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    result
+}
+
+/* Reads the SE file records of a DF/MF from card, up to the first empty record, and refreshes the SACinfo stored with the DF */
+#[allow(clippy::missing_errors_doc)]
+pub fn se_read_records(card: &mut sc_card, file_id_dir: u16) -> Result<Vec<SACinfo>, i32>
+{
+    let (path, mrl, nor) = se_file_of_dir(card, file_id_dir)?;
+    let rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv != SC_SUCCESS {
+        return Err(rv);
+    }
+    let mut result = Vec::with_capacity(14);
+    for rec_nr in 1..=nor {
+        let mut buf = [0_u8; 255];
+        let rv = unsafe { sc_read_record(card, rec_nr, buf.as_mut_ptr(), std::cmp::min(mrl, buf.len()), SC_RECORD_BY_REC_NR) };
+        if rv < 0 {
+            return Err(rv);
+        }
+        let len = usize::try_from(rv).unwrap();
+        if len < 3 || buf[0] == 0 || buf[2] == 0 { // "empty" record
+            break;
+        }
+        let mut sac_info = SACinfo::default();
+        se_parse_sac(u32::from(buf[2]), &buf[3..len], &mut sac_info);
+        result.push(sac_info);
+    }
+
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    if let Some(dp_files_value) = dp.files.get_mut(&file_id_dir) {
+        /* keep the virtual records from SAE */
+        let vec_sac_info = dp_files_value.3.get_or_insert(Vec::new());
+        vec_sac_info.retain(|sac_info| sac_info.reference > 15);
+        vec_sac_info.extend_from_slice(&result);
+    }
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+    Ok(result)
+}
+
+/* Checks the record id and that the pins/keys referred to exist: A pin/key id must not exceed the number of records
+   of the pin file/sym. key file in the DF (local, reference | 0x80) or MF (global) */
+fn se_validate_sac(card: &mut sc_card, file_id_dir: u16, sac_info: &SACinfo) -> Result<(), i32>
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"se_validate_sac\0");
+    if sac_info.reference == 0 || sac_info.reference > 14 || sac_info.crts_len == 0 || sac_info.crts_len > SC_MAX_CRTS_IN_SE {
+        log3if!(ctx,f,line!(), cstru!(b"Error: record id must be 1-14, and the record must have 1-%zu CRTs\0"), SC_MAX_CRTS_IN_SE);
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let count_records = |file_id_dir: u16, fdb: u8| -> u8 {
+        match dp.files.get(&file_id_dir) {
+            Some(val_dir) => dp.files.values().find(|val| val.1[0] == fdb && is_child_of(val, val_dir)).map_or(0, |val| val.1[5]),
+            None => 0,
+        }
+    };
+    let mut result = Ok(());
+    'outer: for crt in &sac_info.crts[..sac_info.crts_len] {
+        for &elem in crt.refs.iter().take_while(|&&elem| elem != 0) {
+            let (is_pin, is_key) = if crt.tag == 0xA4 { ((crt.usage & 0x08) != 0, (crt.usage & 0x80) != 0) }
+                                   else               { (false, elem != 0x84) };
+            let reference = match u8::try_from(elem) {
+                Ok(val) => val,
+                Err(_) => { result = Err(SC_ERROR_INVALID_ARGUMENTS); break 'outer; },
+            };
+            let dir = if (reference & 0x80) != 0 { file_id_dir } else { 0x3F00 };
+            let id = reference & 0x7F;
+            if is_pin && (id == 0 || id > count_records(dir, FDB_CHV_EF)) {
+                log3if!(ctx,f,line!(), cstru!(b"Error: the pin reference 0x%02X refers to a non-existing pin\0"), reference);
+                result = Err(SC_ERROR_INVALID_ARGUMENTS);
+                break 'outer;
+            }
+            if is_key && (id == 0 || id > count_records(dir, FDB_SYMMETRIC_KEY_EF)) {
+                log3if!(ctx,f,line!(), cstru!(b"Error: the key reference 0x%02X refers to a non-existing sym. key\0"), reference);
+                result = Err(SC_ERROR_INVALID_ARGUMENTS);
+                break 'outer;
+            }
+        }
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    result
+}
+
+/**
+ * Reads a record of the SE file of a DF/MF from card
+ * @param   card         INOUT
+ * @param   file_id_dir  IN    the DF/MF
+ * @param   reference    IN    the record id == record no.
+ * @return  the record's content or an error, e.g. SC_ERROR_RECORD_NOT_FOUND
+ */
+#[allow(clippy::missing_errors_doc)]
+pub fn se_record_get(card: &mut sc_card, file_id_dir: u16, reference: u32) -> Result<SACinfo, i32>
+{
+    let records = se_read_records(card, file_id_dir)?;
+    records.into_iter().find(|sac_info| sac_info.reference == reference).ok_or(SC_ERROR_RECORD_NOT_FOUND)
+}
+
+/**
+ * Adds or replaces a record of the SE file of a DF/MF on card (and in DataPrivate.files)
+ * @apiNote An added record get's the next free id, i.e. the one following the last non-empty record.
+ *          The OpenSC ACLs of files selected already won't be updated
+ * @param   card         INOUT
+ * @param   file_id_dir  IN    the DF/MF
+ * @param   sac_info     INOUT the record's content; reference is IN for replace, OUT for add
+ * @param   is_replace   IN
+ * @return  SC_SUCCESS or an error, e.g. SC_ERROR_INVALID_ARGUMENTS if a pin/key referred to doesn't exist
+ */
+#[allow(clippy::missing_errors_doc)]
+pub fn se_record_put(card: &mut sc_card, file_id_dir: u16, sac_info: &mut SACinfo, is_replace: bool) -> Result<(), i32>
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"se_record_put\0");
+    log3ifc!(ctx,f,line!());
+
+    let (path, mrl, nor) = se_file_of_dir(card, file_id_dir)?;
+    let records = se_read_records(card, file_id_dir)?;
+    if is_replace {
+        if !records.iter().any(|rec| rec.reference == sac_info.reference) {
+            return Err(SC_ERROR_RECORD_NOT_FOUND);
+        }
+    }
+    else {
+        sac_info.reference = u32::try_from(records.len()+1).unwrap();
+        if sac_info.reference > nor {
+            log3if!(ctx,f,line!(), cstru!(b"Error: the SE file has no free record\0"));
+            return Err(SC_ERROR_NOT_ENOUGH_MEMORY);
+        }
+    }
+    se_validate_sac(card, file_id_dir, sac_info)?;
+
+    let mut record = vec![0x80, 1, u8::try_from(sac_info.reference).unwrap()];
+    record.extend_from_slice(&se_encode_sac(sac_info));
+    if record.len() > mrl {
+        log3if!(ctx,f,line!(), cstru!(b"Error: the record's content doesn't fit into MRL %zu\0"), mrl);
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    record.resize(mrl, 0);
+    let mut rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv == SC_SUCCESS {
+        rv = unsafe { sc_update_record(card, sac_info.reference, record.as_ptr(), record.len(), SC_RECORD_BY_REC_NR) };
+    }
+    if rv < SC_SUCCESS {
+        return Err(rv);
+    }
+    se_read_records(card, file_id_dir).map(|_| ())
+}
+
+/**
+ * Removes (zeroizes) the last record of the SE file of a DF/MF on card (and in DataPrivate.files)
+ * @apiNote Only the last non-empty record may be removed, as records following an empty one won't be read any more.
+ *          A record that is referred to by any scb8 of the DF/MF itself or it's EFs, or by it's SAE, won't be removed
+ * @param   card         INOUT
+ * @param   file_id_dir  IN    the DF/MF
+ * @param   reference    IN    the record id == record no.
+ * @return  SC_SUCCESS or an error, e.g. SC_ERROR_NOT_ALLOWED if the record is in use
+ */
+#[allow(clippy::missing_errors_doc)]
+pub fn se_record_remove(card: &mut sc_card, file_id_dir: u16, reference: u32) -> Result<(), i32>
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"se_record_remove\0");
+    log3ifc!(ctx,f,line!());
+
+    let (path, mrl, _nor) = se_file_of_dir(card, file_id_dir)?;
+    let records = se_read_records(card, file_id_dir)?;
+    if !records.iter().any(|rec| rec.reference == reference) {
+        return Err(SC_ERROR_RECORD_NOT_FOUND);
+    }
+    if records.last().unwrap().reference != reference {
+        log3if!(ctx,f,line!(), cstru!(b"Error: only the last record of the SE file can be removed\0"));
+        return Err(SC_ERROR_NOT_ALLOWED);
+    }
+
+    let is_referenced = |scb: u8| scb != 0 && scb != 0xFF && u32::from(scb & 0x0F) == reference;
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let mut vec_file_id = vec![file_id_dir];
+    let mut is_referenced_by_sae = false;
+    if let Some(val_dir) = dp.files.get(&file_id_dir) {
+        vec_file_id.extend(dp.files.iter().filter(|(_, val)| !is_DFMF(val.1[0]) && is_child_of(val, val_dir)).map(|(&key, _)| key));
+        if let Some(vec_sae_info) = &val_dir.4 {
+            is_referenced_by_sae = vec_sae_info.iter().any(|sae_info|
+                sae_info.scbs[..usize::from(sae_info.scbs_len)].iter().any(|&scb| (scb & 0xF0) != 0x10 && is_referenced(scb)));
+        }
+    }
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    if is_referenced_by_sae {
+        log3if!(ctx,f,line!(), cstru!(b"Error: the record is referred to by SAE\0"));
+        return Err(SC_ERROR_NOT_ALLOWED);
+    }
+    for file_id in vec_file_id {
+        if se_get_scb8(card, file_id)?.iter().any(|&scb| is_referenced(scb)) {
+            log3if!(ctx,f,line!(), cstru!(b"Error: the record is referred to by file 0x%04X\0"), u32::from(file_id));
+            return Err(SC_ERROR_NOT_ALLOWED);
+        }
+    }
+
+    /* not sc_delete_record: that writes 255 zero bytes, more than MRL */
+    let record = vec![0_u8; mrl];
+    let mut rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv == SC_SUCCESS {
+        rv = unsafe { sc_update_record(card, reference, record.as_ptr(), record.len(), SC_RECORD_BY_REC_NR) };
+    }
+    if rv < SC_SUCCESS {
+        return Err(rv);
+    }
+    se_read_records(card, file_id_dir).map(|_| ())
+}
+
+
 /// # Errors
 ///
 /// Will return `Err` if there are errors in the SAE encoding
 pub fn se_parse_sae(vec_sac_info_opt: &mut Option<Vec<SACinfo>>, value_bytes_tag_fcp_sae: &[u8]) -> Result<Vec<SAEinfo>, i32>
 {
-    use num_integer::Integer;
     use crate::no_cdecl::{convert_amdo_to_cla_ins_p1_p2_array};
 
     // add the A4 tag as virtual SE-file record (SAC), starting with se record id 16, the max. of real ones is 14
@@ -619,39 +1206,35 @@ pub fn se_parse_sae(vec_sac_info_opt: &mut Option<Vec<SACinfo>>, value_bytes_tag
         /* at least 1 SCDO must follow */
         tlv = match tlv.next() {
             Some(item) => item,
-            None => return Err(-1),
+            None => return Err(SC_ERROR_INVALID_DATA),
         };
 //println!("parsed: {:X?}", tlv);
-        assert!([0x90, 0x97, 0x9E, 0xA4, 0xA0, 0xAF].contains(&tlv.tag()));
         sae_info.tag_SCDO = tlv.tag();
         match tlv.tag() {
-            0x90 => sae_info.scb = 0,
-            0x97 => sae_info.scb = 0xFF,
-            0x9E => {
-                assert_eq!(1, tlv.length());
-                sae_info.scb = tlv.value()[0];
-            },
-            0xA4 => {
-                assert!(tlv.length()>=6 && tlv.length().is_multiple_of(&3));
-                let mut sac_info = SACinfo::default();
-                idx_virtual += 1;
-                sae_info.scb       = idx_virtual;
-                sac_info.reference = idx_virtual.into();
-                sac_info.crts_len  = 1;
-                sac_info.crts[0].tag = tlv.tag().into();
-                let mut idx_ref = 0;
-                for chunk in tlv.value().chunks(3) {
-                    assert_eq!(1, chunk[1]);
-                    match chunk[0] {
-                        0x95 => { sac_info.crts[0].usage         = chunk[2].into(); },
-                        0x83 => { sac_info.crts[0].refs[idx_ref] = chunk[2].into(); idx_ref += 1; },
-                        0x81 => { /*if card.type_== SC_CARD_TYPE_ACOS5_EVO_V4 {TODO EVO also has tag 0x81} else {panic!()}*/ }
-                        _    => unreachable!("Encountered unexpected byte"),
+            0xA0 | 0xAF => {
+                let mut tlv_group = Tlv::new(tlv.value());
+                loop {
+                    tlv_group = match tlv_group.next() {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    if usize::from(sae_info.scbs_len) == SAE_MAX_SCDO {
+                        return Err(SC_ERROR_INVALID_DATA);
                     }
+                    let scb = se_parse_scdo(vec_sac_info_opt, &mut idx_virtual, tlv_group.tag(), tlv_group.value())?;
+                    sae_info.scbs[usize::from(sae_info.scbs_len)] = scb;
+                    sae_info.scbs_len += 1;
                 }
-                vec_sac_info_opt.get_or_insert(Vec::new()).push(sac_info);
+                if sae_info.scbs_len == 0 {
+                    return Err(SC_ERROR_INVALID_DATA);
+                }
+                sae_info.scb = sae_info.scbs[0];
+            },
+            _ => {
+                sae_info.scb      = se_parse_scdo(vec_sac_info_opt, &mut idx_virtual, tlv.tag(), tlv.value())?;
+                sae_info.scbs[0]  = sae_info.scb;
+                sae_info.scbs_len = 1;
             },
-            _ => {} // TODO for now: skip support of tags A0 and AF
         }
         vec_sae_info.push(sae_info);
     } // loop
@@ -663,6 +1246,22 @@ pub fn se_parse_sae(vec_sac_info_opt: &mut Option<Vec<SACinfo>>, value_bytes_tag
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants_types::{AcSaeReport, SC_CARD_TYPE_ACOS5_64_V3};
+    use crate::simulator::{Acos5Simulator, SimulatedCard};
+    use opensc_sys::opensc::{sc_file_new, sc_file_free, sc_file_get_acl_entry};
+
+    /* info/card_initialization/card_initialization.scriptor with replacements applied, the SO PIN verified */
+    fn connect_initialized(replacements: &[(&str, &str)]) -> SimulatedCard<Acos5Simulator> {
+        let sim = Acos5Simulator::new_initialized_modified(SC_CARD_TYPE_ACOS5_64_V3, replacements);
+        let mut sim_card = SimulatedCard::connect(sim).unwrap();
+        let resp = sim_card.simulator().process(&[0, 0x20, 0, 1, 8, 0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31]);
+        assert_eq!(resp, [0x90, 0]);
+        sim_card
+    }
+
+    fn se_record_4103(sim_card: &mut SimulatedCard<Acos5Simulator>, rec_nr: usize) -> Vec<u8> {
+        sim_card.simulator().file_by_path(&[0x3F, 0, 0x41, 0, 0x41, 0x03]).unwrap().records[rec_nr-1].clone()
+    }
 
     #[test]
     fn test_se_parse_sac() {
@@ -765,6 +1364,144 @@ mod tests {
         assert_eq!(sac_info.crts[2],   sc_crt{tag: 0xB8, usage: 0x30, algo: 2, refs: [0x84,0,0,0,0,0,0,0]});
     }
 
+    #[test]
+    fn test_se_encode_sac() { // the inverse of test_se_parse_sac
+        let mut sac_info = SACinfo { reference: 1, crts_len: 1, ..SACinfo::default() };
+        sac_info.crts[0] = sc_crt{tag: 0xA4, usage: 8, algo: 0, refs: [0x81,0x01,0,0,0,0,0,0]};
+        assert_eq!(se_encode_sac(&sac_info), [0xA4, 0x09, 0x83, 0x01, 0x81, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08]);
+
+        let mut sac_info = SACinfo { reference: 2, crts_len: 3, ..SACinfo::default() };
+        sac_info.crts[0] = sc_crt{tag: 0xA4, usage: 0x80, algo: 0, refs: [0x84,0,0,0,0,0,0,0]};
+        sac_info.crts[1] = sc_crt{tag: 0xB4, usage: 0x30, algo: 2, refs: [0x84,0,0,0,0,0,0,0]};
+        sac_info.crts[2] = sc_crt{tag: 0xB8, usage: 0x08, algo: 2, refs: [   1,0,0,0,0,0,0,0]};
+        assert_eq!(se_encode_sac(&sac_info), [
+            0xA4, 0x06, 0x83, 0x01, 0x84, 0x95, 0x01, 0x80,
+            0xB4, 0x08, 0x84, 0x00, 0x95, 0x01, 0x30, 0x80, 0x01, 0x02,
+            0xB8, 0x09, 0x83, 0x01, 0x01, 0x95, 0x01, 0x08, 0x80, 0x01, 0x02]);
+        assert!(se_encode_sac(&SACinfo::default()).is_empty());
+    }
+
+    #[test]
+    fn test_se_parse_sac_of_encoded() { // se_parse_sac(se_encode_sac(x)) == x
+        let mut sac_info = SACinfo { reference: 2, crts_len: 3, ..SACinfo::default() };
+        sac_info.crts[0] = sc_crt{tag: 0xA4, usage: 0x88, algo: 0, refs: [0x81,0x84,0x01,0,0,0,0,0]};
+        sac_info.crts[1] = sc_crt{tag: 0xB4, usage: 0x30, algo: 2, refs: [0x84,0,0,0,0,0,0,0]};
+        sac_info.crts[2] = sc_crt{tag: 0xB8, usage: 0x08, algo: 2, refs: [   1,0,0,0,0,0,0,0]};
+        let data = se_encode_sac(&sac_info);
+        let mut parsed = SACinfo::default();
+        assert_eq!(usize::try_from(se_parse_sac(2, &data, &mut parsed)).unwrap(), data.len());
+        assert_eq!(parsed, sac_info);
+    }
+
+    /* DF 4100 of the initialization script: SE file 4103 has records 1-6 of 8, only 1 and 3 are referred to */
+    #[test]
+    fn test_se_record_put_remove_simulated() {
+        let mut sim_card = connect_initialized(&[]);
+        let card = unsafe { &mut *sim_card.card };
+        assert_eq!(se_read_records(card, 0x4100).unwrap().len(), 6);
+        /* only the last record can be removed */
+        assert_eq!(se_record_remove(card, 0x4100, 5), Err(SC_ERROR_NOT_ALLOWED));
+        assert_eq!(se_record_remove(card, 0x4100, 7), Err(SC_ERROR_RECORD_NOT_FOUND));
+        assert_eq!(se_record_remove(card, 0x4100, 6), Ok(()));
+        assert_eq!(se_read_records(card, 0x4100).unwrap().len(), 5);
+        assert!(se_record_4103(&mut sim_card, 6).iter().all(|&b| b == 0));
+
+        /* put adds with the next free id */
+        let mut sac_info = SACinfo { crts_len: 1, ..SACinfo::default() };
+        sac_info.crts[0] = sc_crt{tag: 0xA4, usage: 8, algo: 0, refs: [0x81,0,0,0,0,0,0,0]};
+        assert_eq!(se_record_put(card, 0x4100, &mut sac_info, false), Ok(()));
+        assert_eq!(sac_info.reference, 6);
+        assert_eq!(se_record_4103(&mut sim_card, 6)[..11], [0x80, 1, 6, 0xA4, 6, 0x83, 1, 0x81, 0x95, 1, 8]);
+
+        /* replace: global PIN 1 instead of local PIN 1 */
+        sac_info.crts[0].refs[0] = 1;
+        assert_eq!(se_record_put(card, 0x4100, &mut sac_info, true), Ok(()));
+        assert_eq!(se_record_get(card, 0x4100, 6).unwrap().crts[0].refs[..2], [1, 0]);
+        sac_info.reference = 7;
+        assert_eq!(se_record_put(card, 0x4100, &mut sac_info, true), Err(SC_ERROR_RECORD_NOT_FOUND));
+        /* the local PIN file has 1 record only */
+        sac_info.reference = 6;
+        sac_info.crts[0].refs[0] = 0x85;
+        assert_eq!(se_record_put(card, 0x4100, &mut sac_info, true), Err(SC_ERROR_INVALID_ARGUMENTS));
+        assert_eq!(se_record_4103(&mut sim_card, 6)[..8], [0x80, 1, 6, 0xA4, 6, 0x83, 1, 1]);
+    }
+
+    #[test]
+    fn test_se_record_remove_referenced_simulated() {
+        let replacements = [
+            /* EF.DODF's TERMINATE refers to record 6 */
+            ("83 02 41 17 8A 01 05 82 02 01 00 80 02 01 00 8C 08 7F 00 FF 00 03 FF 00 00",
+             "83 02 41 17 8A 01 05 82 02 01 00 80 02 01 00 8C 08 7F 00 06 00 03 FF 00 00"),
+            /* the SAE of DF 4100: Reset Retry Counter refers to record 6 */
+            ("00 E0 00 00 2D 62 2B 83 02 41 00 8A 01 01 82 02 38 00 8D 02 41 03 84 10 41 43 4F 53 50 4B 43 53 2D 31 35 76 31 2E 30 30 8C 08 7F 03 FF 03 03 01 01 01",
+             "00 E0 00 00 35 62 33 83 02 41 00 8A 01 01 82 02 38 00 8D 02 41 03 84 10 41 43 4F 53 50 4B 43 53 2D 31 35 76 31 2E 30 30 8C 08 7F 03 FF 03 03 01 01 01 AB 06 84 01 2C 9E 01 06"),
+        ];
+        for replacement in &replacements {
+            let mut sim_card = connect_initialized(&[*replacement]);
+            let card = unsafe { &mut *sim_card.card };
+            assert_eq!(se_record_remove(card, 0x4100, 6), Err(SC_ERROR_NOT_ALLOWED));
+            assert_eq!(se_record_4103(&mut sim_card, 6)[..3], [0x80, 1, 6]);
+        }
+    }
+
+    #[test]
+    fn test_se_combine_alternatives() {
+        /* SE record [A4 09 83 01 81 83 01 01 95 01 08]: local PIN 1, global PIN 1 */
+        let chv129 = vec![(SC_AC_CHV, 0x81)];
+        let chv1   = vec![(SC_AC_CHV, 1)];
+        assert_eq!(se_combine_alternatives(vec![chv129.clone(), chv1.clone(), chv129.clone()], true, |_| unreachable!()),
+                   [(SC_AC_CHV, 0x81), (SC_AC_CHV, 1)]);
+        assert_eq!(se_combine_alternatives(vec![chv129.clone(), chv1.clone()], false, |mut alternatives| alternatives.swap_remove(1)),
+                   chv1);
+        assert_eq!(se_combine_alternatives(vec![chv129, chv1], false, |mut alternatives| alternatives.swap_remove(0)),
+                   [(SC_AC_CHV, 0x81)]);
+        /* SE record [A4 09 83 01 81 83 01 01 95 01 88]: each reference requires both the pin and the key */
+        let alternatives = vec![vec![(SC_AC_CHV, 0x81), (SC_AC_AUT, 0x81)], vec![(SC_AC_CHV, 1), (SC_AC_AUT, 1)]];
+        assert_eq!(se_combine_alternatives(alternatives, true, |_| unreachable!()),
+                   [(SC_AC_CHV, 0x81), (SC_AC_AUT, 0x81), (SC_AC_CHV, 1), (SC_AC_AUT, 1)]);
+        /* OR, with key 0x81 authenticated by SM */
+        assert_eq!(se_combine_alternatives(vec![vec![(SC_AC_AUT, 1)], vec![]], false, |_| unreachable!()), []);
+        assert_eq!(se_combine_alternatives(vec![vec![(SC_AC_AUT, 1)], vec![]], true,  |_| unreachable!()), [(SC_AC_AUT, 1)]);
+    }
+
+    #[test]
+    fn test_se_file_add_acl_entry_simulated() {
+        fn acl_read(card: &mut sc_card, scb: u8) -> Vec<(u32, u32)> {
+            let file = unsafe { sc_file_new() };
+            assert!(!file.is_null());
+            unsafe { (*file).id = 0x4100 };
+            se_file_add_acl_entry(card, unsafe { &mut *file }, scb, SC_AC_OP_READ);
+            let mut result = Vec::new();
+            let mut entry = unsafe { sc_file_get_acl_entry(file, SC_AC_OP_READ) };
+            while !entry.is_null() {
+                let e = unsafe { &*entry };
+                result.push((e.method, e.key_ref));
+                entry = e.next;
+            }
+            unsafe { sc_file_free(file) };
+            result
+        }
+
+        let mut sim_card = SimulatedCard::connect(Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3)).unwrap();
+        let card = unsafe { &mut *sim_card.card };
+        assert_eq!(se_read_records(card, 0x4100).unwrap().len(), 6);
+        /* record 4: [A4 09 83 01 81 83 01 01 95 01 08] */
+        assert_eq!(acl_read(card, 0x04), [(SC_AC_CHV, 0x81)]); // OR, nothing verified: the first alternative
+        assert_eq!(acl_read(card, 0x84), [(SC_AC_CHV, 0x81), (SC_AC_CHV, 1)]);
+
+        let resp = sim_card.simulator().process(&[0, 0x20, 0, 1, 8, 0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31]);
+        assert_eq!(resp, [0x90, 0]);
+        let card = unsafe { &mut *sim_card.card };
+        assert_eq!(acl_read(card, 0x04), [(SC_AC_CHV, 1)]);    // OR, V3: the alternative fulfilled already
+        assert_eq!(acl_read(card, 0x84), [(SC_AC_CHV, 0x81), (SC_AC_CHV, 1)]);
+        /* record 1: [A4 06 83 01 81 95 01 08], a single alternative */
+        assert_eq!(acl_read(card, 0x01), [(SC_AC_CHV, 0x81)]);
+        assert_eq!(acl_read(card, 0x81), [(SC_AC_CHV, 0x81)]);
+        /* key_ref is SC_AC_KEY_REF_NONE for these */
+        assert_eq!(acl_read(card, 0x00).iter().map(|&(method, _)| method).collect::<Vec<_>>(), [SC_AC_NONE]);
+        assert_eq!(acl_read(card, 0xFF).iter().map(|&(method, _)| method).collect::<Vec<_>>(), [SC_AC_NEVER]);
+    }
+
     #[test]
     fn test_se_parse_sae() { // $ cargo test test_se_parse_sae
         let v =   [0x84, 0x01, 0x20, 0x9E, 0x01, 0x46,
@@ -774,14 +1511,73 @@ mod tests {
         let mut sac_info_expected = SACinfo { reference: 0x10, crts_len: 1, ..SACinfo::default() };
         sac_info_expected.crts[0] = sc_crt { tag: 0xA4, usage: 8, algo: 0, refs: [0x81, 0, 0, 0, 0, 0, 0, 0] };
         let vec_sae_info_expected = vec![
-            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x20, p1: 0, p2: 0, tag_SCDO: 0x9E, scb: 0x46 },
-            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x24, p1: 0, p2: 0, tag_SCDO: 0x9E, scb: 0x46 },
-            SAEinfo { tag_AMDO: 0x8C, cla: 0x80, ins: 0x30, p1: 0, p2: 0, tag_SCDO: 0x97, scb: 0xFF },
-            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x22, p1: 0, p2: 0, tag_SCDO: 0xA4, scb: 0x10 } ];
+            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x20, p1: 0, p2: 0, tag_SCDO: 0x9E, scb: 0x46, scbs: [0x46, 0, 0], scbs_len: 1 },
+            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x24, p1: 0, p2: 0, tag_SCDO: 0x9E, scb: 0x46, scbs: [0x46, 0, 0], scbs_len: 1 },
+            SAEinfo { tag_AMDO: 0x8C, cla: 0x80, ins: 0x30, p1: 0, p2: 0, tag_SCDO: 0x97, scb: 0xFF, scbs: [0xFF, 0, 0], scbs_len: 1 },
+            SAEinfo { tag_AMDO: 0x84, cla: 0,    ins: 0x22, p1: 0, p2: 0, tag_SCDO: 0xA4, scb: 0x10, scbs: [0x10, 0, 0], scbs_len: 1 } ];
 
         let mut vec_sac_info_opt : Option<Vec<SACinfo>> = None;
         let res = se_parse_sae(&mut vec_sac_info_opt, &v);
         assert_eq!(sac_info_expected, vec_sac_info_opt.unwrap()[0]);
         assert_eq!(vec_sae_info_expected, res.unwrap());
     }
+
+    #[test]
+    fn test_se_parse_sae_any_all() {
+        /* Unblock PIN: local PIN 1 verified OR SM; Change PIN: SCB 0x03 AND local key 1 authenticated */
+        let v =   [0x84, 0x01, 0x2C, 0xA0, 0x0B, 0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x08,  0x9E, 0x01, 0x46,
+                            0x84, 0x01, 0x24, 0xAF, 0x0B, 0x9E, 0x01, 0x03,  0xA4, 0x06, 0x83, 0x01, 0x81, 0x95, 0x01, 0x80];
+        let mut vec_sac_info_opt : Option<Vec<SACinfo>> = None;
+        let vec_sae_info = se_parse_sae(&mut vec_sac_info_opt, &v).unwrap();
+        assert_eq!(vec_sae_info, vec![
+            SAEinfo { tag_AMDO: 0x84, cla: 0, ins: 0x2C, p1: 0, p2: 0, tag_SCDO: 0xA0, scb: 0x10, scbs: [0x10, 0x46, 0], scbs_len: 2 },
+            SAEinfo { tag_AMDO: 0x84, cla: 0, ins: 0x24, p1: 0, p2: 0, tag_SCDO: 0xAF, scb: 0x03, scbs: [0x03, 0x11, 0], scbs_len: 2 } ]);
+        let vec_sac_info = vec_sac_info_opt.unwrap();
+        assert_eq!(vec_sac_info.len(), 2);
+        assert_eq!(vec_sac_info[1].reference, 0x11);
+        assert_eq!(vec_sac_info[1].crts[0], sc_crt { tag: 0xA4, usage: 0x80, algo: 0, refs: [0x81, 0, 0, 0, 0, 0, 0, 0] });
+
+        assert_eq!(sae_select_scb(&vec_sae_info[0], |_| true),  0x46);
+        assert_eq!(sae_select_scb(&vec_sae_info[0], |_| false), 0x10);
+        assert_eq!(sae_select_scb(&vec_sae_info[1], |_| true),  0x03);
+        let any = SAEinfo { tag_SCDO: 0xA0, scbs: [0xFF, 0, 0x46], scbs_len: 3, ..SAEinfo::default() };
+        assert_eq!(sae_select_scb(&any, |_| false), 0);
+        let all = SAEinfo { tag_SCDO: 0xAF, scbs: [0x45, 0, 0x03], scbs_len: 3, ..SAEinfo::default() };
+        assert_eq!(sae_select_scb(&all, |_| false), 0x45);
+        let all = SAEinfo { tag_SCDO: 0xAF, scbs: [0x45, 0xFF, 0], scbs_len: 2, ..SAEinfo::default() };
+        assert_eq!(sae_select_scb(&all, |_| true), 0xFF);
+
+        /* nesting of groups is not allowed */
+        let v = [0x84, 0x01, 0x2C, 0xA0, 0x04, 0xAF, 0x02, 0x90, 0x00];
+        assert_eq!(se_parse_sae(&mut None, &v), Err(SC_ERROR_INVALID_DATA));
+    }
+
+    #[test]
+    fn test_se_render_ac_report() {
+        let mut report = CardCtlAcReport { file_id: 0x4101, fdb: 1, scb8: [0xFF, 0x41, 2, 0x83, 4, 0, 0, 0xFF], ..CardCtlAcReport::default() };
+        report.ops[0] = AcOpReport { scb: 0xFF, kind: AC_KIND_NEVER, ..AcOpReport::default() };
+        report.ops[1] = AcOpReport { scb: 0x41, kind: AC_KIND_CONDITIONS, sm_mac: true, conditions_len: 2, ..AcOpReport::default() };
+        report.ops[1].conditions[..2].copy_from_slice(&[AcCondition { pin: 1, key: 0 }, AcCondition { pin: 0x81, key: 0 }]);
+        report.ops[2] = AcOpReport { scb: 2, kind: AC_KIND_UNUSED, ..AcOpReport::default() };
+        report.ops[3] = AcOpReport { scb: 0x83, kind: AC_KIND_CONDITIONS, is_all: true, conditions_len: 2, ..AcOpReport::default() };
+        report.ops[3].conditions[..2].copy_from_slice(&[AcCondition { pin: 0x81, key: 0x81 }, AcCondition { pin: 0, key: 2 }]);
+        report.ops[4] = AcOpReport { scb: 4, kind: AC_KIND_INVALID, ..AcOpReport::default() };
+        report.ops[6] = AcOpReport { scb: 0xFF, kind: AC_KIND_NEVER, ..AcOpReport::default() };
+        report.sae_len = 1;
+        report.sae[0] = AcSaeReport { tag_amdo: 0x85, cla_ins_p1_p2: [0, 0x2C, 0, 0x81], tag_scdo: 0xA0, scbs_len: 2, ..AcSaeReport::default() };
+        report.sae[0].scbs[0] = AcOpReport { scb: 0xFF, kind: AC_KIND_NEVER, ..AcOpReport::default() };
+        report.sae[0].scbs[1] = AcOpReport { scb: 0x46, kind: AC_KIND_CONDITIONS, sm_mac: true, sm_enc: true, ..AcOpReport::default() };
+
+        assert_eq!(se_render_ac_report(&report),
+"File 4101 (FDB 0x01), scb8 [FF, 41, 02, 83, 04, 00, 00, FF]
+  READ        : never
+  UPDATE      : SCB 0x41: any of: global PIN 1 OR local PIN 1, SM (MAC)
+  DEACTIVATE  : SCB 0x83: all of: (local PIN 1 and local key 1) AND global key 2
+  ACTIVATE    : SCB 0x04: INVALID (bad encoding, or SE record missing or unsuitable for SM)
+  TERMINATE   : always
+  DELETE_SELF : never
+  SAE of directory (instruction level, takes precedence):
+    INS 2C P2 81          : any of: [never] OR [SCB 0x46: SM (MAC and encryption)]
+");
+    }
 }
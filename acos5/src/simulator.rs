@@ -0,0 +1,1610 @@
+/*
+ * simulator.rs: Driver 'acos5' - In-process ACOS5 card simulator, used for driver tests without hardware
+ *
+ * Copyright (C) 2019  Carsten Blüggel <bluecars@posteo.eu>
+ *
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; if not, write to the Free Software
+ * Foundation, 51 Franklin Street, Fifth Floor  Boston, MA 02110-1335  USA
+ */
+
+/*
+The simulator answers the APDUs the driver sends to a ACOS5-64 V2.00, V3.00 or ACOS5-EVO card, sufficiently close to
+the reference manuals, that the real sc_card_operations from acos5_get_card_driver can be driven end to end in
+cargo test:
+
+Acos5Simulator::process takes a command APDU (as serialized by sc_apdu2bytes) and returns response data with SW1 SW2
+appended. It maintains
+ - a file system (MF, DF, transparent/record based EFs, PIN, sym. key, SE and RSA key files)
+ - the security state (verified PINs, authenticated keys), the current DF/EF, the MSE settings and the bytes pending
+   for Get Response
+ - the SCB (Security Condition Byte) enforcement based on the records of the SE file associated with a DF.
+   Files in LCSI 'creation state' (1) are not access controlled, as with the real card.
+   SM (SCB bit 0x40) is not simulated: Such a condition is never met.
+
+SimulatedCard plugs the simulator underneath sc_transmit_apdu: It creates an sc_context, an sc_reader whose
+sc_reader_operations.transmit forwards to the simulator and an sc_card with the driver's operations, and then runs
+acos5_match_card and acos5_init. It is generic over ApduProcessor, thus a transcript::Replayer may take the place of
+the simulator.
+
+Not simulated: SM, symmetric en-/decryption, ECC, and the exact file system overhead on free space.
+*/
+
+use std::os::raw::{c_int, c_void};
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::ptr::null_mut;
+
+use opensc_sys::opensc::{sc_context, sc_card, sc_reader, sc_reader_operations, sc_context_param, sc_context_create,
+                         sc_release_context, SC_PROTO_T0, SC_PROTO_T1};
+use opensc_sys::types::{sc_apdu, SC_MAX_ATR_SIZE};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_INVALID_CARD};
+
+use crate::constants_types::{SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3, SC_CARD_TYPE_ACOS5_EVO_V4,
+                             SC_CARD_TYPE_ACOS5_BASE, ATR_V2, ATR_V3, ATR_V4_2, FDB_MF, FDB_DF, FDB_TRANSPARENT_EF,
+                             FDB_LINEAR_FIXED_EF, FDB_LINEAR_VARIABLE_EF, FDB_CYCLIC_EF, FDB_RSA_KEY_EF, FDB_CHV_EF,
+                             FDB_SYMMETRIC_KEY_EF, FDB_PURSE_EF, FDB_ECC_KEY_EF, FDB_SE_FILE, CRT_TAG_AT,
+                             CRT_TAG_DST, CRT_TAG_CT, READ, UPDATE, CRYPTO, CREATE_EF, CREATE_DF, DELETE_SELF,
+                             is_DFMF, convert_bytes_tag_fcp_sac_to_scb_array};
+use crate::crypto::{des_ecb3_unpadded_8, Encrypt};
+use crate::transcript::{apdu_to_bytes, transcript_start, transcript_stop, Replayer};
+
+/* indices of scb8 for Deactivate, Activate and Terminate; the same for all file types */
+const DEACTIVATE : usize = 3;
+const ACTIVATE   : usize = 4;
+const TERMINATE  : usize = 5;
+
+/* Life Cycle Status Integer */
+const LCSI_CREATION    : u8 = 0x01;
+const LCSI_ACTIVATED   : u8 = 0x05;
+const LCSI_DEACTIVATED : u8 = 0x04;
+const LCSI_TERMINATED  : u8 = 0x0C;
+
+/* EEPROM addresses accessible by 00 B0/D6 C1 xx, only while there is no MF */
+const EEPROM_OP_MODE          : u8 = 0x91;
+const EEPROM_ZEROIZE_DISABLE  : u8 = 0x92;
+const EEPROM_CARD_LIFE_CYCLE  : u8 = 0x84;
+
+const SW_OK                       : [u8; 2] = [0x90, 0x00];
+const SW_WRONG_LENGTH             : [u8; 2] = [0x67, 0x00];
+const SW_FILE_INVALIDATED         : [u8; 2] = [0x62, 0x83];
+const SW_INCOMPATIBLE_FILE        : [u8; 2] = [0x69, 0x81];
+const SW_SECURITY_NOT_SATISFIED   : [u8; 2] = [0x69, 0x82];
+const SW_AUTH_METHOD_BLOCKED      : [u8; 2] = [0x69, 0x83];
+const SW_CONDITIONS_NOT_SATISFIED : [u8; 2] = [0x69, 0x85];
+const SW_NO_CURRENT_EF            : [u8; 2] = [0x69, 0x86];
+const SW_WRONG_DATA               : [u8; 2] = [0x6A, 0x80];
+const SW_FUNC_NOT_SUPPORTED       : [u8; 2] = [0x6A, 0x81];
+const SW_FILE_NOT_FOUND           : [u8; 2] = [0x6A, 0x82];
+const SW_RECORD_NOT_FOUND         : [u8; 2] = [0x6A, 0x83];
+const SW_NOT_ENOUGH_MEMORY        : [u8; 2] = [0x6A, 0x84];
+const SW_INCORRECT_P1P2           : [u8; 2] = [0x6A, 0x86];
+const SW_REF_DATA_NOT_FOUND       : [u8; 2] = [0x6A, 0x88];
+const SW_FILE_EXISTS              : [u8; 2] = [0x6A, 0x89];
+const SW_WRONG_OFFSET             : [u8; 2] = [0x6B, 0x00];
+const SW_INS_NOT_SUPPORTED        : [u8; 2] = [0x6D, 0x00];
+const SW_CLA_NOT_SUPPORTED        : [u8; 2] = [0x6E, 0x00];
+const SW_NO_PRECISE_DIAGNOSIS     : [u8; 2] = [0x6F, 0x00];
+
+/* The EMSA-PKCS1-v1_5 DigestInfo prefixes, that the card prepends to a hash for 'Compute Digital Signature' */
+const DIGEST_INFO_SHA1   : [u8; 15] = [0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00,
+                                       0x04, 0x14];
+const DIGEST_INFO_SHA256 : [u8; 19] = [0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04,
+                                       0x02, 0x01, 0x05, 0x00, 0x04, 0x20];
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+enum BIGNUM {}
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+enum BN_CTX {}
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+enum RSA {}
+
+extern "C" {
+    fn BN_new() -> *mut BIGNUM;
+    fn BN_free(a: *mut BIGNUM);
+    fn BN_bin2bn(s: *const u8, len: c_int, ret: *mut BIGNUM) -> *mut BIGNUM;
+    fn BN_bn2binpad(a: *const BIGNUM, to: *mut u8, tolen: c_int) -> c_int;
+    fn BN_mod_exp(r: *mut BIGNUM, a: *const BIGNUM, p: *const BIGNUM, m: *const BIGNUM, ctx: *mut BN_CTX) -> c_int;
+    fn BN_CTX_new() -> *mut BN_CTX;
+    fn BN_CTX_free(c: *mut BN_CTX);
+    fn RSA_new() -> *mut RSA;
+    fn RSA_free(rsa: *mut RSA);
+    fn RSA_generate_key_ex(rsa: *mut RSA, bits: c_int, e: *mut BIGNUM, cb: *mut c_void) -> c_int;
+    fn RSA_get0_key(rsa: *const RSA, n: *mut *const BIGNUM, e: *mut *const BIGNUM, d: *mut *const BIGNUM);
+}
+
+/* base^exp mod modulus, big-endian, left-padded to modulus.len() */
+fn rsa_mod_exp(base: &[u8], exp: &[u8], modulus: &[u8]) -> Vec<u8> {
+    let mut result = vec![0_u8; modulus.len()];
+    unsafe {
+        let ctx = BN_CTX_new();
+        let a = BN_bin2bn(base.as_ptr(),    c_int::try_from(base.len()).unwrap(),    null_mut());
+        let p = BN_bin2bn(exp.as_ptr(),     c_int::try_from(exp.len()).unwrap(),     null_mut());
+        let m = BN_bin2bn(modulus.as_ptr(), c_int::try_from(modulus.len()).unwrap(), null_mut());
+        let r = BN_new();
+        assert_eq!(1, BN_mod_exp(r, a, p, m, ctx));
+        assert!(BN_bn2binpad(r, result.as_mut_ptr(), c_int::try_from(result.len()).unwrap()) > 0);
+        BN_free(r);
+        BN_free(m);
+        BN_free(p);
+        BN_free(a);
+        BN_CTX_free(ctx);
+    }
+    result
+}
+
+/* returns (modulus, private exponent), both of length bits/8 */
+fn rsa_generate(bits: usize, exponent: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let len = bits/8;
+    let mut modulus = vec![0_u8; len];
+    let mut priv_exp = vec![0_u8; len];
+    unsafe {
+        let rsa = RSA_new();
+        let e = BN_bin2bn(exponent.as_ptr(), c_int::try_from(exponent.len()).unwrap(), null_mut());
+        let ok = RSA_generate_key_ex(rsa, c_int::try_from(bits).unwrap(), e, null_mut()) == 1;
+        if ok {
+            let mut n = std::ptr::null();
+            let mut d = std::ptr::null();
+            RSA_get0_key(rsa, &mut n, null_mut(), &mut d);
+            BN_bn2binpad(n, modulus.as_mut_ptr(),  c_int::try_from(len).unwrap());
+            BN_bn2binpad(d, priv_exp.as_mut_ptr(), c_int::try_from(len).unwrap());
+        }
+        BN_free(e);
+        RSA_free(rsa);
+        if ok { Some((modulus, priv_exp)) } else { None }
+    }
+}
+
+/* simple-TLV splitting, tolerant to trailing zero bytes (unused part of records) and truncation */
+fn tlv_list(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut result = Vec::new();
+    while data.len() >= 2 && data[0] != 0 {
+        let len = usize::from(data[1]);
+        if data.len() < 2+len {
+            break;
+        }
+        result.push((data[0], &data[2..2+len]));
+        data = &data[2+len..];
+    }
+    result
+}
+
+fn with_sw(mut data: Vec<u8>, sw: [u8; 2]) -> Vec<u8> {
+    data.extend_from_slice(&sw);
+    data
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimFile {
+    pub fid     : u16,
+    pub fdb     : u8,
+    pub lcsi    : u8,
+    pub sfi     : u8,
+    pub scb8    : [u8; 8],
+    pub sae     : Vec<u8>,
+    pub seid    : u16,          // DF/MF only: the id of the associated SE file
+    pub df_name : Vec<u8>,
+    pub size    : u16,          // transparent EF and internal key files
+    pub mrl     : u16,          // record based files
+    pub nor     : u16,
+    pub data    : Vec<u8>,
+    pub records : Vec<Vec<u8>>,
+    pub parent  : usize,        // the MF is it's own parent
+    pub children: Vec<usize>,
+    rsa_key     : Option<(Vec<u8>, Vec<u8>)>, // RSA private key file: (modulus, private exponent), never readable
+}
+
+impl SimFile {
+    fn is_record_based(&self) -> bool {
+        [FDB_LINEAR_FIXED_EF, FDB_LINEAR_VARIABLE_EF, FDB_CYCLIC_EF, FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_PURSE_EF,
+         FDB_SE_FILE].contains(&self.fdb)
+    }
+
+    fn is_transparent(&self) -> bool {
+        [FDB_TRANSPARENT_EF, FDB_RSA_KEY_EF, FDB_ECC_KEY_EF].contains(&self.fdb)
+    }
+
+    /* the reserved EEPROM space; transparent or record based */
+    fn space(&self) -> u32 {
+        if self.is_record_based() { u32::from(self.mrl) * u32::from(self.nor) } else { u32::from(self.size) }
+    }
+
+    /* the 8 bytes of 'Get Card Info, P1=2: File Information' */
+    fn file_info(&self) -> [u8; 8] {
+        let fid = self.fid.to_be_bytes();
+        let slot = if is_DFMF(self.fdb) { self.seid.to_be_bytes() }
+                   else if self.is_record_based() { [u8::try_from(self.mrl & 0xFF).unwrap(), u8::try_from(self.nor & 0xFF).unwrap()] }
+                   else { self.size.to_be_bytes() };
+        [self.fdb, 0, fid[0], fid[1], slot[0], slot[1], self.sfi, self.lcsi]
+    }
+
+    /* FCI as returned by select with P2=0: 6F L FCP */
+    fn fci(&self) -> Vec<u8> {
+        let mut fcp = Vec::with_capacity(64);
+        if is_DFMF(self.fdb) {
+            fcp.extend_from_slice(&[0x82, 2, self.fdb, 0]);
+        }
+        else if self.is_record_based() {
+            let mrl = self.mrl.to_be_bytes();
+            let nor = self.nor.to_be_bytes();
+            fcp.extend_from_slice(&[0x82, 6, self.fdb, 0, mrl[0], mrl[1], nor[0], nor[1]]);
+        }
+        else {
+            fcp.extend_from_slice(&[0x82, 2, self.fdb, 0]);
+            fcp.extend_from_slice(&[0x80, 2]);
+            fcp.extend_from_slice(&self.size.to_be_bytes());
+        }
+        fcp.extend_from_slice(&[0x83, 2]);
+        fcp.extend_from_slice(&self.fid.to_be_bytes());
+        if !is_DFMF(self.fdb) {
+            fcp.extend_from_slice(&[0x88, 1, self.sfi]);
+        }
+        fcp.extend_from_slice(&[0x8A, 1, self.lcsi]);
+        fcp.extend_from_slice(&[0x8C, 8, 0x7F]);
+        for pos in (0..7).rev() {
+            fcp.push(self.scb8[pos]);
+        }
+        if is_DFMF(self.fdb) {
+            fcp.extend_from_slice(&[0x8D, 2]);
+            fcp.extend_from_slice(&self.seid.to_be_bytes());
+            if !self.df_name.is_empty() {
+                fcp.extend_from_slice(&[0x84, u8::try_from(self.df_name.len()).unwrap()]);
+                fcp.extend_from_slice(&self.df_name);
+            }
+            if !self.sae.is_empty() {
+                fcp.extend_from_slice(&[0xAB, u8::try_from(self.sae.len()).unwrap()]);
+                fcp.extend_from_slice(&self.sae);
+            }
+        }
+        let mut fci = vec![0x6F, u8::try_from(fcp.len()).unwrap()];
+        fci.extend_from_slice(&fcp);
+        fci
+    }
+}
+
+/* One MSE Set entry: CRT tag B6 (DST) or B8 (CT), with algorithm reference, file id and usage qualifier */
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct MseEntry {
+    tag   : u8,
+    algo  : u8,
+    fid   : u16,
+    usage : u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Acos5Simulator {
+    pub card_type       : i32,
+    pub op_mode         : u8,
+    pub zeroize_disable : u8,
+    pub card_life_cycle : u8,
+    pub serial          : [u8; 8],
+    files               : Vec<Option<SimFile>>, // index 0 is the MF, if it exists
+    curr_df             : Option<usize>,
+    curr_ef             : Option<usize>,
+    pins_verified       : Vec<u8>, // references: 0x80 set for local ones
+    keys_authenticated  : Vec<u8>, // references: 0x80 set for local ones
+    mse                 : Vec<MseEntry>,
+    challenge           : Option<[u8; 8]>,
+    pending_response    : Vec<u8>,
+    chained_data        : Vec<u8>,
+    capacity            : u32,
+}
+
+impl Acos5Simulator {
+    /// A virgin card: no MF, factory setting of the operation mode byte
+    #[must_use]
+    pub fn new(card_type: i32) -> Self {
+        assert!([SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3, SC_CARD_TYPE_ACOS5_EVO_V4].contains(&card_type));
+        Self {
+            card_type,
+            op_mode: match card_type { SC_CARD_TYPE_ACOS5_64_V3 => 2, SC_CARD_TYPE_ACOS5_EVO_V4 => 1, _ => 0 },
+            zeroize_disable: 0,
+            card_life_cycle: 0,
+            serial: [0x42, 0x03, 0x50, 0x05, 0x5B, 0xA1, 0x0A, 0x65],
+            files: Vec::new(),
+            curr_df: None,
+            curr_ef: None,
+            pins_verified: Vec::new(),
+            keys_authenticated: Vec::new(),
+            mse: Vec::new(),
+            challenge: None,
+            pending_response: Vec::new(),
+            chained_data: Vec::new(),
+            capacity: if card_type == SC_CARD_TYPE_ACOS5_EVO_V4 {192_000} else {62_997},
+        }
+    }
+
+    /// A card initialized by info/card_initialization/card_initialization.scriptor
+    #[must_use]
+    pub fn new_initialized(card_type: i32) -> Self {
+        let mut sim = Self::new(card_type);
+        for (cmd, resp) in sim.run_script(include_str!("../../info/card_initialization/card_initialization.scriptor")) {
+            /* the leading SO PIN verification fails on a virgin card, everything else must succeed */
+            assert!(cmd[1] == 0x20 || resp[resp.len()-2..] == SW_OK, "cmd: {:X?}, resp: {:X?}", cmd, resp);
+        }
+        sim
+    }
+
+    /// A card initialized by a modified info/card_initialization/card_initialization.scriptor: Each (from, to) replaces
+    /// text of the script, e.g. to change an SCB, or to comment out a command by prefixing '#'. Commands may fail
+    #[must_use]
+    pub fn new_initialized_modified(card_type: i32, replacements: &[(&str, &str)]) -> Self {
+        let mut script = include_str!("../../info/card_initialization/card_initialization.scriptor").to_string();
+        for (from, to) in replacements {
+            assert!(script.contains(from), "not in script: {}", from);
+            script = script.replace(from, to);
+        }
+        let mut sim = Self::new(card_type);
+        sim.run_script(&script);
+        sim
+    }
+
+    /// Runs a scriptor-style text (one hex APDU per line, # comments, 'reset'), returns (command, response) pairs
+    pub fn run_script(&mut self, script: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut result = Vec::new();
+        for line in script.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "reset" {
+                self.reset();
+                continue;
+            }
+            let cmd : Vec<u8> = line.split_whitespace().map(|b| u8::from_str_radix(b, 16).unwrap()).collect();
+            let resp = self.process(&cmd);
+            result.push((cmd, resp));
+        }
+        result
+    }
+
+    /// Card reset: the file system remains, the volatile state is cleared
+    pub fn reset(&mut self) {
+        self.curr_df = if self.files.is_empty() {None} else {Some(0)};
+        self.curr_ef = None;
+        self.pins_verified.clear();
+        self.keys_authenticated.clear();
+        self.mse.clear();
+        self.challenge = None;
+        self.pending_response.clear();
+        self.chained_data.clear();
+    }
+
+    #[must_use]
+    pub fn does_mf_exist(&self) -> bool {
+        matches!(self.files.first(), Some(Some(_)))
+    }
+
+    /// Finds a file by absolute path (starting with 3F00)
+    #[must_use]
+    pub fn file_by_path(&self, path: &[u8]) -> Option<&SimFile> {
+        if path.len() < 2 || path.len()%2 != 0 || path[..2] != [0x3F, 0] || !self.does_mf_exist() {
+            return None;
+        }
+        let mut idx = 0;
+        for chunk in path[2..].chunks_exact(2) {
+            let fid = u16::from_be_bytes([chunk[0], chunk[1]]);
+            idx = self.child_by_fid(idx, fid)?;
+        }
+        self.files[idx].as_ref()
+    }
+
+    fn file(&self, idx: usize) -> &SimFile {
+        self.files[idx].as_ref().unwrap()
+    }
+
+    fn file_mut(&mut self, idx: usize) -> &mut SimFile {
+        self.files[idx].as_mut().unwrap()
+    }
+
+    fn child_by_fid(&self, df: usize, fid: u16) -> Option<usize> {
+        self.file(df).children.iter().copied().find(|&c| self.file(c).fid == fid)
+    }
+
+    fn child_by_fdb(&self, df: usize, fdb: u8) -> Option<usize> {
+        self.file(df).children.iter().copied().find(|&c| self.file(c).fdb == fdb)
+    }
+
+    /* the DF, that is the context of file idx: for a DF/MF that is itself */
+    fn df_of(&self, idx: usize) -> usize {
+        if is_DFMF(self.file(idx).fdb) { idx } else { self.file(idx).parent }
+    }
+
+    fn curr_file(&self) -> Option<usize> {
+        self.curr_ef.or(self.curr_df)
+    }
+
+    fn set_curr_df(&mut self, df: usize) {
+        if self.curr_df != Some(df) {
+            /* local security status is bound to the DF */
+            self.pins_verified.retain(|r| r & 0x80 == 0);
+            self.keys_authenticated.retain(|r| r & 0x80 == 0);
+        }
+        self.curr_df = Some(df);
+        self.curr_ef = None;
+    }
+
+    fn free_space(&self) -> u32 {
+        let used : u32 = self.files.iter().flatten().map(|f| f.space() + 32).sum();
+        self.capacity.saturating_sub(used)
+    }
+
+    /* SCB enforcement: Ok, or the SW to respond with */
+    fn check_access(&self, idx: usize, op: usize) -> Result<(), [u8; 2]> {
+        let file = self.file(idx);
+        if file.lcsi == LCSI_CREATION {
+            return Ok(());
+        }
+        if file.lcsi == LCSI_TERMINATED {
+            return Err(SW_CONDITIONS_NOT_SATISFIED);
+        }
+        match file.scb8[op] {
+            0    => Ok(()),
+            0xFF => Err(SW_SECURITY_NOT_SATISFIED),
+            scb if scb & 0x40 != 0 => Err(SW_SECURITY_NOT_SATISFIED), // SM is not simulated
+            scb  => {
+                let df = self.df_of(idx);
+                if self.is_se_condition_met(df, scb & 0x0F, scb & 0x80 != 0) { Ok(()) }
+                else { Err(SW_SECURITY_NOT_SATISFIED) }
+            },
+        }
+    }
+
+    /* evaluates the AT CRTs of SE record se_id of the DF's associated SE file;
+       all_required: the SCB's MSB: all of the referenced PINs/keys, otherwise any of them */
+    fn is_se_condition_met(&self, df: usize, se_id: u8, all_required: bool) -> bool {
+        let seid = self.file(df).seid;
+        let se_file = match self.child_by_fid(df, seid) {
+            Some(idx) if self.file(idx).fdb == FDB_SE_FILE => idx,
+            _ => return false,
+        };
+        let record = match self.file(se_file).records.iter().find(|r| r.len() >= 3 && r[..3] == [0x80, 1, se_id]) {
+            Some(rec) => rec,
+            None => return false,
+        };
+        let mut refs = Vec::new();
+        for (tag, value) in tlv_list(&record[3..]) {
+            if tag == CRT_TAG_AT {
+                let inner = tlv_list(value);
+                let usage = inner.iter().find(|(t, _)| *t == 0x95).map_or(0, |(_, v)| v[0]);
+                for (t, v) in inner {
+                    if t == 0x83 && v.len() == 1 {
+                        refs.push((v[0], usage));
+                    }
+                }
+            }
+            else if [0xB4, CRT_TAG_CT].contains(&tag) {
+                return false; // requires SM
+            }
+        }
+        if refs.is_empty() {
+            return false;
+        }
+        let is_met = |&(reference, usage): &(u8, u8)|
+            if usage & 0x08 != 0 { self.pins_verified.contains(&reference) }
+            else { self.keys_authenticated.contains(&reference) };
+        if all_required { refs.iter().all(is_met) } else { refs.iter().any(is_met) }
+    }
+
+    /* PIN or sym. key file for reference: local (0x80) in current DF, otherwise in MF */
+    fn key_file(&self, reference: u8, fdb: u8) -> Option<usize> {
+        let df = if reference & 0x80 != 0 { self.curr_df? } else { 0 };
+        if !self.does_mf_exist() {
+            return None;
+        }
+        self.child_by_fdb(df, fdb)
+    }
+
+    fn key_record_idx(&self, file: usize, reference: u8) -> Option<usize> {
+        self.file(file).records.iter().position(|r| !r.is_empty() && r[0] & 0x1F == reference & 0x1F && r[0] & 0x80 != 0)
+    }
+
+    /* response data possibly exceeding le are held back for Get Response */
+    fn respond(&mut self, mut data: Vec<u8>, le: usize) -> Vec<u8> {
+        if data.len() <= le {
+            return with_sw(data, SW_OK);
+        }
+        self.pending_response = data.split_off(le);
+        let remaining = u8::try_from(self.pending_response.len().min(256) & 0xFF).unwrap();
+        with_sw(data, [0x61, remaining])
+    }
+
+    /// Processes one command APDU, returns response data || SW1 SW2
+    #[allow(clippy::missing_panics_doc)]
+    pub fn process(&mut self, cmd: &[u8]) -> Vec<u8> {
+        if cmd.len() < 4 {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        let (cla, ins, p1, p2) = (cmd[0], cmd[1], cmd[2], cmd[3]);
+        let body = &cmd[4..];
+        let (mut data, le) : (Vec<u8>, usize) =
+            if body.is_empty() { (Vec::new(), 0) }
+            else if body.len() == 1 { (Vec::new(), if body[0] == 0 {256} else {usize::from(body[0])}) }
+            else if body[0] == 0 && body.len() == 3 { /* case 2 extended */
+                let le = usize::from(u16::from_be_bytes([body[1], body[2]]));
+                (Vec::new(), if le == 0 {65536} else {le})
+            }
+            else if body[0] == 0 && body.len() > 3 { /* case 3/4 extended */
+                let lc = usize::from(u16::from_be_bytes([body[1], body[2]]));
+                if body.len() < 3+lc { return SW_WRONG_LENGTH.to_vec(); }
+                let le = if body.len() == 3+lc+2 { usize::from(u16::from_be_bytes([body[3+lc], body[4+lc]])) } else {0};
+                (body[3..3+lc].to_vec(), le)
+            }
+            else {
+                let lc = usize::from(body[0]);
+                if body.len() < 1+lc { return SW_WRONG_LENGTH.to_vec(); }
+                let le = if body.len() == 2+lc { if body[1+lc] == 0 {256} else {usize::from(body[1+lc])} } else {0};
+                (body[1..1+lc].to_vec(), le)
+            };
+
+        if cla & 0x10 != 0 { /* command chaining */
+            self.chained_data.extend_from_slice(&data);
+            return SW_OK.to_vec();
+        }
+        if !self.chained_data.is_empty() {
+            let mut all = std::mem::take(&mut self.chained_data);
+            all.extend_from_slice(&data);
+            data = all;
+        }
+        if ins != 0xC0 {
+            self.pending_response.clear();
+        }
+
+        match (cla & 0xF0, ins) {
+            (0x80, 0x14) => self.get_card_info(p1, p2, le),
+            (0x80, 0x30) => self.zeroize(),
+            (0x80, 0x2E) => self.logout(p2, true),
+            (0x80, 0x8A) => self.logout(p2, false),
+            (0x00, 0xA4) => self.select(p1, p2, &data, le),
+            (0x00, 0xC0) => self.get_response(le),
+            (0x00, 0xB0) => self.read_binary(p1, p2, le),
+            (0x00, 0xD6) => self.update_binary(p1, p2, &data),
+            (0x00, 0x0E) => self.erase_binary(p1, p2, &data),
+            (0x00, 0xB2) => self.read_record(p1, le),
+            (0x00, 0xDC) => self.update_record(p1, &data),
+            (0x00, 0xE2) => self.append_record(&data),
+            (0x00, 0x20) => self.verify(p2, &data),
+            (0x00, 0x24) => self.change_reference_data(p2, &data),
+            (0x00, 0x2C) => self.reset_retry_counter(p1, p2, &data),
+            (0x00, 0x84) => self.get_challenge(le),
+            (0x00, 0x82) => self.external_authenticate(p2, &data),
+            (0x00, 0x88) => self.internal_authenticate(p2, &data, le),
+            (0x00, 0x22) => self.manage_security_env(p1, p2, &data),
+            (0x00, 0x2A) => self.perform_security_operation(p1, p2, &data, le),
+            (0x00, 0x46) => self.generate_key_pair(&data),
+            (0x00, 0xE0) => self.create_file(&data),
+            (0x00, 0xE4) => self.delete_file(&data),
+            (0x00, 0x44) => self.set_life_cycle(&data, ACTIVATE,   LCSI_ACTIVATED),
+            (0x00, 0x04) => self.set_life_cycle(&data, DEACTIVATE, LCSI_DEACTIVATED),
+            (0x00, 0xE6) |
+            (0x00, 0xE8) => self.set_life_cycle(&data, TERMINATE,  LCSI_TERMINATED),
+            (0x00, _) | (0x80, _) => SW_INS_NOT_SUPPORTED.to_vec(),
+            _ => SW_CLA_NOT_SUPPORTED.to_vec(),
+        }
+    }
+
+    fn get_card_info(&mut self, p1: u8, p2: u8, le: usize) -> Vec<u8> {
+        let is_v2  = self.card_type == SC_CARD_TYPE_ACOS5_64_V2;
+        let is_evo = self.card_type == SC_CARD_TYPE_ACOS5_EVO_V4;
+        if is_v2 && p1 > 6 {
+            return SW_INCORRECT_P1P2.to_vec();
+        }
+        match p1 {
+            0 => {
+                let len = if is_v2 {6} else {8};
+                if le != len { return SW_WRONG_LENGTH.to_vec(); }
+                with_sw(self.serial[..len].to_vec(), SW_OK)
+            },
+            1 => match self.curr_df {
+                Some(df) => vec![0x90, u8::try_from(self.file(df).children.len()).unwrap_or(0xFF)],
+                None     => SW_NO_CURRENT_EF.to_vec(),
+            },
+            2 => {
+                let df = match self.curr_df { Some(df) => df, None => return SW_NO_CURRENT_EF.to_vec() };
+                let pos = if is_evo { usize::from(p2).wrapping_sub(1) } else { usize::from(p2) };
+                match self.file(df).children.get(pos) {
+                    Some(&child) => with_sw(self.file(child).file_info().to_vec(), SW_OK),
+                    None => SW_FILE_NOT_FOUND.to_vec(),
+                }
+            },
+            4 => {
+                let free = self.free_space().to_be_bytes();
+                with_sw(if is_evo { free[1..].to_vec() } else { free[2..].to_vec() }, SW_OK)
+            },
+            5 => vec![0x95, if is_evo {0xC0} else {0x40}],
+            6 => {
+                let major = u8::try_from(self.card_type - SC_CARD_TYPE_ACOS5_BASE).unwrap();
+                let minor = if self.card_type == SC_CARD_TYPE_ACOS5_64_V3 {1} else {0};
+                with_sw(vec![0x41, 0x43, 0x4F, 0x53, 0x05, major, minor, 0x40], SW_OK)
+            },
+            7 => with_sw(vec![0x20, 0x19, 0x06, 0x14], SW_OK),
+            8 => with_sw((0..20).collect(), SW_OK),
+            9 => vec![if is_evo {0x95} else {0x90}, self.op_mode],
+            10 => {
+                let is_fips_mode = (self.card_type == SC_CARD_TYPE_ACOS5_64_V3 || is_evo) && self.op_mode == 0;
+                if is_fips_mode { SW_OK.to_vec() } else { SW_NO_PRECISE_DIAGNOSIS.to_vec() }
+            },
+            11 => if self.pins_verified.contains(&p2) { SW_OK.to_vec() } else { SW_NO_PRECISE_DIAGNOSIS.to_vec() },
+            12 => if self.keys_authenticated.contains(&p2) { SW_OK.to_vec() } else { SW_NO_PRECISE_DIAGNOSIS.to_vec() },
+            _ => SW_INCORRECT_P1P2.to_vec(),
+        }
+    }
+
+    fn zeroize(&mut self) -> Vec<u8> {
+        if self.zeroize_disable != 0 {
+            return SW_CONDITIONS_NOT_SATISFIED.to_vec();
+        }
+        if self.does_mf_exist() {
+            if let Err(sw) = self.check_access(0, DELETE_SELF) {
+                return sw.to_vec();
+            }
+        }
+        self.files.clear();
+        self.reset();
+        SW_OK.to_vec()
+    }
+
+    fn logout(&mut self, reference: u8, is_pin: bool) -> Vec<u8> {
+        if is_pin { self.pins_verified.retain(|&r| r != reference); }
+        else      { self.keys_authenticated.retain(|&r| r != reference); }
+        SW_OK.to_vec()
+    }
+
+    fn select(&mut self, p1: u8, p2: u8, data: &[u8], le: usize) -> Vec<u8> {
+        if !self.does_mf_exist() {
+            return SW_NO_CURRENT_EF.to_vec();
+        }
+        let curr_df = self.curr_df.unwrap_or(0);
+        let found = match p1 {
+            0 => {
+                if data.len() != 2 { return SW_WRONG_LENGTH.to_vec(); }
+                let fid = u16::from_be_bytes([data[0], data[1]]);
+                let parent = self.file(curr_df).parent;
+                if fid == 0x3F00 { Some(0) }
+                else if fid == 0x3FFF || fid == self.file(curr_df).fid { Some(curr_df) }
+                else {
+                    self.child_by_fid(curr_df, fid)
+                        .or_else(|| if self.file(parent).fid == fid { Some(parent) } else { None })
+                        .or_else(|| self.child_by_fid(parent, fid))
+                }
+            },
+            3 => Some(self.file(curr_df).parent),
+            4 => (0..self.files.len()).find(|&i| matches!(&self.files[i], Some(f) if is_DFMF(f.fdb) && f.df_name == data)),
+            8 | 9 => {
+                if data.is_empty() || data.len()%2 != 0 { return SW_WRONG_LENGTH.to_vec(); }
+                let mut idx = Some(if p1 == 8 {0} else {curr_df});
+                for chunk in data.chunks_exact(2) {
+                    idx = idx.and_then(|i| self.child_by_fid(i, u16::from_be_bytes([chunk[0], chunk[1]])));
+                }
+                idx
+            },
+            _ => return SW_INCORRECT_P1P2.to_vec(),
+        };
+        let idx = match found { Some(idx) => idx, None => return SW_FILE_NOT_FOUND.to_vec() };
+        if is_DFMF(self.file(idx).fdb) {
+            self.set_curr_df(idx);
+        }
+        else {
+            let df = self.file(idx).parent;
+            self.set_curr_df(df);
+            self.curr_ef = Some(idx);
+        }
+        let sw = if [LCSI_DEACTIVATED, LCSI_TERMINATED].contains(&self.file(idx).lcsi) {SW_FILE_INVALIDATED} else {SW_OK};
+        if p2 == 0 && le > 0 && sw == SW_OK {
+            let fci = self.file(idx).fci();
+            return self.respond(fci, le);
+        }
+        sw.to_vec()
+    }
+
+    fn get_response(&mut self, le: usize) -> Vec<u8> {
+        if self.pending_response.is_empty() {
+            return SW_CONDITIONS_NOT_SATISFIED.to_vec();
+        }
+        let pending = std::mem::take(&mut self.pending_response);
+        self.respond(pending, le)
+    }
+
+    /* the current EF, if transparent, and if access condition op is met */
+    fn curr_ef_checked(&self, op: usize, record_based: bool) -> Result<usize, [u8; 2]> {
+        let ef = self.curr_ef.ok_or(SW_NO_CURRENT_EF)?;
+        let file = self.file(ef);
+        if (record_based && !file.is_record_based()) || (!record_based && !file.is_transparent()) {
+            return Err(SW_INCOMPATIBLE_FILE);
+        }
+        self.check_access(ef, op)?;
+        Ok(ef)
+    }
+
+    fn read_binary(&mut self, p1: u8, p2: u8, le: usize) -> Vec<u8> {
+        if p1 == 0xC1 {
+            if self.does_mf_exist() { return SW_CONDITIONS_NOT_SATISFIED.to_vec(); }
+            return match p2 {
+                EEPROM_OP_MODE         => with_sw(vec![self.op_mode], SW_OK),
+                EEPROM_ZEROIZE_DISABLE => with_sw(vec![self.zeroize_disable], SW_OK),
+                EEPROM_CARD_LIFE_CYCLE => with_sw(vec![self.card_life_cycle], SW_OK),
+                _ => SW_INCORRECT_P1P2.to_vec(),
+            };
+        }
+        let ef = match self.curr_ef_checked(READ, false) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let offset = usize::from(u16::from_be_bytes([p1 & 0x7F, p2]));
+        let file = self.file(ef);
+        if offset >= usize::from(file.size) {
+            return SW_WRONG_OFFSET.to_vec();
+        }
+        let end = usize::from(file.size).min(offset + le);
+        let data = file.data[offset..end].to_vec();
+        self.respond(data, le)
+    }
+
+    fn update_binary(&mut self, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+        if p1 == 0xC1 {
+            if self.does_mf_exist() { return SW_CONDITIONS_NOT_SATISFIED.to_vec(); }
+            if data.len() != 1 { return SW_WRONG_LENGTH.to_vec(); }
+            match p2 {
+                EEPROM_OP_MODE         => self.op_mode = data[0],
+                EEPROM_ZEROIZE_DISABLE => self.zeroize_disable = data[0],
+                _ => return SW_INCORRECT_P1P2.to_vec(),
+            }
+            return SW_OK.to_vec();
+        }
+        let ef = match self.curr_ef_checked(UPDATE, false) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let offset = usize::from(u16::from_be_bytes([p1 & 0x7F, p2]));
+        let file = self.file_mut(ef);
+        if offset + data.len() > usize::from(file.size) {
+            return SW_WRONG_OFFSET.to_vec();
+        }
+        file.data[offset..offset+data.len()].copy_from_slice(data);
+        SW_OK.to_vec()
+    }
+
+    fn erase_binary(&mut self, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+        let ef = match self.curr_ef_checked(UPDATE, false) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let file = self.file_mut(ef);
+        let start = usize::from(u16::from_be_bytes([p1, p2]));
+        let end = if data.len() == 2 { usize::from(u16::from_be_bytes([data[0], data[1]])) } else { usize::from(file.size) };
+        let end = end.min(usize::from(file.size));
+        if start > end {
+            return SW_WRONG_OFFSET.to_vec();
+        }
+        file.data[start..end].iter_mut().for_each(|b| *b = 0);
+        SW_OK.to_vec()
+    }
+
+    fn read_record(&mut self, rec_nr: u8, le: usize) -> Vec<u8> {
+        let ef = match self.curr_ef_checked(READ, true) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let file = self.file(ef);
+        match file.records.get(usize::from(rec_nr).wrapping_sub(1)) {
+            Some(rec) if !rec.is_empty() => {
+                let data = rec[..rec.len().min(le)].to_vec();
+                with_sw(data, SW_OK)
+            },
+            _ => SW_RECORD_NOT_FOUND.to_vec(),
+        }
+    }
+
+    fn update_record(&mut self, rec_nr: u8, data: &[u8]) -> Vec<u8> {
+        let ef = match self.curr_ef_checked(UPDATE, true) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let file = self.file_mut(ef);
+        if data.len() > usize::from(file.mrl) {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        let mrl = usize::from(file.mrl);
+        match file.records.get_mut(usize::from(rec_nr).wrapping_sub(1)) {
+            Some(rec) => {
+                rec.resize(mrl, 0);
+                rec[..data.len()].copy_from_slice(data);
+                SW_OK.to_vec()
+            },
+            None => SW_RECORD_NOT_FOUND.to_vec(),
+        }
+    }
+
+    fn append_record(&mut self, data: &[u8]) -> Vec<u8> {
+        let ef = match self.curr_ef_checked(UPDATE, true) { Ok(ef) => ef, Err(sw) => return sw.to_vec() };
+        let file = self.file_mut(ef);
+        if data.len() > usize::from(file.mrl) {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        let mut rec = data.to_vec();
+        rec.resize(usize::from(file.mrl), 0);
+        if file.fdb == FDB_CYCLIC_EF {
+            file.records.insert(0, rec);
+            file.records.truncate(usize::from(file.nor));
+            return SW_OK.to_vec();
+        }
+        /* linear files: the first unused record */
+        match file.records.iter_mut().find(|r| r.is_empty()) {
+            Some(slot) => { *slot = rec; SW_OK.to_vec() },
+            None => SW_NOT_ENOUGH_MEMORY.to_vec(),
+        }
+    }
+
+    /* CHV record: ID|0x80(|0x40 if PUK), PIN tries (max<<4|left), PIN len, PIN[len], PUK tries, PUK len, PUK[len] */
+    fn pin_record(&self, reference: u8) -> Result<(usize, usize), [u8; 2]> {
+        let file = self.key_file(reference, FDB_CHV_EF).ok_or(SW_REF_DATA_NOT_FOUND)?;
+        let rec = self.key_record_idx(file, reference).ok_or(SW_REF_DATA_NOT_FOUND)?;
+        Ok((file, rec))
+    }
+
+    /* compares secret at record offset pos (tries byte, len byte, secret), maintaining the error counter */
+    fn check_secret(&mut self, file: usize, rec: usize, pos: usize, candidate: &[u8]) -> Result<(), [u8; 2]> {
+        let record = &mut self.file_mut(file).records[rec];
+        if record.len() < pos+2 {
+            return Err(SW_REF_DATA_NOT_FOUND);
+        }
+        let tries = record[pos];
+        if tries & 0x0F == 0 {
+            return Err(SW_AUTH_METHOD_BLOCKED);
+        }
+        let len = usize::from(record[pos+1]);
+        let stored = &record[pos+2 .. (pos+2+len).min(record.len())];
+        let candidate = candidate.iter().copied().rev().skip_while(|&b| b == 0xFF).collect::<Vec<u8>>();
+        let candidate : Vec<u8> = candidate.into_iter().rev().collect();
+        if candidate == stored {
+            record[pos] = (tries & 0xF0) | (tries >> 4);
+            Ok(())
+        }
+        else {
+            let left = (tries & 0x0F) - 1;
+            record[pos] = (tries & 0xF0) | left;
+            Err(if left == 0 {SW_AUTH_METHOD_BLOCKED} else {[0x63, 0xC0 | left]})
+        }
+    }
+
+    fn verify(&mut self, reference: u8, data: &[u8]) -> Vec<u8> {
+        let (file, rec) = match self.pin_record(reference) { Ok(val) => val, Err(sw) => return sw.to_vec() };
+        if data.is_empty() {
+            let tries = self.file(file).records[rec][1];
+            return if tries & 0x0F == 0 {SW_AUTH_METHOD_BLOCKED.to_vec()} else {vec![0x63, 0xC0 | (tries & 0x0F)]};
+        }
+        match self.check_secret(file, rec, 1, data) {
+            Ok(()) => {
+                if !self.pins_verified.contains(&reference) {
+                    self.pins_verified.push(reference);
+                }
+                SW_OK.to_vec()
+            },
+            Err(sw) => {
+                self.pins_verified.retain(|&r| r != reference);
+                sw.to_vec()
+            },
+        }
+    }
+
+    fn set_secret(&mut self, file: usize, rec: usize, pos: usize, new: &[u8]) {
+        let record = &mut self.file_mut(file).records[rec];
+        let len = usize::from(record[pos+1]);
+        let mut padded = new.to_vec();
+        padded.resize(len, 0xFF);
+        record[pos+2 .. pos+2+len].copy_from_slice(&padded);
+    }
+
+    fn change_reference_data(&mut self, reference: u8, data: &[u8]) -> Vec<u8> {
+        let (file, rec) = match self.pin_record(reference) { Ok(val) => val, Err(sw) => return sw.to_vec() };
+        let len = usize::from(self.file(file).records[rec][2]);
+        if data.len() != 2*len {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        if let Err(sw) = self.check_secret(file, rec, 1, &data[..len]) {
+            return sw.to_vec();
+        }
+        self.set_secret(file, rec, 1, &data[len..]);
+        SW_OK.to_vec()
+    }
+
+    fn reset_retry_counter(&mut self, p1: u8, reference: u8, data: &[u8]) -> Vec<u8> {
+        let (file, rec) = match self.pin_record(reference) { Ok(val) => val, Err(sw) => return sw.to_vec() };
+        let pin_len = usize::from(self.file(file).records[rec][2]);
+        let puk_pos = 3 + pin_len;
+        let puk_len = usize::from(*self.file(file).records[rec].get(puk_pos+1).unwrap_or(&0));
+        if puk_len == 0 {
+            return SW_CONDITIONS_NOT_SATISFIED.to_vec();
+        }
+        let expected_len = if p1 == 0 {puk_len + pin_len} else {puk_len};
+        if data.len() != expected_len {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        if let Err(sw) = self.check_secret(file, rec, puk_pos, &data[..puk_len]) {
+            return sw.to_vec();
+        }
+        if p1 == 0 {
+            self.set_secret(file, rec, 1, &data[puk_len..]);
+        }
+        let record = &mut self.file_mut(file).records[rec];
+        record[1] = (record[1] & 0xF0) | (record[1] >> 4);
+        SW_OK.to_vec()
+    }
+
+    fn get_challenge(&mut self, le: usize) -> Vec<u8> {
+        if le != 8 {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        let mut challenge = [0_u8; 8];
+        assert_eq!(1, unsafe { crate::crypto::RAND_bytes(challenge.as_mut_ptr(), 8) });
+        self.challenge = Some(challenge);
+        with_sw(challenge.to_vec(), SW_OK)
+    }
+
+    /* Sym. key record: ID|0x80, key type (bit 0: ext. auth., bit 1: int. auth.), ext. auth. error counter (1 byte),
+       int. auth. usage counter (2 bytes), algorithm reference, key */
+    fn sym_key(&self, reference: u8) -> Option<Vec<u8>> {
+        let file = self.key_file(reference, FDB_SYMMETRIC_KEY_EF)?;
+        let rec = &self.file(file).records[self.key_record_idx(file, reference)?];
+        let key_type = rec[1];
+        let mut pos = 2;
+        if key_type & 1 != 0 { pos += 1; }
+        if key_type & 2 != 0 { pos += 2; }
+        let algo = *rec.get(pos)?;
+        let key_len = match algo { 0x14 | 0x22 | 0x06 => 24, 0x12 | 0x02 => 16, 0x04 | 0x20 => 32, _ => 24 };
+        rec.get(pos+1 .. pos+1+key_len).map(<[u8]>::to_vec)
+    }
+
+    fn external_authenticate(&mut self, reference: u8, data: &[u8]) -> Vec<u8> {
+        let challenge = match self.challenge.take() { Some(c) => c, None => return SW_CONDITIONS_NOT_SATISFIED.to_vec() };
+        let key = match self.sym_key(reference) { Some(k) => k, None => return SW_REF_DATA_NOT_FOUND.to_vec() };
+        if des_ecb3_unpadded_8(&challenge, &key, Encrypt) == data {
+            if !self.keys_authenticated.contains(&reference) {
+                self.keys_authenticated.push(reference);
+            }
+            SW_OK.to_vec()
+        }
+        else {
+            SW_SECURITY_NOT_SATISFIED.to_vec()
+        }
+    }
+
+    fn internal_authenticate(&mut self, reference: u8, data: &[u8], le: usize) -> Vec<u8> {
+        let key = match self.sym_key(reference) { Some(k) => k, None => return SW_REF_DATA_NOT_FOUND.to_vec() };
+        if data.len() != 8 {
+            return SW_WRONG_LENGTH.to_vec();
+        }
+        let resp = des_ecb3_unpadded_8(data, &key, Encrypt);
+        self.respond(resp, le)
+    }
+
+    fn manage_security_env(&mut self, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+        if p1 != 0x01 && p1 != 0x41 && p1 != 0x81 {
+            return SW_INCORRECT_P1P2.to_vec();
+        }
+        let mut entry = MseEntry { tag: p2, ..MseEntry::default() };
+        for (tag, value) in tlv_list(data) {
+            match (tag, value.len()) {
+                (0x80, 1) => entry.algo  = value[0],
+                (0x81, 2) => entry.fid   = u16::from_be_bytes([value[0], value[1]]),
+                (0x95, 1) => entry.usage = value[0],
+                (0x83, _) | (0x84, _) | (0x87, _) => {},
+                _ => return SW_WRONG_DATA.to_vec(),
+            }
+        }
+        self.mse.retain(|e| !(e.tag == entry.tag && e.usage == entry.usage));
+        self.mse.push(entry);
+        SW_OK.to_vec()
+    }
+
+    fn mse_entry(&self, tag: u8, usage: u8) -> Option<MseEntry> {
+        self.mse.iter().copied().find(|e| e.tag == tag && e.usage == usage)
+    }
+
+    /* the key file referenced by an MSE entry, searched in the current DF */
+    fn mse_key_file(&self, tag: u8, usage: u8, op: usize) -> Result<usize, [u8; 2]> {
+        let entry = self.mse_entry(tag, usage).ok_or(SW_CONDITIONS_NOT_SATISFIED)?;
+        let df = self.curr_df.ok_or(SW_CONDITIONS_NOT_SATISFIED)?;
+        let file = self.child_by_fid(df, entry.fid).ok_or(SW_FILE_NOT_FOUND)?;
+        if self.file(file).fdb != FDB_RSA_KEY_EF {
+            return Err(SW_INCOMPATIBLE_FILE);
+        }
+        self.check_access(file, op)?;
+        Ok(file)
+    }
+
+    fn perform_security_operation(&mut self, p1: u8, p2: u8, data: &[u8], le: usize) -> Vec<u8> {
+        match (p1, p2) {
+            (0x9E, 0x9A) => { /* compute digital signature: input is a SHA-1 or SHA-256 hash */
+                let file = match self.mse_key_file(CRT_TAG_DST, 0x40, CRYPTO) { Ok(f) => f, Err(sw) => return sw.to_vec() };
+                let (n, d) = match &self.file(file).rsa_key { Some(key) => key.clone(), None => return SW_REF_DATA_NOT_FOUND.to_vec() };
+                if self.file(file).data[0] % 3 == 2 {
+                    return SW_CONDITIONS_NOT_SATISFIED.to_vec(); // decrypt-only key
+                }
+                let mut digest_info = match data.len() { 20 => DIGEST_INFO_SHA1.to_vec(), 32 => DIGEST_INFO_SHA256.to_vec(),
+                                                          _ => return SW_WRONG_LENGTH.to_vec() };
+                digest_info.extend_from_slice(data);
+                let mut em = vec![0xFF_u8; n.len()];
+                em[0] = 0;
+                em[1] = 1;
+                em[n.len()-digest_info.len()-1] = 0;
+                em[n.len()-digest_info.len()..].copy_from_slice(&digest_info);
+                let signature = rsa_mod_exp(&em, &d, &n);
+                self.respond(signature, le)
+            },
+            (0x80, 0x84) => { /* RSA private key decrypt (raw) */
+                let file = match self.mse_key_file(CRT_TAG_CT, 0x40, CRYPTO) { Ok(f) => f, Err(sw) => return sw.to_vec() };
+                let (n, d) = match &self.file(file).rsa_key { Some(key) => key.clone(), None => return SW_REF_DATA_NOT_FOUND.to_vec() };
+                if self.file(file).data[0] % 3 == 1 || data.len() != n.len() {
+                    return SW_WRONG_DATA.to_vec();
+                }
+                let plain = rsa_mod_exp(data, &d, &n);
+                self.respond(plain, le)
+            },
+            (0x84, 0x80) => { /* RSA public key encrypt (raw) */
+                let file = match self.mse_key_file(CRT_TAG_CT, 0x80, READ) { Ok(f) => f, Err(sw) => return sw.to_vec() };
+                let pub_file = self.file(file);
+                let mod_len = usize::from(pub_file.data[1]) * 16;
+                if pub_file.data.len() < 21+mod_len || data.len() != mod_len {
+                    return SW_WRONG_DATA.to_vec();
+                }
+                let cipher = rsa_mod_exp(data, &pub_file.data[5..21], &pub_file.data[21..21+mod_len]);
+                self.respond(cipher, le)
+            },
+            _ => SW_FUNC_NOT_SUPPORTED.to_vec(),
+        }
+    }
+
+    /* data: key_len_code (modulus bits/128), key_priv_type_code, optionally 16 bytes public exponent */
+    fn generate_key_pair(&mut self, data: &[u8]) -> Vec<u8> {
+        if data.len() != 2 && data.len() != 18 {
+            return if data.len() == 1 {SW_FUNC_NOT_SUPPORTED.to_vec()} else {SW_WRONG_LENGTH.to_vec()}; // ECC isn't simulated
+        }
+        let (key_len_code, priv_type_code) = (data[0], data[1]);
+        let bits = usize::from(key_len_code) * 128;
+        let max_bits = if self.card_type == SC_CARD_TYPE_ACOS5_64_V3 && self.op_mode == 0 {3072} else {4096};
+        if bits < 512 || bits > max_bits || !(1..=6).contains(&priv_type_code) {
+            return SW_WRONG_DATA.to_vec();
+        }
+        let mut exponent = [0_u8; 16];
+        if data.len() == 18 { exponent.copy_from_slice(&data[2..]); } else { exponent[13..].copy_from_slice(&[1, 0, 1]); }
+
+        let file_priv = match self.mse_key_file(CRT_TAG_DST, 0x40, UPDATE) { Ok(f) => f, Err(sw) => return sw.to_vec() };
+        let file_pub  = match self.mse_key_file(CRT_TAG_DST, 0x80, UPDATE) { Ok(f) => f, Err(sw) => return sw.to_vec() };
+        let mod_len = bits/8;
+        let priv_len = if priv_type_code > 3 {5 + 5*mod_len/2} else {5 + mod_len};
+        if usize::from(self.file(file_pub).size) < 21 + mod_len || usize::from(self.file(file_priv).size) < priv_len {
+            return SW_NOT_ENOUGH_MEMORY.to_vec();
+        }
+        let (n, d) = match rsa_generate(bits, &exponent) { Some(key) => key, None => return SW_WRONG_DATA.to_vec() };
+
+        let fid_priv = self.file(file_priv).fid.to_be_bytes();
+        let fid_pub  = self.file(file_pub).fid.to_be_bytes();
+        let pub_file = self.file_mut(file_pub);
+        pub_file.data[..5].copy_from_slice(&[0, key_len_code, fid_priv[0], fid_priv[1], 3]);
+        pub_file.data[5..21].copy_from_slice(&exponent);
+        pub_file.data[21..21+mod_len].copy_from_slice(&n);
+        let priv_file = self.file_mut(file_priv);
+        priv_file.data.iter_mut().for_each(|b| *b = 0);
+        priv_file.data[..5].copy_from_slice(&[priv_type_code, key_len_code, fid_pub[0], fid_pub[1], 3]);
+        priv_file.rsa_key = Some((n, d));
+        SW_OK.to_vec()
+    }
+
+    /* data: 62 L FCP */
+    fn create_file(&mut self, data: &[u8]) -> Vec<u8> {
+        let fcp = match tlv_list(data).first() { Some((0x62, fcp)) => tlv_list(fcp), _ => return SW_WRONG_DATA.to_vec() };
+        let mut file = SimFile { lcsi: LCSI_CREATION, ..SimFile::default() };
+        file.scb8[7] = 0xFF;
+        for (tag, value) in fcp {
+            match tag {
+                0x82 => {
+                    if value.is_empty() { return SW_WRONG_DATA.to_vec(); }
+                    file.fdb = value[0];
+                    if value.len() == 6 {
+                        file.mrl = u16::from_be_bytes([value[2], value[3]]);
+                        file.nor = u16::from_be_bytes([value[4], value[5]]);
+                    }
+                },
+                0x83 if value.len() == 2 => file.fid = u16::from_be_bytes([value[0], value[1]]),
+                0x80 if value.len() == 2 => file.size = u16::from_be_bytes([value[0], value[1]]),
+                0x88 if value.len() == 1 => file.sfi = value[0],
+                0x8A if value.len() == 1 => file.lcsi = value[0],
+                0x8C => file.scb8 = match convert_bytes_tag_fcp_sac_to_scb_array(value) {
+                    Ok(scb8) => scb8,
+                    Err(_) => return SW_WRONG_DATA.to_vec(),
+                },
+                0x8D if value.len() == 2 => file.seid = u16::from_be_bytes([value[0], value[1]]),
+                0x84 => file.df_name = value.to_vec(),
+                0xAB => file.sae = value.to_vec(),
+                _ => return SW_WRONG_DATA.to_vec(),
+            }
+        }
+        if file.fid == 0 || file.fid == 0x3FFF || file.fid == 0xFFFF {
+            return SW_WRONG_DATA.to_vec();
+        }
+        if file.is_record_based() {
+            if file.mrl == 0 || file.nor == 0 { return SW_WRONG_DATA.to_vec(); }
+            let initial = if [FDB_LINEAR_VARIABLE_EF, FDB_CYCLIC_EF].contains(&file.fdb) {Vec::new()} else {vec![0; usize::from(file.mrl)]};
+            file.records = vec![initial; usize::from(file.nor)];
+            if file.fdb == FDB_CYCLIC_EF { file.records.clear(); }
+        }
+        else if !is_DFMF(file.fdb) {
+            if ![FDB_TRANSPARENT_EF, FDB_RSA_KEY_EF, FDB_ECC_KEY_EF].contains(&file.fdb) { return SW_WRONG_DATA.to_vec(); }
+            file.data = vec![0; usize::from(file.size)];
+        }
+        if file.space() + 32 > self.free_space() {
+            return SW_NOT_ENOUGH_MEMORY.to_vec();
+        }
+
+        if file.fdb == FDB_MF {
+            if self.does_mf_exist() || file.fid != 0x3F00 { return SW_FILE_EXISTS.to_vec(); }
+            self.files.push(Some(file));
+            self.reset();
+            return SW_OK.to_vec();
+        }
+        if !self.does_mf_exist() || file.fdb == FDB_MF {
+            return SW_NO_CURRENT_EF.to_vec();
+        }
+        let df = self.curr_df.unwrap_or(0);
+        if let Err(sw) = self.check_access(df, if file.fdb == FDB_DF {CREATE_DF} else {CREATE_EF}) {
+            return sw.to_vec();
+        }
+        if self.child_by_fid(df, file.fid).is_some() || file.fid == self.file(df).fid {
+            return SW_FILE_EXISTS.to_vec();
+        }
+        if [FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_SE_FILE].contains(&file.fdb) && self.child_by_fdb(df, file.fdb).is_some() {
+            return SW_FILE_EXISTS.to_vec(); // max. 1 file of these types per DF
+        }
+        file.parent = df;
+        let is_df = file.fdb == FDB_DF;
+        self.files.push(Some(file));
+        let idx = self.files.len()-1;
+        self.file_mut(df).children.push(idx);
+        /* the new file becomes the current one */
+        if is_df { self.set_curr_df(idx); } else { self.curr_ef = Some(idx); }
+        SW_OK.to_vec()
+    }
+
+    /* the file addressed by data (fid, searched like select by fid), or the current file */
+    fn target_file(&self, data: &[u8]) -> Result<usize, [u8; 2]> {
+        if data.is_empty() {
+            return self.curr_file().ok_or(SW_NO_CURRENT_EF);
+        }
+        if data.len() != 2 {
+            return Err(SW_WRONG_LENGTH);
+        }
+        let fid = u16::from_be_bytes([data[0], data[1]]);
+        let df = self.curr_df.ok_or(SW_NO_CURRENT_EF)?;
+        if fid == self.file(df).fid { return Ok(df); }
+        self.child_by_fid(df, fid).ok_or(SW_FILE_NOT_FOUND)
+    }
+
+    fn delete_file(&mut self, data: &[u8]) -> Vec<u8> {
+        let idx = match self.target_file(data) { Ok(idx) => idx, Err(sw) => return sw.to_vec() };
+        if let Err(sw) = self.check_access(idx, DELETE_SELF) {
+            return sw.to_vec();
+        }
+        if idx == 0 {
+            self.files.clear();
+            self.reset();
+            return SW_OK.to_vec();
+        }
+        let parent = self.file(idx).parent;
+        self.file_mut(parent).children.retain(|&c| c != idx);
+        let mut stack = vec![idx];
+        while let Some(i) = stack.pop() {
+            stack.extend_from_slice(&self.file(i).children);
+            self.files[i] = None;
+        }
+        let curr_df = self.curr_df.unwrap_or(0);
+        if self.files[curr_df].is_none() { self.set_curr_df(parent); }
+        self.curr_ef = None;
+        SW_OK.to_vec()
+    }
+
+    fn set_life_cycle(&mut self, data: &[u8], op: usize, lcsi: u8) -> Vec<u8> {
+        let idx = match self.target_file(data) { Ok(idx) => idx, Err(sw) => return sw.to_vec() };
+        if self.file(idx).lcsi != LCSI_CREATION {
+            if let Err(sw) = self.check_access(idx, op) {
+                return sw.to_vec();
+            }
+        }
+        self.file_mut(idx).lcsi = lcsi;
+        SW_OK.to_vec()
+    }
+}
+
+
+/* Plugging the simulator underneath sc_transmit_apdu */
+
+/// Anything that answers command APDUs with response data || SW1 SW2, like a card does
+pub trait ApduProcessor {
+    fn card_type(&self) -> i32;
+    fn process(&mut self, cmd: &[u8]) -> Vec<u8>;
+}
+
+impl ApduProcessor for Acos5Simulator {
+    fn card_type(&self) -> i32 { self.card_type }
+    fn process(&mut self, cmd: &[u8]) -> Vec<u8> { Acos5Simulator::process(self, cmd) }
+}
+
+impl ApduProcessor for Replayer {
+    fn card_type(&self) -> i32 { self.card_type }
+    fn process(&mut self, cmd: &[u8]) -> Vec<u8> { Replayer::process(self, cmd) }
+}
+
+unsafe extern "C" fn sim_reader_transmit<T: ApduProcessor>(reader: *mut sc_reader, apdu: *mut sc_apdu) -> i32 {
+    if reader.is_null() || apdu.is_null() || (*reader).drv_data.is_null() {
+        return SC_ERROR_INVALID_ARGUMENTS;
+    }
+    let sim = &mut *(*reader).drv_data.cast::<T>();
+    let apdu = &mut *apdu;
+    let resp = sim.process(&apdu_to_bytes(apdu));
+    let (data, sw) = resp.split_at(resp.len()-2);
+    let len = data.len().min(apdu.resplen);
+    if len > 0 {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), apdu.resp, len);
+    }
+    apdu.resplen = len;
+    apdu.sw1 = u32::from(sw[0]);
+    apdu.sw2 = u32::from(sw[1]);
+    SC_SUCCESS
+}
+
+unsafe extern "C" fn sim_reader_noop(_reader: *mut sc_reader) -> i32 {
+    SC_SUCCESS
+}
+
+/// An sc_card with the driver's sc_card_operations, connected via an sc_reader to an `ApduProcessor`
+pub struct SimulatedCard<T: ApduProcessor> {
+    pub card : *mut sc_card,
+    ctx      : *mut sc_context,
+    reader   : *mut sc_reader,
+    ops      : *mut sc_reader_operations,
+    sim      : *mut T,
+}
+
+impl<T: ApduProcessor> SimulatedCard<T> {
+    /// Connects to sim as OpenSC does: acos5_match_card, then acos5_init
+    ///
+    /// # Errors
+    /// The error code of sc_context_create or acos5_init, or `SC_ERROR_INVALID_CARD` if the card doesn't match
+    pub fn connect(sim: T) -> Result<Self, i32> {
+        Self::connect_with_transcript(sim, None)
+    }
+
+    /// Like connect, additionally recording the APDU transcript (already for acos5_match_card) to file path
+    ///
+    /// # Errors
+    /// The error code of sc_context_create, transcript_start or acos5_init, or `SC_ERROR_INVALID_CARD` if the card
+    /// doesn't match
+    #[allow(clippy::missing_panics_doc)]
+    pub fn connect_with_transcript(sim: T, path: Option<&CStr>) -> Result<Self, i32> {
+        let card_type = sim.card_type();
+        let atr_str : &[u8] = match card_type {
+            SC_CARD_TYPE_ACOS5_64_V2 => ATR_V2,
+            SC_CARD_TYPE_ACOS5_64_V3 => ATR_V3,
+            _ => ATR_V4_2,
+        };
+        let atr : Vec<u8> = std::str::from_utf8(&atr_str[..atr_str.len()-1]).unwrap().split(':')
+            .map(|b| u8::from_str_radix(b, 16).unwrap()).collect();
+        assert!(atr.len() <= SC_MAX_ATR_SIZE);
+        let is_evo = card_type == SC_CARD_TYPE_ACOS5_EVO_V4;
+
+        let mut ctx = null_mut();
+        let param = sc_context_param { ver: 0, app_name: cstru!(b"acos5_simulator\0").as_ptr(), flags: 0,
+                                       thread_ctx: null_mut() };
+        let rv = unsafe { sc_context_create(&mut ctx, &param) };
+        if rv != SC_SUCCESS {
+            return Err(rv);
+        }
+
+        let ops = Box::into_raw(Box::new(sc_reader_operations {
+            transmit: Some(sim_reader_transmit::<T>),
+            lock:     Some(sim_reader_noop),
+            unlock:   Some(sim_reader_noop),
+            ..unsafe { std::mem::zeroed() }
+        }));
+        let sim = Box::into_raw(Box::new(sim));
+        let mut reader : Box<sc_reader> = Box::new(unsafe { std::mem::zeroed() });
+        reader.ctx = ctx;
+        reader.ops = ops;
+        reader.drv_data = sim.cast::<c_void>();
+        reader.name = cstru!(b"ACOS5 simulator\0").as_ptr() as *mut _;
+        reader.active_protocol = if is_evo {SC_PROTO_T1} else {SC_PROTO_T0};
+        reader.atr.value[..atr.len()].copy_from_slice(&atr);
+        reader.atr.len = atr.len();
+        let reader = Box::into_raw(reader);
+
+        let driver = unsafe { &*crate::acos5_get_card_driver() };
+        let mut card : Box<sc_card> = Box::new(unsafe { std::mem::zeroed() });
+        card.ctx = ctx;
+        card.reader = reader;
+        card.atr = unsafe { (*reader).atr };
+        card.max_send_size = 255;
+        card.max_recv_size = 256;
+        card.driver = driver as *const _ as *mut _;
+        card.ops = driver.ops;
+        card.name = driver.name;
+        let card = Box::into_raw(card);
+        let result = Self { card, ctx, reader, ops, sim };
+
+        if let Some(path) = path {
+            transcript_start(unsafe { &mut *card }, path)?;
+        }
+        let ops = unsafe { &*driver.ops };
+        if unsafe { ops.match_card.unwrap()(card) } != 1 {
+            return Err(SC_ERROR_INVALID_CARD);
+        }
+        let rv = unsafe { ops.init.unwrap()(card) };
+        if rv != SC_SUCCESS {
+            return Err(rv);
+        }
+        Ok(result)
+    }
+
+    /// Access to the simulator's state, e.g. for asserting the file system
+    #[must_use]
+    pub fn simulator(&mut self) -> &mut T {
+        unsafe { &mut *self.sim }
+    }
+}
+
+impl<T: ApduProcessor> Drop for SimulatedCard<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let card = &mut *self.card;
+            if !card.drv_data.is_null() {
+                if let Some(finish) = (*card.ops).finish { finish(card); }
+            }
+            transcript_stop(card);
+            drop(Box::from_raw(card.ops));
+            drop(Box::from_raw(card.driver));
+            drop(Box::from_raw(self.card));
+            drop(Box::from_raw(self.reader));
+            drop(Box::from_raw(self.ops));
+            drop(Box::from_raw(self.sim));
+            sc_release_context(self.ctx);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opensc_sys::opensc::{sc_select_file, sc_read_binary, sc_format_path, sc_file_new, sc_file_free};
+    use opensc_sys::types::{sc_path, SC_FILE_TYPE_DF};
+    use opensc_sys::errors::{SC_ERROR_CARD_CMD_FAILED, SC_ERROR_INVALID_PIN_REFERENCE, SC_ERROR_TOO_MANY_OBJECTS,
+                             SC_ERROR_SECURITY_STATUS_NOT_SATISFIED};
+    use crate::constants_types::{pin_id_select, is_pin_reference_in_use_sw, PIN_ID_MAX};
+
+    fn sw(resp: &[u8]) -> [u8; 2] {
+        [resp[resp.len()-2], resp[resp.len()-1]]
+    }
+
+    #[test]
+    fn test_card_info() {
+        for &card_type in &[SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3, SC_CARD_TYPE_ACOS5_EVO_V4] {
+            let mut sim = Acos5Simulator::new(card_type);
+            let resp = sim.process(&[0x80, 0x14, 6, 0, 8]);
+            assert_eq!(resp[..5], [0x41, 0x43, 0x4F, 0x53, 0x05]);
+            assert_eq!(SC_CARD_TYPE_ACOS5_BASE + i32::from(resp[5]), card_type);
+            assert_eq!(sw(&resp), SW_OK);
+            let resp = sim.process(&[0x80, 0x14, 5, 0]);
+            assert_eq!(resp, [0x95, if card_type == SC_CARD_TYPE_ACOS5_EVO_V4 {0xC0} else {0x40}]);
+            let len = if card_type == SC_CARD_TYPE_ACOS5_64_V2 {6} else {8};
+            assert_eq!(sim.process(&[0x80, 0x14, 0, 0, len]).len(), usize::from(len)+2);
+            /* virgin card */
+            assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x3F, 0]), SW_NO_CURRENT_EF);
+        }
+    }
+
+    #[test]
+    fn test_initialization_script() {
+        let mut sim = Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3);
+        assert!(sim.does_mf_exist());
+        let token_info = sim.file_by_path(&[0x3F, 0, 0x41, 0, 0x50, 0x32]).unwrap();
+        assert_eq!(token_info.lcsi, LCSI_ACTIVATED);
+        assert_eq!(token_info.data[..2], [0x30, 0x7B]);
+
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0]), SW_OK);
+        assert_eq!(sim.process(&[0x80, 0x14, 1, 0]), [0x90, 16]);
+        let resp = sim.process(&[0x80, 0x14, 2, 0, 8]);
+        assert_eq!(resp, [FDB_CHV_EF, 0, 0x41, 0x01, 0x15, 0x01, 0, LCSI_ACTIVATED, 0x90, 0]);
+        let resp = sim.process(&[0, 0xA4, 0, 0, 2, 0x50, 0x32, 0]);
+        assert_eq!(sw(&resp), SW_OK);
+        assert_eq!(resp[0], 0x6F);
+        let resp = sim.process(&[0, 0xB0, 0, 0, 4]);
+        assert_eq!(resp, [0x30, 0x7B, 0x02, 0x01, 0x90, 0]);
+    }
+
+    #[test]
+    fn test_scb_enforcement_and_pin() {
+        let mut sim = Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3);
+        /* PIN file: READ never */
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0]), SW_OK);
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0x01]), SW_OK);
+        assert_eq!(sim.process(&[0, 0xB2, 1, 4, 0x15]), SW_SECURITY_NOT_SATISFIED);
+        /* MF: CREATE_EF requires SE record 1 of 0003: global PIN 1 */
+        let create_ef = [0, 0xE0, 0, 0, 0x0E, 0x62, 0x0C, 0x83, 2, 0, 0x10, 0x82, 2, 1, 0, 0x80, 2, 0, 0x10];
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x3F, 0]), SW_OK);
+        assert_eq!(sim.process(&create_ef), SW_SECURITY_NOT_SATISFIED);
+        assert_eq!(sim.process(&[0, 0x20, 0, 1]), [0x63, 0xC8]);
+        assert_eq!(sim.process(&[0, 0x20, 0, 1, 8, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31]), [0x63, 0xC7]);
+        assert_eq!(sim.process(&[0x80, 0x14, 11, 1]), SW_NO_PRECISE_DIAGNOSIS);
+        assert_eq!(sim.process(&[0, 0x20, 0, 1, 8, 0x38, 0x37, 0x36, 0x35, 0x34, 0x33, 0x32, 0x31]), SW_OK);
+        assert_eq!(sim.process(&[0, 0x20, 0, 1]), [0x63, 0xC8]);
+        assert_eq!(sim.process(&[0x80, 0x14, 11, 1]), SW_OK);
+        assert_eq!(sim.process(&create_ef), SW_OK);
+        assert_eq!(sim.process(&create_ef), SW_FILE_EXISTS);
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0]), SW_OK);
+        /* local PIN with padding */
+        assert_eq!(sim.process(&[0, 0x20, 0, 0x81, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38]), SW_OK);
+        assert_eq!(sim.process(&[0x80, 0x2E, 0, 0x81]), SW_OK);
+        assert_eq!(sim.process(&[0x80, 0x14, 11, 0x81]), SW_NO_PRECISE_DIAGNOSIS);
+        /* leaving the DF resets the local security status */
+        assert_eq!(sim.process(&[0, 0x20, 0, 0x81, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38]), SW_OK);
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x3F, 0]), SW_OK);
+        assert_eq!(sim.process(&[0x80, 0x14, 11, 0x81]), SW_NO_PRECISE_DIAGNOSIS);
+        assert_eq!(sim.process(&[0x80, 0x14, 11, 1]), SW_OK);
+    }
+
+    #[test]
+    fn test_pin_id_select() {
+        fn is_in_use(sim: &mut Acos5Simulator, reference: u8) -> Result<bool, i32> {
+            let resp = sim.process(&[0, 0x20, 0, reference]);
+            is_pin_reference_in_use_sw(resp[0], resp[1]).ok_or(SC_ERROR_CARD_CMD_FAILED)
+        }
+        let mut sim = Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3);
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0]), SW_OK);
+        /* local PIN 1 exists: the first free one is 2; an in-use one gets rejected */
+        assert_eq!(pin_id_select(0, PIN_ID_MAX, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Ok(2));
+        assert_eq!(pin_id_select(3, PIN_ID_MAX, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Ok(3));
+        assert_eq!(pin_id_select(1, PIN_ID_MAX, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Err(SC_ERROR_INVALID_PIN_REFERENCE));
+        assert_eq!(pin_id_select(3, 2, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Err(SC_ERROR_INVALID_PIN_REFERENCE));
+        assert_eq!(pin_id_select(0, 1, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Err(SC_ERROR_TOO_MANY_OBJECTS));
+        /* a blocked PIN is in use as well */
+        for _ in 0..8 {
+            sim.process(&[0, 0x20, 0, 0x81, 8, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31, 0x31]);
+        }
+        assert_eq!(sim.process(&[0, 0x20, 0, 0x81]), SW_AUTH_METHOD_BLOCKED);
+        assert_eq!(pin_id_select(1, PIN_ID_MAX, |pin_id| is_in_use(&mut sim, 0x80 | pin_id)), Err(SC_ERROR_INVALID_PIN_REFERENCE));
+    }
+
+    #[test]
+    fn test_generate_sign_decrypt() {
+        let mut sim = Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3);
+        assert_eq!(sim.process(&[0, 0xA4, 0, 0, 2, 0x41, 0]), SW_OK);
+        assert_eq!(sim.process(&[0, 0x20, 0, 0x81, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38]), SW_OK);
+        /* RSA key files 41F1 (private, CRYPTO requires local PIN 1) and 4131 (public) */
+        let resp = sim.process(&[0, 0xE0, 0, 0, 0x1E, 0x62, 0x1C, 0x83, 2, 0x41, 0xF1, 0x88, 1, 0, 0x8A, 1, 1, 0x82, 2, 0x09, 0,
+            0x80, 2, 0x02, 0x85, 0x8C, 8, 0x7F, 1, 0xFF, 1, 1, 1, 1, 0xFF]);
+        assert_eq!(resp, SW_OK);
+        assert_eq!(sim.process(&[0, 0x44, 0, 0, 2, 0x41, 0xF1]), SW_OK);
+        let resp = sim.process(&[0, 0xE0, 0, 0, 0x1E, 0x62, 0x1C, 0x83, 2, 0x41, 0x31, 0x88, 1, 0, 0x8A, 1, 1, 0x82, 2, 0x09, 0,
+            0x80, 2, 0x01, 0x15, 0x8C, 8, 0x7F, 1, 0xFF, 1, 1, 0, 1, 0]);
+        assert_eq!(resp, SW_OK);
+        assert_eq!(sim.process(&[0, 0x44, 0, 0, 2, 0x41, 0x31]), SW_OK);
+
+        assert_eq!(sim.process(&[0, 0x22, 1, 0xB6, 0x0A, 0x80, 1, 0x10, 0x81, 2, 0x41, 0xF1, 0x95, 1, 0x40]), SW_OK);
+        assert_eq!(sim.process(&[0, 0x22, 1, 0xB6, 0x0A, 0x80, 1, 0x10, 0x81, 2, 0x41, 0x31, 0x95, 1, 0x80]), SW_OK);
+        assert_eq!(sim.process(&[0, 0x46, 0, 0, 2, 8, 3]), SW_OK);
+        let pub_file = sim.file_by_path(&[0x3F, 0, 0x41, 0, 0x41, 0x31]).unwrap().data.clone();
+        assert_eq!(pub_file[..2], [0, 8]);
+
+        /* sign a SHA-256 hash, response > 256 bytes is not required for 1024 bit */
+        let hash = [0x5A_u8; 32];
+        let mut cmd = vec![0, 0x2A, 0x9E, 0x9A, 32];
+        cmd.extend_from_slice(&hash);
+        cmd.push(0);
+        let resp = sim.process(&cmd);
+        assert_eq!(sw(&resp), SW_OK);
+        let signature = &resp[..resp.len()-2];
+        assert_eq!(signature.len(), 128);
+        let em = rsa_mod_exp(signature, &pub_file[5..21], &pub_file[21..21+128]);
+        assert_eq!(em[..2], [0, 1]);
+        assert_eq!(em[128-32..], hash);
+        assert_eq!(em[128-51..128-32], DIGEST_INFO_SHA256);
+
+        /* decrypt what was encrypted with the public key; with Le=0x40 the remainder is fetched by Get Response */
+        assert_eq!(sim.process(&[0, 0x22, 1, 0xB8, 0x0A, 0x80, 1, 0x12, 0x81, 2, 0x41, 0xF1, 0x95, 1, 0x40]), SW_OK);
+        let mut plain = vec![0x33_u8; 128];
+        plain[0] = 0;
+        let cipher = rsa_mod_exp(&plain, &pub_file[5..21], &pub_file[21..21+128]);
+        let mut cmd = vec![0, 0x2A, 0x80, 0x84, 128];
+        cmd.extend_from_slice(&cipher);
+        cmd.push(0x40);
+        let resp = sim.process(&cmd);
+        assert_eq!(sw(&resp), [0x61, 0x40]);
+        let mut result = resp[..0x40].to_vec();
+        let resp = sim.process(&[0, 0xC0, 0, 0, 0x40]);
+        assert_eq!(sw(&resp), SW_OK);
+        result.extend_from_slice(&resp[..0x40]);
+        assert_eq!(result, plain);
+
+        /* without the PIN, the private key is unusable */
+        assert_eq!(sim.process(&[0x80, 0x2E, 0, 0x81]), SW_OK);
+        let mut cmd = vec![0, 0x2A, 0x9E, 0x9A, 32];
+        cmd.extend_from_slice(&hash);
+        cmd.push(0);
+        assert_eq!(sim.process(&cmd), SW_SECURITY_NOT_SATISFIED);
+    }
+
+    /* acos5_create_file takes the SCB of the parent DF's CREATE_DF for a DF, that of CREATE_EF for an EF: One of them
+       is set to 0x41 here, which requires SM, but SE record 1 of DF 4100 has no CT for it */
+    #[test]
+    fn test_create_file_scb_of_parent_simulated() {
+        const DF_4100: &str = "8D 02 41 03 84 10 41 43 4F 53 50 4B 43 53 2D 31 35 76 31 2E 30 30 8C 08 7F 03 FF 03 03 01 01 01";
+        fn create(sim_card: &mut SimulatedCard<Acos5Simulator>, id: i32, type_: u32) -> i32 {
+            let card = unsafe { &mut *sim_card.card };
+            let file = unsafe { sc_file_new() };
+            assert!(!file.is_null());
+            unsafe {
+                (*file).id = id;
+                (*file).type_ = type_;
+                if type_ == u32::from(FDB_TRANSPARENT_EF) { (*file).size = 16; }
+            }
+            let rv = crate::acos5_create_file(card, file);
+            unsafe { sc_file_free(file) };
+            rv
+        }
+        fn connect_4100(create_df_ef: &str) -> SimulatedCard<Acos5Simulator> {
+            let modified = DF_4100.replace("03 01 01 01", create_df_ef);
+            let sim = Acos5Simulator::new_initialized_modified(SC_CARD_TYPE_ACOS5_64_V3, &[(DF_4100, &modified)]);
+            let mut sim_card = SimulatedCard::connect(sim).unwrap();
+            let card = unsafe { &mut *sim_card.card };
+            let mut path = sc_path::default();
+            unsafe { sc_format_path(cstru!(b"3F004100\0").as_ptr(), &mut path) };
+            assert_eq!(SC_SUCCESS, unsafe { sc_select_file(card, &path, null_mut()) });
+            let resp = sim_card.simulator().process(&[0, 0x20, 0, 0x81, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38]);
+            assert_eq!(resp, SW_OK);
+            sim_card
+        }
+
+        /* CREATE_DF 0x41, CREATE_EF 0x01 */
+        let mut sim_card = connect_4100("03 41 01 01");
+        assert_eq!(create(&mut sim_card, 0x4200, u32::from(FDB_DF)), SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+        assert_eq!(create(&mut sim_card, 0x4200, SC_FILE_TYPE_DF),   SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+        assert_eq!(create(&mut sim_card, 0x4150, u32::from(FDB_TRANSPARENT_EF)), SC_SUCCESS);
+        assert!(sim_card.simulator().file_by_path(&[0x3F, 0, 0x41, 0, 0x41, 0x50]).is_some());
+
+        /* CREATE_DF 0x01, CREATE_EF 0x41 */
+        let mut sim_card = connect_4100("03 01 41 01");
+        assert_eq!(create(&mut sim_card, 0x4150, u32::from(FDB_TRANSPARENT_EF)), SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+        assert!(sim_card.simulator().file_by_path(&[0x3F, 0, 0x41, 0, 0x41, 0x50]).is_none());
+    }
+
+    /* end to end: acos5_match_card, acos5_init (enum_dir), select and read via libopensc */
+    #[test]
+    fn test_driver_on_simulated_card() {
+        let mut sim_card = SimulatedCard::connect(Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3)).unwrap();
+        let card = unsafe { &mut *sim_card.card };
+        assert_eq!(card.type_, SC_CARD_TYPE_ACOS5_64_V3);
+        let mut path = sc_path::default();
+        unsafe { sc_format_path(cstru!(b"3F0041005032\0").as_ptr(), &mut path) };
+        assert_eq!(SC_SUCCESS, unsafe { sc_select_file(card, &path, null_mut()) });
+        let mut buf = [0_u8; 4];
+        assert_eq!(4, unsafe { sc_read_binary(card, 0, buf.as_mut_ptr(), 4, 0) });
+        assert_eq!(buf, [0x30, 0x7B, 0x02, 0x01]);
+        assert!(sim_card.simulator().does_mf_exist());
+    }
+
+    /* acos5_match_card accepts exactly the Operation Mode Bytes, that card_ctl SC_CARDCTL_ACOS5_OP_MODE_BYTE may set */
+    #[test]
+    fn test_match_card_op_mode_byte_simulated() {
+        for &(card_type, op_mode, is_match) in &[(SC_CARD_TYPE_ACOS5_64_V3, 0, true), (SC_CARD_TYPE_ACOS5_64_V3, 1, false),
+                (SC_CARD_TYPE_ACOS5_64_V3, 2, true), (SC_CARD_TYPE_ACOS5_64_V3, 16, false),
+                (SC_CARD_TYPE_ACOS5_EVO_V4, 0, true), (SC_CARD_TYPE_ACOS5_EVO_V4, 1, true)] {
+            let mut sim = Acos5Simulator::new_initialized(card_type);
+            sim.op_mode = op_mode;
+            match SimulatedCard::connect(sim) {
+                Ok(mut sim_card) => {
+                    assert!(is_match, "card_type {}, op_mode {}", card_type, op_mode);
+                    assert_eq!(unsafe { (*sim_card.card).type_ }, card_type);
+                    assert_eq!(sim_card.simulator().op_mode, op_mode);
+                },
+                Err(e) => {
+                    assert!(!is_match, "card_type {}, op_mode {}", card_type, op_mode);
+                    assert_eq!(e, SC_ERROR_INVALID_CARD);
+                },
+            }
+        }
+    }
+}
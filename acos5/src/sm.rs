@@ -23,17 +23,17 @@ towards OpenSC (OpenSC wouldn't help anyway but just confuse if setting SC_AC_PR
 */
 
 /*
-TODO There is a lot of code duplication here: Abstract as much as possible for the 4 APDU cases, but first test
-  thoroughly that all is working as expected
+The SM framing (tags 0x87/0x81, 0x97, 0x8E, 0x99) for all APDU cases is done in one place: SmSession::wrap/unwrap,
+used by sm_transmit
 */
 
 use libc::{free, strlen};
 use num_integer::Integer;
 
 use std::os::raw::{c_char, c_ulong, c_void};
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::ptr::{null, null_mut};
-use std::slice::from_raw_parts;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
 use std::convert::{TryFrom/*, TryInto*/};
 
 use opensc_sys::opensc::{sc_context, sc_card, sc_hex_to_bin, sc_transmit_apdu,
@@ -41,24 +41,28 @@ use opensc_sys::opensc::{sc_context, sc_card, sc_hex_to_bin, sc_transmit_apdu,
                          SC_PIN_CMD_VERIFY, SC_PIN_CMD_CHANGE, SC_PIN_CMD_UNBLOCK};
 #[cfg(not(target_os = "windows"))]
 use opensc_sys::opensc::{sc_select_file};
-use opensc_sys::types::{SC_APDU_CASE_4_SHORT, sc_aid};
-use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_SM_KEYSET_NOT_FOUND, SC_ERROR_UNKNOWN_DATA_RECEIVED, SC_ERROR_INVALID_DATA,
+use opensc_sys::types::{sc_apdu, sc_aid, SC_APDU_CASE_1, SC_APDU_CASE_2_SHORT, SC_APDU_CASE_3_SHORT, SC_APDU_CASE_4_SHORT,
+                        SC_APDU_CASE_4_EXT, SC_APDU_SHORT_MASK, SC_APDU_EXT};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_SM_KEYSET_NOT_FOUND, SC_ERROR_UNKNOWN_DATA_RECEIVED, SC_ERROR_INVALID_DATA, SC_ERROR_NOT_SUPPORTED,
                          SC_ERROR_SM_IFD_DATA_MISSING, SC_ERROR_SM_AUTHENTICATION_FAILED,
                          SC_ERROR_SM_NOT_INITIALIZED, SC_ERROR_SM, SC_ERROR_PIN_CODE_INCORRECT, SC_ERROR_AUTH_METHOD_BLOCKED,
-                         SC_ERROR_KEYPAD_MSG_TOO_LONG};
+                         SC_ERROR_KEYPAD_MSG_TOO_LONG, SC_ERROR_BUFFER_TOO_SMALL, SC_ERROR_INVALID_ARGUMENTS,
+                         SC_ERROR_NOT_ALLOWED};
     /*, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_SECURITY_STATUS_NOT_SATISFIED, SC_ERROR_NOT_SUPPORTED*/
 use opensc_sys::sm::{sm_info, SM_SMALL_CHALLENGE_LEN, SM_CMD_FILE_READ, SM_CMD_FILE_UPDATE, SM_CMD_PIN};
     /*, sm_cwa_session, SM_CMD_PIN_VERIFY, SM_CMD_FILE_CREATE, SM_CMD_FILE_DELETE, SM_CMD_FILE,*/
 use opensc_sys::log::{sc_dump_hex}; /*, SC_LOG_DEBUG_NORMAL, SC_LOG_DEBUG_SM*/
-use opensc_sys::scconf::{scconf_block, scconf_find_blocks, scconf_get_str};
+use opensc_sys::scconf::{scconf_block, scconf_find_blocks, scconf_get_str, scconf_get_bool, scconf_get_int};
 
 use crate::constants_types::{ACOS5_OBJECT_REF_LOCAL, ACOS5_OBJECT_REF_MAX, CARD_DRV_SHORT_NAME, DataPrivate, build_apdu,
-                             /*p_void,*/ SC_CARD_TYPE_ACOS5_EVO_V4};
-use crate::crypto::{DES_KEY_SZ, DES_KEY_SZ_u8, des_ecb3_unpadded_8, des_ede3_cbc_pad_80_mac, des_ede3_cbc_pad_80,
-                    DES_set_odd_parity, DES_cblock, Encrypt, Decrypt};
-use crate::no_cdecl::{authenticate_external, authenticate_internal};
-use crate::wrappers::{wr_do_log, wr_do_log_rv, wr_do_log_sds, wr_do_log_t, wr_do_log_tttt, wr_do_log_tu, wr_do_log_tuv,
-                      wr_do_log_tuvw};
+                             /*p_void,*/ SC_CARD_TYPE_ACOS5_EVO_V4, SmPolicy,
+                             SM_POLICY_ALWAYS_NEW, SM_POLICY_REUSE, SM_POLICY_TIMED};
+use crate::crypto::{DES_KEY_SZ, des_ecb3_unpadded_8, des_ede3_cbc_pad_80_mac, des_ede3_cbc_pad_80,
+                    DES_set_odd_parity, DES_cblock, Encrypt, Decrypt,
+                    AES_BLOCK_SIZE, aes_cbc_pad_80, pbkdf2_hmac_sha256,
+                    Zeroizing, cleanse};
+use crate::no_cdecl::{authenticate_external, authenticate_internal, pin_set_policy};
+use crate::wrappers::{wr_do_log, wr_do_log_rv, wr_do_log_sds, wr_do_log_t, wr_do_log_tu};
 
 
 #[allow(clippy::cast_possible_truncation)]
@@ -93,13 +97,509 @@ fn get_cs_mac(card: &sc_card/*cwa: &sm_cwa_session*/) -> [u8; 3*DES_KEY_SZ] { //
     result
 }
 
-fn sm_incr_ssc(card: &mut sc_card) {
-    let ssc = unsafe { card.sm_ctx.info.session.cwa.ssc };
-    let mut x = u16::from_be_bytes([ssc[6], ssc[7]]);
-    x = x.wrapping_add(1);
-    unsafe { card.sm_ctx.info.session.cwa.ssc[6..8].copy_from_slice(&x.to_be_bytes()) };
+/*
+The keys and the SSC of an established SM session, detached from sc_card, such that the SM framing can be done (and
+tested) without a card: TDES with 24 byte keys and an 8 byte SSC
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmSession {
+    pub enc : Vec<u8>,
+    pub mac : Vec<u8>,
+    pub ssc : Vec<u8>,
+}
+
+/* decoded SM response: the status word from tag 0x99, the plain response data and the result of MAC verification */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmResponse {
+    pub sw1    : u8,
+    pub sw2    : u8,
+    pub data   : Vec<u8>,
+    pub mac_ok : bool,
+}
+
+/* simple-TLV: length 0..=254 in 1 byte, else 0xFF followed by 2 bytes */
+fn sm_tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(value.len()+4);
+    result.push(tag);
+    if value.len() < 0xFF { result.push(u8::try_from(value.len()).unwrap()); }
+    else {
+        result.push(0xFF);
+        result.extend_from_slice(&u16::try_from(value.len()).unwrap().to_be_bytes());
+    }
+    result.extend_from_slice(value);
+    result
+}
+
+/* the card always pads encrypted response data (0x80 0x00...), even if it's length is a multiple of the block size */
+#[must_use]
+pub fn sm_resp_len(le: usize, has_ct: bool, block_size: usize) -> usize {
+    let len_tlv = |len: usize| if len < 0xFF {2+len} else {4+len};
+    10 /* 99 02 SW1 SW2 8E 04 MAC */ + if le == 0 {0} else if has_ct {len_tlv(1+ (le/block_size +1)*block_size)}
+                                                       else {len_tlv(le)}
+}
+
+impl SmSession {
+    #[must_use]
+    pub fn block_size(&self) -> usize { self.ssc.len() }
+
+    /* the SSC is incremented as a 2 byte big-endian counter in the last 2 bytes */
+    pub fn incr_ssc(&mut self) {
+        let len = self.ssc.len();
+        let x = u16::from_be_bytes([self.ssc[len-2], self.ssc[len-1]]).wrapping_add(1);
+        self.ssc[len-2..].copy_from_slice(&x.to_be_bytes());
+    }
+
+    /* The following 3 use the current SSC as IV; padding 0x80 0x00... gets applied only if required */
+    #[must_use]
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let mut ivec = [0_u8; DES_KEY_SZ];
+        ivec.copy_from_slice(&self.ssc);
+        des_ede3_cbc_pad_80(data, &self.enc, &mut ivec, Encrypt, 0)
+    }
+
+    #[must_use]
+    pub fn decrypt(&self, data: &[u8], pi: u8) -> Vec<u8> {
+        let mut ivec = [0_u8; DES_KEY_SZ];
+        ivec.copy_from_slice(&self.ssc);
+        des_ede3_cbc_pad_80(data, &self.enc, &mut ivec, Decrypt, pi)
+    }
+
+    /* CBC-MAC; the first 4 bytes of the result get transmitted as tag 0x8E */
+    #[must_use]
+    pub fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let mut ivec = [0_u8; DES_KEY_SZ];
+        ivec.copy_from_slice(&self.ssc);
+        des_ede3_cbc_pad_80_mac(data, &self.mac, &mut ivec)
+    }
+
+    /*
+    Returns the SM protected command APDU bytes (always case 4), it's case (short or extended) and the expected
+    length of response data, for the plain command with header hdr (CLA INS P1 P2), command data `data` (empty for
+    case 1 and 2) and `le` (None for case 1 and 3; Some(0) means 256 or 65536 if `extended`).
+    Command data objects: 0x87 (padding indicator and encrypted data, if has_ct) or 0x81 (plain data), 0x97 (le),
+    0x8E (MAC over 0x89 04 CLA' INS P1 P2 and the former data objects). The SSC gets incremented first.
+    */
+    pub fn wrap(&mut self, hdr: [u8; 4], data: &[u8], le: Option<usize>, has_ct: bool, extended: bool)
+        -> (Vec<u8>, i32, usize)
+    {
+        self.incr_ssc();
+        let mac_hdr = [0x89_u8, 4, hdr[0] | 0x0C, hdr[1], hdr[2], hdr[3]];
+        let mut dos : Vec<u8> = Vec::with_capacity(data.len() + 2*self.block_size() + 16);
+        if !data.is_empty() {
+            if has_ct {
+                let mut value = vec![u8::from(!data.len().is_multiple_of(&self.block_size()))];
+                value.extend_from_slice(&self.encrypt(data));
+                dos.extend_from_slice(&sm_tlv(0x87, &value));
+            }
+            else {
+                dos.extend_from_slice(&sm_tlv(0x81, data));
+            }
+        }
+        let le = le.map(|le| if le > 0 {le} else if extended {0x1_0000} else {0x100});
+        if let Some(le) = le {
+            if le > 0x100 || extended { dos.extend_from_slice(&sm_tlv(0x97, &u16::try_from(le & 0xFFFF).unwrap().to_be_bytes())); }
+            else                      { dos.extend_from_slice(&sm_tlv(0x97, &[u8::try_from(le & 0xFF).unwrap()])); }
+        }
+        let mut mac_in = mac_hdr.to_vec();
+        mac_in.extend_from_slice(&dos);
+        let mac = self.mac(&mac_in);
+        dos.extend_from_slice(&[0x8E, 4]);
+        dos.extend_from_slice(&mac[..4]);
+
+        let resp_len = sm_resp_len(le.unwrap_or(0), has_ct, self.block_size());
+        let is_ext = extended || dos.len() > 0xFF || resp_len > 0x100;
+        let mut cmd = Vec::with_capacity(dos.len() + 9);
+        cmd.extend_from_slice(&mac_hdr[2..]);
+        if is_ext {
+            cmd.push(0);
+            cmd.extend_from_slice(&u16::try_from(dos.len()).unwrap().to_be_bytes());
+            cmd.extend_from_slice(&dos);
+            cmd.extend_from_slice(&u16::try_from(resp_len & 0xFFFF).unwrap().to_be_bytes());
+        }
+        else {
+            cmd.push(u8::try_from(dos.len()).unwrap());
+            cmd.extend_from_slice(&dos);
+            cmd.push(u8::try_from(resp_len & 0xFF).unwrap());
+        }
+        (cmd, if is_ext {SC_APDU_CASE_4_EXT} else {SC_APDU_CASE_4_SHORT}, resp_len)
+    }
+
+    /*
+    Verifies and decodes the response data `resp` (without the trailing status word of the transmission) of the SM
+    protected command for the plain command header hdr: Data objects 0x99 (status word), 0x8E (MAC over 0x89 04 CLA' INS
+    P1 P2 and all data objects except 0x8E), optionally 0x81 (plain data) or 0x87 (padding indicator and encrypted data).
+    The SSC gets incremented first. Response data get decrypted only if the MAC verified.
+    */
+    #[allow(clippy::missing_errors_doc)]
+    pub fn unwrap(&mut self, hdr: [u8; 4], resp: &[u8]) -> Result<SmResponse, i32> {
+        self.incr_ssc();
+        let mut mac_in = vec![0x89_u8, 4, hdr[0] | 0x0C, hdr[1], hdr[2], hdr[3]];
+        let mut sw : Option<[u8; 2]> = None;
+        let mut mac_card : Option<&[u8]> = None;
+        let mut data_plain : Option<&[u8]> = None;
+        let mut data_enc : Option<&[u8]> = None;
+        let mut pos = 0;
+        while pos < resp.len() {
+            if pos+2 > resp.len() { return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED); }
+            let (len, len_len) = if resp[pos+1] < 0xFF { (usize::from(resp[pos+1]), 1) }
+                else if pos+4 <= resp.len() { (usize::from(u16::from_be_bytes([resp[pos+2], resp[pos+3]])), 3) }
+                else { return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED); };
+            let pos_value = pos+1+len_len;
+            if pos_value+len > resp.len() { return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED); }
+            let value = &resp[pos_value..pos_value+len];
+            match resp[pos] {
+                0x99 if len == 2 => sw = Some([value[0], value[1]]),
+                0x8E if len == 4 => mac_card = Some(value),
+                0x81             => data_plain = Some(value),
+                0x87 if len > 1 && (len-1).is_multiple_of(&self.block_size()) => data_enc = Some(value),
+                _                => return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED),
+            }
+            if resp[pos] != 0x8E { mac_in.extend_from_slice(&resp[pos..pos_value+len]); }
+            pos = pos_value+len;
+        }
+        let (sw, mac_card) = match (sw, mac_card) {
+            (Some(sw), Some(mac_card)) => (sw, mac_card),
+            _ => return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED),
+        };
+        let mac_ok = self.mac(&mac_in)[..4] == *mac_card;
+        let data = match (data_plain, data_enc) {
+            (Some(_), Some(_)) => return Err(SC_ERROR_UNKNOWN_DATA_RECEIVED),
+            (Some(plain), None) => plain.to_vec(),
+            (None, Some(enc)) if mac_ok => self.decrypt(&enc[1..], enc[0]),
+            _ => Vec::new(),
+        };
+        Ok(SmResponse { sw1: sw[0], sw2: sw[1], data, mac_ok })
+    }
+}
+
+/* the current SM session of card; keys as established by sm_cwa_initialize */
+fn get_sm_session(card: &mut sc_card) -> SmSession {
+    SmSession {
+        enc: get_cs_enc(card).to_vec(),
+        mac: get_cs_mac(card).to_vec(),
+        ssc: unsafe { card.sm_ctx.info.session.cwa.ssc }.to_vec(),
+    }
+}
+
+fn set_sm_session_ssc(card: &mut sc_card, sess: &SmSession) {
+    unsafe { card.sm_ctx.info.session.cwa.ssc.copy_from_slice(&sess.ssc) };
+}
+
+fn sm_count_command(card: &mut sc_card) {
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let counters = &mut dp.sm_counters;
+    counters.commands = counters.commands.wrapping_add(1);
+    counters.commands_curr_session += 1;
+    counters.commands_max_session = std::cmp::max(counters.commands_max_session, counters.commands_curr_session);
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+}
+
+/* the next SM command will open a new session (sm_manage_initialize) */
+fn sm_end_session(card: &mut sc_card) {
+    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    dp.sm_cmd = 0;
+    card.drv_data = Box::into_raw(dp).cast::<c_void>();
+}
+
+/*
+SM protects the plain apdu (any case 1-4, short or extended), transmits it, verifies and decodes the response:
+On return apdu.sw1/sw2 hold the status word from the SM response and apdu.resp/resplen the plain response data.
+Returns SC_ERROR_SM, if the MAC of the response didn't verify (apdu.sw1/sw2 are set nevertheless).
+The SM session must be established already (see sm_manage_keyset, sm_manage_initialize)
+*/
+pub fn sm_transmit(card: &mut sc_card, apdu: &mut sc_apdu, has_ct: bool) -> i32
+{
+    assert!(!card.ctx.is_null());
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"sm_transmit\0");
+    log3if!(ctx,f,line!(), cstru!(b"called for INS: %02X\0"), apdu.ins);
+
+    let cse = apdu.cse & SC_APDU_SHORT_MASK;
+    let data = if (cse == SC_APDU_CASE_3_SHORT || cse == SC_APDU_CASE_4_SHORT) && !apdu.data.is_null() && apdu.datalen>0
+        { unsafe { from_raw_parts(apdu.data, apdu.datalen) } } else { &[] };
+    let le = if cse == SC_APDU_CASE_2_SHORT || cse == SC_APDU_CASE_4_SHORT {Some(apdu.le)} else {None};
+    let hdr = [apdu.cla, apdu.ins, apdu.p1, apdu.p2];
+
+    let mut sess = get_sm_session(card);
+    let (cmd, cse_sm, resp_len) = sess.wrap(hdr, data, le, has_ct, (apdu.cse & SC_APDU_EXT) != 0);
+    set_sm_session_ssc(card, &sess);
+    sm_count_command(card);
+    let mut rbuf = vec![0_u8; resp_len];
+    let mut apdu_sm = build_apdu(ctx, &cmd, cse_sm, &mut rbuf);
+    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu_sm) };
+    if rv == SC_SUCCESS {
+        rv = unsafe { sc_check_sw(card, apdu_sm.sw1, apdu_sm.sw2) };
+    }
+    if rv != SC_SUCCESS {
+        sm_end_session(card);
+        log3ifr!(ctx,f,line!(), rv);
+        return rv;
+    }
+
+    let resp = sess.unwrap(hdr, &rbuf[..apdu_sm.resplen]);
+    set_sm_session_ssc(card, &sess);
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(e) => {
+            sm_end_session(card);
+            log3ifr!(ctx,f,line!(), e);
+            return e;
+        },
+    };
+    apdu.sw1 = u32::from(resp.sw1);
+    apdu.sw2 = u32::from(resp.sw2);
+    log3if!(ctx,f,line!(), cstru!(b"mac_resp verification: %s\0"),
+        if resp.mac_ok {cstru!(b"OK\0")} else {cstru!(b"FAILED\0")}.as_ptr());
+    if !resp.mac_ok {
+        sm_end_session(card);
+        return SC_ERROR_SM;
+    }
+    /* with pi==0, the padding block appended by the card (see sm_resp_len) didn't get removed by decrypt */
+    let len = if le.is_some() && apdu.le > 0 {std::cmp::min(resp.data.len(), apdu.le)} else {resp.data.len()};
+    if len > apdu.resplen {
+        rv = SC_ERROR_BUFFER_TOO_SMALL;
+        log3ifr!(ctx,f,line!(), rv);
+        return rv;
+    }
+    if len > 0 {
+        unsafe { from_raw_parts_mut(apdu.resp, len) }.copy_from_slice(&resp.data[..len]);
+    }
+    apdu.resplen = len;
+    SC_SUCCESS
+}
+
+/* the card_driver acos5_external block in opensc.conf, or null */
+pub fn sm_conf_block(ctx: &mut sc_context) -> *mut scconf_block
+{
+    let mut sm_conf_block = null_mut::<scconf_block>();
+    /* look for sc block in opensc.conf */
+    for elem in &ctx.conf_blocks {
+        if (*elem).is_null() { break; }
+        let blocks_ptr = unsafe { scconf_find_blocks(ctx.conf, *elem,
+            cstru!(/*b"secure_messaging\0"*/ b"card_driver\0").as_ptr(),
+        /*sm_info.config_section.as_ptr()*/ cstru!(CARD_DRV_SHORT_NAME).as_ptr()) };
+        if blocks_ptr.is_null() { continue; }
+        sm_conf_block = unsafe { *blocks_ptr }; // blocks[0];
+
+        unsafe { free(blocks_ptr.cast::<c_void>()) };
+        if !sm_conf_block.is_null() { break; }
+    }
+    sm_conf_block
 }
 
+/* SM session policy from opensc.conf, card_driver acos5_external block; see sm_manage_initialize
+    sm_session_policy = timed;  # or always_new, or reuse
+    sm_timeout_read_update = 300; sm_timeout_pin = 200; sm_timeout_other = 150; # ms, for timed
+    sm_session_max_commands = 1000; # for reuse
+*/
+#[must_use]
+pub fn sm_policy_from_conf(ctx: &mut sc_context) -> SmPolicy
+{
+    let f = cstru!(b"sm_policy_from_conf\0");
+    let mut policy = SmPolicy::default();
+    let blk = sm_conf_block(ctx);
+    if blk.is_null() {
+        return policy;
+    }
+    let mode = unsafe { scconf_get_str(blk, cstru!(b"sm_session_policy\0").as_ptr(), null::<c_char>()) };
+    if !mode.is_null() {
+        policy.mode = match unsafe { CStr::from_ptr(mode) }.to_bytes() {
+            b"always_new" => SM_POLICY_ALWAYS_NEW,
+            b"reuse"      => SM_POLICY_REUSE,
+            b"timed"      => SM_POLICY_TIMED,
+            _ => {
+                log3if!(ctx,f,line!(), cstru!(b"Unknown sm_session_policy %s; using timed\0"), mode);
+                SM_POLICY_TIMED
+            },
+        };
+    }
+    let get_u32 = |name: &CStr, def: u32| u32::try_from(unsafe { scconf_get_int(blk, name.as_ptr(),
+        i32::try_from(def).unwrap()) }).unwrap_or(def);
+    policy.timeout_read_update = get_u32(cstru!(b"sm_timeout_read_update\0"), policy.timeout_read_update);
+    policy.timeout_pin         = get_u32(cstru!(b"sm_timeout_pin\0"),         policy.timeout_pin);
+    policy.timeout_other       = get_u32(cstru!(b"sm_timeout_other\0"),       policy.timeout_other);
+    policy.max_commands        = get_u32(cstru!(b"sm_session_max_commands\0"), policy.max_commands);
+    log3if!(ctx,f,line!(), cstru!(b"sm_session_policy: %u\0"), u32::from(policy.mode));
+    policy
+}
+
+/*
+The keyset file (opensc.conf: card_driver acos5_external { keyset_file = /path/to/file; }) is an alternative to
+specifying SM keys in opensc.conf (which usually is world-readable): Text lines (# starts a comment)
+    <card serial number hex or *>  <keyset name as in opensc.conf, e.g. keyset_02_enc>  <key hex>
+A line with the card's serial number takes precedence over a line with *.
+The file must not be accessible by group/others (unix) and may be encrypted by
+    openssl enc -aes-256-cbc -pbkdf2 -iter <keyset_file_pbkdf2_iter, default 10000> -in keyset.txt -out keyset.enc
+with the passphrase taken from the environment variable named by keyset_file_passphrase_env
+(default ACOS5_KEYSET_PASSPHRASE), or, if not set and keyset_file_prompt = yes;, asked for on the terminal.
+*/
+fn sm_keyset_file_read(ctx: &mut sc_context, sm_conf_block: *mut scconf_block, path: &CStr) -> Result<Zeroizing, i32>
+{
+    let f = cstru!(b"sm_keyset_file_read\0");
+    let path_str = match path.to_str() {
+        Ok(path_str) => path_str,
+        Err(_e) => return Err(SC_ERROR_INVALID_ARGUMENTS),
+    };
+    let metadata = match std::fs::metadata(path_str) {
+        Ok(metadata) => metadata,
+        Err(_e) => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: keyset_file %s not accessible\0"), path.as_ptr());
+            return Err(SC_ERROR_SM_KEYSET_NOT_FOUND);
+        },
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let euid = unsafe { libc::geteuid() };
+        if (metadata.mode() & 0o077) != 0 || (metadata.uid() != euid && metadata.uid() != 0) {
+            log3if!(ctx,f,line!(),
+                cstru!(b"Error: keyset_file %s must be owned by the user (or root) and not be accessible by group/others\0"),
+                path.as_ptr());
+            return Err(SC_ERROR_NOT_ALLOWED);
+        }
+    }
+    #[cfg(not(unix))]
+    let _unused = metadata;
+
+    let content = match std::fs::read(path_str) {
+        Ok(content) => Zeroizing(content),
+        Err(_e) => return Err(SC_ERROR_SM_KEYSET_NOT_FOUND),
+    };
+    if !content.starts_with(b"Salted__") {
+        return Ok(content);
+    }
+    let iter = unsafe { scconf_get_int(sm_conf_block, cstru!(b"keyset_file_pbkdf2_iter\0").as_ptr(), 10000) };
+    if iter < 1 {
+        log3if!(ctx,f,line!(), cstru!(b"Error: keyset_file_pbkdf2_iter must be at least 1, but is %d\0"), iter);
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+
+    let env_name = unsafe { scconf_get_str(sm_conf_block, cstru!(b"keyset_file_passphrase_env\0").as_ptr(),
+                                           cstru!(b"ACOS5_KEYSET_PASSPHRASE\0").as_ptr()) };
+    let env_name = unsafe { CStr::from_ptr(env_name) }.to_str().unwrap_or("ACOS5_KEYSET_PASSPHRASE");
+    let passphrase = match std::env::var_os(env_name) {
+        Some(passphrase) => Zeroizing(passphrase.to_string_lossy().as_bytes().to_vec()),
+        None if unsafe { scconf_get_bool(sm_conf_block, cstru!(b"keyset_file_prompt\0").as_ptr(), 0) } != 0 => {
+            match sm_keyset_file_prompt() {
+                Some(passphrase) => passphrase,
+                None => return Err(SC_ERROR_SM_KEYSET_NOT_FOUND),
+            }
+        },
+        None => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: keyset_file is encrypted, but there is no passphrase\0"));
+            return Err(SC_ERROR_SM_KEYSET_NOT_FOUND);
+        },
+    };
+    match sm_keyset_file_decrypt(&content, &passphrase, iter) {
+        Some(content) => Ok(content),
+        None => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: keyset_file decryption failed (wrong passphrase ?)\0"));
+            Err(SC_ERROR_SM_KEYSET_NOT_FOUND)
+        },
+    }
+}
+
+#[cfg(unix)]
+fn sm_keyset_file_prompt() -> Option<Zeroizing>
+{
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::io::AsRawFd;
+    let mut tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    tty.write_all(b"Passphrase for the acos5 keyset_file: ").ok()?;
+    let fd = tty.as_raw_fd();
+    let mut termios_old = unsafe { std::mem::zeroed::<libc::termios>() };
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut termios_old) } == 0;
+    if is_terminal {
+        let mut termios_new = termios_old;
+        termios_new.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios_new) };
+    }
+    let mut line = Zeroizing(Vec::with_capacity(256));
+    let res = BufReader::new(&tty).read_until(b'\n', &mut line);
+    if is_terminal {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios_old) };
+    }
+    tty.write_all(b"\n").ok()?;
+    res.ok()?;
+    while matches!(line.last(), Some(b'\r') | Some(b'\n')) {
+        line.pop();
+    }
+    Some(line)
+}
+
+#[cfg(not(unix))]
+fn sm_keyset_file_prompt() -> Option<Zeroizing> { None }
+
+/* decrypts content in the format of `openssl enc -aes-256-cbc -pbkdf2` (PKCS#7 padding); None if that fails.
+   The derived key and iv and the decrypted content get cleansed */
+fn sm_keyset_file_decrypt(content: &[u8], passphrase: &[u8], iter: i32) -> Option<Zeroizing>
+{
+    if content.len() < 32 || !content.starts_with(b"Salted__") || !(content.len()-16).is_multiple_of(&AES_BLOCK_SIZE) {
+        return None;
+    }
+    let key_iv = pbkdf2_hmac_sha256(passphrase, &content[8..16], iter, 32+AES_BLOCK_SIZE).ok()?;
+    let mut ivec = [0_u8; AES_BLOCK_SIZE];
+    ivec.copy_from_slice(&key_iv[32..]);
+    let mut result = Zeroizing(aes_cbc_pad_80(&content[16..], &key_iv[..32], &mut ivec, Decrypt, 0));
+    cleanse(&mut ivec);
+    let pad = usize::from(*result.last()?);
+    if pad == 0 || pad > AES_BLOCK_SIZE || result[result.len()-pad..].iter().any(|&b| usize::from(b) != pad) {
+        return None;
+    }
+    let len = result.len()-pad;
+    result.truncate(len);
+    Some(result)
+}
+
+/* the key for keyset name (e.g. b"keyset_02_enc") and the card's serial number from the keyset file content, together
+   with the optional 4. field: the key type byte as stored in the card's symmetric key file record (e.g. 14 or 24) */
+/* lines, that aren't UTF-8, get skipped */
+fn sm_keyset_file_lookup(content: &[u8], serial: &[u8], name: &[u8]) -> Option<(Zeroizing, Option<u8>)>
+{
+    fn from_hex(hex: &str) -> Option<Zeroizing> {
+        let hex = Zeroizing(hex.bytes().filter(|&b| b != b':').collect());
+        if hex.is_empty() || !hex.len().is_multiple_of(&2) { return None; }
+        hex.chunks_exact(2).map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok()).collect::<Option<Vec<u8>>>()
+            .map(Zeroizing)
+    }
+
+    let mut result = None;
+    for line in content.split(|&b| b == b'\n') {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line,
+            Err(_e) => continue,
+        };
+        let line = line.split('#').next().unwrap_or("");
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        if !(3..=4).contains(&fields.len()) || fields[1].as_bytes() != name { continue; }
+        let key_type = match fields.get(3) {
+            None => None,
+            Some(field) => match u8::from_str_radix(field, 16) {
+                Ok(key_type) => Some(key_type),
+                Err(_e) => continue,
+            },
+        };
+        if fields[0] == "*" {
+            if result.is_none() { result = from_hex(fields[2]).map(|key| (key, key_type)); }
+        }
+        else if from_hex(fields[0]).as_deref().map(|hex| &hex[..]) == Some(serial) {
+            return from_hex(fields[2]).map(|key| (key, key_type));
+        }
+    }
+    result
+}
+
+/* Checks a SM key against the key type byte of the symmetric key file record (see algo_ref_sym_store), if given:
+   SM is implemented for TDES keys (24 bytes) only. AES keys (EVO) get refused: AES based SM isn't implemented, as long
+   as it's not verified against the reference manual or a real card */
+fn sm_keyset_check(key_type: Option<u8>, key_len: usize) -> Result<(), i32>
+{
+    match (key_type, key_len) {
+        (None, 24) | (Some(0x14), 24) => Ok(()),
+        (None, 16) | (None, 32) | (Some(0x22), 16) | (Some(0x24), 24) | (Some(0x28), 32) => Err(SC_ERROR_NOT_SUPPORTED),
+        _ => Err(SC_ERROR_INVALID_DATA),
+    }
+}
 
 fn sm_cwa_config_get_keyset(ctx: &mut sc_context, sm_info: &mut sm_info) -> i32
 {
@@ -127,7 +627,6 @@ fn sm_cwa_config_get_keyset(ctx: &mut sc_context, sm_info: &mut sm_info) -> i32
 
     let cwa_session = unsafe { &mut sm_info.session.cwa };
     let cwa_keyset = &mut cwa_session.cwa_keyset;
-    let mut sm_conf_block = null_mut::<scconf_block>();
     let crt_at = &cwa_session.params.crt_at;
 
     let mut name : CString; // [c_char; 128] = [0; 128];
@@ -140,17 +639,7 @@ fn sm_cwa_config_get_keyset(ctx: &mut sc_context, sm_info: &mut sm_info) -> i32
     let f  = cstru!(b"sm_cwa_config_get_keyset\0");
 
     /* look for sc block in opensc.conf */
-    for elem in &ctx.conf_blocks {
-        if (*elem).is_null() { break; }
-        let blocks_ptr = unsafe { scconf_find_blocks(ctx.conf, *elem,
-            cstru!(/*b"secure_messaging\0"*/ b"card_driver\0").as_ptr(),
-        /*sm_info.config_section.as_ptr()*/ cstru!(CARD_DRV_SHORT_NAME).as_ptr()) };
-        if blocks_ptr.is_null() { continue; }
-        sm_conf_block = unsafe { *blocks_ptr }; // blocks[0];
-
-        unsafe { free(blocks_ptr.cast::<c_void>()) };
-        if !sm_conf_block.is_null() { break; }
-    }
+    let sm_conf_block = sm_conf_block(ctx);
 
     /*
         for (ii = 0; ctx->conf_blocks[ii]; ii++) {
@@ -170,97 +659,73 @@ fn sm_cwa_config_get_keyset(ctx: &mut sc_context, sm_info: &mut sm_info) -> i32
     P:4268; T:4384 2020-11-07 03:36:37.559 [opensc-notify] reader-pcsc.c:1707:pcsc_wait_for_event: returning with: -1112 (Timeout while waiting for event from card reader)
     */
 
-    /* Keyset ENC */
-    if sm_info.current_aid.len>0 && (u8::try_from(crt_at.refs[0]).unwrap() & ACOS5_OBJECT_REF_LOCAL) >0 {
-        // unsafe { snprintf(name.as_mut_ptr(), name.len(), cstru!(b"keyset_%s_%02i_enc\0").as_ptr(),
-        //                   sc_dump_hex(sm_info.current_aid.value.as_ptr(), sm_info.current_aid.len), ref_) };
-        name = sprintf_aid_ref(&sm_info.current_aid, ref_, "enc");
-    }
-    else {
-        // unsafe { snprintf(name.as_mut_ptr(), name.len(), cstru!(b"keyset_%02i_enc\0").as_ptr(), ref_) };
-        name = sprintf_ref(ref_, "enc");
-    }
-    let mut value = unsafe { scconf_get_str(sm_conf_block, name.as_ptr(), null::<c_char>()) };
-
-    if value.is_null() {
-////                sc_debug(ctx, SC_LOG_DEBUG_VERBOSE, "No %s value in OpenSC config", name);
-        log3if!(ctx,f,line!(), cstru!(b"No %s value in OpenSC config\0"), name.as_ptr());
-        return SC_ERROR_SM_KEYSET_NOT_FOUND;
-    }
+    /* keyset_file: if set, the keys are taken from that file instead of from opensc.conf */
+    let keyset_file_ptr = unsafe { scconf_get_str(sm_conf_block, cstru!(b"keyset_file\0").as_ptr(), null::<c_char>()) };
+    let keyset_file = if keyset_file_ptr.is_null() { None }
+        else {
+            match sm_keyset_file_read(ctx, sm_conf_block, unsafe { CStr::from_ptr(keyset_file_ptr) }) {
+                Ok(content) => Some(content),
+                Err(e) => return e,
+            }
+        };
 
-////            sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "keyset::enc(%"SC_FORMAT_LEN_SIZE_T"u) %s", strlen(value), value);
-    log3if!(ctx,f,line!(), cstru!(b"keyset::enc(%zu) %s\0"), unsafe { strlen(value) }, value);
-    if unsafe { strlen(value) } == 3*DES_KEY_SZ {
-        cwa_keyset.enc.copy_from_slice(unsafe { from_raw_parts(value.cast::<u8>(), 2*DES_KEY_SZ) });
-        cwa_session.icc.k[..DES_KEY_SZ].copy_from_slice(unsafe { from_raw_parts(
-            value.add(2*DES_KEY_SZ).cast::<u8>(), DES_KEY_SZ) });
-    }
-    else {
-        hex_len = hex.len();
-        rv = unsafe { sc_hex_to_bin(value, hex.as_mut_ptr(), &mut hex_len) };
-        if rv != SC_SUCCESS {
-////sc_debug(ctx, SC_LOG_DEBUG_VERBOSE, "SM get %s: hex to bin failed for '%s'; error %i", name, value, rv);
-            log3if!(ctx,f,line!(), cstru!(b"SM get %s: hextobin failed for '%s'; error %i\0"), name.as_ptr(),value,rv);
-            return SC_ERROR_UNKNOWN_DATA_RECEIVED;
+    /* Keyset ENC and MAC; key material must never be logged */
+    let mut keys : Vec<Zeroizing> = Vec::with_capacity(2);
+    for qualifier in &["enc", "mac"] {
+        if sm_info.current_aid.len>0 && (u8::try_from(crt_at.refs[0]).unwrap() & ACOS5_OBJECT_REF_LOCAL) >0 {
+            name = sprintf_aid_ref(&sm_info.current_aid, ref_, qualifier);
         }
-
-////sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "ENC(%"SC_FORMAT_LEN_SIZE_T"u) %s", hex_len, sc_dump_hex(hex, hex_len));
-        log3if!(ctx,f,line!(), cstru!(b"ENC(%zu) %s\0"), hex_len, unsafe {sc_dump_hex(hex.as_ptr(), hex_len)});
-        if hex_len != 3*DES_KEY_SZ {
-            return SC_ERROR_INVALID_DATA;
+        else {
+            name = sprintf_ref(ref_, qualifier);
         }
-        cwa_keyset.enc.copy_from_slice(&hex[..2*DES_KEY_SZ]);
-        cwa_session.icc.k[..DES_KEY_SZ].copy_from_slice(&hex[2*DES_KEY_SZ..3*DES_KEY_SZ]);
-    }
-////            sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "%s %s", name, sc_dump_hex(cwa_keyset->enc, 2*DES_KEY_SZ));
-    log3if!(ctx,f,line!(), cstru!(b"%s %s\0"),name.as_ptr(),unsafe{sc_dump_hex(cwa_keyset.enc.as_ptr(), 2*DES_KEY_SZ)});
-
-    /* Keyset MAC */
-    if sm_info.current_aid.len>0 && (u8::try_from(crt_at.refs[0]).unwrap() & ACOS5_OBJECT_REF_LOCAL) >0 {
-        // unsafe { snprintf(name.as_mut_ptr(), name.len(), cstru!(b"keyset_%s_%02i_mac\0").as_ptr(),
-        //                   sc_dump_hex(sm_info.current_aid.value.as_ptr(), sm_info.current_aid.len), ref_) };
-        name = sprintf_aid_ref(&sm_info.current_aid, ref_, "mac");
-    }
-    else {
-//                snprintf(name, sizeof(name), "keyset_%02i_mac", ref);
-//         unsafe { snprintf(name.as_mut_ptr(), name.len(), cstru!(b"keyset_%02i_mac\0").as_ptr(), ref_) };
-        name = sprintf_ref(ref_, "mac");
-    }
-    value = unsafe { scconf_get_str(sm_conf_block, name.as_ptr(), null::<c_char>()) };
-
-    if value.is_null() {
-////                sc_debug(ctx, SC_LOG_DEBUG_VERBOSE, "No %s value in OpenSC config", name);
-        log3if!(ctx,f,line!(), cstru!(b"No %s value in OpenSC config\0"), name.as_ptr());
-        return SC_ERROR_SM_KEYSET_NOT_FOUND;
-    }
-
-////sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "keyset::mac(%"SC_FORMAT_LEN_SIZE_T"u) %s", strlen(value), value);
-    log3if!(ctx,f,line!(), cstru!(b"keyset::mac(%zu) %s\0"), unsafe { strlen(value) }, value);
-    if unsafe { strlen(value) } == 3*DES_KEY_SZ {
-        cwa_keyset.mac.copy_from_slice(unsafe { from_raw_parts(value.cast::<u8>(), 2*DES_KEY_SZ) });
-        cwa_session.ifd.k[..DES_KEY_SZ].copy_from_slice(unsafe {
-            from_raw_parts(value.add(2*DES_KEY_SZ).cast::<u8>(), DES_KEY_SZ) });
-    }
-    else   {
-        hex_len = hex.len();
-        rv = unsafe { sc_hex_to_bin(value, hex.as_mut_ptr(), &mut hex_len) };
-        if rv != SC_SUCCESS {
-////sc_debug(ctx, SC_LOG_DEBUG_VERBOSE, "SM get '%s': hex to bin failed for '%s'; error %i", name, value, rv);
-            log3if!(ctx,f,line!(), cstru!(b"SM get %s: hextobin failed for '%s'; error %i\0"),name.as_ptr(), value, rv);
-            return SC_ERROR_UNKNOWN_DATA_RECEIVED;
+        let (key, key_type) = if let Some(content) = &keyset_file {
+            match sm_keyset_file_lookup(content, &sm_info.serialnr.value[..sm_info.serialnr.len], name.as_bytes()) {
+                Some(key_and_type) => key_and_type,
+                None => {
+                    log3if!(ctx,f,line!(), cstru!(b"No %s value in keyset_file for this card\0"), name.as_ptr());
+                    return SC_ERROR_SM_KEYSET_NOT_FOUND;
+                },
+            }
         }
-
-////sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "MAC(%"SC_FORMAT_LEN_SIZE_T"u) %s", hex_len, sc_dump_hex(hex, hex_len));
-        log3if!(ctx,f,line!(), cstru!(b"MAC(%zu) %s\0"), hex_len, unsafe {sc_dump_hex(hex.as_ptr(), hex_len)});
-        if hex_len != 3*DES_KEY_SZ {
-            return SC_ERROR_INVALID_DATA;
+        else {
+            let value = unsafe { scconf_get_str(sm_conf_block, name.as_ptr(), null::<c_char>()) };
+            if value.is_null() {
+                log3if!(ctx,f,line!(), cstru!(b"No %s value in OpenSC config\0"), name.as_ptr());
+                return SC_ERROR_SM_KEYSET_NOT_FOUND;
+            }
+            if unsafe { strlen(value) } == 3*DES_KEY_SZ {
+                (Zeroizing(unsafe { from_raw_parts(value.cast::<u8>(), 3*DES_KEY_SZ) }.to_vec()), None)
+            }
+            else {
+                hex_len = hex.len();
+                rv = unsafe { sc_hex_to_bin(value, hex.as_mut_ptr(), &mut hex_len) };
+                if rv != SC_SUCCESS {
+                    cleanse(&mut hex);
+                    log3if!(ctx,f,line!(), cstru!(b"SM get %s: hextobin failed; error %i\0"), name.as_ptr(), rv);
+                    return SC_ERROR_UNKNOWN_DATA_RECEIVED;
+                }
+                let key = Zeroizing(hex[..hex_len].to_vec());
+                cleanse(&mut hex);
+                (key, None)
+            }
+        };
+        log3if!(ctx,f,line!(), cstru!(b"keyset %s: length %zu\0"), name.as_ptr(), key.len());
+        if let Err(e) = sm_keyset_check(key_type, key.len()) {
+            if e == SC_ERROR_NOT_SUPPORTED {
+                log3if!(ctx,f,line!(), cstru!(b"Error: keyset %s: AES based SM isn't supported, TDES keys required\0"), name.as_ptr());
+            }
+            else {
+                log3if!(ctx,f,line!(), cstru!(b"Error: keyset %s: key type and length don't match\0"), name.as_ptr());
+            }
+            return e;
         }
-
-        cwa_keyset.mac.copy_from_slice(&hex[..2*DES_KEY_SZ]);
-        cwa_session.ifd.k[..DES_KEY_SZ].copy_from_slice(&hex[2*DES_KEY_SZ..3*DES_KEY_SZ]);
+        keys.push(key);
     }
-//sc_debug(ctx, SC_LOG_DEBUG_NORMAL, "%s %s", name, sc_dump_hex(cwa_keyset->mac, 2*DES_KEY_SZ));
-    log3if!(ctx,f,line!(), cstru!(b"%s %s\0"),name.as_ptr(),unsafe{sc_dump_hex(cwa_keyset.mac.as_ptr(), 2*DES_KEY_SZ)});
+    let (enc, mac) = (&keys[0], &keys[1]);
+    cwa_keyset.enc.copy_from_slice(&enc[..2*DES_KEY_SZ]);
+    cwa_session.icc.k[..DES_KEY_SZ].copy_from_slice(&enc[2*DES_KEY_SZ..3*DES_KEY_SZ]);
+    cwa_keyset.mac.copy_from_slice(&mac[..2*DES_KEY_SZ]);
+    cwa_session.ifd.k[..DES_KEY_SZ].copy_from_slice(&mac[2*DES_KEY_SZ..3*DES_KEY_SZ]);
 
     cwa_keyset.sdo_reference = crt_at.refs[0];
 
@@ -268,7 +733,7 @@ fn sm_cwa_config_get_keyset(ctx: &mut sc_context, sm_info: &mut sm_info) -> i32
     /* IFD parameters */
     //memset(cwa_session, 0, sizeof(struct sm_cwa_session));
 //            value = scconf_get_str(sm_conf_block, "ifd_serial", NULL);
-    value = unsafe { scconf_get_str(sm_conf_block, cstru!(b"ifd_serial\0").as_ptr(), null::<c_char>()) };
+    let value = unsafe { scconf_get_str(sm_conf_block, cstru!(b"ifd_serial\0").as_ptr(), null::<c_char>()) };
     if value.is_null() {
         return SC_ERROR_SM_IFD_DATA_MISSING;
     }
@@ -343,7 +808,7 @@ fn sm_cwa_initialize(card: &mut sc_card/*, sm_info: &mut sm_info, _rdata: &mut s
     }
     /* session key(s) generation. acos5 does it internally automatically and we must do the same here */
     /* EVO: allows TDES and AES; for TDES the ref. manual is inconsistent: 32 byte deriv data for a max 24 byte session key ? */
-    /* TODO : for EVO, switch to AES/256 */
+    /* TODO : for EVO, switch to AES/256, once that is verified against the ref. manual or a real card (sm_keyset_check) */
     let mut deriv_data = Vec::with_capacity(3 * DES_KEY_SZ);
     unsafe {
         deriv_data.extend_from_slice(&card.sm_ctx.info.session.cwa.card_challenge[4..8]);
@@ -384,13 +849,13 @@ fn sm_cwa_initialize(card: &mut sc_card/*, sm_info: &mut sm_info, _rdata: &mut s
     SC_SUCCESS
 }
 
-fn sm_dur(cmd: u32) -> u128
+fn sm_dur(policy: &SmPolicy, cmd: u32) -> u128
 {
-    match cmd {
-        SM_CMD_FILE_READ | SM_CMD_FILE_UPDATE => 300,
-        SM_CMD_PIN  => 200,
-        _           => 150,
-    }
+    u128::from(match cmd {
+        SM_CMD_FILE_READ | SM_CMD_FILE_UPDATE => policy.timeout_read_update,
+        SM_CMD_PIN  => policy.timeout_pin,
+        _           => policy.timeout_other,
+    })
 }
 
 fn sm_manage_keyset(card: &mut sc_card) -> i32
@@ -431,6 +896,7 @@ fn sm_manage_keyset(card: &mut sc_card) -> i32
 }
 
 /*
+SM_POLICY_TIMED (default):
 try to pass repetitive commands through the same SM session, but open a new session for new commands.
 Also, some commands like verify always get a new session
 repetitive commands: e.g. read_binary with large count will be chunk'ed to max. 240 bytes : same session
@@ -440,6 +906,10 @@ timeline:                   *x                *x                *y
          <- unknown cmd ? -> <- curr cmd: x -> <- curr cmd: x -> <- curr cmd: y -> <- curr cmd: x ->
                              <- last cmd: ? -> <- last cmd: x -> <- last cmd: x -> <- last cmd: y -> <- last cmd: x ->
                             =new_session      =dep on timing    =new_session      =new_session
+SM_POLICY_ALWAYS_NEW: each SM command opens a new session
+SM_POLICY_REUSE: any SM command reuses an existing session, unless it was used for max_commands already or the SSC
+would wrap; also select_file doesn't end the session then, unless the current DF changes (the card resets the security
+state of the DF left). A failing SM command ends the session (see sm_transmit)
 */
 fn sm_manage_initialize(card: &mut sc_card) -> i32
 {
@@ -453,22 +923,34 @@ fn sm_manage_initialize(card: &mut sc_card) -> i32
     dp.sm_cmd = card.sm_ctx.info.cmd;
     let last_time_stamp = dp.time_stamp;
     dp.time_stamp = std::time::Instant::now();
+    let policy = dp.sm_policy;
+    let commands_curr_session = dp.sm_counters.commands_curr_session;
     card.drv_data = Box::into_raw(dp).cast::<c_void>();
 
-//println!("elapsed ms: {}, last_sm_cmd: {:X}, this_sm_cmd: {:X}", last_time_stamp.elapsed().as_millis(), last_sm_cmd, card.sm_ctx.info.cmd);
-    if  last_sm_cmd==0 || last_sm_cmd!=card.sm_ctx.info.cmd ||
-        last_time_stamp.elapsed().as_millis() > sm_dur(last_sm_cmd) ||
-        ![SM_CMD_FILE_READ, SM_CMD_FILE_UPDATE].contains(&card.sm_ctx.info.cmd)
-    {
+    let new_session = last_sm_cmd==0 || match policy.mode {
+        SM_POLICY_ALWAYS_NEW => true,
+        SM_POLICY_REUSE => {
+            let ssc = get_sm_session(card).ssc;
+            commands_curr_session >= policy.max_commands ||
+                u16::from_be_bytes([ssc[ssc.len()-2], ssc[ssc.len()-1]]) >= 0xFFFE
+        },
+        _ => last_sm_cmd!=card.sm_ctx.info.cmd ||
+            last_time_stamp.elapsed().as_millis() > sm_dur(&policy, last_sm_cmd) ||
+            ![SM_CMD_FILE_READ, SM_CMD_FILE_UPDATE].contains(&card.sm_ctx.info.cmd),
+    };
+    if new_session {
         let rv = sm_cwa_initialize(card/*, sm_info, rdata*/);
+        let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
         if rv != SC_SUCCESS {
             log3if!(ctx,f,line!(),cstru!(b"Error: #################### SM initializing failed ####################\0"));
+            dp.sm_cmd = 0;
+        }
+        else {
+            dp.sm_counters.sessions_opened += 1;
+            dp.sm_counters.commands_curr_session = 0;
         }
-
-        let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
         dp.time_stamp = std::time::Instant::now();
-        card.drv_data = Box::into_raw(dp).cast::<c_void>()
-        ;
+        card.drv_data = Box::into_raw(dp).cast::<c_void>();
         rv
     }
     else {
@@ -476,26 +958,12 @@ fn sm_manage_initialize(card: &mut sc_card) -> i32
     }
 }
 
-/* Naming
-hdr:     bytes for mac_cmd/mac_resp generation, starting with 0x89, 0x04, cla, ins, p1, p2 and possibly more bytes; encapsulates the variability of cla, ins
-hdr_vec: bytes for mac_cmd/mac_resp generation, if assembling bytes is more complex than just using fixed hdr
-
-data: either plain or encrypted; input, the command data to be used in cmd/cmd_vec and mac_cmd
-data_encrypted
-
-resp: either plain or encrypted, output, command's response data to be used for Decrypting them first if applicable and mac_resp
-resp_decrypted
-
-cmd:     bytes for the APDU
-cmd_vec: bytes for the APDU, if assembling bytes is more complex than just using fixed cmd
-
-mac_cmd:
-mac_resp:
-
+/*
+All sm_* command functions below build the plain (unprotected) APDU just as without SM and let sm_transmit do the SM
+wrapping of the command and unwrapping of the response (SmSession::wrap/unwrap).
+The SM status word (tag 0x99) is then checked as usual, the outer status word got checked by sm_transmit already.
 */
 
-// TODO all code below assumes block_size is 8, i.e. using exclusively TDES for SM, which is not true anymore for ACOS5-EVO
-
 //#[no_mangle] // original APDU type (without SM): SC_APDU_CASE_2_SHORT: no command data, but expects response data (with SM, there are command data: the tagged le)
 pub fn sm_common_read(card: &mut sc_card,
                       idx: u16,
@@ -517,95 +985,30 @@ pub fn sm_common_read(card: &mut sc_card,
 
     /* sc_read_binary has a loop to chunk input into max. sc_get_max_recv_size(card) bytes,
        i.e. count<=255; it's okay to read less*/
-
-//println!("sm_common_read          get_cs_mac:  {:X?}\n", get_cs_mac(card));
     assert!(buf.len()<256);
     let count = std::cmp::min(buf.len(), 255);
     let len_read = std::cmp::min(if has_ct {239_u8} else {240_u8},u8::try_from(count).unwrap());
-////println!("len_read : {}", len_read);
-    let len_read2 : u8 = len_read.next_multiple_of(&DES_KEY_SZ_u8);// padding added if required
-////println!("len_read2: {}", len_read2);
-    debug_assert!(len_read2.is_multiple_of(&DES_KEY_SZ_u8));
-    assert!(len_read2 <= 240);
-    let pos : usize = if has_ct {13} else {12};
-    let len_resp = u8::try_from(pos).unwrap() +
-        if has_ct { len_read2 + if len_read.is_multiple_of(&DES_KEY_SZ_u8) {DES_KEY_SZ_u8} else {0} } else { len_read };
-////println!("len_resp: {}", len_resp);
-    /* cmd without SM: SC_APDU_CASE_2_SHORT; with SM: SC_APDU_CASE_4_SHORT */
-/*
-    let hdr;
-    if bin {
-        hdr = [0x89u8,4, if fdb!=9 {0x0C} else {0x8C},
-                         if fdb!=9 {0xB0} else {0xCA}, idx_arr[0],idx_arr[1],  0x97,1,len_read];
-    }
-    else {
-        assert!(idx<32);
-        hdr = [0x89u8,4, 0x0C, 0xB2, idx as u8,4,  0x97,1,len_read]; // there is no separate command for read sym. key record
-    }
-*/
-    let hdr =
+    let cmd =
         if bin {
             let idx_arr : [u8; 2] = idx.to_be_bytes();
-            [0x89_u8,4, if fdb!=9 {0x0C}else{0x8C}, if fdb!=9 {0xB0}else{0xCA}, idx_arr[0],idx_arr[1], 0x97,1,len_read]
+            [if fdb!=9 {0x00}else{0x80}, if fdb!=9 {0xB0}else{0xCA}, idx_arr[0],idx_arr[1], len_read]
         }
         else {
             assert!(idx<32);
-            [0x89_u8,4, 0x0C, 0xB2, u8::try_from(idx).unwrap(),4,  0x97,1,len_read]
+            [0x00, 0xB2, u8::try_from(idx).unwrap(),4, len_read] // there is no separate command for read sym. key record
         };
-
-////println!("sm_common_read ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-    let mut ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-////println!("sm_common_read ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mac_cmd = des_ede3_cbc_pad_80_mac(&hdr, &get_cs_mac(card), &mut ivec);
-////println!("mac_cmd:                 {:X?}", mac_cmd);
-    let cmd  = [hdr[2],hdr[3],hdr[4],hdr[5], 9,  hdr[6],hdr[7],hdr[8],
-        0x8E,4, mac_cmd[0],mac_cmd[1],mac_cmd[2],mac_cmd[3], len_resp /*>0, otherwise arbitrary*/];
-    let mut rbuf = vec![0_u8; usize::from(len_resp)];
-    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    assert_eq!(apdu.le, rbuf.len());
-
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_2_SHORT, &mut buf[..usize::from(len_read)]);
+    let mut rv = sm_transmit(card, &mut apdu, has_ct);
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f, line!(), rv);
         return rv;
     }
-
-    /* verify mac_resp */
-    let mut mac_resp_in = vec![hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x90, 0];
-    if has_ct { mac_resp_in.extend_from_slice(&[0x87_u8, len_resp -u8::try_from(pos-1).unwrap(), rbuf[pos-1]]); }
-    else      { mac_resp_in.extend_from_slice(&[0x81_u8, len_read]); }
-    mac_resp_in.extend_from_slice(&rbuf[pos..]);
-
-    sm_incr_ssc(card);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-//println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
-
-    if has_ct {
-        ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-        let resp_decrypted = des_ede3_cbc_pad_80(&rbuf[pos..pos+usize::from(len_read2)],
-                                          &get_cs_enc(card), &mut ivec, Decrypt, rbuf[pos-1]);
-        assert_eq!(usize::from(len_read), resp_decrypted.len());
-        assert!(resp_decrypted.len() <= count);
-        buf[..usize::from(len_read)].copy_from_slice(&resp_decrypted);
-    }
-    else {
-        buf[..usize::from(len_read)].copy_from_slice(&rbuf[pos..pos+usize::from(len_read)]);
-    }
-
-    rv = i32::from(len_read);
+    rv = i32::try_from(apdu.resplen).unwrap();
     log3ifr!(ctx,f,line!(), rv);
     rv
 }
@@ -631,97 +1034,31 @@ pub fn sm_common_update(card: &mut sc_card,
     }
 
     /* sc_update_binary has a loop to chunk input into max. sc_get_max_recv_size(card) bytes, i.e. count<=255; it's okay to update less*/
-//println!("sm_common_update        get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_common_update        get_cs_mac:  {:X?}\n", get_cs_mac(card));
     assert!(buf.len()<256);
     let count = std::cmp::min(buf.len(), 255);
     let len_update = std::cmp::min(if has_ct {232_u8} else {240_u8/*checked*/},u8::try_from(count).unwrap());
-////println!("len_update : {}", len_update);
-    let len_update2 = len_update.next_multiple_of(&DES_KEY_SZ_u8); // padding added if required
-////println!("len_update2: {}", len_update2);
-    debug_assert!(len_update2.is_multiple_of(&DES_KEY_SZ_u8));
-    assert!(len_update2 <= 240);
-    let pi = if has_ct && !len_update.is_multiple_of(&DES_KEY_SZ_u8) {1_u8} else {0_u8};
-////println!("pi: {}", pi);
-    /* cmd without SM: SC_APDU_CASE_3_SHORT; with SM: SC_APDU_CASE_4_SHORT */
-    let hdr =
-        if bin {
-            let idx_arr : [u8; 2] = idx.to_be_bytes();
-            [0x89_u8,4, 0x0C, // if fdb!=9 {0x0C} else {0x0C/ *0x8C* /}
-                        0xD6, // if fdb!=9 {0xD6} else {0xD6/ *0xDA* /}
-                idx_arr[0], idx_arr[1] ]
-        }
-        else {
-            assert!(idx<32);
-            [0x89_u8,4, 0x0C, if idx==0 && flags==0 {0xE2} else {0xDC},
-                u8::try_from(idx).unwrap(),        if idx==0 && flags==0 {0}    else {4} ]
-        };
-
-////println!("sm_common_update ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-////println!("sm_common_update ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mut ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let data_encrypted = des_ede3_cbc_pad_80(&buf[..usize::from(len_update)],
-          &get_cs_enc(card), &mut ivec, Encrypt, 0);
-////println!("data_encrypted.len(): {}, data_encrypted: {:X?}", data_encrypted.len(), data_encrypted);
-    assert_eq!(data_encrypted.len(), usize::from(len_update2));
-
-    let mut hdr_vec : Vec<u8> = Vec::with_capacity(hdr.len()+ 3+ usize::from(len_update2));
-    hdr_vec.extend_from_slice(&hdr);
-    if has_ct {
-        hdr_vec.extend_from_slice(& [0x87, 1+ len_update2, pi] );
-        hdr_vec.extend_from_slice(&data_encrypted);
+    let mut cmd : Vec<u8> = Vec::with_capacity(5+usize::from(len_update));
+    if bin {
+        let idx_arr : [u8; 2] = idx.to_be_bytes();
+        cmd.extend_from_slice(&[0x00, 0xD6, idx_arr[0], idx_arr[1], len_update]);
     }
     else {
-        hdr_vec.extend_from_slice(& [0x81,    len_update] );
-        hdr_vec.extend_from_slice(&buf[..usize::from(len_update)]);
-    }
-////println!("hdr_vec: {:X?}", hdr_vec);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac = des_ede3_cbc_pad_80_mac(&hdr_vec, &get_cs_mac(card), &mut ivec);
-////println!("mac:                 {:X?}", mac);
-    let mut cmd_vec : Vec<u8> = Vec::with_capacity(hdr.len()-2 +4 +usize::from(len_update2) +6 +1);
-    cmd_vec.extend_from_slice(&hdr[2..]);
-    if has_ct {
-        cmd_vec.extend_from_slice(& [9 +len_update2, 0x87, 1 +len_update2, pi] );
-        cmd_vec.extend_from_slice(&data_encrypted);
+        assert!(idx<32);
+        cmd.extend_from_slice(&[0x00, if idx==0 && flags==0 {0xE2} else {0xDC},
+            u8::try_from(idx).unwrap(), if idx==0 && flags==0 {0} else {4}, len_update]);
     }
-    else {
-        cmd_vec.extend_from_slice(& [8 +len_update,  0x81,    len_update] );
-        cmd_vec.extend_from_slice(&buf[..usize::from(len_update)]);
-    }
-    cmd_vec.extend_from_slice(&[0x8E, 4]);
-    cmd_vec.extend_from_slice(&mac[0..4]);
-    cmd_vec.push(10);
-////println!("cmd_vec:                 {:X?}", cmd_vec);
-    let mut rbuf = vec![0_u8; 10];
-    let mut apdu = build_apdu(ctx, &cmd_vec, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    assert_eq!(apdu.le, rbuf.len());
-
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    cmd.extend_from_slice(&buf[..usize::from(len_update)]);
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_3_SHORT, &mut []);
+    let mut rv = sm_transmit(card, &mut apdu, has_ct);
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    /* verify mac_resp */
-    let mac_resp_in = [hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x90, 0];
-    sm_incr_ssc(card);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-//println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
     rv = i32::from(len_update);
     log3ifr!(ctx,f,line!(), rv);
     rv
@@ -729,7 +1066,7 @@ pub fn sm_common_update(card: &mut sc_card,
 
 
 //#[no_mangle] // original APDU type (without SM): SC_APDU_CASE_3_SHORT:
-// no command data, but expects response data (with SM, there are command data: the tagged le)
+// command data: the exclusive end offset, doesn't expect response data
 pub fn sm_erase_binary(card: &mut sc_card, idx: u16, count: u16, flags: c_ulong, has_ct: bool) -> i32
 {
     assert!(!card.ctx.is_null());
@@ -741,83 +1078,29 @@ pub fn sm_erase_binary(card: &mut sc_card, idx: u16, count: u16, flags: c_ulong,
         return SC_ERROR_SM_NOT_INITIALIZED;
     }
 
-//println!("sm_erase_binary         get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_erase_binary         get_cs_mac:  {:X?}\n", get_cs_mac(card));
-    /* cmd without SM: SC_APDU_CASE_2_SHORT; with SM: SC_APDU_CASE_4_SHORT */
     let idx_arr : [u8; 2] = idx.to_be_bytes();
-    let hdr = [0x89_u8,4, 0x0C, 0x0E, idx_arr[0],idx_arr[1] /*,  0x97,1,len_read*/];
-////println!("sm_common_read ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-    let mut ivec = unsafe { card.sm_ctx.info.session.cwa.ssc };
-////println!("sm_common_read ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let data_encrypted = des_ede3_cbc_pad_80(&(idx + count).to_be_bytes(),
-        &get_cs_enc(card), &mut ivec, Encrypt, 0);
-////println!("data_encrypted.len(): {}, data_encrypted: {:X?}", data_encrypted.len(), data_encrypted);
-    assert_eq!(DES_KEY_SZ, data_encrypted.len());
-
-    let mut hdr_vec : Vec<u8> = Vec::with_capacity(hdr.len()+ 11);
-    hdr_vec.extend_from_slice(&hdr);
-    if has_ct {
-        hdr_vec.extend_from_slice(& [0x87, 9, 1] );
-        hdr_vec.extend_from_slice(&data_encrypted);
-    }
-    else {
-        hdr_vec.extend_from_slice(& [0x81,    2] );
-        hdr_vec.extend_from_slice(& (idx + count).to_be_bytes() );
-    }
-////println!("hdr_vec: {:X?}", hdr_vec);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_cmd = des_ede3_cbc_pad_80_mac(&hdr_vec, &get_cs_mac(card), &mut ivec);
-////println!("mac_cmd:                 {:X?}", mac_cmd);
-    let mut cmd_vec : Vec<u8> = Vec::with_capacity(hdr.len()-2 +4 +8 +6 +1);
-    cmd_vec.extend_from_slice(&hdr[2..]);
-    if has_ct {
-        cmd_vec.extend_from_slice(&[17, 0x87, 9, 1]);
-        cmd_vec.extend_from_slice(&data_encrypted);
-    }
-    else {
-        cmd_vec.extend_from_slice(&[10, 0x81, 2]);
-        cmd_vec.extend_from_slice(& (idx + count).to_be_bytes() );
-    }
-    cmd_vec.extend_from_slice(&[0x8E, 4]);
-    cmd_vec.extend_from_slice(&mac_cmd[0..4]);
-    cmd_vec.push(10);
-////println!("cmd_vec:                 {:X?}", cmd_vec);
-    let mut rbuf = [0; 10];
-    let mut apdu = build_apdu(ctx, &cmd_vec, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    debug_assert_eq!(apdu.le, rbuf.len());
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    let end_arr : [u8; 2] = (idx + count).to_be_bytes();
+    let cmd = [0x00, 0x0E, idx_arr[0],idx_arr[1], 2, end_arr[0],end_arr[1]];
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_3_SHORT, &mut []);
+    let mut rv = sm_transmit(card, &mut apdu, has_ct);
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    /* verify mac_resp */
-    let /*mut*/ mac_resp_in = vec![hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x90, 0];
-    sm_incr_ssc(card);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-//println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
     rv = i32::from(count);
     log3ifr!(ctx,f,line!(), rv);
     rv
 } // sm_erase_binary
 
 
-//#[no_mangle] // original APDU type (without SM): SC_APDU_CASE_1 or SC_APDU_CASE_3_SHORT:
-// Doesn't expect response data (with SM, )
-pub fn sm_delete_file(card: &mut sc_card) -> i32
+//#[no_mangle] // original APDU type (without SM): SC_APDU_CASE_1 (deletes the currently selected file):
+// Doesn't expect response data; there are no command data to be encrypted, even if the SCB demands has_ct
+pub fn sm_delete_file(card: &mut sc_card, has_ct: bool) -> i32
 {
     assert!(!card.ctx.is_null());
     let ctx = unsafe { &mut *card.ctx };
@@ -827,53 +1110,25 @@ pub fn sm_delete_file(card: &mut sc_card) -> i32
     if sm_manage_keyset(card) != SC_SUCCESS || sm_manage_initialize(card) != SC_SUCCESS {
         return SC_ERROR_SM_NOT_INITIALIZED;
     }
-//println!("sm_delete_file          get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_delete_file          get_cs_mac:  {:X?}\n", get_cs_mac(card));
-    /* cmd without SM: SC_APDU_CASE_1; with SM: SC_APDU_CASE_4_SHORT */
-    let hdr = [0x89_u8,4, 0x0C, 0xE4, 0,0];
-////println!("sm_common_read ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-    let mut ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-////println!("sm_common_read ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mac_cmd = des_ede3_cbc_pad_80_mac(&hdr, &get_cs_mac(card), &mut ivec);
-////println!("mac_cmd:                 {:X?}", mac_cmd);
-    let cmd  = [hdr[2],hdr[3],hdr[4],hdr[5], 6, 0x8E,4, mac_cmd[0],mac_cmd[1],mac_cmd[2],mac_cmd[3], 10];
-    let mut rbuf = [0; 10];
-    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    debug_assert_eq!(apdu.le, rbuf.len());
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
-    if rv != SC_SUCCESS {
-        log3ifr!(ctx,f,line!(), rv);
-        return rv;
-    }
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
+
+    let cmd = [0x00, 0xE4, 0, 0];
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_1, &mut []);
+    let mut rv = sm_transmit(card, &mut apdu, has_ct);
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    /* verify mac_resp */
-    let mac_resp_in = vec![hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x90, 0];
-    sm_incr_ssc(card);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-//println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     log3ifr!(ctx,f,line!(), rv);
     rv
 } // sm_delete_file
 
 
-//TODO this doesn't work for SM Confidentiality (has_ct==true)
-#[allow(dead_code)]
-fn sm_create_file(card: &mut sc_card,
-                  buf: &[u8], // starting with 0x62
-                  has_ct: bool) -> i32
+//#[no_mangle] // original APDU type (without SM): SC_APDU_CASE_3_SHORT: command data: the FCP (as constructed by
+// acos5_construct_fci), encrypted if has_ct; doesn't expect response data
+pub fn sm_create_file(card: &mut sc_card,
+                      buf: &[u8], // starting with 0x62 or 0x6F
+                      has_ct: bool) -> i32
 {
     assert!(!card.ctx.is_null());
     let ctx = unsafe { &mut *card.ctx };
@@ -884,86 +1139,22 @@ fn sm_create_file(card: &mut sc_card,
         return SC_ERROR_SM_NOT_INITIALIZED;
     }
 
-//println!("sm_create_file          get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_create_file          get_cs_mac:  {:X?}\n", get_cs_mac(card));
-//    let count = std::cmp::min(buf.len(), 255);
-    let len_update = std::cmp::min(if has_ct {232_u8} else {240_u8},u8::try_from(buf.len()).unwrap());
-    assert!(buf.len()<= usize::from(len_update));
-////println!("len_update : {}", len_update);
-    let len_update2 = len_update.next_multiple_of(&DES_KEY_SZ_u8); // padding added if required
-////println!("len_update2: {}", len_update2);
-    debug_assert!(len_update2.is_multiple_of(&DES_KEY_SZ_u8));
-    assert!(len_update2 <= 240);
-    let pi = if has_ct && !len_update.is_multiple_of(&DES_KEY_SZ_u8) {1_u8} else {0_u8};
-////println!("pi: {}", pi);
-    /* cmd without SM: SC_APDU_CASE_3_SHORT; with SM: SC_APDU_CASE_4_SHORT */
-    let hdr = [0x89_u8,4, 0x0C, 0xE0, 0, 0];
-//println!("sm_common_update ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-//println!("sm_common_update ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mut ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let data_encrypted = des_ede3_cbc_pad_80(&buf[..usize::from(len_update)],
-       &get_cs_enc(card), &mut ivec, Encrypt, 0);
-////println!("data_encrypted.len(): {}, data_encrypted: {:X?}", data_encrypted.len(), data_encrypted);
-    assert_eq!(data_encrypted.len(), usize::from(len_update2));
-
-    let mut hdr_vec : Vec<u8> = Vec::with_capacity(hdr.len()+ 3+ usize::from(len_update2));
-    hdr_vec.extend_from_slice(&hdr);
-    if has_ct {
-        hdr_vec.extend_from_slice(& [0x87, 1+ len_update2, pi] );
-        hdr_vec.extend_from_slice(&data_encrypted);
-    }
-    else {
-        hdr_vec.extend_from_slice(& [0x81,    len_update] );
-        hdr_vec.extend_from_slice(&buf[..usize::from(len_update)]);
-    }
-////println!("hdr_vec: {:X?}", hdr_vec);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac = des_ede3_cbc_pad_80_mac(&hdr_vec, &get_cs_mac(card), &mut ivec);
-////println!("mac:                 {:X?}", mac);
-    let mut cmd_vec : Vec<u8> = Vec::with_capacity(hdr.len()-2 +4 +usize::from(len_update2) +6 +1);
-    cmd_vec.extend_from_slice(&hdr[2..]);
-    if has_ct {
-        cmd_vec.extend_from_slice(& [9 +len_update2, 0x87, 1 +len_update2, pi] );
-        cmd_vec.extend_from_slice(&data_encrypted);
-    }
-    else {
-        cmd_vec.extend_from_slice(& [8 +len_update,  0x81,    len_update] );
-        cmd_vec.extend_from_slice(&buf[..usize::from(len_update)]);
-    }
-    cmd_vec.extend_from_slice(&[0x8E, 4]);
-    cmd_vec.extend_from_slice(&mac[0..4]);
-    cmd_vec.push(10);
-////println!("cmd_vec:                 {:X?}", cmd_vec);
-    let mut rbuf = [0; 10];
-    let mut apdu = build_apdu(ctx, &cmd_vec, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    assert_eq!(apdu.le, rbuf.len());
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
-    if rv != SC_SUCCESS {
+    /* the FCP can't be chunked */
+    if buf.is_empty() || buf.len() > if has_ct {232} else {240} {
+        let rv = SC_ERROR_INVALID_ARGUMENTS;
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
+    let mut cmd : Vec<u8> = Vec::with_capacity(5+buf.len());
+    cmd.extend_from_slice(&[0x00, 0xE0, 0, 0, u8::try_from(buf.len()).unwrap()]);
+    cmd.extend_from_slice(buf);
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_3_SHORT, &mut []);
+    let mut rv = sm_transmit(card, &mut apdu, has_ct);
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-
-    /* verify mac_resp */
-    let mac_resp_in = [hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x90, 0];
-    sm_incr_ssc(card);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in,
-                            &get_cs_mac(card), &mut ivec);
-//println!("mac_resp:                 {:X?}", mac_resp);
-    log3if!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
-//    rv = i32::from(len_update);
+    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     log3ifr!(ctx,f,line!(), rv);
     rv
 } // sm_create_file
@@ -986,8 +1177,6 @@ pub fn sm_pin_cmd(card: &mut sc_card,
         return SC_ERROR_SM_NOT_INITIALIZED;
     }
 
-//println!("sm_pin_cmd  verify      get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_pin_cmd  verify      get_cs_mac:  {:X?}\n", get_cs_mac(card));
     let ins : u8 = match pin_cmd_data.cmd {
         SC_PIN_CMD_VERIFY  => 0x20,
         SC_PIN_CMD_CHANGE  => 0x24,
@@ -997,91 +1186,35 @@ pub fn sm_pin_cmd(card: &mut sc_card,
     let mut pin_data : Vec<u8> = Vec::with_capacity(16);
     pin_data.extend_from_slice(unsafe { from_raw_parts(pin_cmd_data.pin1.data,
                                                        usize::try_from(pin_cmd_data.pin1.len).unwrap()) });
-    let mut len_pin = u8::try_from(pin_cmd_data.pin1.len).unwrap();
     if ins == 0x24 || ins == 0x2C {
-        len_pin *= 2;
         pin_data.extend_from_slice(unsafe { from_raw_parts(pin_cmd_data.pin2.data,
                                                            usize::try_from(pin_cmd_data.pin2.len).unwrap()) });
     }
-////println!("len_pin : {}", len_pin);
-    let len2_pin : u8 = len_pin.next_multiple_of(&DES_KEY_SZ_u8); // padding added if required
-////println!("len2_pin: {}", len2_pin);
-    debug_assert!(len2_pin.is_multiple_of(&DES_KEY_SZ_u8));
-//    assert!(len2_pin <= DES_KEY_SZ_u8);
-    let pi = if has_ct && !len_pin.is_multiple_of(&DES_KEY_SZ_u8) {1_u8} else {0_u8};
-////println!("pi: {}", pi);
-    /* cmd without SM: SC_APDU_CASE_3_SHORT; with SM: SC_APDU_CASE_4_SHORT */
-    let hdr = [0x89_u8,4, 0x0C, ins, 0, u8::try_from(pin_cmd_data.pin_reference).unwrap()];
-////println!("sm_pin_cmd ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-////println!("sm_pin_cmd ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mut ivec = unsafe { card.sm_ctx.info.session.cwa.ssc };
-    let data_encrypted = des_ede3_cbc_pad_80(&pin_data, &get_cs_enc(card), &mut ivec, Encrypt, 0);
-////println!("data_encrypted.len(): {}, data_encrypted: {:X?}", data_encrypted.len(), data_encrypted);
-    assert_eq!(data_encrypted.len(), usize::from(len2_pin));
-
-    let mut hdr_vec : Vec<u8> = Vec::with_capacity(hdr.len()+ 3+ usize::from(len2_pin));
-    hdr_vec.extend_from_slice(&hdr);
-    if has_ct {
-        hdr_vec.extend_from_slice(& [0x87, 1+ len2_pin, pi] );
-        hdr_vec.extend_from_slice(&data_encrypted);
+    let mut cmd : Vec<u8> = Vec::with_capacity(5+pin_data.len());
+    cmd.extend_from_slice(&[0x00, ins, 0, u8::try_from(pin_cmd_data.pin_reference).unwrap(),
+        u8::try_from(pin_data.len()).unwrap()]);
+    cmd.extend_from_slice(&pin_data);
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_3_SHORT, &mut []);
+    let rv = sm_transmit(card, &mut apdu, has_ct);
+    if rv != SC_SUCCESS && rv != SC_ERROR_SM {
+        log3ifr!(ctx,f,line!(), rv);
+        return rv;
     }
-    else {
-        hdr_vec.extend_from_slice(& [0x81,    len_pin] );
-        hdr_vec.extend_from_slice(&pin_data);
-    }
-////println!("hdr_vec: {:X?}", hdr_vec);
-    ivec = unsafe {card.sm_ctx.info.session.cwa.ssc};
-    let mac = des_ede3_cbc_pad_80_mac(&hdr_vec, &get_cs_mac(card), &mut ivec);
-////println!("mac:                 {:X?}", mac);
-    let mut cmd_vec : Vec<u8> = Vec::with_capacity(hdr.len()-2 +4 +usize::from(len2_pin) +6 +1);
-    cmd_vec.extend_from_slice(&hdr[2..]);
-    if has_ct {
-        cmd_vec.extend_from_slice(& [9 +len2_pin, 0x87, 1 +len2_pin, pi] );
-        cmd_vec.extend_from_slice(&data_encrypted);
+    /* a wrong pin is reported regardless of the MAC verification, which may also be skipped by the card then */
+    let rv_sw = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    if rv_sw != SC_SUCCESS {
+        pin_cmd_data.pin1.logged_in = SC_PIN_STATE_LOGGED_OUT;
+        if      rv_sw==SC_ERROR_PIN_CODE_INCORRECT  { pin_cmd_data.pin1.tries_left = i32::try_from(apdu.sw2 & 0x0F).unwrap();
+                                                      *tries_left = pin_cmd_data.pin1.tries_left; }
+        else if rv_sw==SC_ERROR_AUTH_METHOD_BLOCKED { pin_cmd_data.pin1.tries_left = 0;
+                                                      *tries_left = pin_cmd_data.pin1.tries_left; }
+        log3ifr!(ctx,f,line!(), rv_sw);
+        return rv_sw;
     }
-    else {
-        cmd_vec.extend_from_slice(& [8 +len_pin,  0x81,    len_pin] );
-        cmd_vec.extend_from_slice(&pin_data);
-    }
-    cmd_vec.extend_from_slice(&[0x8E, 4]);
-    cmd_vec.extend_from_slice(&mac[0..4]);
-    cmd_vec.push(10);
-////println!("cmd_vec:                 {:X?}", cmd_vec);
-    let mut rbuf = [0; 10];
-    let mut apdu = build_apdu(ctx, &cmd_vec, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    assert_eq!(apdu.le, rbuf.len());
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
     if rv != SC_SUCCESS {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
-    let mut cmd_failure = false;
-    rv = unsafe { sc_check_sw(card, u32::from(rbuf[2]), u32::from(rbuf[3])) };
-    if rv != SC_SUCCESS {
-        cmd_failure = true;
-        pin_cmd_data.pin1.logged_in = SC_PIN_STATE_LOGGED_OUT;
-        if      rv==SC_ERROR_PIN_CODE_INCORRECT  { pin_cmd_data.pin1.tries_left = i32::from(rbuf[3]&0x0F);
-                                                   *tries_left = pin_cmd_data.pin1.tries_left; }
-        else if rv==SC_ERROR_AUTH_METHOD_BLOCKED { pin_cmd_data.pin1.tries_left = 0;
-                                                   *tries_left = pin_cmd_data.pin1.tries_left; }
-//        log3ifr!(ctx,f,line!(), rv);
-//        return rv;
-    }
-
-    /* verify mac_resp */
-    let mac_resp_in = [hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, rbuf[2], rbuf[3]];
-    sm_incr_ssc(card);
-    ivec = unsafe { card.sm_ctx.info.session.cwa.ssc };
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-////println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if cmd_failure || rbuf[2]!=0x90 || rbuf[3]!=0 { log3ifr!(ctx,f,line!(), rv); return rv; }
-    if rbuf[6..10] != mac_resp[..4] {
-        return SC_ERROR_SM;
-    }
     pin_cmd_data.pin1.logged_in = SC_PIN_STATE_LOGGED_IN;
 
     log3ifr!(ctx,f,line!(), rv);
@@ -1097,54 +1230,151 @@ pub fn sm_pin_cmd_get_policy(card: &mut sc_card,
     let f = cstru!( b"sm_pin_cmd_get_policy\0");
     log3ifc!(ctx,f,line!());
 
+    pin_set_policy(card, pin_cmd_data);
     pin_cmd_data.pin1.tries_left = -1;
     *tries_left = pin_cmd_data.pin1.tries_left;
 
     if sm_manage_keyset(card) != SC_SUCCESS || sm_manage_initialize(card) != SC_SUCCESS {
         return SC_ERROR_SM_NOT_INITIALIZED;
     }
-//println!("sm_pin_cmd_get_policy   get_cs_enc:  {:X?}\n", get_cs_enc(card));
-//println!("sm_pin_cmd_get_policy   get_cs_mac:  {:X?}\n", get_cs_mac(card));
-    /* cmd without SM: SC_APDU_CASE_1; with SM: SC_APDU_CASE_4_SHORT */
-    let hdr = [0x89_u8,4, 0x0C, 0x20, 0, u8::try_from(pin_cmd_data.pin_reference).unwrap()];
-////println!("sm_common_read ssc old:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    sm_incr_ssc(card);
-    let mut ivec = unsafe { card.sm_ctx.info.session.cwa.ssc };
-////println!("sm_common_read ssc new:                 {:X?}", unsafe { card.sm_ctx.info.session.cwa.ssc });
-    let mac_cmd = des_ede3_cbc_pad_80_mac(&hdr, &get_cs_mac(card), &mut ivec);
-////println!("mac_cmd:                 {:X?}", mac_cmd);
-    let cmd  = [hdr[2],hdr[3],hdr[4],hdr[5], 6, 0x8E,4, mac_cmd[0],mac_cmd[1],mac_cmd[2],mac_cmd[3], 10];
-    let mut rbuf = [0; 10];
-    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_4_SHORT, &mut rbuf);
-    assert_eq!(apdu.le, rbuf.len());
-    let mut rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
-    if rv != SC_SUCCESS {
+    /* verify without pin data: SW 63Cn reports the remaining tries */
+    let cmd = [0x00, 0x20, 0, u8::try_from(pin_cmd_data.pin_reference).unwrap()];
+    let mut apdu = build_apdu(ctx, &cmd, SC_APDU_CASE_1, &mut []);
+    let rv = sm_transmit(card, &mut apdu, false);
+    if rv != SC_SUCCESS && rv != SC_ERROR_SM {
         log3ifr!(ctx,f,line!(), rv);
         return rv;
     }
 
-    if !(u32::from(rbuf[2]) == 0x63 && (u32::from(rbuf[3]) & 0xC0) == 0xC0) {
+    if !(apdu.sw1 == 0x63 && (apdu.sw2 & 0xC0) == 0xC0) {
         log3if!(ctx,f,line!(), cstru!(b"Error: 'Get remaining number of retries left for the PIN' failed\0"));
         return SC_ERROR_KEYPAD_MSG_TOO_LONG;
     }
-
-
-    /* verify mac_resp */
-    let mac_resp_in = vec![hdr[0],hdr[1],hdr[2],hdr[3],hdr[4],hdr[5], 0x99, 2, 0x63, rbuf[3]];
-    sm_incr_ssc(card);
-    ivec = unsafe { card.sm_ctx.info.session.cwa.ssc };
-    let mac_resp = des_ede3_cbc_pad_80_mac(&mac_resp_in, &get_cs_mac(card), &mut ivec);
-////println!("mac_resp:                 {:X?}", mac_resp);
-    log3ift!(ctx,f,line!(), cstru!(b"mac_resp verification: [%02X %02X %02X %02X]\0"),
-        mac_resp[0], mac_resp[1], mac_resp[2], mac_resp[3]);
-    if rbuf[6..10] != mac_resp[..4] {
-        rv = SC_ERROR_SM;
-    }
-    else {
-        pin_cmd_data.pin1.tries_left = i32::from(rbuf[3] & 0x0F); //  63 Cnh     n is remaining tries
+    if rv == SC_SUCCESS {
+        pin_cmd_data.pin1.tries_left = i32::try_from(apdu.sw2 & 0x0F).unwrap(); //  63 Cnh     n is remaining tries
         *tries_left = pin_cmd_data.pin1.tries_left;
     }
     log3ifr!(ctx,f,line!(), rv);
     rv
 }
+
+
+#[cfg(test)]
+mod tests {
+    use num_integer::Integer;
+    use super::{SmSession, SmResponse, sm_tlv, sm_keyset_file_decrypt, sm_keyset_file_lookup, sm_keyset_check};
+    use opensc_sys::types::SC_APDU_CASE_4_SHORT;
+    use opensc_sys::errors::{SC_ERROR_UNKNOWN_DATA_RECEIVED, SC_ERROR_INVALID_DATA, SC_ERROR_NOT_SUPPORTED};
+
+    /* The card side of a SM response to the command last wrapped by sess: data objects 0x87 (if has_ct) or 0x81, 0x99
+       and 0x8E, emulated with the primitives of SmSession */
+    fn sm_card_resp(sess: &SmSession, hdr: [u8; 4], sw: [u8; 2], data: &[u8], has_ct: bool) -> Vec<u8> {
+        let mut sess = sess.clone();
+        sess.incr_ssc();
+        let mut dos = Vec::new();
+        if !data.is_empty() {
+            if has_ct {
+                let mut value = vec![u8::from(!data.len().is_multiple_of(&sess.block_size()))];
+                value.extend_from_slice(&sess.encrypt(data));
+                dos.extend_from_slice(&sm_tlv(0x87, &value));
+            }
+            else {
+                dos.extend_from_slice(&sm_tlv(0x81, data));
+            }
+        }
+        dos.extend_from_slice(&[0x99, 2, sw[0], sw[1]]);
+        let mut mac_in = vec![0x89_u8, 4, hdr[0] | 0x0C, hdr[1], hdr[2], hdr[3]];
+        mac_in.extend_from_slice(&dos);
+        dos.extend_from_slice(&[0x8E, 4]);
+        dos.extend_from_slice(&sess.mac(&mac_in)[..4]);
+        dos
+    }
+
+    /* There is no SM transcript of a real card to test against: The card side gets emulated by SmSession itself, thus
+       this tests the framing (data objects, lengths, SSC handling, MAC verification), not the cryptography.
+       read binary (case 2, has_ct), update binary (case 3, plain), verify pin (case 3, has_ct, wrong pin) */
+    #[test]
+    fn test_sm_session_tdes() {
+        let mut sess = SmSession { enc: vec![0x13; 24], mac: vec![0x25; 24], ssc: vec![1, 2, 3, 4, 5, 6, 7, 8] };
+        let (cmd, cse, resp_len) = sess.wrap([0, 0xB0, 0, 0x10], &[], Some(10), true, false);
+        assert_eq!((SC_APDU_CASE_4_SHORT, 10+3+16), (cse, resp_len));
+        assert_eq!((&[0x0C, 0xB0, 0, 0x10, 9, 0x97, 1, 10, 0x8E, 4][..], 15, 29), (&cmd[..10], cmd.len(), cmd[14]));
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 9], sess.ssc);
+        let plain : Vec<u8> = (0xA0..0xAA).collect();
+        let resp = sm_card_resp(&sess, [0, 0xB0, 0, 0x10], [0x90, 0], &plain, true);
+        assert_eq!(resp_len, resp.len());
+        assert_eq!(Ok(SmResponse { sw1: 0x90, sw2: 0, data: plain, mac_ok: true }), sess.unwrap([0, 0xB0, 0, 0x10], &resp));
+
+        let data : Vec<u8> = (0x30..0x38).collect();
+        let (cmd, _cse, resp_len) = sess.wrap([0, 0xD6, 0, 0], &data, None, false, false);
+        assert_eq!((&[0x0C, 0xD6, 0, 0, 0x10, 0x81, 8][..], &data[..], &[0x8E, 4][..]), (&cmd[..7], &cmd[7..15], &cmd[15..17]));
+        assert_eq!((10, 22), (resp_len, cmd.len()));
+        let resp = sm_card_resp(&sess, [0, 0xD6, 0, 0], [0x90, 0], &[], false);
+        assert_eq!(Ok(SmResponse { sw1: 0x90, sw2: 0, data: vec![], mac_ok: true }), sess.unwrap([0, 0xD6, 0, 0], &resp));
+
+        let (cmd, _cse, _resp_len) = sess.wrap([0, 0x20, 0, 0x81], b"12345", None, true, false);
+        assert_eq!(&[0x0C, 0x20, 0, 0x81, 0x11, 0x87, 9, 1], &cmd[..8]);
+        assert_eq!(b"12345".to_vec(), sess.decrypt(&cmd[8..16], 1));
+        let resp = sm_card_resp(&sess, [0, 0x20, 0, 0x81], [0x63, 0xC2], &[], false);
+        let sess_copy = sess.clone();
+        assert_eq!(Ok(SmResponse { sw1: 0x63, sw2: 0xC2, data: vec![], mac_ok: true }), sess.unwrap([0, 0x20, 0, 0x81], &resp));
+
+        /* a tampered status word must fail MAC verification; malformed data objects are rejected */
+        let mut tampered = resp.clone();
+        tampered[3] = 0xC3;
+        assert!(!sess_copy.clone().unwrap([0, 0x20, 0, 0x81], &tampered).unwrap().mac_ok);
+        assert_eq!(Err(SC_ERROR_UNKNOWN_DATA_RECEIVED), sess_copy.clone().unwrap([0, 0x20, 0, 0x81], &resp[..8]));
+    }
+
+    #[test]
+    fn test_sm_session_extended() {
+        /* le > 256 or command data > 255 bytes switch to an extended length SM APDU */
+        let mut sess = SmSession { enc: vec![0x13; 24], mac: vec![0x25; 24], ssc: vec![0; 8] };
+        let (cmd, cse, resp_len) = sess.wrap([0, 0xB0, 0, 0], &[], Some(0x200), false, false);
+        assert_eq!(opensc_sys::types::SC_APDU_CASE_4_EXT, cse);
+        assert_eq!(10 + 4 + 0x200, resp_len);
+        assert_eq!(&[0x0C, 0xB0, 0, 0, 0, 0, 10, 0x97, 2, 2, 0], &cmd[..11]);
+        assert_eq!(&[0x02, 0x0E], &cmd[cmd.len()-2..]);
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 1], sess.ssc);
+    }
+
+    #[test]
+    fn test_sm_keyset_file() {
+        let content = b"# serial               name            key\n\
+                        *                      keyset_02_enc   01:02:03\n\
+                        0102030405060708       keyset_02_enc   0A0B0C # serial specific takes precedence\n\
+                        *                      keyset_02_mac   112233\n";
+        let lookup = |content: &[u8], serial: &[u8], name: &[u8]| sm_keyset_file_lookup(content, serial, name).map(|key| key.0.to_vec());
+        assert_eq!(Some(vec![10, 11, 12]), lookup(content, &[1, 2, 3, 4, 5, 6, 7, 8], b"keyset_02_enc"));
+        assert_eq!(Some(vec![1, 2, 3]),    lookup(content, &[1, 2, 3, 4, 5, 6, 7, 9], b"keyset_02_enc"));
+        assert_eq!(Some(vec![0x11, 0x22, 0x33]), lookup(content, &[0; 8], b"keyset_02_mac"));
+        assert_eq!(None, lookup(content, &[0; 8], b"keyset_01_mac"));
+        /* a line, that isn't UTF-8, gets skipped */
+        assert_eq!(Some(vec![0x11, 0x22, 0x33]), lookup(b"\xFF\xFE garbage\n* keyset_02_mac 112233\n", &[0; 8], b"keyset_02_mac"));
+
+        /* printf '* keyset_02_enc 0102\n' | openssl enc -aes-256-cbc -pbkdf2 -iter 1000 -pass pass:secret */
+        let encrypted = [0x53, 0x61, 0x6C, 0x74, 0x65, 0x64, 0x5F, 0x5F, 0x6D, 0x4A, 0x89, 0x75, 0x7D, 0x71, 0x57, 0x97,
+            0xBD, 0x21, 0x63, 0xFC, 0xA2, 0x11, 0x07, 0x85, 0xE0, 0x39, 0xF4, 0x60, 0x27, 0x28, 0x09, 0xE5,
+            0xDF, 0x0C, 0xF8, 0xE9, 0xB1, 0xEA, 0xF4, 0xB9, 0x03, 0xAA, 0xE5, 0x36, 0x47, 0x7E, 0xD8, 0xB1];
+        let decrypt = |passphrase: &[u8], iter: i32| sm_keyset_file_decrypt(&encrypted, passphrase, iter).map(|content| content.to_vec());
+        assert_eq!(Some(b"* keyset_02_enc 0102\n".to_vec()), decrypt(b"secret", 1000));
+        assert_ne!(Some(b"* keyset_02_enc 0102\n".to_vec()), decrypt(b"wrong", 1000));
+        assert_eq!(None, decrypt(b"secret", 0));
+    }
+
+    #[test]
+    fn test_sm_keyset_check() {
+        let lookup = |content: &[u8]| sm_keyset_file_lookup(content, &[0; 8], b"keyset_02_enc").map(|key| (key.0.to_vec(), key.1));
+        assert_eq!(Some((vec![1, 2], Some(0x24))), lookup(b"* keyset_02_enc 0102 24\n"));
+        assert_eq!(Some((vec![1, 2], None)),       lookup(b"* keyset_02_enc 0102 XY\n* keyset_02_enc 0102\n"));
+
+        assert_eq!(Ok(()), sm_keyset_check(None, 24));
+        assert_eq!(Ok(()), sm_keyset_check(Some(0x14), 24));
+        /* AES keys get refused */
+        assert_eq!(Err(SC_ERROR_NOT_SUPPORTED), sm_keyset_check(Some(0x24), 24));
+        assert_eq!(Err(SC_ERROR_NOT_SUPPORTED), sm_keyset_check(None, 16));
+        assert_eq!(Err(SC_ERROR_NOT_SUPPORTED), sm_keyset_check(None, 32));
+        assert_eq!(Err(SC_ERROR_INVALID_DATA),  sm_keyset_check(Some(0x14), 16));
+        assert_eq!(Err(SC_ERROR_INVALID_DATA),  sm_keyset_check(Some(0x28), 24));
+    }
+}
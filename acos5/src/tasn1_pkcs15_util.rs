@@ -20,15 +20,16 @@
 
 use std::os::raw::{c_char, c_void/*, c_int*/};
 use std::ptr::{null_mut};
-use std::ffi::{CStr};
+use std::ffi::{CStr, CString};
 use std::convert::{TryFrom, TryInto};
 use std::ops::{Deref, DerefMut, Range};
+use std::collections::HashMap;
 
-use opensc_sys::opensc::{sc_card, sc_format_path, sc_path_set, sc_select_file, sc_read_binary};
+use opensc_sys::opensc::{sc_card, sc_format_path, sc_path_set, sc_select_file, sc_read_binary, sc_update_binary};
 use opensc_sys::types::{sc_path, sc_aid, SC_PATH_TYPE_PATH, SC_MAX_AID_SIZE, SC_MAX_PATH_SIZE};
-use opensc_sys::errors::SC_SUCCESS;
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_INVALID_ARGUMENTS};
 
-use crate::constants_types::{DataPrivate, PKCS15_FILE_TYPE_APPDF, PKCS15_FILE_TYPE_DIR, //p_void,
+use crate::constants_types::{DataPrivate, KeyTypeFiles, ValueTypeFiles, PKCS15_FILE_TYPE_APPDF, PKCS15_FILE_TYPE_DIR, //p_void,
                              PKCS15_FILE_TYPE_TOKENINFO,
                              PKCS15_FILE_TYPE_ODF,
                              PKCS15_FILE_TYPE_PRKDF,
@@ -52,7 +53,12 @@ use crate::constants_types::{DataPrivate, PKCS15_FILE_TYPE_APPDF, PKCS15_FILE_TY
                              PKCS15_FILE_TYPE_BIOMETRIC,
                              PKCS15_FILE_TYPE_AUTHKEY,
                              PKCS15_FILE_TYPE_NONE,
-                             GuardFile, is_DFMF, file_id_from_path_value, file_id_se, is_child_of};
+                             GuardFile, is_DFMF, file_id_from_path_value, file_id_se, is_child_of,
+                             FDB_TRANSPARENT_EF, FDB_RSA_KEY_EF, FDB_ECC_KEY_EF, FDB_SYMMETRIC_KEY_EF,
+                             SANITY_SEVERITY_INFO, SANITY_SEVERITY_WARNING, SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR,
+                             SANITY_CHECK_PKCS15_ODF, SANITY_CHECK_PKCS15_TOKENINFO, SANITY_CHECK_PKCS15_OBJECTS};
+use crate::sanity::Finding;
+use crate::no_cdecl::aodf_pin_attributes;
 
 use crate::tasn1_sys::{asn1_node_st, asn1_node, asn1_delete_structure, ASN1_SUCCESS,
                        asn1_create_element, asn1_der_decoding, asn1_read_value, asn1_strerror, asn1_get_length_der};
@@ -323,6 +329,85 @@ pub fn analyze_PKCS15_DIRRecord_2F00(card: &mut sc_card, aid: &mut sc_aid) {
     }
 }
 
+/* the path of an EF.ODF entry of type idx (PKCS15_FILE_TYPE_PRKDF..=PKCS15_FILE_TYPE_AODF) */
+fn get_arr<'a>(idx: u8) -> &'a CStr {
+    match idx {
+        PKCS15_FILE_TYPE_PRKDF => cstru!(b"privateKeys.path.path\0"),
+        PKCS15_FILE_TYPE_PUKDF => cstru!(b"publicKeys.path.path\0"),
+        PKCS15_FILE_TYPE_PUKDF_TRUSTED => cstru!(b"trustedPublicKeys.path.path\0"),
+        PKCS15_FILE_TYPE_SKDF => cstru!(b"secretKeys.path.path\0"),
+        PKCS15_FILE_TYPE_CDF => cstru!(b"certificates.path.path\0"),
+        PKCS15_FILE_TYPE_CDF_TRUSTED => cstru!(b"trustedCertificates.path.path\0"),
+        PKCS15_FILE_TYPE_CDF_USEFUL => cstru!(b"usefulCertificates.path.path\0"),
+        PKCS15_FILE_TYPE_DODF => cstru!(b"dataObjects.path.path\0"),
+        PKCS15_FILE_TYPE_AODF => cstru!(b"authObjects.path.path\0"),
+        _ => cstru!(b"noObject\0"),
+    }
+}
+
+/* the ASN.1 type of the entries of an xDF of type idx_0 */
+fn get_arr0<'a>(idx_0: u8) -> &'a CStr {
+    match idx_0 {
+        PKCS15_FILE_TYPE_PRKDF => cstru!(b"PKCS15.PrivateKeyType\0"),
+        PKCS15_FILE_TYPE_PUKDF |
+        PKCS15_FILE_TYPE_PUKDF_TRUSTED => cstru!(b"PKCS15.PublicKeyType\0"),
+        PKCS15_FILE_TYPE_SKDF => cstru!(b"PKCS15.SecretKeyType\0"),
+        PKCS15_FILE_TYPE_CDF |
+        PKCS15_FILE_TYPE_CDF_TRUSTED |
+        PKCS15_FILE_TYPE_CDF_USEFUL => cstru!(b"PKCS15.CertificateType\0"),
+        PKCS15_FILE_TYPE_DODF => cstru!(b"PKCS15.DataType\0"),
+        PKCS15_FILE_TYPE_AODF => cstru!(b"PKCS15.AuthenticationType\0"),
+        _ => cstru!(b"no_type\0"),
+    }
+}
+
+/* the number of CHOICE alternatives of get_arr0 */
+#[allow(clippy::match_same_arms)]
+fn get_size(idx_0: u8) -> u8 {
+    match idx_0 {
+        PKCS15_FILE_TYPE_PRKDF          => 2,
+        PKCS15_FILE_TYPE_PUKDF       |
+        PKCS15_FILE_TYPE_PUKDF_TRUSTED  => 2,
+        PKCS15_FILE_TYPE_SKDF           => 4,
+        PKCS15_FILE_TYPE_CDF         |
+        PKCS15_FILE_TYPE_CDF_TRUSTED |
+        PKCS15_FILE_TYPE_CDF_USEFUL     => 1,
+        PKCS15_FILE_TYPE_DODF           => 1,
+        PKCS15_FILE_TYPE_AODF           => 3,
+        _                               => 1,
+    }
+}
+
+/* the path of the object referred to by the CHOICE alternative idx_1 of an xDF entry of type idx_0 */
+fn get_arr1<'a>(idx_0: u8, idx_1: u8) -> &'a CStr {
+    match (idx_0, idx_1) {
+        (PKCS15_FILE_TYPE_PRKDF, 0) => cstru!(b"privateRSAKey.privateRSAKeyAttributes.value.indirect.path.path\0"),
+        (PKCS15_FILE_TYPE_PRKDF, 1) => cstru!(b"privateECKey.privateECKeyAttributes.value.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_PUKDF, 0) |
+        (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 0) => cstru!(b"publicRSAKey.publicRSAKeyAttributes.value.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_PUKDF, 1) |
+        (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 1) => cstru!(b"publicECKey.publicECKeyAttributes.value.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_SKDF, 0) => cstru!(b"genericSecretKey.genericSecretKeyAttributes.value.indirect.path.path\0"),
+        (PKCS15_FILE_TYPE_SKDF, 1) => cstru!(b"desKey.genericSecretKeyAttributes.value.indirect.path.path\0"),
+        (PKCS15_FILE_TYPE_SKDF, 2) => cstru!(b"des2Key.genericSecretKeyAttributes.value.indirect.path.path\0"),
+        (PKCS15_FILE_TYPE_SKDF, 3) => cstru!(b"des3Key.genericSecretKeyAttributes.value.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_CDF, 0) |
+        (PKCS15_FILE_TYPE_CDF_TRUSTED, 0) |
+        (PKCS15_FILE_TYPE_CDF_USEFUL, 0)  => cstru!(b"x509Certificate.x509CertificateAttributes.value.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_DODF, 0) => cstru!(b"opaqueDO.opaque.indirect.path.path\0"),
+
+        (PKCS15_FILE_TYPE_AODF, 0) => cstru!(b"pinAuthObj.pinAttributes.path.path\0"),
+        (PKCS15_FILE_TYPE_AODF, 1) => cstru!(b"biometricAuthObj.biometricAttributes.path.path\0"),
+        (PKCS15_FILE_TYPE_AODF, 2) => cstru!(b"authKeyAuthObj.authKeyAttributes.authKeyId\0"), // TODO the buffer [0_u8; SC_MAX_PATH_SIZE] may be too small, needed: [0_u8; 255]
+        (_, _) => cstru!(b"no_path\0"),
+    }
+}
+
 #[allow(dead_code)]
 #[allow(non_snake_case)]
 pub fn analyze_PKCS15_PKCS15Objects_5031(card: &mut sc_card) {
@@ -332,21 +417,6 @@ pub fn analyze_PKCS15_PKCS15Objects_5031(card: &mut sc_card) {
     PKCS15_FILE_TYPE_ODF are set but file(s) not yet checked
  */
 
-    fn get_arr<'a>(idx: u8) -> &'a CStr {
-        match idx {
-            PKCS15_FILE_TYPE_PRKDF => cstru!(b"privateKeys.path.path\0"),
-            PKCS15_FILE_TYPE_PUKDF => cstru!(b"publicKeys.path.path\0"),
-            PKCS15_FILE_TYPE_PUKDF_TRUSTED => cstru!(b"trustedPublicKeys.path.path\0"),
-            PKCS15_FILE_TYPE_SKDF => cstru!(b"secretKeys.path.path\0"),
-            PKCS15_FILE_TYPE_CDF => cstru!(b"certificates.path.path\0"),
-            PKCS15_FILE_TYPE_CDF_TRUSTED => cstru!(b"trustedCertificates.path.path\0"),
-            PKCS15_FILE_TYPE_CDF_USEFUL => cstru!(b"usefulCertificates.path.path\0"),
-            PKCS15_FILE_TYPE_DODF => cstru!(b"dataObjects.path.path\0"),
-            PKCS15_FILE_TYPE_AODF => cstru!(b"authObjects.path.path\0"),
-            _ => cstru!(b"noObject\0"),
-        }
-    }
-
     #[derive(Debug, Eq, PartialEq)]
     struct FidPath<'a>(u16, &'a [u8]);
 
@@ -449,11 +519,21 @@ pub fn analyze_PKCS15_PKCS15Objects_5031(card: &mut sc_card) {
     Box::leak(dp);
     // card.drv_data = Box::into_raw(dp) as p_void;
 
+    /* cache the attributes of the AODFs' PIN objects for pin_set_policy */
+    let mut pin_attributes = Vec::new();
+    for &FidPkcs15Type(fid, pkcs15_type) in &vec_FidPkcs15Type {
+        if pkcs15_type == PKCS15_FILE_TYPE_AODF {
+            if let Ok(content) = read_file_content(card, fid) {
+                pin_attributes.extend(aodf_pin_attributes(&content).into_iter().map(|(reference, attrs)| (fid, reference, attrs)));
+            }
+        }
+    }
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
     for FidPkcs15Type(fid, pkcs15_type) in &vec_FidPkcs15Type {
         let mut dp_files_value = dp.files.get_mut(fid).unwrap();
         dp_files_value.1[6] = *pkcs15_type;
     }
+    dp.pin_attributes = pin_attributes;
     card.drv_data = Box::into_raw(dp).cast::<c_void>();
     for elem in vec_FidPkcs15Type {
         if does_pkcs15type_need_filemarking(elem.1) {
@@ -483,36 +563,7 @@ fn does_pkcs15type_need_filemarking(pkcs15_type: u8) -> bool {
 #[allow(non_snake_case)]
 pub fn analyze_PKCS15_PKCS15Objects(card: &mut sc_card, elem: FidPkcs15Type) {
 
-    fn get_arr0<'a>(idx_0: u8) -> &'a CStr {
-        match idx_0 {
-            PKCS15_FILE_TYPE_PRKDF => cstru!(b"PKCS15.PrivateKeyType\0"),
-            PKCS15_FILE_TYPE_PUKDF |
-            PKCS15_FILE_TYPE_PUKDF_TRUSTED => cstru!(b"PKCS15.PublicKeyType\0"),
-            PKCS15_FILE_TYPE_SKDF => cstru!(b"PKCS15.SecretKeyType\0"),
-            PKCS15_FILE_TYPE_CDF |
-            PKCS15_FILE_TYPE_CDF_TRUSTED |
-            PKCS15_FILE_TYPE_CDF_USEFUL => cstru!(b"PKCS15.CertificateType\0"),
-            PKCS15_FILE_TYPE_DODF => cstru!(b"PKCS15.DataType\0"),
-            PKCS15_FILE_TYPE_AODF => cstru!(b"PKCS15.AuthenticationType\0"),
-            _ => cstru!(b"no_type\0"),
-        }
-    }
 
-    #[allow(clippy::match_same_arms)]
-    fn get_size(idx_0: u8) -> u8 {
-        match idx_0 {
-            PKCS15_FILE_TYPE_PRKDF          => 2,
-            PKCS15_FILE_TYPE_PUKDF       |
-            PKCS15_FILE_TYPE_PUKDF_TRUSTED  => 2,
-            PKCS15_FILE_TYPE_SKDF           => 4,
-            PKCS15_FILE_TYPE_CDF         |
-            PKCS15_FILE_TYPE_CDF_TRUSTED |
-            PKCS15_FILE_TYPE_CDF_USEFUL     => 1,
-            PKCS15_FILE_TYPE_DODF           => 1,
-            PKCS15_FILE_TYPE_AODF           => 3,
-            _                               => 1,
-        }
-    }
 
     fn get_type(idx_0: u8, idx_1: u8) -> u8 {
         match (idx_0, idx_1) {
@@ -542,34 +593,6 @@ pub fn analyze_PKCS15_PKCS15Objects(card: &mut sc_card, elem: FidPkcs15Type) {
         }
     }
 
-fn get_arr1<'a>(idx_0: u8, idx_1: u8) -> &'a CStr {
-    match (idx_0, idx_1) {
-        (PKCS15_FILE_TYPE_PRKDF, 0) => cstru!(b"privateRSAKey.privateRSAKeyAttributes.value.indirect.path.path\0"),
-        (PKCS15_FILE_TYPE_PRKDF, 1) => cstru!(b"privateECKey.privateECKeyAttributes.value.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_PUKDF, 0) |
-        (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 0) => cstru!(b"publicRSAKey.publicRSAKeyAttributes.value.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_PUKDF, 1) |
-        (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 1) => cstru!(b"publicECKey.publicECKeyAttributes.value.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_SKDF, 0) => cstru!(b"genericSecretKey.genericSecretKeyAttributes.value.indirect.path.path\0"),
-        (PKCS15_FILE_TYPE_SKDF, 1) => cstru!(b"desKey.genericSecretKeyAttributes.value.indirect.path.path\0"),
-        (PKCS15_FILE_TYPE_SKDF, 2) => cstru!(b"des2Key.genericSecretKeyAttributes.value.indirect.path.path\0"),
-        (PKCS15_FILE_TYPE_SKDF, 3) => cstru!(b"des3Key.genericSecretKeyAttributes.value.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_CDF, 0) |
-        (PKCS15_FILE_TYPE_CDF_TRUSTED, 0) |
-        (PKCS15_FILE_TYPE_CDF_USEFUL, 0)  => cstru!(b"x509Certificate.x509CertificateAttributes.value.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_DODF, 0) => cstru!(b"opaqueDO.opaque.indirect.path.path\0"),
-
-        (PKCS15_FILE_TYPE_AODF, 0) => cstru!(b"pinAuthObj.pinAttributes.path.path\0"),
-        (PKCS15_FILE_TYPE_AODF, 1) => cstru!(b"biometricAuthObj.biometricAttributes.path.path\0"),
-        (PKCS15_FILE_TYPE_AODF, 2) => cstru!(b"authKeyAuthObj.authKeyAttributes.authKeyId\0"), // TODO the buffer [0_u8; SC_MAX_PATH_SIZE] may be too small, needed: [0_u8; 255]
-        (_, _) => cstru!(b"no_path\0"),
-    }
-}
 
 //println!("elem: FidPkcs15Type: {:X?}", elem);
     let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
@@ -624,148 +647,470 @@ fn get_arr1<'a>(idx_0: u8, idx_1: u8) -> &'a CStr {
     }
     Box::leak(dp);
 //println!("vec_FidPkcs15Type: {:X?}", vec_FidPkcs15Type);
+    /* cache the attributes of the AODFs' PIN objects for pin_set_policy */
+    let mut pin_attributes = Vec::new();
+    for &FidPkcs15Type(fid, pkcs15_type) in &vec_FidPkcs15Type {
+        if pkcs15_type == PKCS15_FILE_TYPE_AODF {
+            if let Ok(content) = read_file_content(card, fid) {
+                pin_attributes.extend(aodf_pin_attributes(&content).into_iter().map(|(reference, attrs)| (fid, reference, attrs)));
+            }
+        }
+    }
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
     for FidPkcs15Type(fid, pkcs15_type) in &vec_FidPkcs15Type {
         let mut dp_files_value = dp.files.get_mut(fid).unwrap();
         dp_files_value.1[6] = *pkcs15_type;
     }
+    dp.pin_attributes = pin_attributes;
     card.drv_data = Box::into_raw(dp).cast::<c_void>();
 }
 
-/*
-// [ "EF(TokenInfo)",    "PKCS15.TokenInfo",          "",                              "PKCS15.TokenInfoChoice", "tokenInfo"],
-#[allow(dead_code)]
+/* Reads the content of a transparent EF known to the hashmap */
+fn read_file_content(card: &mut sc_card, file_id: u16) -> Result<Vec<u8>, i32> {
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let (path_value, path_len, size) = match dp.files.get(&file_id) {
+        Some(val) => (val.0, usize::from(val.1[1]), usize::from(file_id_se(val.1))),
+        None => { Box::leak(dp); return Err(SC_ERROR_FILE_NOT_FOUND); },
+    };
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    let mut path = sc_path::default();
+    unsafe { sc_path_set(&mut path, SC_PATH_TYPE_PATH, path_value.as_ptr(), path_len, 0, -1) };
+    let rv = unsafe { sc_select_file(card, &path, null_mut()) };
+    if rv != SC_SUCCESS { return Err(rv); }
+    let mut rbuf = vec![0_u8; size];
+    let rv = unsafe { sc_read_binary(card, 0, rbuf.as_mut_ptr(), rbuf.len(), 0) };
+    if rv <= 0 { return Err(if rv < 0 {rv} else {SC_ERROR_FILE_NOT_FOUND}); }
+    rbuf.truncate(usize::try_from(rv).unwrap());
+    Ok(rbuf)
+}
+
+/* DER-decodes der as ASN.1 type type_name of the PKCS#15 definitions and hands the structure to f */
+fn with_decoded<R, F: FnOnce(asn1_node) -> R>(pkcs15_definitions: asn1_node, type_name: &CStr, der: &[u8], f: F) -> Result<R, String> {
+    let mut structure : asn1_node = null_mut();
+    let guard_structure = GuardAsn1Node::new(&mut structure);
+    let mut asn1_result = unsafe { asn1_create_element(pkcs15_definitions, type_name.as_ptr(), *guard_structure) };
+    if ASN1_SUCCESS != asn1_result.try_into().unwrap() {
+        return Err(format!("Error in structure creation: {}", unsafe { CStr::from_ptr(asn1_strerror(asn1_result)) }.to_string_lossy()));
+    }
+    let mut error_description = [0x00 as c_char; 129];
+    asn1_result = unsafe { asn1_der_decoding(*guard_structure, der.as_ptr().cast::<c_void>(),
+                                             der.len().try_into().unwrap(), error_description.as_mut_ptr()) };
+    if ASN1_SUCCESS != asn1_result.try_into().unwrap() {
+        return Err(format!("{} ({})", unsafe { CStr::from_ptr(asn1_strerror(asn1_result)) }.to_string_lossy(),
+                           unsafe { CStr::from_ptr(error_description.as_ptr()) }.to_string_lossy()));
+    }
+    Ok(f(structure))
+}
+
+/* Reads the value of an element of a decoded structure, None if it's absent */
+fn read_value(structure: asn1_node, name: &CStr) -> Option<Vec<u8>> {
+    let mut buf = vec![0_u8; 256];
+    let mut outlen = i32::try_from(buf.len()).unwrap();
+    let asn1_result = unsafe { asn1_read_value(structure, name.as_ptr(), buf.as_mut_ptr().cast::<c_void>(), &mut outlen) };
+    if ASN1_SUCCESS != asn1_result.try_into().unwrap() { return None; }
+    buf.truncate(usize::try_from(outlen).unwrap());
+    Some(buf)
+}
+
+/* INTEGER value (big endian two's complement) as i64 */
+fn integer_value(value: &[u8]) -> i64 {
+    value.iter().fold(if !value.is_empty() && value[0] >= 0x80 {-1} else {0}, |acc, &b| (acc << 8) | i64::from(b))
+}
+
+/* the FDB of the file, that the CHOICE alternative idx_1 of an xDF entry of type idx_0 must refer to */
+fn expected_fdb(idx_0: u8, idx_1: u8) -> u8 {
+    match (idx_0, idx_1) {
+        (PKCS15_FILE_TYPE_PRKDF, 0) | (PKCS15_FILE_TYPE_PUKDF, 0) | (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 0) => FDB_RSA_KEY_EF,
+        (PKCS15_FILE_TYPE_PRKDF, 1) | (PKCS15_FILE_TYPE_PUKDF, 1) | (PKCS15_FILE_TYPE_PUKDF_TRUSTED, 1) => FDB_ECC_KEY_EF,
+        (PKCS15_FILE_TYPE_SKDF, _) => FDB_SYMMETRIC_KEY_EF,
+        _ => FDB_TRANSPARENT_EF,
+    }
+}
+
+fn xdf_name(pkcs15_type: u8) -> &'static str {
+    match pkcs15_type {
+        PKCS15_FILE_TYPE_PRKDF => "PrKDF",
+        PKCS15_FILE_TYPE_PUKDF => "PuKDF",
+        PKCS15_FILE_TYPE_PUKDF_TRUSTED => "PuKDF (trusted)",
+        PKCS15_FILE_TYPE_SKDF => "SKDF",
+        PKCS15_FILE_TYPE_CDF => "CDF",
+        PKCS15_FILE_TYPE_CDF_TRUSTED => "CDF (trusted)",
+        PKCS15_FILE_TYPE_CDF_USEFUL => "CDF (useful)",
+        PKCS15_FILE_TYPE_DODF => "DODF",
+        PKCS15_FILE_TYPE_AODF => "AODF",
+        _ => "unknown xDF",
+    }
+}
+
+fn finding(severity: u8, check_id: u8, file_id: u16, message: String, suggested_fix: &str) -> Finding {
+    Finding { severity, check_id, file_id, message, suggested_fix: suggested_fix.to_string() }
+}
+
+/* The absolute path of a PKCS#15 path: One not starting with the MF is relative to the appDF at path_app */
+fn path_absolute(path: &[u8], path_app: &[u8]) -> Vec<u8> {
+    if path.starts_with(&[0x3F, 0]) { path.to_vec() } else { [path_app, path].concat() }
+}
+
+/* The file id of path (relative to path_app, see path_absolute), if it's a file of the hashmap with that path */
+fn file_id_of_path(files: &HashMap<KeyTypeFiles, ValueTypeFiles>, path: &[u8], path_app: &[u8]) -> Option<u16> {
+    let path = path_absolute(path, path_app);
+    if path.len() < 2 || path.len() > SC_MAX_PATH_SIZE { return None; }
+    let file_id = file_id_from_path_value(&path);
+    match files.get(&file_id) {
+        Some(val) if val.0[..usize::from(val.1[1])] == path[..] => Some(file_id),
+        _ => None,
+    }
+}
+
+/* Whether an xDF entry with path refers to a file id, that doesn't exist at all; a mismatching path only is reported,
+   but such an entry doesn't get removed by the sanity repair */
+fn is_dangling(files: &HashMap<KeyTypeFiles, ValueTypeFiles>, path: &[u8]) -> bool {
+    path.len() >= 2 && !files.contains_key(&file_id_from_path_value(path))
+}
+
+/* The path of a file of the hashmap */
+fn path_of_file_id(files: &HashMap<KeyTypeFiles, ValueTypeFiles>, file_id: u16) -> Vec<u8> {
+    files.get(&file_id).map_or_else(Vec::new, |val| val.0[..usize::from(val.1[1])].to_vec())
+}
+
+/// Validates the PKCS#15 structure (EF.DIR, appDF(s), EF.ODF, EF.TokenInfo and key directory files) against the
+/// PKCS#15 ASN.1 definitions and the file system; for asymmetric keys, the key reference is the file itself,
+/// for secret keys, the key reference is a record of the sym. key file
 #[allow(non_snake_case)]
-pub fn analyze_PKCS15_TokenInfo_5032(card: &mut sc_card) {
-    /* This relies on function analyze_PKCS15_DIRRecord_2F00 having marked as PKCS15_FILE_TYPE_ODF
-       any EF.ODF specified by EF.DIR, so we can iterate over hashmah dp.files and search byte dp_files_value.1[6]
-        [ "EF(ODF)",          "PKCS15.PKCS15Objects",      "",                              "PKCS15.PKCS15ObjectsChoice", "pkcs15Objects"],
-        PKCS15_FILE_TYPE_ODF are set but file(s) not yet checked
-     */
-    #[derive(Debug, Eq, PartialEq)]
-    struct FidPath<'a>(u16, &'a [u8]);
-    // #[derive(Debug, Eq, PartialEq)]
-    // struct FidPkcs15Type(u16, u8);
+pub fn validate_PKCS15(card: &mut sc_card) -> Vec<Finding> {
+    validate_PKCS15_collect(card, &mut Vec::new())
+}
 
-    let mut vec_appdf : Vec<FidPath> = Vec::with_capacity(4);
-    // let mut vec_FidPkcs15Type : Vec<FidPkcs15Type> = Vec::with_capacity(9);
+/// The PrKDF/PuKDF/SKDF entries, whose key file id doesn't exist: (file id of xDF, byte range of the entry)
+#[must_use]
+pub fn dangling_xdf_entries(card: &mut sc_card) -> Vec<(u16, Range<usize>)> {
+    let mut dangling = Vec::new();
+    validate_PKCS15_collect(card, &mut dangling);
+    dangling
+}
+
+/* The content without the byte ranges (non-overlapping, any order), zero-padded to the original length */
+fn remove_ranges(content: &[u8], ranges: &[Range<usize>]) -> Vec<u8> {
+    let mut result : Vec<u8> = content.iter().enumerate().filter(|(i, _)| !ranges.iter().any(|range| range.contains(i)))
+        .map(|(_, &b)| b).collect();
+    result.resize(content.len(), 0);
+    result
+}
+
+/**
+ * Removes entries from a transparent xDF: The entries behind move forward, the space freed at the end gets zeroized
+ * @param   card         INOUT
+ * @param   file_id_xdf  IN    the xDF
+ * @param   ranges       IN    the entries' byte ranges, as reported by dangling_xdf_entries
+ * @return  SC_SUCCESS or an error
+ */
+#[allow(clippy::missing_errors_doc)]
+pub fn remove_xdf_entries(card: &mut sc_card, file_id_xdf: u16, ranges: &[Range<usize>]) -> Result<(), i32> {
+    let content = read_file_content(card, file_id_xdf)?; // this selected the xDF
+    if ranges.iter().any(|range| range.end > content.len()) {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    let content = remove_ranges(&content, ranges);
+    let rv = unsafe { sc_update_binary(card, 0, content.as_ptr(), content.len(), 0) };
+    if rv < 0 { Err(rv) } else { Ok(()) }
+}
 
+#[allow(non_snake_case)]
+#[allow(clippy::too_many_lines)]
+fn validate_PKCS15_collect(card: &mut sc_card, dangling: &mut Vec<(u16, Range<usize>)>) -> Vec<Finding> {
+    struct AppInfo {
+        file_id_app : u16,
+        file_id_odf : u16,
+        file_id_tokeninfo : u16,
+    }
+    let mut result = Vec::with_capacity(8);
     let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
     let pkcs15_definitions = dp.pkcs15_definitions;
-    if pkcs15_definitions.is_null() { Box::leak(dp); return; }
-    // let fmt1  = cstru!(b"key: %04X, val.1: %s\0");
-    for (&key, val) in &dp.files {
-        if val.1[6] == PKCS15_FILE_TYPE_APPDF {
-            vec_appdf.push(FidPath(key, &val.0[..val.1[1].into()]));
-            // log3if!(ctx,f,line!(), fmt1, *key, unsafe { sc_dump_hex(val.1.as_ptr(), 8) });
+    let is_dir_okay = file_id_of_path(&dp.files, &[0x3F, 0, 0x2F, 0], &[0x3F, 0]).map(|file_id| dp.files[&file_id].1[0]) == Some(FDB_TRANSPARENT_EF);
+    Box::leak(dp);
+    // card.drv_data = Box::into_raw(dp) as p_void;
+    if pkcs15_definitions.is_null() {
+        result.push(finding(SANITY_SEVERITY_WARNING, SANITY_CHECK_PKCS15_DIR, 0,
+            "The PKCS#15 ASN.1 definitions are not available: PKCS#15 structure not validated".to_string(), ""));
+        return result;
+    }
+    if !is_dir_okay {
+        result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+            "EF.DIR 3F002F00 doesn't exist or is no transparent EF".to_string(), "Create EF.DIR 3F002F00 with a DIRRecord for each appDF"));
+        return result;
+    }
+
+    /* EF.DIR */
+    let rbuf = match read_file_content(card, 0x2F00) {
+        Ok(rbuf) => rbuf,
+        Err(e) => {
+            result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+                format!("EF.DIR 3F002F00 is not readable (error {})", e), "Make EF.DIR always readable"));
+            return result;
+        },
+    };
+    let mut vec_app_info = Vec::with_capacity(2);
+    for (idx, range) in DirectoryRange::new(&rbuf).enumerate() {
+        let decoded = with_decoded(pkcs15_definitions, cstru!(b"PKCS15.DIRRecord\0"), &rbuf[range], |structure|
+            [cstru!(b"aid\0"), cstru!(b"path\0"), cstru!(b"label\0"), cstru!(b"ddo.odfPath.path\0"), cstru!(b"ddo.tokenInfoPath.path\0")]
+                .iter().map(|name| read_value(structure, name)).collect::<Vec<Option<Vec<u8>>>>() );
+        let values = match decoded {
+            Ok(values) => values,
+            Err(e) => {
+                result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+                    format!("EF.DIR record no. {} doesn't decode as PKCS15.DIRRecord: {}", idx+1, e), "Rewrite the EF.DIR record"));
+                continue;
+            },
+        };
+        let aid = values[0].clone().unwrap_or_default();
+        let path_app = values[1].clone().unwrap_or_default();
+        if aid.is_empty() {
+            result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+                format!("EF.DIR record no. {} has no aid", idx+1), "Add the appDF's aid to the EF.DIR record"));
+        }
+        let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+        let file_id_app = file_id_of_path(&dp.files, &path_app, &[0x3F, 0]).filter(|file_id| is_DFMF(dp.files[file_id].1[0]));
+        let file_id_app = match file_id_app {
+            Some(file_id_app) => file_id_app,
+            None => {
+                Box::leak(dp);
+                result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+                    format!("EF.DIR record no. {}: The appDF path {:02X?} doesn't exist", idx+1, path_app), "Correct the path in the EF.DIR record"));
+                continue;
+            },
+        };
+        result.push(finding(SANITY_SEVERITY_INFO, SANITY_CHECK_PKCS15_DIR, file_id_app,
+            format!("appDF {:04X}: aid {:02X?} ({}), label '{}'", file_id_app, aid, String::from_utf8_lossy(&aid),
+                String::from_utf8_lossy(&values[2].clone().unwrap_or_default())), ""));
+        /* EF.ODF and EF.TokenInfo: as specified or the default 5031 and 5032 within the appDF */
+        let mut file_ids = [0_u16; 2];
+        for (i, (what, default)) in [("EF.ODF", 0x5031_u16), ("EF.TokenInfo", 0x5032_u16)].iter().enumerate() {
+            let file_id = match &values[3+i] {
+                Some(path) => file_id_of_path(&dp.files, path, &path_of_file_id(&dp.files, file_id_app)),
+                None => Some(*default).filter(|file_id| dp.files.contains_key(file_id)),
+            };
+            match file_id {
+                Some(file_id) if is_child_of(&dp.files[&file_id], &dp.files[&file_id_app]) && dp.files[&file_id].1[0] == FDB_TRANSPARENT_EF =>
+                    file_ids[i] = file_id,
+                _ => result.push(finding(SANITY_SEVERITY_ERROR, if i==0 {SANITY_CHECK_PKCS15_ODF} else {SANITY_CHECK_PKCS15_TOKENINFO}, file_id_app,
+                    format!("appDF {:04X}: {} doesn't exist within the appDF or is no transparent EF", file_id_app, what),
+                    &format!("Create {} as transparent EF within the appDF", what))),
+            }
         }
+        Box::leak(dp);
+        // card.drv_data = Box::into_raw(dp) as p_void;
+        vec_app_info.push(AppInfo { file_id_app, file_id_odf: file_ids[0], file_id_tokeninfo: file_ids[1] });
+    }
+    if vec_app_info.is_empty() {
+        result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_DIR, 0x2F00,
+            "EF.DIR doesn't specify any existing appDF".to_string(), "Add a DIRRecord with aid and path of the appDF to EF.DIR"));
     }
-    println!("{} PKCS#15 application(s) is/are specified. File ids:  {:X?}", vec_appdf.len(), vec_appdf);
 
-    for FidPath(fid, path_app) in vec_appdf {
-        println!("fid:  {:X}", fid);
-        println!("len: {}",  path_app.len());
-        println!("path_app: {:X?}", path_app);
-        for (_, val) in &dp.files {
-            if val.1[6] == PKCS15_FILE_TYPE_TOKENINFO && path_app.len()+2 == val.1[1].into() && path_app == &val.0[..path_app.len()] {
-                println!("dp.files entry with PKCS15_FILE_TYPE_TOKENINFO:  {:X?}", val);
-                /* this will mark the file 3F002F00 as PKCS15_FILE_TYPE_DIR (after check that it contains valid data for "PKCS15.DIRRecord")
-                   and mark the appDF as PKCS15_FILE_TYPE_APPDF (appDF=="path" extracted from EF.DIR)
-            result from asn1_check_version: "4.13"
-            ready to inspect
-            path of application directory: 0x[3F, 0, 41, 0], fid: 0x4100
-            aid of application directory: 0x[41, 43, 4F, 53, 50, 4B, 43, 53, 2D, 31, 35, 76, 31, 2E, 30, 30]
-            aid of application directory: ACOSPKCS-15v1.00
-            label of application directory: eCert
-                */
-                    let dp_files_value = &dp.files[&0x5032];
-                    let size: usize = file_id_se(dp_files_value.1).into();
-
-                    let mut path_5032 = sc_path::default();
-                    unsafe { sc_format_path(cstru!(b"3F0041005032\0").as_ptr(), &mut path_5032); } // type = SC_PATH_TYPE_PATH;
-                    unsafe { sc_select_file(card, &path_5032, null_mut()) };
-                    let mut rbuf = vec![0_u8; size];
-                    let rv = unsafe { sc_read_binary(card, 0, rbuf.as_mut_ptr(), rbuf.len(), 0) };
-                    assert!(rv>0);
-
-                    // this "loops" only once !
-                    for range in DirectoryRange::new(&rbuf[..rv.try_into().unwrap()]) {
-                        let mut structure = null_mut();
-                        let guard_structure = GuardAsn1Node::new(&mut structure);
-                        let source_name = cstru!(b"PKCS15.TokenInfo\0");
-                        let mut asn1_result = unsafe { asn1_create_element(pkcs15_definitions, source_name.as_ptr(), *guard_structure) };
-                        if ASN1_SUCCESS != asn1_result.try_into().unwrap() {
-                            println!("### Error in structure creation: {:?}", unsafe { CStr::from_ptr(asn1_strerror(asn1_result)) });
-                        }
-                        else {
-                            let rbuf2 = &rbuf[range];
-// println!("range: {:?}, rbuf2: {:X?}", range, rbuf2);
-                            let mut error_description = [0x00 as c_char; 129];
-                            asn1_result = unsafe { asn1_der_decoding(*guard_structure, rbuf2.as_ptr() as *const c_void,
-                                rbuf2.len().try_into().unwrap(), error_description.as_mut_ptr()) };
-
-                            if ASN1_SUCCESS != asn1_result.try_into().unwrap() {
-                                println!("Not ready to inspect");
-                                let c_str = unsafe { CStr::from_ptr(error_description.as_ptr()) };
-                                println!("asn1_result (asn1_der_decoding): {}, error_description: {:?}", asn1_result, c_str);
+    for app_info in vec_app_info {
+        /* EF.TokenInfo */
+        if app_info.file_id_tokeninfo != 0 {
+            let file_id = app_info.file_id_tokeninfo;
+            match read_file_content(card, file_id) {
+                Err(e) => result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_TOKENINFO, file_id,
+                    format!("EF.TokenInfo {:04X} is not readable (error {})", file_id, e), "Make EF.TokenInfo always readable")),
+                Ok(rbuf) => {
+                    let der = DirectoryRange::new(&rbuf).next().map_or(&rbuf[..0], |range| &rbuf[range]);
+                    match with_decoded(pkcs15_definitions, cstru!(b"PKCS15.TokenInfo\0"), der, |structure|
+                        (read_value(structure, cstru!(b"version\0")), read_value(structure, cstru!(b"label\0")))) {
+                        Err(e) => result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_TOKENINFO, file_id,
+                            format!("EF.TokenInfo {:04X} doesn't decode as PKCS15.TokenInfo: {}", file_id, e),
+                            "Rewrite EF.TokenInfo, see info/card_initialization")),
+                        Ok((version, label)) => {
+                            let version = version.map_or(-1, |v| integer_value(&v));
+                            if ![0, 1].contains(&version) {
+                                result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_TOKENINFO, file_id,
+                                    format!("EF.TokenInfo {:04X} has an invalid version {}", file_id, version), "Set version to v1 (0) or v2 (1)"));
                             }
-                            else {
-
-                                // for type_ in PKCS15_FILE_TYPE_PRKDF..=PKCS15_FILE_TYPE_AODF {
-                                //     println!("ready to inspect");
-                                //     println!("ready to inspect");
-                                let name = cstru!(b"label\0"); // UTF8String
-                                    let mut buf_str = [0_u8; 64];
-                                    let mut outlen = buf_str.len() as c_int;
-                                    asn1_result = unsafe { asn1_read_value(structure, name.as_ptr(),
-                                                               buf_str.as_mut_ptr() as *mut c_void, &mut outlen) };
-                                    if ASN1_SUCCESS != asn1_result.try_into().unwrap() {
-                                        println!("asn1_result (asn1_read_value  label): {}, error_description: {:?}", asn1_result, unsafe { CStr::from_ptr(asn1_strerror(asn1_result)) });
-                                    }
-                                    else {
-                                        let outlen = usize::try_from(outlen).unwrap();
-                                        // let buf_slice = &buf_str[..outlen];
-                                        // let mut file_id = 0;
-                                        // if outlen >= 2 {
-                                        //     file_id = file_id_from_path_value(buf_slice);
-                                        // }
-                                        // println!("buf_str of EF(TokenInfo): 0x{:X?}", buf_slice);
-                                        let x = &buf_str[..outlen.try_into().unwrap()];
-                                        println!("label EF(TokenInfo): {}", String::from_utf8(x.to_vec()).unwrap_or_default());
-                                        // let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
-                                        // if file_id > 0 {
-                                        //     let dp_files_value = dp.files.get(&file_id).unwrap();
-                                        //     let path_obj = &dp_files_value.0[0..dp_files_value.1[1].into()];
-                                        //     assert_eq!(buf_slice, path_obj);
-                                        //     // dp_files_value.1[6] = type_;
-                                        //     // struct FidPkcs15Type(u16, u8);
-                                        //     // vec_FidPkcs15Type.push(FidPkcs15Type(file_id, type_));// : Vec<FidPkcs15Type> = Vec::with_capacity(9);
-                                        // }
-                                        break;
-                                    } // if ASN1_SUCCESS for asn1_read_value
-                                // } // for type_ in PKCS15_FILE_TYPE_PRKDF..=PKCS15_FILE_TYPE_AODF
-                            } // if ASN1_SUCCESS for asn1_der_decoding
-                        } // if ASN1_SUCCESS for asn1_create_element
-                    } // for range in DirectoryRange
-// println!("One more drop GuardAsn1Node will follow ...");
-            } //  if val.1[6] == PKCS15_FILE_TYPE_ODF // for each PKCS15_FILE_TYPE_ODF
-        } // for (_, val) in &dp.files
-    } // for FidPath(fid, path_app) in vec_appdf      // for each application
-    card.drv_data = Box::into_raw(dp).cast::<c_void>();
-/*
-    let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
-    for FidPkcs15Type(fid, pkcs15_type) in vec_FidPkcs15Type {
-        let mut dp_files_value = dp.files.get_mut(&fid).unwrap();
-        dp_files_value.1[6] = pkcs15_type;
+                            result.push(finding(SANITY_SEVERITY_INFO, SANITY_CHECK_PKCS15_TOKENINFO, file_id,
+                                format!("EF.TokenInfo {:04X}: version v{}, label '{}'", file_id, version+1,
+                                    String::from_utf8_lossy(&label.unwrap_or_default())), ""));
+                        },
+                    }
+                },
+            }
+        }
+
+        /* EF.ODF */
+        if app_info.file_id_odf == 0 { continue; }
+        let file_id_odf = app_info.file_id_odf;
+        let rbuf = match read_file_content(card, file_id_odf) {
+            Ok(rbuf) => rbuf,
+            Err(e) => {
+                result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_ODF, file_id_odf,
+                    format!("EF.ODF {:04X} is not readable (error {})", file_id_odf, e), "Make EF.ODF always readable"));
+                continue;
+            },
+        };
+        let mut vec_xdf = Vec::with_capacity(9);
+        for (idx, range) in DirectoryRange::new(&rbuf).enumerate() {
+            let decoded = with_decoded(pkcs15_definitions, cstru!(b"PKCS15.PKCS15Objects\0"), &rbuf[range], |structure|
+                (PKCS15_FILE_TYPE_PRKDF..=PKCS15_FILE_TYPE_AODF).find_map(|type_|
+                    read_value(structure, get_arr(type_)).map(|path| (type_, path))) );
+            let (type_, path) = match decoded {
+                Ok(Some(val)) => val,
+                Ok(None) => {
+                    result.push(finding(SANITY_SEVERITY_WARNING, SANITY_CHECK_PKCS15_ODF, file_id_odf,
+                        format!("EF.ODF {:04X} entry no. {} doesn't refer to an xDF by path", file_id_odf, idx+1), ""));
+                    continue;
+                },
+                Err(e) => {
+                    result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_ODF, file_id_odf,
+                        format!("EF.ODF {:04X} entry no. {} doesn't decode as PKCS15.PKCS15Objects: {}", file_id_odf, idx+1, e),
+                        "Rewrite the EF.ODF entry"));
+                    continue;
+                },
+            };
+            let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+            match file_id_of_path(&dp.files, &path, &path_of_file_id(&dp.files, app_info.file_id_app)) {
+                Some(file_id) if dp.files[&file_id].1[0] == FDB_TRANSPARENT_EF => {
+                    if !is_child_of(&dp.files[&file_id], &dp.files[&app_info.file_id_app]) {
+                        result.push(finding(SANITY_SEVERITY_WARNING, SANITY_CHECK_PKCS15_ODF, file_id,
+                            format!("EF.ODF {:04X}: The {} {:04X} is not located within the appDF {:04X}", file_id_odf, xdf_name(type_),
+                                file_id, app_info.file_id_app), "Move the xDF into the appDF"));
+                    }
+                    vec_xdf.push((file_id, type_));
+                },
+                _ => result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_ODF, file_id_odf,
+                    format!("EF.ODF {:04X}: The {} path {:02X?} doesn't exist or is no transparent EF", file_id_odf, xdf_name(type_), path),
+                    "Correct the path in EF.ODF or create the xDF")),
+            }
+            Box::leak(dp);
+            // card.drv_data = Box::into_raw(dp) as p_void;
+        }
+
+        /* PrKDF, PuKDF, SKDF: the keys referred to */
+        for (file_id_xdf, type_) in vec_xdf {
+            if ![PKCS15_FILE_TYPE_PRKDF, PKCS15_FILE_TYPE_PUKDF, PKCS15_FILE_TYPE_PUKDF_TRUSTED, PKCS15_FILE_TYPE_SKDF].contains(&type_) {
+                continue;
+            }
+            let rbuf = match read_file_content(card, file_id_xdf) {
+                Ok(rbuf) => rbuf,
+                Err(e) => {
+                    result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                        format!("{} {:04X} is not readable (error {})", xdf_name(type_), file_id_xdf, e), "Make the xDF readable"));
+                    continue;
+                },
+            };
+            for (idx, range) in DirectoryRange::new(&rbuf).enumerate() {
+                /* (CHOICE alternative, path, keyReference, path index) */
+                let decoded = with_decoded(pkcs15_definitions, get_arr0(type_), &rbuf[range.clone()], |structure|
+                    (0..get_size(type_)).find_map(|idx_1| {
+                        let name_path = get_arr1(type_, idx_1);
+                        read_value(structure, name_path).map(|path| {
+                            let name_path = name_path.to_string_lossy();
+                            let choice = name_path.split('.').next().unwrap_or_default();
+                            let name_key_ref = CString::new(format!("{}.commonKeyAttributes.keyReference", choice)).unwrap();
+                            let name_index = CString::new(format!("{}index", name_path.trim_end_matches("path"))).unwrap();
+                            (idx_1, path, read_value(structure, &name_key_ref), read_value(structure, &name_index))
+                        })
+                    }) );
+                let (idx_1, path, key_ref, index) = match decoded {
+                    Ok(Some(val)) => val,
+                    Ok(None) => continue, // the key value is direct or in an unsupported form
+                    Err(e) => {
+                        result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                            format!("{} {:04X} entry no. {} doesn't decode as {}: {}", xdf_name(type_), file_id_xdf, idx+1,
+                                get_arr0(type_).to_string_lossy(), e), "Rewrite the xDF entry"));
+                        continue;
+                    },
+                };
+                let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+                let fdb = expected_fdb(type_, idx_1);
+                match file_id_of_path(&dp.files, &path, &path_of_file_id(&dp.files, app_info.file_id_app)) {
+                    None => {
+                        result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                            format!("{} {:04X} entry no. {}: The key file path {:02X?} doesn't exist", xdf_name(type_), file_id_xdf, idx+1, path),
+                            "Correct the path in the xDF entry or delete the entry"));
+                        if is_dangling(&dp.files, &path) {
+                            dangling.push((file_id_xdf, range));
+                        }
+                    },
+                    Some(file_id) if dp.files[&file_id].1[0] != fdb => result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                        format!("{} {:04X} entry no. {}: The file {:04X} referred to has FDB 0x{:02X}, expected is 0x{:02X}", xdf_name(type_),
+                            file_id_xdf, idx+1, file_id, dp.files[&file_id].1[0], fdb), "Correct the path in the xDF entry")),
+                    Some(file_id) if type_ == PKCS15_FILE_TYPE_SKDF => {
+                        /* the keyReference is 0x80 | record no. for a local key, or the record no. of MF's sym. key file */
+                        let nor = i64::from(dp.files[&file_id].1[5]);
+                        match key_ref.map(|key_ref| integer_value(&key_ref) & 0x7F) {
+                            None => result.push(finding(SANITY_SEVERITY_WARNING, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                                format!("SKDF {:04X} entry no. {}: There is no keyReference", file_id_xdf, idx+1), "Add the keyReference")),
+                            Some(rec_nr) if rec_nr < 1 || rec_nr > nor => result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                                format!("SKDF {:04X} entry no. {}: The keyReference refers to record no. {}, but the sym. key file {:04X} has {} records",
+                                    file_id_xdf, idx+1, rec_nr, file_id, nor), "Correct the keyReference")),
+                            Some(rec_nr) => if let Some(index) = index.map(|index| integer_value(&index)) {
+                                if index != rec_nr {
+                                    result.push(finding(SANITY_SEVERITY_ERROR, SANITY_CHECK_PKCS15_OBJECTS, file_id_xdf,
+                                        format!("SKDF {:04X} entry no. {}: The keyReference refers to record no. {}, but the path index is {}",
+                                            file_id_xdf, idx+1, rec_nr, index), "Make keyReference and path index consistent"));
+                                }
+                            },
+                        }
+                    },
+                    Some(_) => (),
+                }
+                Box::leak(dp);
+                // card.drv_data = Box::into_raw(dp) as p_void;
+            }
+        }
     }
-    card.drv_data = Box::into_raw(dp).cast::<c_void>();
-*/
+    result
 }
-*/
 
 #[cfg(test)]
 mod tests {
-    use super::{DirectoryRange};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use super::{DirectoryRange, integer_value, expected_fdb, remove_ranges, file_id_of_path, is_dangling};
+    use crate::constants_types::{KeyTypeFiles, ValueTypeFiles, FDB_DF, PKCS15_FILE_TYPE_PRKDF, PKCS15_FILE_TYPE_PUKDF,
+                                 PKCS15_FILE_TYPE_SKDF,
+                                 FDB_RSA_KEY_EF, FDB_ECC_KEY_EF, FDB_SYMMETRIC_KEY_EF};
+
+    #[test]
+    fn test_integer_value_expected_fdb() {
+        assert_eq!(integer_value(&[0x00]), 0);
+        assert_eq!(integer_value(&[0x00, 0x83]), 0x83);
+        assert_eq!(integer_value(&[0x81]), -127);
+        assert_eq!(integer_value(&[]), 0);
+        assert_eq!(expected_fdb(PKCS15_FILE_TYPE_PRKDF, 0), FDB_RSA_KEY_EF);
+        assert_eq!(expected_fdb(PKCS15_FILE_TYPE_PUKDF, 1), FDB_ECC_KEY_EF);
+        assert_eq!(expected_fdb(PKCS15_FILE_TYPE_SKDF, 3), FDB_SYMMETRIC_KEY_EF);
+    }
+
+    #[test]
+    fn test_remove_ranges() {
+        let content = [1_u8, 2, 3, 4, 5, 6, 7, 0];
+        assert_eq!(remove_ranges(&content, &[]), content.to_vec());
+        assert_eq!(remove_ranges(&content, &[4..6, 0..2]), vec![3, 4, 7, 0, 0, 0, 0, 0]);
+        assert_eq!(remove_ranges(&content, &[0..4, 4..8]), vec![0; 8]);
+    }
+
+    #[test]
+    fn test_file_id_of_path_relative() {
+        let mut files : HashMap<KeyTypeFiles, ValueTypeFiles> = HashMap::new();
+        for (file_id, path, fdb) in [(0x4100_u16, &[0x3F_u8, 0, 0x41, 0][..], FDB_DF),
+                                     (0x41F1, &[0x3F, 0, 0x41, 0, 0x41, 0xF1][..], FDB_RSA_KEY_EF)].iter() {
+            let mut value = [0_u8; 16];
+            value[..path.len()].copy_from_slice(path);
+            files.insert(*file_id, (value, [*fdb, u8::try_from(path.len()).unwrap(), path[path.len()-2], path[path.len()-1],
+                0, 0, 0xFF, 5], None, None, None));
+        }
+        let path_app = [0x3F, 0, 0x41, 0];
+        /* an xDF entry's path relative to the appDF, and the absolute one */
+        assert_eq!(file_id_of_path(&files, &[0x41, 0xF1], &path_app), Some(0x41F1));
+        assert_eq!(file_id_of_path(&files, &[0x3F, 0, 0x41, 0, 0x41, 0xF1], &path_app), Some(0x41F1));
+        assert_eq!(file_id_of_path(&files, &[0x41, 0xF1], &[0x3F, 0]), None);
+        assert!(!is_dangling(&files, &[0x41, 0xF1]));
+        /* the file id exists, but at another path: reported, but no dangling entry to be removed */
+        assert_eq!(file_id_of_path(&files, &[0x3F, 0, 0x41, 0xF1], &path_app), None);
+        assert!(!is_dangling(&files, &[0x3F, 0, 0x41, 0xF1]));
+        assert!(is_dangling(&files, &[0x41, 0xF2]));
+    }
 
     #[test]
     fn test_directory_range() { // $ cargo test test_directory_range -- --nocapture
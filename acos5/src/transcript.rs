@@ -0,0 +1,556 @@
+/*
+ * transcript.rs: Driver 'acos5' - APDU transcript recording (and replay for regression tests)
+ *
+ * Copyright (C) 2019  Carsten Blüggel <bluecars@posteo.eu>
+ *
+ * This library is free software; you can redistribute it and/or
+ * modify it under the terms of the GNU Lesser General Public
+ * License as published by the Free Software Foundation; either
+ * version 2.1 of the License, or (at your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public
+ * License along with this library; if not, write to the Free Software
+ * Foundation, 51 Franklin Street, Fifth Floor  Boston, MA 02110-1335  USA
+ */
+
+/*
+Recording is switched on in opensc.conf, card_driver acos5_external block:
+    card_driver acos5_external {
+        transcript_file = "/tmp/acos5_transcript.txt";
+    }
+acos5_init then hooks sc_reader_operations.transmit of the card's reader and appends every command/response APDU pair
+(as transmitted, i.e. after SM wrapping, Get Response as separate pairs) to that file; acos5_finish unhooks.
+The file is human readable, 2 lines per APDU pair:
+    > acos5_init: 80 14 06 00 08
+    < 41 43 4F 53 05 03 01 40 90 00
+The label is the outermost sc_card_operations entry point of the driver, that was running when the APDU was sent
+(marked by op_scope at the beginning of each acos5_* operation), or '-' if none (e.g. sc_lock, pkcs15 emulators).
+
+For replay (see Replayer in the tests), a command line may be written as  '>~ label: ...'  instead: Then only
+CLA INS P1 P2 get compared, which is required for commands containing random or session dependent data (e.g. SM
+wrapped commands, external authenticate).
+
+The file gets created with mode 0600; an existing one, that is accessible by group/others (unix) or not owned by the
+user, is refused. The command data of VERIFY, CHANGE REFERENCE DATA and RESET RETRY COUNTER (unblock) and of any
+UPDATE BINARY/UPDATE RECORD/APPEND RECORD to a PIN file or key file (i.e. PINs, PUKs and keys, also if SM wrapped)
+get zeroed and those lines are written as  '>~'. Anything else is recorded unredacted, e.g. the data of files
+read or written and SM session data.
+*/
+
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::raw::{c_char, c_void};
+use std::ptr::null;
+use std::sync::Mutex;
+
+use opensc_sys::opensc::{sc_card, sc_reader, sc_reader_operations};
+use opensc_sys::types::{sc_apdu, SC_APDU_CASE_1, SC_APDU_CASE_2_SHORT, SC_APDU_CASE_3_SHORT, SC_APDU_CASE_4_SHORT,
+                        SC_APDU_SHORT_MASK, SC_APDU_EXT, SC_APDU_FLAGS_CHAINING};
+use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_NOT_ALLOWED};
+#[cfg(test)]
+use opensc_sys::errors::SC_ERROR_INVALID_DATA;
+use opensc_sys::scconf::{scconf_find_blocks, scconf_get_str};
+
+use crate::constants_types::{CARD_DRV_SHORT_NAME, DataPrivate, FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_RSA_KEY_EF,
+                             FDB_ECC_KEY_EF};
+use crate::path::file_id_from_cache_current_path;
+use crate::wrappers::{wr_do_log_t, wr_do_log_tu};
+
+/// One command/response APDU pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    pub op       : String,  // the calling sc_card_operations entry point
+    pub command  : Vec<u8>,
+    pub response : Vec<u8>, // response data || SW1 SW2
+    pub header_only : bool, // replay compares CLA INS P1 P2 only
+}
+
+impl TranscriptEntry {
+    /// The 2-line text representation, as written to the transcript file
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ");
+        format!(">{} {}: {}\n< {}\n", if self.header_only {"~"} else {""}, self.op, hex(&self.command),
+                hex(&self.response))
+    }
+}
+
+/// Parses a transcript file's content (for replay); blank lines and lines starting with # are ignored
+///
+/// # Errors
+/// `SC_ERROR_INVALID_DATA`, if a line isn't in the format written by `TranscriptEntry::to_text`
+#[cfg(test)]
+pub fn parse_transcript(text: &str) -> Result<Vec<TranscriptEntry>, i32> {
+    fn hex_to_bin(hex: &str) -> Result<Vec<u8>, i32> {
+        hex.split_whitespace().map(|b| u8::from_str_radix(b, 16).map_err(|_| SC_ERROR_INVALID_DATA)).collect()
+    }
+    let mut result : Vec<TranscriptEntry> = Vec::new();
+    let mut pending : Option<TranscriptEntry> = None;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if let Some(rest) = line.strip_prefix('>') {
+            if pending.is_some() {
+                return Err(SC_ERROR_INVALID_DATA);
+            }
+            let (header_only, rest) = match rest.strip_prefix('~') { Some(r) => (true, r), None => (false, rest) };
+            let colon = rest.find(':').ok_or(SC_ERROR_INVALID_DATA)?;
+            let command = hex_to_bin(&rest[colon+1..])?;
+            if command.len() < 4 {
+                return Err(SC_ERROR_INVALID_DATA);
+            }
+            pending = Some(TranscriptEntry { op: rest[..colon].trim().to_string(), command, response: Vec::new(),
+                                             header_only });
+        }
+        else if let Some(rest) = line.strip_prefix('<') {
+            let mut entry = pending.take().ok_or(SC_ERROR_INVALID_DATA)?;
+            entry.response = hex_to_bin(rest)?;
+            if entry.response.len() < 2 {
+                return Err(SC_ERROR_INVALID_DATA);
+            }
+            result.push(entry);
+        }
+        else {
+            return Err(SC_ERROR_INVALID_DATA);
+        }
+    }
+    if pending.is_some() { Err(SC_ERROR_INVALID_DATA) } else { Ok(result) }
+}
+
+/// Serializes a command APDU like sc_apdu2bytes does (which isn't exported from libopensc)
+#[must_use]
+pub fn apdu_to_bytes(apdu: &sc_apdu) -> Vec<u8> {
+    let mut cmd = vec![if (apdu.flags & SC_APDU_FLAGS_CHAINING) != 0 {apdu.cla | 0x10} else {apdu.cla},
+                       apdu.ins, apdu.p1, apdu.p2];
+    let is_ext = apdu.cse & SC_APDU_EXT != 0;
+    let data = if apdu.datalen == 0 || apdu.data.is_null() { &[][..] }
+               else { unsafe { std::slice::from_raw_parts(apdu.data, apdu.datalen) } };
+    let push_len = |cmd: &mut Vec<u8>, len: usize, lead_zero: bool| {
+        if is_ext {
+            if lead_zero { cmd.push(0); }
+            cmd.extend_from_slice(&u16::try_from(len & 0xFFFF).unwrap().to_be_bytes());
+        }
+        else {
+            cmd.push(u8::try_from(len & 0xFF).unwrap());
+        }
+    };
+    match apdu.cse & SC_APDU_SHORT_MASK {
+        SC_APDU_CASE_1 => {},
+        SC_APDU_CASE_2_SHORT => push_len(&mut cmd, apdu.le, true),
+        SC_APDU_CASE_3_SHORT => {
+            push_len(&mut cmd, apdu.lc, true);
+            cmd.extend_from_slice(data);
+        },
+        SC_APDU_CASE_4_SHORT => {
+            push_len(&mut cmd, apdu.lc, true);
+            cmd.extend_from_slice(data);
+            push_len(&mut cmd, apdu.le, false);
+        },
+        _ => {},
+    }
+    cmd
+}
+
+/// Whether the data of a command must not be recorded: A PIN sent by VERIFY, CHANGE REFERENCE DATA, RESET RETRY
+/// COUNTER, or a PIN/key record or key file content written by UPDATE BINARY, UPDATE RECORD, APPEND RECORD
+/// (is_key_file: the current file is a PIN file or key file)
+#[must_use]
+pub fn is_command_data_secret(ins: u8, is_key_file: bool) -> bool {
+    [0x20, 0x24, 0x2C].contains(&ins) || ([0xD6, 0xDC, 0xE2].contains(&ins) && is_key_file)
+}
+
+/// Zeroes the command data (of length datalen) and marks entry header_only, which makes replay compare CLA INS P1 P2
+pub fn redact_command_data(entry: &mut TranscriptEntry, datalen: usize, is_ext: bool) {
+    let start = if is_ext {7} else {5};
+    let end = std::cmp::min(start + datalen, entry.command.len());
+    if start < end {
+        entry.command[start..end].iter_mut().for_each(|b| *b = 0);
+    }
+    entry.header_only = true;
+}
+
+/* whether the card's current file is a PIN file or key file */
+fn is_current_file_key_file(card: &sc_card) -> bool {
+    if card.drv_data.is_null() || card.cache.current_path.len < 2 {
+        return false;
+    }
+    let dp = unsafe { &*card.drv_data.cast::<DataPrivate>() };
+    match dp.files.get(&file_id_from_cache_current_path(card)) {
+        Some(val) => [FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_RSA_KEY_EF, FDB_ECC_KEY_EF].contains(&val.1[0]),
+        None => false,
+    }
+}
+
+
+thread_local! {
+    static CURRENT_OP : Cell<&'static str> = const { Cell::new("") };
+}
+
+/// Marks the sc_card_operations entry point, that is running; nested calls of other operations don't override
+pub struct OpScope {
+    is_outermost : bool,
+}
+
+impl Drop for OpScope {
+    fn drop(&mut self) {
+        if self.is_outermost {
+            CURRENT_OP.with(|op| op.set(""));
+        }
+    }
+}
+
+/// To be bound to a local variable (`let _op = op_scope("acos5_...");`) at the beginning of an operation
+#[must_use]
+pub fn op_scope(name: &'static str) -> OpScope {
+    CURRENT_OP.with(|op| {
+        let is_outermost = op.get().is_empty();
+        if is_outermost {
+            op.set(name);
+        }
+        OpScope { is_outermost }
+    })
+}
+
+fn current_op() -> &'static str {
+    CURRENT_OP.with(|op| { let name = op.get(); if name.is_empty() {"-"} else {name} })
+}
+
+
+/* a hooked reader; pointers stored as usize, as raw pointers aren't Send */
+struct Recording {
+    reader     : usize,
+    card       : usize, // *const sc_card
+    ops_orig   : usize, // *const sc_reader_operations
+    ops_hooked : usize, // *mut sc_reader_operations, owned
+    file       : File,
+}
+
+static RECORDINGS : Mutex<Vec<Recording>> = Mutex::new(Vec::new());
+
+unsafe extern "C" fn transcript_transmit(reader: *mut sc_reader, apdu: *mut sc_apdu) -> i32 {
+    if reader.is_null() || apdu.is_null() {
+        return SC_ERROR_INVALID_ARGUMENTS;
+    }
+    let (transmit, card) = {
+        let recordings = RECORDINGS.lock().unwrap();
+        match recordings.iter().find(|r| r.reader == reader as usize) {
+            Some(r) => ((*(r.ops_orig as *const sc_reader_operations)).transmit, r.card as *const sc_card),
+            None => (None, std::ptr::null()),
+        }
+    };
+    let transmit = match transmit { Some(f) => f, None => return SC_ERROR_INVALID_ARGUMENTS };
+    let command = apdu_to_bytes(&*apdu);
+    let is_secret = is_command_data_secret((*apdu).ins, !card.is_null() && is_current_file_key_file(&*card));
+    let rv = transmit(reader, apdu);
+    if rv == SC_SUCCESS {
+        let apdu = &*apdu;
+        let mut response = if apdu.resplen == 0 || apdu.resp.is_null() { Vec::new() }
+                           else { std::slice::from_raw_parts(apdu.resp, apdu.resplen).to_vec() };
+        response.extend_from_slice(&[u8::try_from(apdu.sw1 & 0xFF).unwrap(), u8::try_from(apdu.sw2 & 0xFF).unwrap()]);
+        let mut entry = TranscriptEntry { op: current_op().to_string(), command, response, header_only: false };
+        if is_secret {
+            redact_command_data(&mut entry, apdu.datalen, apdu.cse & SC_APDU_EXT != 0);
+        }
+        let mut recordings = RECORDINGS.lock().unwrap();
+        if let Some(r) = recordings.iter_mut().find(|r| r.reader == reader as usize) {
+            /* a failing write must not disturb the card communication */
+            let _ = r.file.write_all(entry.to_text().as_bytes());
+        }
+    }
+    rv
+}
+
+/// Opens the transcript file for appending; a new one gets created with mode 0600
+///
+/// # Errors
+/// `SC_ERROR_FILE_NOT_FOUND` if path can't be opened, `SC_ERROR_NOT_ALLOWED` if it's accessible by group/others
+/// or not owned by the user (or root)
+pub fn transcript_file_open(path: &str) -> Result<File, i32> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(path).map_err(|_| SC_ERROR_FILE_NOT_FOUND)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = file.metadata().map_err(|_| SC_ERROR_FILE_NOT_FOUND)?;
+        let euid = unsafe { libc::geteuid() };
+        if (metadata.mode() & 0o077) != 0 || (metadata.uid() != euid && metadata.uid() != 0) {
+            return Err(SC_ERROR_NOT_ALLOWED);
+        }
+    }
+    Ok(file)
+}
+
+/// Starts recording all APDUs transmitted via card's reader, appending to file path
+///
+/// Calling it again for the same reader is a no-op
+/// # Errors
+/// `SC_ERROR_INVALID_ARGUMENTS` for a card without reader, otherwise as `transcript_file_open`
+pub fn transcript_start(card: &mut sc_card, path: &CStr) -> Result<(), i32> {
+    if card.reader.is_null() || unsafe { (*card.reader).ops.is_null() } {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    let reader = unsafe { &mut *card.reader };
+    let mut recordings = RECORDINGS.lock().unwrap();
+    if recordings.iter().any(|r| r.reader == reader as *mut sc_reader as usize) {
+        return Ok(());
+    }
+    let path = path.to_str().map_err(|_| SC_ERROR_INVALID_ARGUMENTS)?;
+    let file = transcript_file_open(path)?;
+    let mut ops_hooked = Box::new(unsafe { *reader.ops });
+    ops_hooked.transmit = Some(transcript_transmit);
+    let ops_orig = reader.ops;
+    reader.ops = Box::into_raw(ops_hooked);
+    recordings.push(Recording { reader: reader as *mut sc_reader as usize, card: card as *mut sc_card as usize,
+                                ops_orig: ops_orig as usize,
+                                ops_hooked: reader.ops as usize, file });
+    Ok(())
+}
+
+/// Stops recording for card's reader (if it's recording) and restores the reader's original operations
+pub fn transcript_stop(card: &mut sc_card) {
+    if card.reader.is_null() {
+        return;
+    }
+    let reader = unsafe { &mut *card.reader };
+    let mut recordings = RECORDINGS.lock().unwrap();
+    if let Some(pos) = recordings.iter().position(|r| r.reader == reader as *mut sc_reader as usize) {
+        let recording = recordings.remove(pos);
+        reader.ops = recording.ops_orig as *mut sc_reader_operations;
+        drop(unsafe { Box::from_raw(recording.ops_hooked as *mut sc_reader_operations) });
+    }
+}
+
+/// Starts recording, if opensc.conf, card_driver acos5_external block has an entry  transcript_file = "path";
+/// called once only from acos5_init
+pub fn transcript_start_from_conf(card: &mut sc_card) {
+    if card.ctx.is_null() {
+        return;
+    }
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"transcript_start_from_conf\0");
+    let mut path : *const c_char = null();
+    for elem in &ctx.conf_blocks {
+        if (*elem).is_null() { break; }
+        let blocks_ptr = unsafe { scconf_find_blocks(ctx.conf, *elem, cstru!(b"card_driver\0").as_ptr(),
+                                                     cstru!(CARD_DRV_SHORT_NAME).as_ptr()) };
+        if blocks_ptr.is_null() { continue; }
+        let blk_ptr = unsafe { *blocks_ptr };
+        unsafe { libc::free(blocks_ptr.cast::<c_void>()) };
+        if blk_ptr.is_null() { continue; }
+        path = unsafe { scconf_get_str(blk_ptr, cstru!(b"transcript_file\0").as_ptr(), null()) };
+        if !path.is_null() { break; }
+    }
+    if path.is_null() {
+        return;
+    }
+    match transcript_start(card, unsafe { CStr::from_ptr(path) }) {
+        Ok(()) => log3if!(ctx,f,line!(), cstru!(b"Recording APDU transcript to: %s\0"), path),
+        Err(rv) => log3if!(ctx,f,line!(), cstru!(b"### Failed to open APDU transcript file: %s, error: %d\0"), path, rv),
+    }
+}
+
+
+/* Replay: answers commands from a transcript and tracks any divergence of the command stream */
+#[cfg(test)]
+pub struct Replayer {
+    pub card_type   : i32,
+    entries         : Vec<TranscriptEntry>,
+    pos             : usize,
+    pub divergences : Vec<String>,
+}
+
+#[cfg(test)]
+impl Replayer {
+    #[must_use]
+    pub fn new(card_type: i32, entries: Vec<TranscriptEntry>) -> Self {
+        Self { card_type, entries, pos: 0, divergences: Vec::new() }
+    }
+
+    /// The count of transcript entries not yet requested
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.entries.len() - self.pos
+    }
+
+    /// Answers with the recorded response, if cmd matches the next recorded command, otherwise with 6F 00
+    pub fn process(&mut self, cmd: &[u8]) -> Vec<u8> {
+        let entry = match self.entries.get(self.pos) {
+            Some(entry) => entry,
+            None => {
+                self.divergences.push(format!("#{}: unexpected command beyond end of transcript: {:02X?}", self.pos, cmd));
+                return vec![0x6F, 0];
+            },
+        };
+        let is_match = if entry.header_only { cmd.len() >= 4 && cmd[..4] == entry.command[..4] }
+                       else { cmd == entry.command.as_slice() };
+        if !is_match {
+            self.divergences.push(format!("#{} ({}): expected {:02X?}, got {:02X?}", self.pos, entry.op,
+                                          entry.command, cmd));
+            return vec![0x6F, 0];
+        }
+        self.pos += 1;
+        entry.response.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants_types::SC_CARD_TYPE_ACOS5_64_V3;
+    use crate::simulator::{Acos5Simulator, SimulatedCard};
+    use opensc_sys::opensc::{sc_select_file, sc_read_binary, sc_format_path};
+    use opensc_sys::types::sc_path;
+    use std::ptr::null_mut;
+
+    const TRANSCRIPT : &str = "\
+# acos5_init, shortened
+> acos5_init: 80 14 06 00 08
+< 41 43 4F 53 05 03 01 40 90 00
+>~ acos5_get_challenge: 00 84 00 00 08
+< 01 02 03 04 05 06 07 08 90 00
+";
+
+    #[test]
+    fn test_parse_transcript() {
+        let entries = parse_transcript(TRANSCRIPT).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].op, "acos5_init");
+        assert_eq!(entries[0].command, [0x80, 0x14, 6, 0, 8]);
+        assert_eq!(entries[0].response[8..], [0x90, 0]);
+        assert!(entries[1].header_only);
+        let text : String = entries.iter().map(TranscriptEntry::to_text).collect();
+        assert_eq!(parse_transcript(&text).unwrap(), entries);
+        assert_eq!(parse_transcript("> acos5_init: 80 14\n< 90 00"), Err(SC_ERROR_INVALID_DATA));
+        assert_eq!(parse_transcript("< 90 00"), Err(SC_ERROR_INVALID_DATA));
+        assert_eq!(parse_transcript("> acos5_init: 80 14 06 00 08"), Err(SC_ERROR_INVALID_DATA));
+    }
+
+    #[test]
+    fn test_replayer() {
+        let mut replayer = Replayer::new(SC_CARD_TYPE_ACOS5_64_V3, parse_transcript(TRANSCRIPT).unwrap());
+        assert_eq!(replayer.process(&[0x80, 0x14, 6, 0, 8])[..4], [0x41, 0x43, 0x4F, 0x53]);
+        assert_eq!(replayer.process(&[0, 0x84, 0, 0, 9]).len(), 10);
+        assert!(replayer.divergences.is_empty());
+        assert_eq!(replayer.remaining(), 0);
+        assert_eq!(replayer.process(&[0, 0x84, 0, 0, 8]), [0x6F, 0]);
+        assert_eq!(replayer.divergences.len(), 1);
+
+        let mut replayer = Replayer::new(SC_CARD_TYPE_ACOS5_64_V3, parse_transcript(TRANSCRIPT).unwrap());
+        assert_eq!(replayer.process(&[0x80, 0x14, 6, 0, 6]), [0x6F, 0]);
+        assert!(replayer.divergences[0].starts_with("#0 (acos5_init)"));
+    }
+
+    #[test]
+    fn test_apdu_to_bytes() {
+        let data = [1_u8, 2, 3];
+        let mut apdu = sc_apdu { cse: SC_APDU_CASE_4_SHORT, cla: 0, ins: 0x2A, p1: 0x9E, p2: 0x9A, lc: 3, le: 256,
+                                 data: data.as_ptr(), datalen: 3, ..sc_apdu::default() };
+        assert_eq!(apdu_to_bytes(&apdu), [0, 0x2A, 0x9E, 0x9A, 3, 1, 2, 3, 0]);
+        apdu.flags = SC_APDU_FLAGS_CHAINING;
+        assert_eq!(apdu_to_bytes(&apdu)[0], 0x10);
+        apdu.cse = SC_APDU_CASE_2_SHORT | SC_APDU_EXT;
+        apdu.le = 0x200;
+        assert_eq!(apdu_to_bytes(&apdu), [0x10, 0x2A, 0x9E, 0x9A, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_redact_command_data() {
+        assert!(is_command_data_secret(0x20, false));
+        assert!(is_command_data_secret(0x24, false));
+        assert!(is_command_data_secret(0x2C, false));
+        assert!(is_command_data_secret(0xDC, true));
+        assert!(is_command_data_secret(0xE2, true));
+        assert!(is_command_data_secret(0xD6, true));
+        assert!(!is_command_data_secret(0xDC, false));
+        assert!(!is_command_data_secret(0xB0, true));
+        assert!(!is_command_data_secret(0xA4, false));
+
+        let mut entry = TranscriptEntry { op: "acos5_pin_cmd".to_string(),
+            command: vec![0, 0x20, 0, 0x81, 8, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38],
+            response: vec![0x90, 0], header_only: false };
+        redact_command_data(&mut entry, 8, false);
+        assert_eq!(entry.command, [0, 0x20, 0, 0x81, 8, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(entry.header_only);
+        assert_eq!(entry.to_text(), ">~ acos5_pin_cmd: 00 20 00 81 08 00 00 00 00 00 00 00 00\n< 90 00\n");
+        /* Le isn't touched */
+        let mut entry = TranscriptEntry { op: "-".to_string(), command: vec![0, 0xDC, 1, 4, 2, 0xAB, 0xCD, 0],
+                                          response: vec![0x90, 0], header_only: false };
+        redact_command_data(&mut entry, 2, false);
+        assert_eq!(entry.command, [0, 0xDC, 1, 4, 2, 0, 0, 0]);
+        let mut entry = TranscriptEntry { op: "-".to_string(), command: vec![0, 0xD6, 0, 0, 0, 0, 2, 0xAB, 0xCD],
+                                          response: vec![0x90, 0], header_only: false };
+        redact_command_data(&mut entry, 2, true);
+        assert_eq!(entry.command, [0, 0xD6, 0, 0, 0, 0, 2, 0, 0]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_transcript_file_open() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("acos5_transcript_test_{}.txt", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(transcript_file_open(path_str).is_ok());
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(transcript_file_open(path_str).is_ok());
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(transcript_file_open(path_str).err(), Some(SC_ERROR_NOT_ALLOWED));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_op_scope() {
+        assert_eq!(current_op(), "-");
+        {
+            let _op = op_scope("acos5_compute_signature");
+            {
+                let _op = op_scope("acos5_decipher");
+                assert_eq!(current_op(), "acos5_compute_signature");
+            }
+            assert_eq!(current_op(), "acos5_compute_signature");
+        }
+        assert_eq!(current_op(), "-");
+    }
+
+    /* record a session against the simulator, then replay it: the driver must issue the identical command stream */
+    #[test]
+    fn test_record_and_replay() {
+        fn session(card: *mut sc_card) {
+            let mut path = sc_path::default();
+            unsafe { sc_format_path(cstru!(b"3F0041005032\0").as_ptr(), &mut path) };
+            assert_eq!(SC_SUCCESS, unsafe { sc_select_file(card, &path, null_mut()) });
+            let mut buf = [0_u8; 16];
+            assert_eq!(16, unsafe { sc_read_binary(card, 0, buf.as_mut_ptr(), 16, 0) });
+        }
+        let file_name = std::env::temp_dir().join(format!("acos5_transcript_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&file_name);
+        let path = std::ffi::CString::new(file_name.to_str().unwrap()).unwrap();
+        {
+            let sim_card = SimulatedCard::connect_with_transcript(
+                Acos5Simulator::new_initialized(SC_CARD_TYPE_ACOS5_64_V3), Some(&path)).unwrap();
+            session(sim_card.card);
+        }
+        let entries = parse_transcript(&std::fs::read_to_string(&file_name).unwrap()).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        assert!(entries.iter().any(|e| e.op == "acos5_init"));
+        assert!(entries.iter().any(|e| e.op == "acos5_read_binary"));
+
+        let mut replay_card = SimulatedCard::connect(Replayer::new(SC_CARD_TYPE_ACOS5_64_V3, entries)).unwrap();
+        session(replay_card.card);
+        assert_eq!(replay_card.simulator().divergences, Vec::<String>::new());
+        assert_eq!(replay_card.simulator().remaining(), 0);
+    }
+}
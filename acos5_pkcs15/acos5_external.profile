@@ -39,7 +39,7 @@ option default {
     macros {
         protected    = *=$SOPIN, READ=NEVER;
         unprotected    = *=NONE;
-        so-pin-flags    = local, initialized, needs-padding, soPin;
+        so-pin-flags    = initialized, needs-padding, soPin; # global PIN 1 in the MF's PIN file
         so-min-pin-length = 8;
         so-pin-attempts    = 8;
         so-auth-id    = FF;
@@ -55,6 +55,22 @@ option default {
         pukdf-size    = 1536;
         skdf-size    = 256;
         dodf-size    = 256;
+
+        # pkcs15-init --erase-card: If erase-dry-run = yes, then only the pre-flight checks get done, nothing gets erased
+        erase-dry-run    = no;
+
+        # Card initialization by pkcs15-init --erase-card --so-pin <current SO PIN>: If init-card = yes, then the erased
+        # card gets the file system of info/card_initialization/card_initialization.scriptor, with these parameters:
+        # init-op-mode: Operation Mode Byte, hex; FF leaves it unchanged. V2.00: 00; V3.00: 02 (64K) or 00 (FIPS);
+        #               EVO: 01 (non-FIPS) or 00 (FIPS)
+        # init-sm-key-algo: 3DES (16 or 24 bytes keys) or AES (EVO only: 16, 24 or 32 bytes keys)
+        # This file is world-readable, thus the PINs and SM keys don't belong here: pkcs15-init prompts for them on the
+        # terminal (SO PIN, SO PUK, user PIN, user PUK: 8 characters, an empty PUK is the PIN; SM keys host and card: hex,
+        # the keys for external/internal authentication, see opensc.conf keyset). The PINs and keys of
+        # card_initialization.scriptor are public and get rejected
+        init-card        = no;
+        init-op-mode     = FF;
+        init-sm-key-algo = 3DES;
     }
 }
 
@@ -126,6 +142,27 @@ filesystem {
     DF MF {
         path    = 3F00;
         type    = 0x3F;
+        # SCB1 refers to the global SE file's record 1: verify global PIN 1 (SO PIN)
+        ACL     = *=SCB1, LOCK=NEVER;
+
+        # The MF's mandatory files, created by pkcs15-init on a virgin card (see acos5_pkcs15_init_card)
+        BSO global-secret-pin {
+            file-id   = 0001;
+            type      = 10;
+            structure = linear-fixed;
+            record-length = 21;
+            size      = 21;
+            ACL       = *=NEVER, UPDATE=SCB1, DELETE-SELF=SCB1;
+        }
+
+        EF global-secenv {
+            file-id   = 0003;
+            type      = 28;
+            structure = linear-variable;
+            record-length = 48;
+            size      = 48;
+            ACL       = *=NEVER, READ=NONE, UPDATE=SCB1, DELETE-SELF=SCB1;
+        }
 
         # This is the DIR file
         EF DIR {
@@ -223,6 +260,8 @@ filesystem {
                 ACL       = $unprotected;
             }
 
+            # The DF's mandatory files, created by pkcs15-init (see acos5_pkcs15_create_dir). Within this DF,
+            # SCB1 refers to verify local PIN 1 (user PIN) and SCB3 to verify global PIN 1 (SO PIN)
             BSO secret-pin {
                 file-id   = 4101;
                 type      = 10;
@@ -103,30 +103,37 @@ use std::slice::from_raw_parts;
 
 use opensc_sys::opensc::{/*sc_context,*/ sc_card, sc_select_file, sc_card_ctl, SC_ALGORITHM_DES,
                          SC_ALGORITHM_3DES, SC_ALGORITHM_AES, sc_card_find_rsa_alg, sc_file_new, sc_transmit_apdu,
-                         sc_file_dup, sc_delete_file, sc_check_sw, sc_update_record, SC_RECORD_BY_REC_NR, sc_get_version};
+                         sc_file_dup, sc_delete_file, sc_update_record, SC_RECORD_BY_REC_NR, sc_get_version,
+                         sc_create_file, sc_check_sw, sc_mem_clear};
 
 use opensc_sys::profile::{sc_profile};
+use opensc_sys::cardctl::{SC_CARDCTL_ERASE_CARD, SC_CARDCTL_LIFECYCLE_SET, SC_CARDCTRL_LIFECYCLE_USER};
 use opensc_sys::pkcs15::{sc_pkcs15_card, sc_pkcs15_object, sc_pkcs15_prkey, sc_pkcs15_pubkey, sc_pkcs15_skey_info,
                          SC_PKCS15_TYPE_SKEY_DES/*, SC_PKCS15_TYPE_SKEY_2DES*/, SC_PKCS15_TYPE_SKEY_3DES, SC_PKCS15_TYPE_SKEY_GENERIC,
                          sc_pkcs15_prkey_info, sc_pkcs15_pubkey_info, SC_PKCS15_TYPE_PRKEY_EC, //sc_pkcs15_prkey_rsa,
                          SC_PKCS15_TYPE_PRKEY_RSA, SC_PKCS15_TYPE_PUBKEY_RSA, sc_pkcs15_auth_info, //sc_pkcs15_id,
                          SC_PKCS15_PRKDF, SC_PKCS15_PUKDF, SC_PKCS15_SKDF, SC_PKCS15_CDF, SC_PKCS15_CDF_TRUSTED,
-                         SC_PKCS15_DODF, sc_pkcs15_read_pubkey, sc_pkcs15_free_pubkey, sc_pkcs15_der,
+                         SC_PKCS15_DODF, SC_PKCS15_AODF, sc_pkcs15_read_pubkey, sc_pkcs15_free_pubkey, sc_pkcs15_der,
                          SC_PKCS15_PRKEY_ACCESS_EXTRACTABLE, SC_PKCS15_TYPE_PUBKEY_EC,
                          SC_PKCS15_PRKEY_USAGE_SIGN, SC_PKCS15_PRKEY_USAGE_DECRYPT, SC_PKCS15_TYPE_CLASS_MASK, SC_PKCS15_TYPE_SKEY,
-                         SC_PKCS15_PRKEY_ACCESS_SENSITIVE, SC_PKCS15_PRKEY_ACCESS_ALWAYSSENSITIVE, SC_PKCS15_PRKEY_ACCESS_NEVEREXTRACTABLE, SC_PKCS15_PRKEY_ACCESS_LOCAL
+                         SC_PKCS15_PRKEY_ACCESS_SENSITIVE, SC_PKCS15_PRKEY_ACCESS_ALWAYSSENSITIVE, SC_PKCS15_PRKEY_ACCESS_NEVEREXTRACTABLE, SC_PKCS15_PRKEY_ACCESS_LOCAL,
+                         SC_PKCS15_PIN_FLAG_LOCAL
 };
 //, sc_pkcs15_bignum, sc_pkcs15_pubkey_rsa
-use opensc_sys::pkcs15_init::{sc_pkcs15init_operations, sc_pkcs15init_authenticate/*, sc_pkcs15init_pubkeyargs*/};
+use opensc_sys::pkcs15_init::{sc_pkcs15init_operations, sc_pkcs15init_authenticate, sc_pkcs15init_verify_secret,
+                              sc_pkcs15init_create_file, sc_pkcs15init_fixup_file,
+                              SC_PKCS15INIT_SO_PIN/*, sc_pkcs15init_pubkeyargs*/};
 use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_KEYPAD_MSG_TOO_LONG, SC_ERROR_SECURITY_STATUS_NOT_SATISFIED,
                          SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_NOT_SUPPORTED, /*SC_ERROR_NON_UNIQUE_ID,*/
-                         SC_ERROR_INCONSISTENT_PROFILE, SC_ERROR_OUT_OF_MEMORY, SC_ERROR_FILE_NOT_FOUND
+                         SC_ERROR_INCONSISTENT_PROFILE, SC_ERROR_OUT_OF_MEMORY, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_BUFFER_TOO_SMALL,
+                         SC_ERROR_NOT_ALLOWED, SC_ERROR_INVALID_PIN_REFERENCE, SC_ERROR_INVALID_PIN_LENGTH, SC_ERROR_TOO_MANY_OBJECTS
                          //, SC_ERROR_NOT_IMPLEMENTED, SC_ERROR_FILE_ALREADY_EXISTS
                          //,SC_ERROR_INCONSISTENT_CONFIGURATION, SC_ERROR_UNKNOWN, SC_ERROR_FILE_NOT_FOUND
 };
 //use opensc_sys::sm::{sm_info};
 use opensc_sys::types::{sc_file, sc_path, SC_AC_OP_CREATE_EF, SC_AC_OP_DELETE, SC_AC_OP_READ, SC_AC_OP_DELETE_SELF,//SC_AC_OP_DELETE_SELF, SC_FILE_TYPE_INTERNAL_EF,
-                        SC_AC_OP_UPDATE, SC_APDU_CASE_1/*, SC_APDU_CASE_3*/, SC_PATH_TYPE_PATH, sc_acl_entry};
+                        SC_AC_OP_UPDATE, SC_APDU_CASE_1/*, SC_APDU_CASE_3*/, SC_PATH_TYPE_PATH, sc_acl_entry, SC_AC_SYMBOLIC,
+                        SC_FILE_STATUS_CREATION};
 // SC_FILE_EF_TRANSPARENT, SC_FILE_STATUS_CREATION, SC_MAX_PATH_SIZE,  SC_PATH_TYPE_FILE_ID, SC_AC_OP_DELETE
 //use opensc_sys::types::{/*SC_MAX_CRTS_IN_SE, sc_crt*/};
 use opensc_sys::log::{/*sc_do_log, SC_LOG_DEBUG_NORMAL,*/ sc_dump_hex};
@@ -138,11 +145,16 @@ pub mod    macros;
 pub mod    constants_types; // shared file among modules acos5, acos5_pkcs15 and acos5_sm
 use crate::constants_types::{CARD_DRV_SHORT_NAME, CardCtl_generate_crypt_asym, DataPrivate, SC_CARDCTL_ACOS5_SDO_CREATE,
                              SC_CARDCTL_ACOS5_SDO_GENERATE_KEY_FILES, SC_CARD_TYPE_ACOS5_64_V3, build_apdu,
-                             SC_CARDCTL_ACOS5_SANITY_CHECK, GuardFile, file_id_from_path_value,
-                             SC_CARD_TYPE_ACOS5_EVO_V4};
+                             SC_CARDCTL_ACOS5_SANITY_CHECK, SC_CARDCTL_ACOS5_SANITY_REPAIR, CardCtlSanityRepair,
+                             SC_CARDCTL_ACOS5_CARD_INIT, CardCtlCardInit, CardCtlEraseCard, ERASE_AUTH_NEVER,
+                             ERASE_AUTH_NOT_FULFILLED, SE_RECORDS_MF, SE_RECORDS_DF, FDB_LINEAR_FIXED_EF,
+                             FDB_LINEAR_VARIABLE_EF, FDB_CYCLIC_EF, FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_SE_FILE,
+                             GuardFile, file_id_from_path_value, pin_record, pin_id_select, is_pin_reference_in_use_sw,
+                             PIN_ID_MAX, PIN_ID_MAX_EVO, PIN_LEN_MAX, PIN_LEN_MAX_EVO, SC_CARD_TYPE_ACOS5_EVO_V4,
+                             FDB_ECC_KEY_EF, ecc_curve_code, ecc_key_file_sizes};
 
 pub mod    missing_exports; // this is NOT the same as in acos5
-use crate::missing_exports::{me_profile_get_file, me_pkcs15_dup_bignum/*, my_file_dup*/};
+use crate::missing_exports::{me_profile_get_file, me_pkcs15_dup_bignum, me_pkcs15_dup_u8/*, my_file_dup*/};
 
 pub mod    no_cdecl; // this is NOT the same as in acos5
 use crate::no_cdecl::{rsa_modulus_bits_canonical, first_of_free_indices, construct_sym_key_entry, free_fid_asym}; /*call_dynamic_update_hashmap, call_dynamic_sm_test,*/
@@ -213,9 +225,9 @@ extern "C" fn acos5_get_pkcs15init_ops() -> *mut sc_pkcs15init_operations {
              Card-specific initialization of PKCS15 meta-information.
              Currently used by the cflex driver to read the card's serial number and use it as the pkcs15 serial number.
          */
-        init_card  : None, // called only from src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app
+        init_card  : Some(acos5_pkcs15_init_card), // called only from src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app
         /* create_dir : Create a DF */
-        create_dir : None,//Some(acos5_pkcs15_create_dir), // does nothing
+        create_dir : Some(acos5_pkcs15_create_dir),
         /* create_domain: Some cards need to keep all their PINs in separate directories.
            Create a subdirectory now, and put the pin into this subdirectory
              Create a "pin domain". This is for cards such as the cryptoflex that need to put their pins into separate directories
@@ -247,6 +259,148 @@ extern "C" fn acos5_get_pkcs15init_ops() -> *mut sc_pkcs15init_operations {
     Box::into_raw(b_sc_pkcs15init_operations)
 }
 
+/* The value of a macro of acos5_external.profile; None if it's not defined */
+fn profile_macro(profile: &sc_profile, name: &str) -> Option<String> {
+    let mut macro_ptr = profile.macro_list;
+    while !macro_ptr.is_null() {
+        let mac = unsafe { & *macro_ptr };
+        if !mac.name.is_null() && unsafe { CStr::from_ptr(mac.name) }.to_bytes() == name.as_bytes() {
+            if mac.value.is_null() || unsafe { (*mac.value).data.is_null() } {
+                return Some(String::new());
+            }
+            return Some(unsafe { CStr::from_ptr((*mac.value).data) }.to_string_lossy().into_owned());
+        }
+        macro_ptr = mac.next;
+    }
+    None
+}
+
+/* hex digits, optionally separated by ':' like the profile's AID */
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    let digits : Vec<u8> = hex.bytes().filter(|b| *b != b':').collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+/* the SM keys of info/card_initialization/card_initialization.scriptor (published in README.md as well) */
+const SCRIPTOR_SM_KEY_HOST : [u8; 24] = [0xF1, 0xE0, 0xD0, 0xC1, 0xB0, 0xA1, 0x89, 0x08, 0x07, 0x16, 0x45, 0x04, 0x13, 0x02,
+    0x01, 0xF1, 0x89, 0xFE, 0xB3, 0xC8, 0x37, 0x45, 0x16, 0x94];
+const SCRIPTOR_SM_KEY_CARD : [u8; 24] = [0xF1, 0x01, 0x02, 0x13, 0x04, 0x85, 0x16, 0x07, 0x08, 0x49, 0xA1, 0xB0, 0xC1, 0xD0,
+    0xE0, 0xF1, 0x45, 0x89, 0xB3, 0x16, 0xFE, 0x94, 0x37, 0xC8];
+
+/* reads a secret from the terminal, without echo; None if there is no terminal */
+#[cfg(unix)]
+fn init_secret_prompt(prompt: &str) -> Option<Vec<u8>>
+{
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::io::AsRawFd;
+    let mut tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    tty.write_all(prompt.as_bytes()).ok()?;
+    let fd = tty.as_raw_fd();
+    let mut termios_old = unsafe { std::mem::zeroed::<libc::termios>() };
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut termios_old) } == 0;
+    if is_terminal {
+        let mut termios_new = termios_old;
+        termios_new.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios_new) };
+    }
+    let mut line = Vec::with_capacity(128);
+    let res = BufReader::new(&tty).read_until(b'\n', &mut line);
+    if is_terminal {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios_old) };
+    }
+    if tty.write_all(b"\n").is_err() || res.is_err() {
+        unsafe { sc_mem_clear(line.as_mut_ptr().cast::<c_void>(), line.len()) };
+        return None;
+    }
+    while matches!(line.last(), Some(b'\r') | Some(b'\n')) {
+        line.pop();
+    }
+    Some(line)
+}
+
+#[cfg(not(unix))]
+fn init_secret_prompt(_prompt: &str) -> Option<Vec<u8>> { None }
+
+/* reads a secret (characters, or hex if is_hex) from the terminal into dest; returns it's length, 0 for empty input */
+fn init_secret_read(prompt: &str, is_hex: bool, dest: &mut [u8]) -> Result<usize, i32> {
+    let mut input = init_secret_prompt(prompt).ok_or(SC_ERROR_NOT_SUPPORTED)?;
+    let mut secret = if is_hex { std::str::from_utf8(&input).ok().and_then(hex_to_bytes) } else { Some(input.clone()) };
+    let rv = match secret.as_deref() {
+        Some(bytes) if bytes.len() <= dest.len() => {
+            dest[..bytes.len()].copy_from_slice(bytes);
+            Ok(bytes.len())
+        },
+        _ => Err(SC_ERROR_INVALID_ARGUMENTS),
+    };
+    unsafe {
+        sc_mem_clear(input.as_mut_ptr().cast::<c_void>(), input.len());
+        if let Some(bytes) = secret.as_mut() {
+            sc_mem_clear(bytes.as_mut_ptr().cast::<c_void>(), bytes.len());
+        }
+    }
+    rv
+}
+
+/*
+Card initialization parameters; None if acos5_external.profile's macro init-card isn't 'yes'.
+The Operation Mode Byte (hex, FF: leave unchanged) and the SM key algorithm are taken from the profile's macros init-*.
+The profile is world-readable, thus the PINs/PUKs (8 characters, empty PUKs default to the PINs) and the SM keys (hex) are
+prompted for on the terminal; PINs and keys in the profile are an error. There are no defaults for PINs and keys:
+Missing ones and those of card_initialization.scriptor are an error
+*/
+fn card_init_from_profile(profile: &sc_profile) -> Result<Option<CardCtlCardInit>, i32> {
+    if profile_macro(profile, "init-card").as_deref() != Some("yes") {
+        return Ok(None);
+    }
+    if ["init-so-pin", "init-so-puk", "init-user-pin", "init-user-puk", "init-sm-key-host", "init-sm-key-card"].iter()
+        .any(|name| profile_macro(profile, name).is_some()) {
+        return Err(SC_ERROR_INCONSISTENT_PROFILE);
+    }
+    let mut init = CardCtlCardInit {
+        op_mode_byte: match hex_to_bytes(&profile_macro(profile, "init-op-mode").unwrap_or_else(|| "FF".into())).as_deref() {
+            Some(&[op_mode_byte]) => op_mode_byte,
+            _ => return Err(SC_ERROR_INCONSISTENT_PROFILE),
+        },
+        sm_key_aes: match profile_macro(profile, "init-sm-key-algo").as_deref() {
+            None | Some("3DES") => false,
+            Some("AES") => true,
+            _ => return Err(SC_ERROR_INCONSISTENT_PROFILE),
+        },
+        ..CardCtlCardInit::default()
+    };
+    if init_secret_read("Card initialization: New SO PIN (8 characters): ", false, &mut init.so_pin)? != 8 {
+        return Err(SC_ERROR_INVALID_PIN_LENGTH);
+    }
+    match init_secret_read("Card initialization: New SO PUK (8 characters, empty: the SO PIN): ", false, &mut init.so_puk)? {
+        0 => init.so_puk = init.so_pin,
+        8 => (),
+        _ => return Err(SC_ERROR_INVALID_PIN_LENGTH),
+    }
+    if init_secret_read("Card initialization: New user PIN (8 characters): ", false, &mut init.user_pin)? != 8 {
+        return Err(SC_ERROR_INVALID_PIN_LENGTH);
+    }
+    match init_secret_read("Card initialization: New user PUK (8 characters, empty: the user PIN): ", false, &mut init.user_puk)? {
+        0 => init.user_puk = init.user_pin,
+        8 => (),
+        _ => return Err(SC_ERROR_INVALID_PIN_LENGTH),
+    }
+    let key_len = init_secret_read("Card initialization: New SM key host (hex, external authentication): ", true, &mut init.sm_key_host)?;
+    if key_len == 0 || key_len != init_secret_read("Card initialization: New SM key card (hex, internal authentication): ",
+                                                   true, &mut init.sm_key_card)? {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    init.sm_key_len = u8::try_from(key_len).unwrap();
+    /* the PINs and keys of info/card_initialization/card_initialization.scriptor are public */
+    if init.so_pin == *b"87654321" || init.user_pin == *b"12345678" ||
+        init.sm_key_host[..24] == SCRIPTOR_SM_KEY_HOST || init.sm_key_card[..24] == SCRIPTOR_SM_KEY_CARD {
+        return Err(SC_ERROR_INVALID_ARGUMENTS);
+    }
+    Ok(Some(init))
+}
+
 /*
 erase_card is handled differently by cards: Some do delete only PKCS#15 related stuff.
 ACOS5's command 'Zeroize Card User Data' removes everything from the card including MF, thus it will afterwards be in
@@ -260,6 +414,15 @@ reinitializing the card will be impossible forever.
 Get's called only from  src/pkcs15init/pkcs15-lib.c:sc_pkcs15init_erase_card
 How to invoke this:
 pkcs15-init --erase-card --so-pin <arg>          (MF's DELETE_SELF SCB probably is SOPIN)
+
+The erasure is done by card_ctl SC_CARDCTL_ERASE_CARD with pre-flight checks (Zeroize Card User Data Disable Flag, if
+readable; SO authentication, which only V3.00 can confirm) and a post-erase verification, that the card is virgin.
+If acos5_external.profile sets erase-dry-run = yes, then only the pre-flight checks get done, nothing gets erased.
+
+If acos5_external.profile sets init-card = yes, then the erased card gets initialized afterwards with the layout of
+info/card_initialization/card_initialization.scriptor (card_ctl SC_CARDCTL_ACOS5_CARD_INIT), parameterized by the
+profile's init-* macros and the PINs and SM keys prompted for. These get checked against the card type before anything
+is erased
 */
 extern "C" fn acos5_pkcs15_erase_card(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card) -> i32
 {
@@ -270,7 +433,28 @@ extern "C" fn acos5_pkcs15_erase_card(profile_ptr: *mut sc_profile, p15card_ptr:
     let ctx = unsafe { &mut *card.ctx };
     let f = cstru!(b"acos5_pkcs15_erase_card\0");
     log3ifc!(ctx,f,line!());
+    let mut init = match card_init_from_profile(unsafe { & *profile_ptr }) {
+        Ok(init) => init,
+        Err(e) => {
+            log3ifr!(ctx,f,line!(), cstru!(b"Invalid card initialization (init-*) macros in the profile or invalid PINs/SM keys entered\0"), e);
+            return e;
+        },
+    };
     let mut rv;
+    if let Some(init) = init.as_mut() {
+        init.validate_only = true;
+        rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_CARD_INIT, (init as *mut CardCtlCardInit).cast::<c_void>()) };
+        if rv != SC_SUCCESS { return rv; }
+    }
+    /* pre-flight checks: Don't ask for the SOPIN, if erasing is impossible anyway */
+    let mut erase = CardCtlEraseCard { dry_run: true, ..CardCtlEraseCard::default() };
+    rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ERASE_CARD, (&mut erase as *mut CardCtlEraseCard).cast::<c_void>()) };
+    if rv != SC_SUCCESS { return rv; }
+    if erase.so_auth_state == ERASE_AUTH_NEVER {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: ### Impossible to Zeroize Card User Data (access condition) ###\0"),
+            SC_ERROR_SECURITY_STATUS_NOT_SATISFIED);
+        return SC_ERROR_SECURITY_STATUS_NOT_SATISFIED;
+    }
     {
         let mut file = unsafe { sc_file_new() };
         let guard_file = GuardFile::new(&mut file);
@@ -283,25 +467,182 @@ extern "C" fn acos5_pkcs15_erase_card(profile_ptr: *mut sc_profile, p15card_ptr:
         return rv;
     }
 
-    let mut apdu = build_apdu(ctx, &[0x80, 0x30, 0, 0], SC_APDU_CASE_1, &mut[]);
-    rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return rv; }
-    rv = unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) };
+    erase.dry_run = profile_macro(unsafe { & *profile_ptr }, "erase-dry-run").as_deref() == Some("yes");
+    rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ERASE_CARD, (&mut erase as *mut CardCtlEraseCard).cast::<c_void>()) };
     if rv != SC_SUCCESS {
-        log3if!(ctx,f,line!(), cstru!(b"Error: ### Impossible to Zeroize Card User Data ###\0"));
-        return SC_ERROR_KEYPAD_MSG_TOO_LONG;
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: ### Zeroize Card User Data failed ###\0"), rv);
+        return rv;
+    }
+    if erase.dry_run {
+        log3if!(ctx,f,line!(), cstru!(b"Dry run, nothing erased: SO authentication state %d (0: unknown, 1: fulfilled, 2: not fulfilled)\0"),
+            i32::from(erase.so_auth_state));
+        return if erase.so_auth_state == ERASE_AUTH_NOT_FULFILLED {SC_ERROR_SECURITY_STATUS_NOT_SATISFIED} else {SC_SUCCESS};
+    }
+    if let Some(init) = init.as_mut() {
+        init.validate_only = false;
+        rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_CARD_INIT, (init as *mut CardCtlCardInit).cast::<c_void>()) };
+        if rv != SC_SUCCESS {
+            log3ifr!(ctx,f,line!(), cstru!(b"Card initialization failed: The card remains erased\0"), rv);
+        }
     }
 //    log3if!(ctx,f,line!(), cstru!(b"Ready to erase card's content\0"));
     rv
 }
 
 
+/* The profile declares size and record-length of record-based files only */
+fn fixup_record_count(file: &mut sc_file) {
+    let fdb = u8::try_from(file.type_).unwrap_or(0);
+    let record_length = u8::try_from(file.record_length).unwrap_or(0);
+    if [FDB_LINEAR_FIXED_EF, FDB_LINEAR_VARIABLE_EF, FDB_CYCLIC_EF, FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_SE_FILE].contains(&fdb) &&
+        file.record_count == 0 && record_length > 0 {
+        file.record_count = u8::try_from(file.size / usize::from(record_length)).unwrap_or(0xFF).into();
+    }
+}
+
+/* Creates a file from the profile unless it exists already; returns whether it got created */
+fn create_file_if_missing(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card, file: &mut sc_file) -> Result<bool, i32> {
+    let card = unsafe { &mut *(*p15card_ptr).card };
+    /* only 'file not found' means missing, any other error (e.g. communication or security status) is an error */
+    match unsafe { sc_select_file(card, &file.path, null_mut()) } {
+        SC_SUCCESS => return Ok(false),
+        SC_ERROR_FILE_NOT_FOUND => (),
+        e => return Err(e),
+    }
+    fixup_record_count(file);
+    /* sc_pkcs15init_create_file authenticates for the parent DF's CREATE, which isn't enforced in creation state */
+    let mut parent = null_mut();
+    let guard_parent = GuardFile::new(&mut parent);
+    let mut path_parent = file.path;
+    path_parent.len = path_parent.len.saturating_sub(2);
+    let rv = unsafe { sc_select_file(card, &path_parent, *guard_parent) };
+    let rv = if rv == SC_SUCCESS && unsafe { (*parent).status } == SC_FILE_STATUS_CREATION {
+        match unsafe { sc_pkcs15init_fixup_file(profile_ptr, p15card_ptr, file) } {
+            SC_SUCCESS => unsafe { sc_create_file(card, file) },
+            e => e,
+        }
+    }
+    else {
+        unsafe { sc_pkcs15init_create_file(profile_ptr, p15card_ptr, file) }
+    };
+    match rv {
+        SC_SUCCESS => Ok(true),
+        e => Err(e),
+    }
+}
+
+/* pkcs15-init's authentication for op on file, except if the file is in creation state (see acos5_process_fci):
+   ACOS5 doesn't enforce access conditions then, and the PINs/keys, that the ACL refers to, may not exist yet */
+fn authenticate(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card, file: *mut sc_file, op: u32) -> i32 {
+    if !file.is_null() && unsafe { (*file).status } == SC_FILE_STATUS_CREATION {
+        return SC_SUCCESS;
+    }
+    unsafe { sc_pkcs15init_authenticate(profile_ptr, p15card_ptr, file, i32::try_from(op).unwrap()) }
+}
+
+/*
+Creates the files that each MF/DF must contain, as declared by the profile as it's direct children: The PIN file
+(FDB 0x0A), the sym. key file (FDB 0x0C) and the SE file (FDB 0x1C), the latter populated with SE_RECORDS_MF or
+SE_RECORDS_DF, which the profile's ACLs SCB1 (and for a DF, SCB3) refer to.
+The PIN records get written by create_pin.
+*/
+fn create_mandatory_files(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card, df_path: &sc_path) -> i32 {
+    let card = unsafe { &mut *(*p15card_ptr).card };
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"create_mandatory_files\0");
+    let mut fi = unsafe { (*profile_ptr).ef_list };
+    while !fi.is_null() {
+        let fi_ref = unsafe { & *fi };
+        fi = fi_ref.next;
+        let file_ref = unsafe { & *fi_ref.file };
+        let fdb = u8::try_from(file_ref.type_).unwrap_or(0);
+        if ![FDB_CHV_EF, FDB_SYMMETRIC_KEY_EF, FDB_SE_FILE].contains(&fdb) || file_ref.path.len != df_path.len+2 ||
+            file_ref.path.value[..df_path.len] != df_path.value[..df_path.len] {
+            continue;
+        }
+        let mut file = null_mut();
+        let guard_file = GuardFile::new(&mut file);
+        unsafe { sc_file_dup(*guard_file, fi_ref.file) };
+        if file.is_null() {
+            return SC_ERROR_OUT_OF_MEMORY;
+        }
+        let file = unsafe { &mut *file };
+        match create_file_if_missing(profile_ptr, p15card_ptr, file) {
+            Ok(true) => (),
+            Ok(false) => continue, // don't touch the content of existing files
+            Err(e) => {
+                log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to create a mandatory file of the MF/DF\0"), e);
+                return e;
+            },
+        }
+        if fdb == FDB_SE_FILE {
+            let records : &[&[u8]] = if df_path.len == 2 {&SE_RECORDS_MF} else {&SE_RECORDS_DF};
+            /* this processes the new file's FCI, which the driver's update_record relies on */
+            let mut rv = unsafe { sc_select_file(card, &file.path, null_mut()) };
+            if rv != SC_SUCCESS { return rv; }
+            for (i, rec) in records.iter().enumerate() {
+                rv = unsafe { sc_update_record(card, u32::try_from(i+1).unwrap(), rec.as_ptr(), rec.len(), SC_RECORD_BY_REC_NR) };
+                if rv < 0 {
+                    log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to write the SE file's records\0"), rv);
+                    return rv;
+                }
+            }
+        }
+    }
+    SC_SUCCESS
+}
+
+/*
+ * Card-specific initialization before the application DF gets created
+ *
+ * Called only from   src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app
+ *
+ * On a virgin card (e.g. pkcs15-init --erase-card --create-pkcs15 ...; acos5_init rejects a card without MF, thus the
+ * erasure must be within the same session), this creates the profile's MF with it's global PIN file and SE file.
+ * Otherwise there is nothing to do
+ */
+extern "C" fn acos5_pkcs15_init_card(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card) -> i32
+{
+    if profile_ptr.is_null() || p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null() || (*(*p15card_ptr).card).ctx.is_null() } {
+        return SC_ERROR_INVALID_ARGUMENTS;
+    }
+    let profile = unsafe { &mut *profile_ptr };
+    let card = unsafe { &mut *(*p15card_ptr).card };
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"acos5_pkcs15_init_card\0");
+    log3ifc!(ctx,f,line!());
+
+    let path_mf = sc_path { type_: SC_PATH_TYPE_PATH, value: [0x3F,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0], len: 2, ..sc_path::default() };
+    /* the driver refuses to select anything, if there is no MF */
+    let mut rv = unsafe { sc_select_file(card, &path_mf, null_mut()) };
+    if rv != SC_ERROR_NOT_ALLOWED {
+        return rv;
+    }
+    let mut file = null_mut();
+    let guard_file = GuardFile::new(&mut file);
+    rv = me_profile_get_file(profile, cstru!(b"MF\0").as_ptr(), *guard_file);
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Inconsistent profile: cannot find MF\0"), SC_ERROR_INCONSISTENT_PROFILE);
+        return SC_ERROR_INCONSISTENT_PROFILE;
+    }
+    /* sc_pkcs15init_create_file can't select the parent of the MF */
+    rv = unsafe { sc_create_file(card, file) };
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to create the MF\0"), rv);
+        return rv;
+    }
+    create_mandatory_files(profile_ptr, p15card_ptr, unsafe { &(*file).path })
+}
+
 /*
  * Create a DF
  *
  * Called only from   src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app  and  sc_pkcs15_create_pin_domain
+ *
+ * Creates the DF (unless it exists already), it's mandatory files (create_mandatory_files) and all xDFs of the profile.
+ * All new files are in creation state, where ACOS5 doesn't enforce access conditions: pkcs15-init --finalize activates
+ * them (acos5_pkcs15_finalize_card)
  */
-#[allow(dead_code)]
 extern "C" fn acos5_pkcs15_create_dir(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card,
                                       df_ptr: *mut sc_file) -> i32
 {
@@ -311,48 +652,77 @@ extern "C" fn acos5_pkcs15_create_dir(profile_ptr: *mut sc_profile, p15card_ptr:
     let profile = unsafe { &mut *profile_ptr };
     let card = unsafe { &mut *(*p15card_ptr).card };
     let ctx = unsafe { &mut *card.ctx };
-    let df = unsafe { & *df_ptr };
+    let df = unsafe { &mut *df_ptr };
 
     let f  = cstru!(b"acos5_pkcs15_create_dir\0");
     log3if!(ctx,f,line!(), cstru!(b"called  with df.id %X\0"), df.id);
 
+    if let Err(e) = create_file_if_missing(profile_ptr, p15card_ptr, df) {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to create the DF\0"), e);
+        return e;
+    }
+    let rv = create_mandatory_files(profile_ptr, p15card_ptr, &df.path);
+    if rv != SC_SUCCESS {
+        return rv;
+    }
+
     let create_dfs = [(SC_PKCS15_PRKDF, cstru!(b"PKCS15-PrKDF\0")), (SC_PKCS15_PUKDF, cstru!(b"PKCS15-PuKDF\0")),
                       (SC_PKCS15_SKDF, cstru!(b"PKCS15-SKDF\0")),   (SC_PKCS15_DODF, cstru!(b"PKCS15-DODF\0")),
-                      (SC_PKCS15_CDF, cstru!(b"PKCS15-CDF\0")),     (SC_PKCS15_CDF_TRUSTED, cstru!(b"PKCS15-CDF-TRUSTED\0"))];
+                      (SC_PKCS15_CDF, cstru!(b"PKCS15-CDF\0")),     (SC_PKCS15_CDF_TRUSTED, cstru!(b"PKCS15-CDF-TRUSTED\0")),
+                      (SC_PKCS15_AODF, cstru!(b"PKCS15-AODF\0"))];
 
-    if df.id == /* 0x4100 0x5015*/ 0x4100_i32 {
-        log3if!(ctx,f,line!(), cstru!(b"Select (%X)\0"), df.id);
-        /*let mut rv =*/ unsafe { sc_select_file(card, &df.path, null_mut()) };
+    for (_key,value) in &create_dfs {
+        log3if!(ctx,f,line!(), cstru!(b"Create '%s'\0"), value.as_ptr());
 
-        for (_key,value) in &create_dfs {
-            log3if!(ctx,f,line!(), cstru!(b"Create '%s'\0"), value.as_ptr());
-
-            let mut file = null_mut();
-            let guard_file = GuardFile::new(&mut file);
-            let rv = me_profile_get_file(profile, value.as_ptr(), *guard_file);
-            if rv != SC_SUCCESS {
-                log3if!(ctx,f,line!(), cstru!(b"Inconsistent profile: cannot find %s\0"), value.as_ptr());
-                return SC_ERROR_INCONSISTENT_PROFILE;//LOG_FUNC_RETURN(ctx, SC_ERROR_INCONSISTENT_PROFILE);
-            }
-//            rv = sc_pkcs15init_add_object(p15card, profile_ptr, create_dfs_val[ii], NULL);
-
-//pub fn me_pkcs15init_add_object(p15card: *mut sc_pkcs15_card, profile: *mut sc_profile, arg2: u32, arg3: *mut sc_pkcs15_object) -> i32;
-
-//            if (rv != SC_ERROR_FILE_ALREADY_EXISTS)
-//                LOG_TEST_RET(ctx, rv, "Failed to create MyEID xDF file");
+        let mut file = null_mut();
+        let guard_file = GuardFile::new(&mut file);
+        if me_profile_get_file(profile, value.as_ptr(), *guard_file) != SC_SUCCESS {
+            log3if!(ctx,f,line!(), cstru!(b"Inconsistent profile: cannot find %s\0"), value.as_ptr());
+            return SC_ERROR_INCONSISTENT_PROFILE;
+        }
+        if let Err(e) = create_file_if_missing(profile_ptr, p15card_ptr, unsafe { &mut *file }) {
+            log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to create xDF file\0"), e);
+            return e;
         }
     }
+    SC_SUCCESS
+}
 
-//    LOG_FUNC_RETURN(p15card.card.ctx, rv);
 
-    SC_SUCCESS
+/* The PIN file of a DF (local PINs) or of the MF (global PINs): It's path, MRL and NOR */
+fn pin_file_of(card: &mut sc_card, df_path: &sc_path, is_local: bool) -> Option<(sc_path, u8, u8)> {
+    let dir_len = if is_local {df_path.len} else {2};
+    if dir_len < 2 || dir_len > df_path.len || (!is_local && df_path.value[..2] != [0x3F, 0]) {
+        return None;
+    }
+    let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
+    let result = dp.files.values().find(|val| val.1[0] == FDB_CHV_EF && usize::from(val.1[1]) == dir_len+2 &&
+        val.0[..dir_len] == df_path.value[..dir_len])
+        .map(|val| (sc_path { type_: SC_PATH_TYPE_PATH, value: val.0, len: dir_len+2, ..sc_path::default() }, val.1[4], val.1[5]));
+    Box::leak(dp);
+    result
 }
 
+/* Whether the PIN reference is in use (see is_pin_reference_in_use_sw). For a local reference, the DF must be the
+   current one */
+fn is_pin_reference_in_use(card: &mut sc_card, reference: u8) -> Result<bool, i32> {
+    let ctx = unsafe { &mut *card.ctx };
+    let mut apdu = build_apdu(ctx, &[0x00, 0x20, 0x00, reference], SC_APDU_CASE_1, &mut[]);
+    let rv = unsafe { sc_transmit_apdu(card, &mut apdu) };  if rv != SC_SUCCESS { return Err(rv); }
+    match is_pin_reference_in_use_sw(u8::try_from(apdu.sw1).unwrap_or(0), u8::try_from(apdu.sw2).unwrap_or(0)) {
+        Some(in_use) => Ok(in_use),
+        None => Err(unsafe { sc_check_sw(card, apdu.sw1, apdu.sw2) }),
+    }
+}
 
 /*
  * Select a PIN reference
  *
  * Called only from   src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app  and  sc_pkcs15init_create_pin
+ *
+ * A reference < 1 asks for the first unused one; otherwise the PIN ID (the reference's bits 0-4) must be unused.
+ * The PIN ID is also the record number within the PIN file, thus it's limited by the number of records of the PIN file.
+ * Local PINs (SC_PKCS15_PIN_FLAG_LOCAL) get bit 7 set in the reference
  */
 extern "C" fn acos5_pkcs15_select_pin_reference(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card,
                                                 pin_ainfo_ptr: *mut sc_pkcs15_auth_info) -> i32
@@ -360,11 +730,47 @@ extern "C" fn acos5_pkcs15_select_pin_reference(profile_ptr: *mut sc_profile, p1
     if profile_ptr.is_null() ||  p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null()  || (*(*p15card_ptr).card).ctx.is_null() } || pin_ainfo_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
-//    let profile = unsafe { &mut *profile_ptr };
     let card = unsafe { &mut *(*p15card_ptr).card };
     let ctx = unsafe { &mut *card.ctx };
-    log3ifc!(ctx, cstru!(b"acos5_pkcs15_select_pin_reference\0"), line!());
-    SC_SUCCESS
+    let f = cstru!(b"acos5_pkcs15_select_pin_reference\0");
+    log3ifc!(ctx,f,line!());
+    let auth_info = unsafe { &mut *pin_ainfo_ptr };
+    let is_local = (unsafe { auth_info.attrs.pin.flags } & SC_PKCS15_PIN_FLAG_LOCAL) != 0;
+    let reference = unsafe { auth_info.attrs.pin.reference };
+
+    let (_, _, nor) = match pin_file_of(card, &auth_info.path, is_local) {
+        Some(val) => val,
+        None => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: There is no PIN file for the PIN\0"));
+            return SC_ERROR_FILE_NOT_FOUND;
+        },
+    };
+    let pin_id_max = nor.min(if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_ID_MAX_EVO} else {PIN_ID_MAX});
+    if is_local {
+        let rv = unsafe { sc_select_file(card, &auth_info.path, null_mut()) };
+        if rv != SC_SUCCESS { return rv; }
+    }
+    let pin_id = if reference < 1 {0} else {u8::try_from(reference & 0x1F).unwrap()};
+    if reference >= 1 && pin_id == 0 {
+        log3if!(ctx,f,line!(), cstru!(b"Error: The PIN reference %d is out of range or already in use\0"), reference);
+        return SC_ERROR_INVALID_PIN_REFERENCE;
+    }
+    match pin_id_select(pin_id, pin_id_max, |pin_id| is_pin_reference_in_use(card, if is_local {0x80 | pin_id} else {pin_id})) {
+        Ok(pin_id) => {
+            unsafe { auth_info.attrs.pin.reference = i32::from(if is_local {0x80 | pin_id} else {pin_id}) };
+            log3if!(ctx,f,line!(), cstru!(b"Selected PIN reference: 0x%X\0"), unsafe { auth_info.attrs.pin.reference });
+            SC_SUCCESS
+        },
+        Err(SC_ERROR_TOO_MANY_OBJECTS) => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: There is no unused PIN reference left in the PIN file\0"));
+            SC_ERROR_TOO_MANY_OBJECTS
+        },
+        Err(SC_ERROR_INVALID_PIN_REFERENCE) => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: The PIN reference %d is out of range or already in use\0"), reference);
+            SC_ERROR_INVALID_PIN_REFERENCE
+        },
+        Err(e) => e,
+    }
 }
 
 /*
@@ -375,19 +781,92 @@ extern "C" fn acos5_pkcs15_select_pin_reference(profile_ptr: *mut sc_profile, p1
  * the caller needs to adjust it.
  *
  * Called only from   src/pkcs15init/pkcs15-lib.c:  sc_pkcs15init_add_app,  sc_pkcs15init_store_puk  and  sc_pkcs15init_create_pin
+ *
+ * Writes the PIN record (see pin_record) into the local PIN file of the DF or into the global PIN file of MF, record
+ * number = PIN ID. The retry counter for PIN and PUK is the auth_info's max_tries, which must be 1..=15
  */
 extern "C" fn acos5_pkcs15_create_pin(profile_ptr: *mut sc_profile, p15card_ptr: *mut sc_pkcs15_card,
                                       file_ptr: *mut sc_file,
-                                      _object_ptr: *mut sc_pkcs15_object, _arg5: *const u8, _arg6: usize,
-                                      _arg7: *const u8, _arg8: usize) -> i32
+                                      object_ptr: *mut sc_pkcs15_object, pin_ptr: *const u8, pin_len: usize,
+                                      puk_ptr: *const u8, puk_len: usize) -> i32
 {
-    if profile_ptr.is_null() ||  p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null() || (*(*p15card_ptr).card).ctx.is_null() } || file_ptr.is_null()  {
+    if profile_ptr.is_null() ||  p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null() || (*(*p15card_ptr).card).ctx.is_null() } || file_ptr.is_null() ||
+        object_ptr.is_null() || unsafe { (*object_ptr).data.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
-//    let profile = unsafe { &mut *profile_ptr };
     let card = unsafe { &mut *(*p15card_ptr).card };
     let ctx = unsafe { &mut *card.ctx };
-    log3ifc!(ctx, cstru!(b"acos5_pkcs15_create_pin\0"), line!());
+    let f = cstru!(b"acos5_pkcs15_create_pin\0");
+    log3ifc!(ctx,f,line!());
+    let pin_len_max = if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_LEN_MAX_EVO} else {PIN_LEN_MAX};
+    if pin_ptr.is_null() || pin_len == 0 || pin_len > pin_len_max || (puk_ptr.is_null() && puk_len > 0) || puk_len > pin_len_max {
+        log3if!(ctx,f,line!(), cstru!(b"Error: PIN and PUK must not exceed %zu bytes, the PIN is mandatory\0"), pin_len_max);
+        return SC_ERROR_INVALID_PIN_LENGTH;
+    }
+    let pin = unsafe { from_raw_parts(pin_ptr, pin_len) };
+    let puk = if puk_len == 0 {&[][..]} else { unsafe { from_raw_parts(puk_ptr, puk_len) } };
+    let df = unsafe { &*file_ptr };
+    let auth_info = unsafe { &mut *(*object_ptr).data.cast::<sc_pkcs15_auth_info>() };
+    let is_local = (unsafe { auth_info.attrs.pin.flags } & SC_PKCS15_PIN_FLAG_LOCAL) != 0;
+    let reference = unsafe { auth_info.attrs.pin.reference };
+
+    let (path, mrl, nor) = match pin_file_of(card, &df.path, is_local) {
+        Some(val) => val,
+        None => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: There is no PIN file for the PIN\0"));
+            return SC_ERROR_FILE_NOT_FOUND;
+        },
+    };
+    let pin_id_max = nor.min(if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 {PIN_ID_MAX_EVO} else {PIN_ID_MAX});
+    let pin_id = u8::try_from(reference & 0x1F).unwrap_or(0);
+    if reference < 1 || pin_id == 0 || pin_id > pin_id_max {
+        log3if!(ctx,f,line!(), cstru!(b"Error: The PIN reference %d is out of range\0"), reference);
+        return SC_ERROR_INVALID_PIN_REFERENCE;
+    }
+    let reference = if is_local {0x80 | pin_id} else {pin_id};
+    if is_local {
+        let rv = unsafe { sc_select_file(card, &df.path, null_mut()) };
+        if rv != SC_SUCCESS { return rv; }
+    }
+    match pin_id_select(pin_id, pin_id_max, |pin_id| is_pin_reference_in_use(card, if is_local {0x80 | pin_id} else {pin_id})) {
+        Ok(_) => (),
+        Err(SC_ERROR_INVALID_PIN_REFERENCE) => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: The PIN reference 0x%X is already in use\0"), u32::from(reference));
+            return SC_ERROR_INVALID_PIN_REFERENCE;
+        },
+        Err(e) => return e,
+    }
+
+    let tries = match u8::try_from(auth_info.max_tries) {
+        Ok(tries) if (1..=15).contains(&tries) => tries,
+        _ => {
+            log3if!(ctx,f,line!(), cstru!(b"Error: The PIN's max_tries must be 1..=15, but is %d\0"), auth_info.max_tries);
+            return SC_ERROR_INVALID_ARGUMENTS;
+        },
+    };
+    let record = pin_record(pin_id, tries, pin, puk);
+    if record.len() > usize::from(mrl) {
+        log3if!(ctx,f,line!(), cstru!(b"Error: The PIN record doesn't fit into the PIN file's records of length %d\0"), i32::from(mrl));
+        return SC_ERROR_INVALID_PIN_LENGTH;
+    }
+    let mut file = null_mut();
+    let guard_file = GuardFile::new(&mut file);
+    let mut rv = unsafe { sc_select_file(card, &path, *guard_file) };
+    if rv != SC_SUCCESS {
+        return SC_ERROR_FILE_NOT_FOUND;
+    }
+    rv = authenticate(profile_ptr, p15card_ptr, file, SC_AC_OP_UPDATE);
+    if rv != SC_SUCCESS {
+        return SC_ERROR_SECURITY_STATUS_NOT_SATISFIED;
+    }
+    rv = unsafe { sc_update_record(card, u32::from(pin_id), record.as_ptr(), record.len(), SC_RECORD_BY_REC_NR) };
+    if rv < 0 {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: Failed to write the PIN record\0"), rv);
+        return rv;
+    }
+    unsafe { auth_info.attrs.pin.reference = i32::from(reference) };
+    auth_info.max_tries = i32::from(tries);
+    auth_info.tries_left = i32::from(tries);
     SC_SUCCESS
 }
 
@@ -408,7 +887,6 @@ extern "C" fn acos5_pkcs15_create_key(profile_ptr: *mut sc_profile,
 // TODO use dp.agi.do_create_files
 // TODO use dp.agi.file_id_priv
 // TODO use dp.agi.file_id_pub
-// TODO SC_PKCS15_TYPE_PRKEY_EC
     if p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null() || (*(*p15card_ptr).card).ctx.is_null() } {
         return SC_ERROR_INVALID_ARGUMENTS;
     }
@@ -446,19 +924,35 @@ extern "C" fn acos5_pkcs15_create_key(profile_ptr: *mut sc_profile,
         log3if!(ctx,f,line!(), cstru!(b"Failed: Only RSA and ECC is supported\0"));
         return SC_ERROR_NOT_SUPPORTED;
     }
-    key_info.modulus_length = rsa_modulus_bits_canonical(key_info.modulus_length);
-
-    let keybits = key_info.modulus_length;
-    if !(512..=4096).contains(&keybits) || (keybits % 256) > 0 {
-        rv = SC_ERROR_INVALID_ARGUMENTS;
-        log3ifr!(ctx,f,line!(), cstru!(b"Invalid RSA modulus size requested\0"), rv);
-        return rv;
+    let is_ec = object.type_ == SC_PKCS15_TYPE_PRKEY_EC;
+    /* for EC: the file sizes (private, public) */
+    let mut ecc_sizes = (0, 0);
+    let keybits;
+    if is_ec {
+        keybits = key_info.field_length;
+        ecc_sizes = match ecc_key_file_sizes(keybits) {
+            Some(val) if card.type_ == SC_CARD_TYPE_ACOS5_EVO_V4 => val,
+            _ => {
+                rv = SC_ERROR_INVALID_ARGUMENTS;
+                log3if!(ctx,f,line!(), cstru!(b"Failed: Unsupported EC field length %zu (or card isn't ACOS5-EVO)\0"), keybits);
+                return rv;
+            },
+        };
     }
-    /* Check that the card supports the requested modulus length */
-    if unsafe { sc_card_find_rsa_alg(card, u32::try_from(keybits).unwrap()).is_null() } {
-        rv = SC_ERROR_INVALID_ARGUMENTS;
-        log3if!(ctx,f,line!(), cstru!(b"Failed: Unsupported RSA key size %zu\0"), keybits);
-        return rv;
+    else {
+        key_info.modulus_length = rsa_modulus_bits_canonical(key_info.modulus_length);
+        keybits = key_info.modulus_length;
+        if !(512..=4096).contains(&keybits) || (keybits % 256) > 0 {
+            rv = SC_ERROR_INVALID_ARGUMENTS;
+            log3ifr!(ctx,f,line!(), cstru!(b"Invalid RSA modulus size requested\0"), rv);
+            return rv;
+        }
+        /* Check that the card supports the requested modulus length */
+        if unsafe { sc_card_find_rsa_alg(card, u32::try_from(keybits).unwrap()).is_null() } {
+            rv = SC_ERROR_INVALID_ARGUMENTS;
+            log3if!(ctx,f,line!(), cstru!(b"Failed: Unsupported RSA key size %zu\0"), keybits);
+            return rv;
+        }
     }
     /* TODO Think about other checks or possibly refuse to generate keys if file access rights are wrong */
 /* */
@@ -616,7 +1110,13 @@ extern "C" fn acos5_pkcs15_create_key(profile_ptr: *mut sc_profile,
         dp.agc.rsa_pub_exponent = [0; 16];
     }
     let do_create_files = dp.agc.do_create_files;
-    file_priv.size = 5 + keybits/16 * if dp.agc.do_generate_rsa_crt {5} else {2};
+    if is_ec {
+        file_priv.type_ = FDB_ECC_KEY_EF.into();
+        file_priv.size = ecc_sizes.0;
+    }
+    else {
+        file_priv.size = 5 + keybits/16 * if dp.agc.do_generate_rsa_crt {5} else {2};
+    }
     card.drv_data = Box::into_raw(dp).cast::<c_void>();
 //
     if !file_priv.prop_attr.is_null() {
@@ -711,7 +1211,7 @@ log3if!(ctx,f,line!(), cstru!(b"file_priv.path: %s\0"),
         return SC_ERROR_OUT_OF_MEMORY;
     }
     let mut file_pub = unsafe { &mut *file_pub };
-    file_pub.size = 21 + keybits/8;
+    file_pub.size = if is_ec {ecc_sizes.1} else {21 + keybits/8};
     // file_pub.path.value[file_pub.path.len-1] += 0x30;
     file_pub.path.value[file_pub.path.len-2..file_pub.path.len].copy_from_slice(&ay.to_be_bytes());
     file_pub.id = i32::from(file_id_from_path_value(&file_pub.path.value[..file_pub.path.len]));
@@ -745,26 +1245,26 @@ log3if!(ctx,f,line!(), cstru!(b"file_priv.path: %s\0"),
     }
 
     if do_create_files {
-        rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, fileDFparent, i32::try_from(SC_AC_OP_CREATE_EF).unwrap()) };
+        rv = authenticate(profile, p15card, fileDFparent, SC_AC_OP_CREATE_EF);
         if rv < 0 {
             log3ifr!(ctx,f,line!(), cstru!(b"SC_AC_OP_CREATE_EF authentication failed for parent DF\0"), rv);
             return rv;
         }
         if file_priv_has_to_be_deleted || file_pub_has_to_be_deleted {
-            rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, fileDFparent, i32::try_from(SC_AC_OP_DELETE).unwrap()) };
+            rv = authenticate(profile, p15card, fileDFparent, SC_AC_OP_DELETE);
             if rv < 0 {
                 log3ifr!(ctx,f,line!(), cstru!(b"SC_AC_OP_CREATE_EF authentication failed for parent DF\0"), rv);
                 return rv;
             }
         }
         if file_priv_has_to_be_deleted {
-            rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, file_priv, i32::try_from(SC_AC_OP_DELETE_SELF).unwrap()) };
+            rv = authenticate(profile, p15card, file_priv, SC_AC_OP_DELETE_SELF);
             if rv != SC_SUCCESS { return rv; }
             rv = unsafe { sc_delete_file(card, &file_priv.path) };
             if rv != SC_SUCCESS { return rv; }
         }
         if file_pub_has_to_be_deleted {
-            rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, file_pub, i32::try_from(SC_AC_OP_DELETE_SELF).unwrap()) };
+            rv = authenticate(profile, p15card, file_pub, SC_AC_OP_DELETE_SELF);
             if rv != SC_SUCCESS { return rv; }
             rv = unsafe { sc_delete_file(card, &file_pub.path) };
             if rv != SC_SUCCESS { return rv; }
@@ -791,7 +1291,7 @@ log3if!(ctx,f,line!(), cstru!(b"file_priv.path: %s\0"),
             return SC_ERROR_KEYPAD_MSG_TOO_LONG;
         }
 
-        rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, file_priv, i32::try_from(SC_AC_OP_UPDATE).unwrap()) };
+        rv = authenticate(profile, p15card, file_priv, SC_AC_OP_UPDATE);
         if rv != SC_SUCCESS { return rv; }
 
         rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SDO_CREATE, (file_pub as *mut sc_file).cast::<c_void>()) };
@@ -812,31 +1312,25 @@ log3if!(ctx,f,line!(), cstru!(b"file_priv.path: %s\0"),
             return SC_ERROR_KEYPAD_MSG_TOO_LONG;
         }
 
-        rv = unsafe { sc_pkcs15init_authenticate(profile, p15card, file_pub, i32::try_from(SC_AC_OP_UPDATE).unwrap()) };
+        rv = authenticate(profile, p15card, file_pub, SC_AC_OP_UPDATE);
         if rv != SC_SUCCESS { return rv; }
     }
     let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
     dp.agc.file_id_priv = u16::try_from(file_priv.id).unwrap();
     dp.agc.file_id_pub  = u16::try_from(file_pub.id).unwrap();
-    dp.agc.key_len_code = u8::try_from(keybits / 128).unwrap();
-
-    dp.agc.key_curve_code = match key_info.field_length {
-        224 => if card.type_ < SC_CARD_TYPE_ACOS5_EVO_V4 {0} else {1},
-        256 => if card.type_ < SC_CARD_TYPE_ACOS5_EVO_V4 {0} else {2},
-        384 => if card.type_ < SC_CARD_TYPE_ACOS5_EVO_V4 {0} else {3},
-        521 => if card.type_ < SC_CARD_TYPE_ACOS5_EVO_V4 {0} else {4},
-        _   => 0,
-    };
+    dp.agc.key_len_code = if is_ec {0} else {u8::try_from(keybits / 128).unwrap()};
+    /* key_curve_code != 0 selects ECC key pair generation in generate_asym */
+    dp.agc.key_curve_code = if is_ec {ecc_curve_code(keybits).unwrap()} else {0};
     dp.agc.key_priv_type_code = match key_info.usage & BOTH {
         SC_PKCS15_PRKEY_USAGE_SIGN => 1,
         SC_PKCS15_PRKEY_USAGE_DECRYPT => 2,
         BOTH => 3,
         _ => return SC_ERROR_KEYPAD_MSG_TOO_LONG,
     };
-    if dp.agc.key_priv_type_code==1 && dp.agc.do_generate_rsa_add_decrypt_for_sign {
+    if !is_ec && dp.agc.key_priv_type_code==1 && dp.agc.do_generate_rsa_add_decrypt_for_sign {
         dp.agc.key_priv_type_code = 3;
     }
-    if dp.agc.do_generate_rsa_crt {
+    if !is_ec && dp.agc.do_generate_rsa_crt {
         dp.agc.key_priv_type_code += 3;
     }
 
@@ -956,7 +1450,7 @@ extern "C" fn acos5_pkcs15_store_key(profile_ptr: *mut sc_profile, p15card_ptr:
     if rv != SC_SUCCESS {
         return SC_ERROR_FILE_NOT_FOUND;
     }
-    rv = unsafe { sc_pkcs15init_authenticate(profile_ptr, p15card, file, i32::try_from(SC_AC_OP_UPDATE).unwrap()) };
+    rv = authenticate(profile_ptr, p15card, file, SC_AC_OP_UPDATE);
     if rv != SC_SUCCESS {
         return SC_ERROR_SECURITY_STATUS_NOT_SATISFIED;
     }
@@ -978,7 +1472,7 @@ extern "C" fn acos5_pkcs15_generate_key(profile_ptr: *mut sc_profile,
                                         p15card_ptr: *mut sc_pkcs15_card,
                                         p15object_ptr: *mut sc_pkcs15_object,
                                         p15pubkey_ptr: *mut sc_pkcs15_pubkey) -> i32
-{ // TODO must handle create RSA key pair  And  generate ECC key pair
+{
     if profile_ptr.is_null() || p15card_ptr.is_null() || unsafe { (*p15card_ptr).card.is_null() || (*(*p15card_ptr).card).ctx.is_null() } ||
        p15object_ptr.is_null() || unsafe { (*p15object_ptr).data.is_null() } || p15pubkey_ptr.is_null() {
         return SC_ERROR_INVALID_ARGUMENTS;
@@ -997,7 +1491,7 @@ extern "C" fn acos5_pkcs15_generate_key(profile_ptr: *mut sc_profile,
     if   SC_PKCS15_TYPE_PRKEY_RSA != object_priv.type_ &&
         (SC_PKCS15_TYPE_PRKEY_EC  != object_priv.type_ || card.type_ != SC_CARD_TYPE_ACOS5_EVO_V4)
     {
-        log3if!(ctx,f,line!(), cstru!(b"Failed: Only RSA is supported\0"));
+        log3if!(ctx,f,line!(), cstru!(b"Failed: Only RSA and (ACOS5-EVO) ECC is supported\0"));
         return SC_ERROR_NOT_SUPPORTED;
     }
 //    let keybits = rsa_modulus_bits_canonical(key_info_priv.modulus_length);
@@ -1036,6 +1530,9 @@ extern "C" fn acos5_pkcs15_generate_key(profile_ptr: *mut sc_profile,
         return rv;
     }
 */
+    /* The RSA public exponent: sc_pkcs15init_generate_key hands a zeroed p15pubkey to generate_key and OpenSC doesn't
+       forward PKCS#11 CKA_PUBLIC_EXPONENT to pkcs15init. Thus a non-standard exponent is available only by acos5_gui
+       (dp.agi, see acos5_pkcs15_create_key); generate_asym validates it */
     //gen_keypair; the data get prepared in acos5_pkcs15_create_key
     rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SDO_GENERATE_KEY_FILES, (&mut agc as *mut CardCtl_generate_crypt_asym).cast::<c_void>()) };
     if rv != SC_SUCCESS {
@@ -1067,6 +1564,11 @@ extern "C" fn acos5_pkcs15_generate_key(profile_ptr: *mut sc_profile,
             rv = me_pkcs15_dup_bignum(&mut p15pubkey.u.rsa.exponent, &(*p15pubkey2_ptr).u.rsa.exponent);
             if rv != SC_SUCCESS { return rv; }
         }
+        else {
+            /* the curve's params were set by pkcs15init already; what's missing is the public point */
+            rv = me_pkcs15_dup_u8(&mut p15pubkey.u.ec.ecpointQ, &(*p15pubkey2_ptr).u.ec.ecpointQ);
+            if rv != SC_SUCCESS { return rv; }
+        }
         sc_pkcs15_free_pubkey(p15pubkey2_ptr);
     }
 
@@ -1081,6 +1583,8 @@ extern "C" fn acos5_pkcs15_generate_key(profile_ptr: *mut sc_profile,
  * Ends the initialization phase of the smart card/token
  * (actually this command is currently only for starcos spk 2.3
  * cards).
+ * For ACOS5: Activates all files that are still in LCSI 'creation state' (access control isn't enforced there),
+ * e.g. those created by pkcs15-init, via SC_CARDCTL_LIFECYCLE_SET SC_CARDCTRL_LIFECYCLE_USER
  *
  * Called only from   src/pkcs15init/pkcs15-lib.c:
  */
@@ -1090,8 +1594,13 @@ extern "C" fn acos5_pkcs15_finalize_card(card_ptr: *mut sc_card) -> i32
         return SC_ERROR_INVALID_ARGUMENTS;
     }
     let card = unsafe { &mut *card_ptr };
-    log3ifc!(unsafe { &mut *card.ctx }, cstru!(b"acos5_pkcs15_finalize_card\0"), line!());
-    SC_SUCCESS
+    let ctx = unsafe { &mut *card.ctx };
+    let f = cstru!(b"acos5_pkcs15_finalize_card\0");
+    log3ifc!(ctx,f,line!());
+    let mut lifecycle = i32::try_from(SC_CARDCTRL_LIFECYCLE_USER).unwrap();
+    let rv = unsafe { sc_card_ctl(card, SC_CARDCTL_LIFECYCLE_SET, (&mut lifecycle as *mut i32).cast::<c_void>()) };
+    log3ifr!(ctx,f,line!(), rv);
+    rv
 }
 
 /*
@@ -1151,12 +1660,12 @@ extern "C" fn acos5_pkcs15_emu_store_data(p15card: *mut sc_pkcs15_card, profile:
         log3if!(ctx,f,line!(), cstru!(b"path: %s\0"), unsafe { sc_dump_hex((*path).value.as_ptr(), (*path).len) }); // 0
     }
 
-    if SC_PKCS15_TYPE_PRKEY_RSA == object.type_ {
+    if [SC_PKCS15_TYPE_PRKEY_RSA, SC_PKCS15_TYPE_PRKEY_EC].contains(&object.type_) {
         let mut dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
         dp.last_keygen_priv_id = unsafe { (*object.data.cast::<sc_pkcs15_prkey_info>() ).id };
         card.drv_data = Box::into_raw(dp).cast::<c_void>();
     }
-    else if SC_PKCS15_TYPE_PUBKEY_RSA == object.type_ {
+    else if [SC_PKCS15_TYPE_PUBKEY_RSA, SC_PKCS15_TYPE_PUBKEY_EC].contains(&object.type_) {
         let key_info = unsafe { &mut *object.data.cast::<sc_pkcs15_pubkey_info>() };
 /*
     log3if!(ctx,f,line!(), cstru!(b"object.label: %s\0"),   object.label.as_ptr()); // pkcs15-init -G rsa/3072 -a 01 -i 08 -l testkey -u sign,decrypt
@@ -1189,7 +1698,9 @@ extern "C" fn acos5_pkcs15_emu_store_data(p15card: *mut sc_pkcs15_card, profile:
         }
     }
 */
-        key_info.modulus_length = rsa_modulus_bits_canonical(key_info.modulus_length);
+        if SC_PKCS15_TYPE_PUBKEY_RSA == object.type_ {
+            key_info.modulus_length = rsa_modulus_bits_canonical(key_info.modulus_length);
+        }
         key_info.access_flags = SC_PKCS15_PRKEY_ACCESS_EXTRACTABLE | SC_PKCS15_PRKEY_ACCESS_LOCAL;
         key_info.native = 1;
         let dp = unsafe { Box::from_raw(card.drv_data.cast::<DataPrivate>()) };
@@ -1233,7 +1744,33 @@ extern "C" fn acos5_pkcs15_emu_store_data(p15card: *mut sc_pkcs15_card, profile:
     SC_SUCCESS
 }
 
-extern "C" fn acos5_pkcs15_sanity_check(_profile: *mut sc_profile, p15card: *mut sc_pkcs15_card) -> i32
+/* Asks on the terminal, whether the repair plan printed shall be applied; only the answer yes confirms.
+   Without a terminal (or on Windows) there is no confirmation */
+#[cfg(unix)]
+fn repair_plan_confirm(count_actions: usize) -> bool
+{
+    use std::io::{BufRead, BufReader, Write};
+    let mut tty = match std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_e) => return false,
+    };
+    let question = format!("Apply the {} repair(s) listed above? Files get deleted and created again, make a backup before. Type yes to confirm: ",
+        count_actions);
+    if tty.write_all(question.as_bytes()).is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    BufReader::new(&tty).read_line(&mut answer).is_ok() && answer.trim() == "yes"
+}
+
+#[cfg(not(unix))]
+fn repair_plan_confirm(_count_actions: usize) -> bool { false }
+
+/*
+ * Prints the sanity findings, and if some have a mechanical fix, the repair plan.
+ * The plan gets applied after the user confirmed it on the terminal (within each run) and after SO authentication
+ */
+extern "C" fn acos5_pkcs15_sanity_check(profile: *mut sc_profile, p15card: *mut sc_pkcs15_card) -> i32
 {
     if p15card.is_null() || unsafe { (*p15card).card.is_null() } {
         return SC_ERROR_KEYPAD_MSG_TOO_LONG;
@@ -1246,7 +1783,49 @@ extern "C" fn acos5_pkcs15_sanity_check(_profile: *mut sc_profile, p15card: *mut
     let ctx = unsafe { &mut *card.ctx };
     let f = cstru!(b"acos5_pkcs15_sanity_check\0");
     log3ifc!(ctx,f,line!());
-    unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SANITY_CHECK, null_mut()) }
+    let mut rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SANITY_CHECK, null_mut()) };
+    if rv != SC_SUCCESS {
+        return rv;
+    }
+
+    /* the repair plan */
+    let mut plan = vec![0_u8; 1024];
+    let mut repair = CardCtlSanityRepair::default();
+    loop {
+        repair = CardCtlSanityRepair { plan: plan.as_mut_ptr().cast::<c_char>(), plan_cap: plan.len(), ..repair };
+        rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SANITY_REPAIR, (&mut repair as *mut CardCtlSanityRepair).cast::<c_void>()) };
+        if rv != SC_ERROR_BUFFER_TOO_SMALL { break; }
+        plan.resize(repair.plan_len+1, 0);
+    }
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: repair planning failed with\0"), rv);
+        return rv;
+    }
+    if repair.plan_len == 0 {
+        return SC_SUCCESS;
+    }
+    println!("\nThe repair plan (findings NOT repaired are listed as well):");
+    print!("{}", String::from_utf8_lossy(&plan[..repair.plan_len]));
+    if repair.count_actions == 0 {
+        return SC_SUCCESS;
+    }
+    if !repair_plan_confirm(repair.count_actions) {
+        println!("Not confirmed: Nothing got changed");
+        return SC_SUCCESS;
+    }
+    repair.is_confirmed = true;
+
+    rv = unsafe { sc_pkcs15init_verify_secret(profile, p15card, (*p15card).file_app, SC_AC_SYMBOLIC, SC_PKCS15INIT_SO_PIN.try_into().unwrap()) };
+    if rv != SC_SUCCESS {
+        log3ifr!(ctx,f,line!(), cstru!(b"Error: SO authentication failed with\0"), rv);
+        println!("SO authentication failed: Nothing got changed");
+        return rv;
+    }
+    repair.apply = true;
+    rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_SANITY_REPAIR, (&mut repair as *mut CardCtlSanityRepair).cast::<c_void>()) };
+    println!("Applied {} of {} repair(s){}", repair.count_applied, repair.count_actions,
+        if rv == SC_SUCCESS { String::new() } else { format!("; repair no. {} failed with error {}", repair.count_applied+1, rv) });
+    rv
 }
 
 /*
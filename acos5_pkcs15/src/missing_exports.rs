@@ -9,7 +9,7 @@ use opensc_sys::profile::{sc_profile, file_info};
 use opensc_sys::types::{sc_path, sc_file, sc_acl_entry, SC_MAX_AID_SIZE, SC_MAX_AC_OPS
                         /*, SC_AC_OP_CREATE_EF, SC_PATH_TYPE_FILE_ID, SC_AC_OP_DELETE*/};
 use opensc_sys::errors::{SC_SUCCESS, SC_ERROR_FILE_NOT_FOUND, SC_ERROR_OUT_OF_MEMORY, SC_ERROR_INVALID_ARGUMENTS, SC_ERROR_PKCS15INIT};
-use opensc_sys::pkcs15::{sc_pkcs15_bignum, sc_pkcs15_card, sc_pkcs15_df};
+use opensc_sys::pkcs15::{sc_pkcs15_bignum, sc_pkcs15_u8, sc_pkcs15_card, sc_pkcs15_df};
 //use opensc_sys::log::{sc_dump_hex};
 
 use std::ptr::null_mut;
@@ -179,6 +179,20 @@ pub fn me_pkcs15_dup_bignum(dst: &mut sc_pkcs15_bignum, src: &sc_pkcs15_bignum)
     0
 }
 
+pub fn me_pkcs15_dup_u8(dst: &mut sc_pkcs15_u8, src: &sc_pkcs15_u8) -> i32
+{
+     if !src.value.is_null() && src.len > 0  {
+        dst.value = unsafe { calloc(1, src.len) }.cast::<u8>();
+        if dst.value.is_null() {
+            return SC_ERROR_OUT_OF_MEMORY;
+        }
+        unsafe { memcpy(dst.value.cast::<c_void>(), src.value as *const c_void, src.len) };
+        dst.len = src.len;
+    }
+
+    0
+}
+
 #[allow(clippy::missing_errors_doc)]
 pub fn find_df_by_type(p15card: &mut sc_pkcs15_card, type_: u8) -> Result<&mut sc_pkcs15_df, i32>
 {
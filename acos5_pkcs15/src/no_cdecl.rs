@@ -23,7 +23,7 @@ use opensc_sys::log::{sc_dump_hex};
 
 use crate::constants_types::{SC_CARD_TYPE_ACOS5_64_V2, SC_CARD_TYPE_ACOS5_64_V3, SC_CARD_TYPE_ACOS5_EVO_V4, GuardFile,
                              DataPrivate, file_id_from_path_value, file_id_se, SC_CARDCTL_ACOS5_GET_FREE_SPACE, //p_void,
-                             build_apdu, CardCtlAlgoRefSymStore, SC_CARDCTL_ACOS5_ALGO_REF_SYM_STORE //, FCI
+                             build_apdu, CardCtlAlgoRefSymStore, SC_CARDCTL_ACOS5_ALGO_REF_SYM_STORE, ecc_key_file_sizes //, FCI
 };
 use crate::wrappers::{wr_do_log, wr_do_log_t, wr_do_log_rv, wr_do_log_sds};
 use crate::missing_exports::{find_df_by_type};
@@ -265,7 +265,10 @@ pub fn check_enlarge_prkdf_pukdf(profile: &mut sc_profile, p15card: &mut sc_pkcs
     let mut card_free_space : u32 = 0;
     rv = unsafe { sc_card_ctl(card, SC_CARDCTL_ACOS5_GET_FREE_SPACE, (&mut card_free_space  as *mut u32).cast::<c_void>()) };
     assert_eq!(SC_SUCCESS, rv);
-    let key_pair_size_req = key_info.modulus_length/16 * 7 + 26; // min. is 250 bytes for RSA/512
+    let key_pair_size_req = match ecc_key_file_sizes(key_info.field_length) {
+        Some((size_priv, size_pub)) => size_priv + size_pub,
+        None => key_info.modulus_length/16 * 7 + 26, // min. is 250 bytes for RSA/512
+    };
     if  key_pair_size_req > card_free_space.try_into().unwrap() { return Err(SC_ERROR_NOT_ENOUGH_MEMORY); }
     if unused_len < 80  &&  key_pair_size_req + INC <= card_free_space.try_into().unwrap() {
         /* TODO any enlargement only if it makes sense : get_free_space; in any case it MUST BE AVOIDED that EF.PrKDF gets deleted without being able to re-create it enlarged !!! */